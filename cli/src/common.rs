@@ -59,13 +59,25 @@ pub(crate) fn with_device<F>(shared_options: &SharedOptions, f: F) -> Result<()>
 where
     F: FnOnce(Session) -> Result<()>,
 {
-    let mut probe = open_probe(shared_options.n)?;
-
     let target_selector = match &shared_options.chip {
         Some(identifier) => identifier.into(),
         None => TargetSelector::Auto,
     };
 
+    with_device_and_target(shared_options, target_selector, f)
+}
+
+/// Like [with_device], but attaches to the given target instead of the chip of the shared options.
+pub(crate) fn with_device_and_target<F>(
+    shared_options: &SharedOptions,
+    target_selector: TargetSelector,
+    f: F,
+) -> Result<()>
+where
+    F: FnOnce(Session) -> Result<()>,
+{
+    let mut probe = open_probe(shared_options.n)?;
+
     if let Some(protocol) = shared_options.protocol {
         probe.select_protocol(protocol)?;
     }
@@ -4,7 +4,8 @@ use capstone::Capstone;
 use num_traits::Num;
 use probe_rs::architecture::arm::CortexDump;
 use probe_rs::debug::DebugInfo;
-use probe_rs::{Core, CoreRegisterAddress, MemoryInterface};
+use probe_rs::semihosting::{ConsoleStream, Semihosting, SemihostingOutcome};
+use probe_rs::{Core, CoreRegisterAddress, CoreStatus, HaltReason, MemoryInterface};
 
 use std::fs::File;
 use std::{io::prelude::*, time::Duration};
@@ -87,7 +88,7 @@ impl DebugCli {
             help_text: "Show current status of CPU",
 
             function: |cli_data, _args| {
-                let status = cli_data.core.status()?;
+                let status = service_semihosting(cli_data)?;
 
                 println!("Status: {:?}", &status);
 
@@ -107,7 +108,10 @@ impl DebugCli {
             help_text: "Resume execution of the CPU",
 
             function: |cli_data, _args| {
-                cli_data.core.run()?;
+                // Servicing a semihosting request resumes the core.
+                if service_semihosting(cli_data)?.is_halted() {
+                    cli_data.core.run()?;
+                }
 
                 Ok(CliState::Continue)
             },
@@ -356,10 +360,35 @@ impl DebugCli {
     }
 }
 
+/// Service all pending semihosting requests of the core, printing the output
+/// of the target, and return the resulting status of the core.
+fn service_semihosting(cli_data: &mut CliData) -> Result<CoreStatus, CliError> {
+    let mut status = cli_data.core.status()?;
+
+    while status == CoreStatus::Halted(HaltReason::Semihosting) {
+        match cli_data.semihosting.service(&mut cli_data.core)? {
+            SemihostingOutcome::Resumed => (),
+            SemihostingOutcome::Output { stream, data } => match stream {
+                ConsoleStream::Stdout => std::io::stdout().write_all(&data)?,
+                ConsoleStream::Stderr => std::io::stderr().write_all(&data)?,
+            },
+            SemihostingOutcome::Exit(code) => {
+                println!("Target exited with code {}", code);
+                break;
+            }
+        }
+
+        status = cli_data.core.status()?;
+    }
+
+    Ok(status)
+}
+
 pub struct CliData<'p> {
     pub core: Core<'p>,
     pub debug_info: Option<DebugInfo>,
     pub capstone: Capstone,
+    pub semihosting: Semihosting,
 }
 
 pub enum CliState {
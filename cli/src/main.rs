@@ -2,12 +2,14 @@ mod common;
 mod debugger;
 mod info;
 
-use common::with_device;
+use common::{with_device, with_device_and_target};
 use debugger::CliState;
 
 use probe_rs::{
+    config::get_target_by_name,
     debug::DebugInfo,
-    flashing::{download_file, erase_all, Format},
+    flashing::{download_file, erase_all, read_flash_algorithm_from_flm, Format},
+    semihosting::Semihosting,
     MemoryInterface, Probe, Session, WireProtocol,
 };
 
@@ -19,7 +21,7 @@ use structopt::StructOpt;
 use anyhow::{anyhow, Result};
 
 use std::num::ParseIntError;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 fn parse_hex(src: &str) -> Result<u32, ParseIntError> {
@@ -106,6 +108,10 @@ enum Cli {
 
         /// The path to the file to be downloaded to the flash
         path: String,
+
+        /// An FLM file of a CMSIS-Pack, with the flash algorithm to use instead of the one of the chip
+        #[structopt(long, parse(from_os_str))]
+        flash_algorithm: Option<PathBuf>,
     },
     /// Erase all nonvolatile memory of attached target
     #[structopt(name = "erase")]
@@ -166,7 +172,8 @@ fn main() -> Result<()> {
             shared,
             format,
             path,
-        } => download_program_fast(&shared, format.into(), &path),
+            flash_algorithm,
+        } => download_program_fast(&shared, format.into(), &path, flash_algorithm.as_deref()),
         Cli::Erase { shared } => erase(&shared),
         Cli::Trace { shared, loc } => trace_u32_on_target(&shared, loc),
     }
@@ -218,12 +225,30 @@ fn dump_memory(shared_options: &SharedOptions, loc: u32, words: u32) -> Result<(
     })
 }
 
-fn download_program_fast(shared_options: &SharedOptions, format: Format, path: &str) -> Result<()> {
-    with_device(shared_options, |mut session| {
+fn download_program_fast(
+    shared_options: &SharedOptions,
+    format: Format,
+    path: &str,
+    flash_algorithm: Option<&Path>,
+) -> Result<()> {
+    let download = |mut session: Session| {
         download_file(&mut session, &path, format)?;
 
         Ok(())
-    })
+    };
+
+    match flash_algorithm {
+        Some(flash_algorithm) => {
+            let chip = shared_options.chip.as_ref().ok_or_else(|| {
+                anyhow!("The chip has to be given with --chip to use a flash algorithm")
+            })?;
+            let mut target = get_target_by_name(chip)?;
+            target.add_flash_algorithm(read_flash_algorithm_from_flm(flash_algorithm)?);
+
+            with_device_and_target(shared_options, target.into(), download)
+        }
+        None => with_device(shared_options, download),
+    }
 }
 
 fn erase(shared_options: &SharedOptions) -> Result<()> {
@@ -307,6 +332,7 @@ fn debug(shared_options: &SharedOptions, exe: Option<PathBuf>) -> Result<()> {
             core,
             debug_info: di,
             capstone: cs,
+            semihosting: Semihosting::new(),
         };
 
         let mut rl = Editor::<()>::new();
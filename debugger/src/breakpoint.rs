@@ -0,0 +1,140 @@
+//! Breakpoints set by the debug client, and the conditions under which they halt the core.
+
+use crate::{expression::Expression, DebuggerError};
+use anyhow::anyhow;
+use std::{path::PathBuf, str::FromStr};
+
+/// The request which created a breakpoint. `setBreakpoints` replaces the breakpoints of a single
+/// source file, `setFunctionBreakpoints` and `setInstructionBreakpoints` replace all function
+/// and instruction breakpoints.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum BreakpointSource {
+    SourceFile(PathBuf),
+    Function,
+    Instruction,
+}
+
+/// A breakpoint set by the client. The conditions are evaluated by the debugger every time the
+/// core halts at its address, and the core is resumed if they are not met.
+#[derive(Debug)]
+pub(crate) struct ActiveBreakpoint {
+    pub(crate) source: BreakpointSource,
+    pub(crate) address: u32,
+    pub(crate) condition: Option<Expression>,
+    pub(crate) hit_condition: Option<HitCondition>,
+    /// How often the breakpoint was hit while its condition was met.
+    pub(crate) hit_count: u64,
+}
+
+impl ActiveBreakpoint {
+    pub(crate) fn new(
+        source: BreakpointSource,
+        address: u32,
+        condition: Option<&str>,
+        hit_condition: Option<&str>,
+    ) -> Result<Self, DebuggerError> {
+        Ok(ActiveBreakpoint {
+            source,
+            address,
+            condition: condition
+                .filter(|condition| !condition.trim().is_empty())
+                .map(Expression::parse)
+                .transpose()?,
+            hit_condition: hit_condition
+                .filter(|hit_condition| !hit_condition.trim().is_empty())
+                .map(str::parse)
+                .transpose()?,
+            hit_count: 0,
+        })
+    }
+}
+
+/// When a breakpoint halts, based on the number of times it was hit, e.g. `>= 5` or `% 10`.
+/// A number without an operator behaves like `>=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum HitCondition {
+    Equal(u64),
+    GreaterOrEqual(u64),
+    Greater(u64),
+    Multiple(u64),
+}
+
+impl HitCondition {
+    pub(crate) fn is_met(self, hit_count: u64) -> bool {
+        match self {
+            HitCondition::Equal(count) => hit_count == count,
+            HitCondition::GreaterOrEqual(count) => hit_count >= count,
+            HitCondition::Greater(count) => hit_count > count,
+            HitCondition::Multiple(count) => hit_count.checked_rem(count) == Some(0),
+        }
+    }
+}
+
+impl FromStr for HitCondition {
+    type Err = DebuggerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let (constructor, count): (fn(u64) -> HitCondition, &str) =
+            if let Some(count) = s.strip_prefix("==") {
+                (HitCondition::Equal, count)
+            } else if let Some(count) = s.strip_prefix(">=") {
+                (HitCondition::GreaterOrEqual, count)
+            } else if let Some(count) = s.strip_prefix('>') {
+                (HitCondition::Greater, count)
+            } else if let Some(count) = s.strip_prefix('%') {
+                (HitCondition::Multiple, count)
+            } else {
+                (HitCondition::GreaterOrEqual, s)
+            };
+
+        count
+            .trim()
+            .parse()
+            .map(constructor)
+            .map_err(|_| DebuggerError::Other(anyhow!("Invalid hit count condition '{}'", s)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HitCondition;
+
+    #[test]
+    fn parse_hit_condition() {
+        assert_eq!(
+            "5".parse::<HitCondition>().unwrap(),
+            HitCondition::GreaterOrEqual(5)
+        );
+        assert_eq!(
+            "== 3".parse::<HitCondition>().unwrap(),
+            HitCondition::Equal(3)
+        );
+        assert_eq!(
+            ">=2".parse::<HitCondition>().unwrap(),
+            HitCondition::GreaterOrEqual(2)
+        );
+        assert_eq!(
+            "> 7".parse::<HitCondition>().unwrap(),
+            HitCondition::Greater(7)
+        );
+        assert_eq!(
+            "%10".parse::<HitCondition>().unwrap(),
+            HitCondition::Multiple(10)
+        );
+        assert!("< 3".parse::<HitCondition>().is_err());
+    }
+
+    #[test]
+    fn hit_condition_is_met() {
+        assert!(!HitCondition::GreaterOrEqual(2).is_met(1));
+        assert!(HitCondition::GreaterOrEqual(2).is_met(2));
+        assert!(!HitCondition::Greater(2).is_met(2));
+        assert!(HitCondition::Equal(2).is_met(2));
+        assert!(!HitCondition::Equal(2).is_met(3));
+        assert!(HitCondition::Multiple(3).is_met(6));
+        assert!(!HitCondition::Multiple(3).is_met(7));
+        assert!(!HitCondition::Multiple(0).is_met(0));
+    }
+}
@@ -22,6 +22,22 @@ impl TryFrom<&serde_json::Value> for ReadMemoryArguments {
     type Error = DebuggerError;
 }
 
+impl TryFrom<&serde_json::Value> for DisassembleArguments {
+    fn try_from(arguments: &serde_json::Value) -> Result<Self, Self::Error> {
+        let memory_reference = get_string_argument(arguments, "memory_reference", 0)?;
+        let instruction_count = get_int_argument(arguments, "instruction_count", 1)?;
+        Ok(DisassembleArguments {
+            instruction_count,
+            instruction_offset: None,
+            memory_reference,
+            offset: None,
+            resolve_symbols: Some(true),
+        })
+    }
+
+    type Error = DebuggerError;
+}
+
 // SECTION: For various helper functions
 
 /// Parse the argument at the given index.
@@ -1,22 +1,32 @@
+use crate::breakpoint::{ActiveBreakpoint, BreakpointSource};
 use crate::dap_types;
 use crate::debugger::ConsoleLog;
 use crate::debugger::CoreData;
+use crate::disassembly;
+use crate::exceptions::{self, ExceptionFilter};
+use crate::expression::{Expression, Value};
+use crate::peripherals::{self, SvdPeripherals};
+use crate::stepping::{self, SteppingMode};
 use crate::DebuggerError;
 use anyhow::{anyhow, Result};
 use dap_types::*;
 use parse_int::parse;
 use probe_rs::{
-    debug::{ColumnType, VariableKind},
-    CoreStatus, HaltReason, MemoryInterface,
+    debug::{ColumnType, SourceLocation, VariableKind},
+    semihosting::{ConsoleStream, Semihosting, SemihostingOutcome},
+    CoreRegisterAddress, CoreStatus, HaltReason, MemoryInterface,
 };
 use rustyline::Editor;
 use serde::{de::DeserializeOwned, Serialize};
 use serde_json::json;
-use std::{collections::HashMap, string::ToString};
 use std::{
-    convert::TryInto,
+    collections::{HashMap, HashSet},
+    string::ToString,
+};
+use std::{
+    convert::{TryFrom, TryInto},
     io::{BufRead, BufReader, Read, Write},
-    path::{Path, PathBuf},
+    path::PathBuf,
     str, thread,
     time::Duration,
 };
@@ -31,10 +41,12 @@ pub struct DebugAdapter<R: Read, W: Write> {
     seq: i64,
     input: BufReader<R>,
     output: W,
-    /// Track the last_known_status of the probe. The debug client needs to be notified when the probe changes state, and the only way is to poll the probe status periodically. For instance, when the client sets the probe running, and the probe halts because of a breakpoint, we need to notify the client.
-    pub(crate) last_known_status: CoreStatus,
+    /// Track the last_known_status of each core, by core index. The debug client needs to be notified when the probe changes state, and the only way is to poll the probe status periodically. For instance, when the client sets the probe running, and the probe halts because of a breakpoint, we need to notify the client.
+    last_known_status: HashMap<usize, CoreStatus>,
     pub(crate) adapter_type: DebugAdapterType,
     pub(crate) halt_after_reset: bool,
+    /// The DAP client shows progress bars for the `progressStart`, `progressUpdate` and `progressEnd` events.
+    pub(crate) supports_progress_reporting: bool,
     pub(crate) console_log_level: ConsoleLog,
     /// rl is the optional rustyline command line processor instance
     rl: Option<Editor<()>>,
@@ -43,8 +55,28 @@ pub struct DebugAdapter<R: Read, W: Write> {
     scope_map: HashMap<i64, Vec<Scope>>,
     /// variable_map stores a list of all MS DAP Variables with a unique per-level reference
     /// It is cleared by threads(), populated by stack_trace(), for later nested re-use by variables()
-    variable_map_key_seq: i64, //Used to create unique values for self.variable_map keys
+    variable_map_key_seq: i64, //Used to create unique values for self.variable_map keys and stack frame ids
     variable_map: HashMap<i64, Vec<Variable>>,
+    /// variable_values stores the probe-rs Variables behind the MS DAP Variables in variable_map, with the same key, so that setVariable() can write them.
+    variable_values: HashMap<i64, Vec<probe_rs::debug::Variable>>,
+    /// The variable_map key of the registers of the top stack frame of each core, by core index. Registers of other frames were unwound and can't be changed.
+    register_scope_references: HashMap<usize, i64>,
+    /// The variable_map keys of the variables of all stack frames except the top one of each core. The values of their registers were unwound, so variables in registers can't be changed.
+    outer_frame_references: HashSet<i64>,
+    /// The index of the core that each stack frame id of scope_map belongs to.
+    frame_cores: HashMap<i64, usize>,
+    /// The position of each stack frame id of scope_map in the stack trace of its core.
+    frame_indices: HashMap<i64, usize>,
+    /// The index of the core that each variable_map key belongs to.
+    variable_cores: HashMap<i64, usize>,
+    /// The variable_map keys of the peripherals in the "Peripherals" scope, with the index of the peripheral. Their registers are read when the client first asks for them.
+    peripheral_references: HashMap<i64, usize>,
+    /// Services semihosting requests of the target, and keeps track of the files it opened.
+    semihosting: Semihosting,
+    /// The breakpoints set by the client, with their conditions.
+    breakpoints: Vec<ActiveBreakpoint>,
+    /// The enabled exception breakpoints of each core, by core index. They are set again after a reset, which clears the reset vector catch.
+    exception_filters: HashMap<usize, Vec<ExceptionFilter>>,
 }
 
 impl<R: Read, W: Write> DebugAdapter<R, W> {
@@ -53,9 +85,10 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             seq: 1,
             input: BufReader::new(input),
             output,
-            last_known_status: CoreStatus::Unknown,
+            last_known_status: HashMap::new(),
             adapter_type,
             halt_after_reset: false, //default of false
+            supports_progress_reporting: false,
             console_log_level: ConsoleLog::Error,
             rl: match adapter_type {
                 DebugAdapterType::CommandLine => Some(Editor::<()>::new()),
@@ -63,14 +96,53 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             },
             scope_map: HashMap::new(),
             variable_map: HashMap::new(),
+            variable_values: HashMap::new(),
+            register_scope_references: HashMap::new(),
+            outer_frame_references: HashSet::new(),
+            frame_cores: HashMap::new(),
+            frame_indices: HashMap::new(),
+            variable_cores: HashMap::new(),
+            peripheral_references: HashMap::new(),
             variable_map_key_seq: -1,
+            semihosting: Semihosting::new(),
+            breakpoints: Vec::new(),
+            exception_filters: HashMap::new(),
+        }
+    }
+
+    /// The last known status of the core with the given index, `CoreStatus::Unknown` before it was first read.
+    pub(crate) fn last_known_status(&self, core_index: usize) -> CoreStatus {
+        self.last_known_status
+            .get(&core_index)
+            .copied()
+            .unwrap_or(CoreStatus::Unknown)
+    }
+
+    pub(crate) fn set_last_known_status(&mut self, core_index: usize, status: CoreStatus) {
+        self.last_known_status.insert(core_index, status);
+    }
+
+    /// The index of the core that a request is meant for, from its `threadId`, or the core of its `frameId` or `variablesReference`.
+    /// Returns `None` for requests which don't refer to a core, e.g. CLI requests and breakpoint requests.
+    pub(crate) fn core_index(&self, request: &Request) -> Option<usize> {
+        let arguments = request.arguments.as_ref()?;
+        let argument = |name: &str| arguments.get(name).and_then(|value| value.as_i64());
+
+        if let Some(thread_id) = argument("threadId") {
+            usize::try_from(thread_id).ok()
+        } else if let Some(frame_id) = argument("frameId") {
+            self.frame_cores.get(&frame_id).copied()
+        } else if let Some(variables_reference) = argument("variablesReference") {
+            self.variable_cores.get(&variables_reference).copied()
+        } else {
+            None
         }
     }
 
     pub(crate) fn status(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
         let status = match core_data.target_core.status() {
             Ok(status) => {
-                self.last_known_status = status;
+                self.set_last_known_status(core_data.target_core.id(), status);
                 status
             }
             Err(error) => {
@@ -111,11 +183,16 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             Ok(cpu_info) => {
                 let event_body = Some(StoppedEventBody {
                     reason: "pause".to_owned(),
-                    description: Some(self.last_known_status.short_long_status().1.to_owned()),
+                    description: Some(
+                        self.last_known_status(core_data.target_core.id())
+                            .short_long_status()
+                            .1
+                            .to_owned(),
+                    ),
                     thread_id: Some(core_data.target_core.id() as i64),
                     preserve_focus_hint: Some(false),
                     text: None,
-                    all_threads_stopped: Some(true),
+                    all_threads_stopped: Some(false),
                     hit_breakpoint_ids: None,
                 });
                 self.send_event("stopped", event_body);
@@ -126,7 +203,10 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                         cpu_info.pc
                     ))),
                 );
-                self.last_known_status = CoreStatus::Halted(HaltReason::Request);
+                self.set_last_known_status(
+                    core_data.target_core.id(),
+                    CoreStatus::Halted(HaltReason::Request),
+                );
 
                 true
             }
@@ -172,30 +252,96 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                 Err(error) => return self.send_response::<()>(&request, Err(error)),
             },
         };
-        let address: u32 = parse(&arguments.memory_reference.as_ref()).unwrap();
-        let num_words = arguments.count as usize;
-        let mut buff = vec![0u32; num_words];
-        if num_words > 1 {
-            core_data.target_core.read_32(address, &mut buff).unwrap();
-        } else {
-            buff[0] = core_data.target_core.read_word_32(address).unwrap();
+        let address = match memory_address(&arguments.memory_reference, arguments.offset) {
+            Ok(address) => address,
+            Err(error) => return self.send_response::<()>(&request, Err(error)),
+        };
+        match self.adapter_type {
+            DebugAdapterType::CommandLine => {
+                let num_words = arguments.count as usize;
+                let mut buff = vec![0u32; num_words];
+                if let Err(error) = core_data.target_core.read_32(address, &mut buff) {
+                    return self.send_response::<()>(&request, Err(DebuggerError::ProbeRs(error)));
+                }
+                if !buff.is_empty() {
+                    let mut response = "".to_string();
+                    for (offset, word) in buff.iter().enumerate() {
+                        response.push_str(
+                            format!("0x{:08x} = 0x{:08x}\n", address + (offset * 4) as u32, word)
+                                .as_str(),
+                        );
+                    }
+                    self.send_response::<String>(&request, Ok(Some(response)))
+                } else {
+                    self.send_response::<()>(
+                        &request,
+                        Err(DebuggerError::Other(anyhow!(
+                            "Could not read any data at address 0x{:08x}",
+                            address
+                        ))),
+                    )
+                }
+            }
+            DebugAdapterType::DapClient => {
+                // The DAP count is in bytes, and the client can ask for any address, so the memory is read byte by byte.
+                let mut buff = vec![0u8; arguments.count.max(0) as usize];
+                let body = match core_data.target_core.read_8(address, &mut buff) {
+                    Ok(()) => ReadMemoryResponseBody {
+                        address: format!("0x{:08x}", address),
+                        data: Some(base64::encode(&buff)),
+                        unreadable_bytes: None,
+                    },
+                    Err(error) => {
+                        log::warn!(
+                            "Could not read {} bytes at address 0x{:08x}: {}",
+                            buff.len(),
+                            address,
+                            error
+                        );
+                        ReadMemoryResponseBody {
+                            address: format!("0x{:08x}", address),
+                            data: None,
+                            unreadable_bytes: Some(buff.len() as i64),
+                        }
+                    }
+                };
+                self.send_response(&request, Ok(Some(body)))
+            }
         }
-        if !buff.is_empty() {
-            let mut response = "".to_string();
-            for (offset, word) in buff.iter().enumerate() {
-                response.push_str(
-                    format!("0x{:08x} = 0x{:08x}\n", address + (offset * 4) as u32, word).as_str(),
-                );
+    }
+    pub(crate) fn write_memory(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
+        let arguments: WriteMemoryArguments = match get_arguments(&request) {
+            Ok(arguments) => arguments,
+            Err(error) => return self.send_response::<()>(&request, Err(error)),
+        };
+        let address = match memory_address(&arguments.memory_reference, arguments.offset) {
+            Ok(address) => address,
+            Err(error) => return self.send_response::<()>(&request, Err(error)),
+        };
+        let data = match base64::decode(&arguments.data) {
+            Ok(data) => data,
+            Err(error) => {
+                return self.send_response::<()>(
+                    &request,
+                    Err(DebuggerError::Other(anyhow!(
+                        "Invalid base64 data '{}': {}",
+                        arguments.data,
+                        error
+                    ))),
+                )
             }
-            self.send_response::<String>(&request, Ok(Some(response)))
-        } else {
-            self.send_response::<()>(
+        };
+
+        match core_data.target_core.write_8(address, &data) {
+            Ok(()) => self.send_response(
                 &request,
-                Err(DebuggerError::Other(anyhow!(
-                    "Could not read any data at address 0x{:08x}",
-                    address
-                ))),
-            )
+                Ok(Some(WriteMemoryResponseBody {
+                    // The data is always written completely, so there is no partial write to report.
+                    offset: None,
+                    bytes_written: Some(data.len() as i64),
+                })),
+            ),
+            Err(error) => self.send_response::<()>(&request, Err(DebuggerError::ProbeRs(error))),
         }
     }
     pub(crate) fn write(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
@@ -334,6 +480,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                 .reset_and_halt(Duration::from_millis(100))
             {
                 Ok(_) => {
+                    self.restore_vector_catch(core_data);
                     match self.adapter_type {
                         DebugAdapterType::CommandLine => {}
                         DebugAdapterType::DapClient => {
@@ -341,7 +488,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                         }
                     }
                     //Only notify the DAP client if we are NOT in initialization stage (CoreStatus::Unknown)
-                    if self.last_known_status != CoreStatus::Unknown {
+                    if self.last_known_status(core_data.target_core.id()) != CoreStatus::Unknown {
                         let event_body = Some(StoppedEventBody {
                             reason: "reset".to_owned(),
                             description: Some(
@@ -353,11 +500,14 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                             thread_id: Some(core_data.target_core.id() as i64),
                             preserve_focus_hint: None,
                             text: None,
-                            all_threads_stopped: Some(true),
+                            all_threads_stopped: Some(false),
                             hit_breakpoint_ids: None,
                         });
                         self.send_event("stopped", event_body);
-                        self.last_known_status = CoreStatus::Halted(HaltReason::External);
+                        self.set_last_known_status(
+                            core_data.target_core.id(),
+                            CoreStatus::Halted(HaltReason::External),
+                        );
                     }
                     true
                 }
@@ -371,9 +521,9 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         } else if self.adapter_type == DebugAdapterType::CommandLine {
             match core_data.target_core.reset() {
                 Ok(_) => {
-                    self.last_known_status = CoreStatus::Running;
+                    self.set_last_known_status(core_data.target_core.id(), CoreStatus::Running);
                     let event_body = Some(ContinuedEventBody {
-                        all_threads_continued: Some(true),
+                        all_threads_continued: Some(false),
                         thread_id: core_data.target_core.id() as i64,
                     });
                     self.send_event("continued", event_body);
@@ -399,7 +549,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         //Make sure the DAP Client and DAP Server are in synch with status of the core
         match core_data.target_core.status() {
             Ok(core_status) => {
-                self.last_known_status = core_status;
+                self.set_last_known_status(core_data.target_core.id(), core_status);
                 if core_status.is_halted() {
                     if self.halt_after_reset
                         || core_status == CoreStatus::Halted(HaltReason::Breakpoint)
@@ -411,7 +561,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                             thread_id: Some(core_data.target_core.id() as i64),
                             preserve_focus_hint: None,
                             text: None,
-                            all_threads_stopped: Some(true),
+                            all_threads_stopped: Some(false),
                             hit_breakpoint_ids: None,
                         });
                         self.send_event("stopped", event_body)
@@ -458,17 +608,17 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
         arguments.restart.unwrap_or(false)
     }
-    pub(crate) fn threads(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
-        //TODO: Implement actual thread resolution. For now, we just use the core id as the thread id.
-
-        let single_thread = Thread {
-            id: core_data.target_core.id() as i64,
-            name: core_data.target_name.clone(),
-        };
-
-        let threads = vec![single_thread];
+    /// Every core of the target is reported as a thread, with the core index as the thread id.
+    pub(crate) fn threads(&mut self, threads: Vec<Thread>, request: &Request) -> bool {
         self.scope_map.clear();
         self.variable_map.clear();
+        self.variable_values.clear();
+        self.register_scope_references.clear();
+        self.outer_frame_references.clear();
+        self.frame_cores.clear();
+        self.frame_indices.clear();
+        self.variable_cores.clear();
+        self.peripheral_references.clear();
         self.variable_map_key_seq = -1;
         self.send_response(&request, Ok(Some(ThreadsResponseBody { threads })))
     }
@@ -488,29 +638,37 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
         let mut created_breakpoints: Vec<Breakpoint> = Vec::new(); // For returning in the Response
 
-        let source_path = args.source.path.as_ref().map(Path::new);
-
-        // Always clear existing breakpoints before setting new ones. The DAP Specification doesn't make allowances for deleting and setting individual breakpoints.
-        match core_data.target_core.clear_all_hw_breakpoints() {
-            Ok(_) => {}
-            Err(error) => {
+        let source_path = match args.source.path.as_ref() {
+            Some(source_path) => PathBuf::from(source_path),
+            None => {
                 return self.send_response::<()>(
                     &request,
                     Err(DebuggerError::Other(anyhow!(
-                        "Failed to clear existing breakpoints before setting new ones : {}",
-                        error
+                        "Breakpoints can only be set in sources with a path"
                     ))),
                 )
             }
+        };
+        let breakpoint_source = BreakpointSource::SourceFile(source_path.clone());
+
+        // The DAP Specification requires that all existing breakpoints of the source are replaced.
+        if let Err(error) = self.clear_breakpoints(core_data, &breakpoint_source) {
+            return self.send_response::<()>(
+                &request,
+                Err(DebuggerError::Other(anyhow!(
+                    "Failed to clear existing breakpoints before setting new ones : {}",
+                    error
+                ))),
+            );
         }
 
         if let Some(requested_breakpoints) = args.breakpoints.as_ref() {
             for bp in requested_breakpoints {
                 // Try to find source code location
 
-                let source_location: Option<u64> = core_data.debug_info.as_ref().and_then(|di| {
+                let source_location: Option<u64> = core_data.debug_info.and_then(|di| {
                     di.get_breakpoint_location(
-                        source_path.unwrap(),
+                        &source_path,
                         bp.line as u64,
                         bp.column.map(|c| c as u64),
                     )
@@ -518,21 +676,26 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                 });
 
                 if let Some(location) = source_location {
-                    let (verified, reason_msg) =
-                        match core_data.target_core.set_hw_breakpoint(location as u32) {
-                            Ok(_) => (
-                                true,
-                                Some(format!("Breakpoint at memory address: 0x{:08x}", location)),
-                            ),
-                            Err(err) => {
-                                //In addition to sending the error to the 'Hover' message, also write it to the Debug Console Log
-                                self.log_to_console(format!(
+                    let (verified, reason_msg) = match self.add_breakpoint(
+                        core_data,
+                        breakpoint_source.clone(),
+                        location as u32,
+                        bp.condition.as_deref(),
+                        bp.hit_condition.as_deref(),
+                    ) {
+                        Ok(_) => (
+                            true,
+                            Some(format!("Breakpoint at memory address: 0x{:08x}", location)),
+                        ),
+                        Err(err) => {
+                            //In addition to sending the error to the 'Hover' message, also write it to the Debug Console Log
+                            self.log_to_console(format!(
                                 "ERROR: Could not set breakpoint at memory address: 0x{:08x}: {}",
                                 location, err
                             ));
-                                (false, Some(err.to_string()))
-                            }
-                        };
+                            (false, Some(err.to_string()))
+                        }
+                    };
 
                     created_breakpoints.push(Breakpoint {
                         column: bp.column,
@@ -565,10 +728,550 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             }
         }
 
-        let breakpoint_body = SetBreakpointsResponseBody {
-            breakpoints: created_breakpoints,
-        };
-        self.send_response(&request, Ok(Some(breakpoint_body)))
+        let breakpoint_body = SetBreakpointsResponseBody {
+            breakpoints: created_breakpoints,
+        };
+        self.send_response(&request, Ok(Some(breakpoint_body)))
+    }
+
+    pub(crate) fn set_function_breakpoints(
+        &mut self,
+        core_data: &mut CoreData,
+        request: &Request,
+    ) -> bool {
+        let args: SetFunctionBreakpointsArguments = match get_arguments(&request) {
+            Ok(arguments) => arguments,
+            Err(error) => {
+                return self.send_response::<()>(
+                    &request,
+                    Err(DebuggerError::Other(anyhow!(
+                        "Could not read arguments : {}",
+                        error
+                    ))),
+                )
+            }
+        };
+
+        if let Err(error) = self.clear_breakpoints(core_data, &BreakpointSource::Function) {
+            return self.send_response::<()>(
+                &request,
+                Err(DebuggerError::Other(anyhow!(
+                    "Failed to clear existing breakpoints before setting new ones : {}",
+                    error
+                ))),
+            );
+        }
+
+        let mut created_breakpoints: Vec<Breakpoint> = Vec::new();
+
+        for bp in &args.breakpoints {
+            let location = core_data.debug_info.and_then(|di| {
+                di.get_function_breakpoint_location(&bp.name)
+                    .unwrap_or(None)
+            });
+
+            let (verified, reason_msg) = match location {
+                Some(location) => match self.add_breakpoint(
+                    core_data,
+                    BreakpointSource::Function,
+                    location as u32,
+                    bp.condition.as_deref(),
+                    bp.hit_condition.as_deref(),
+                ) {
+                    Ok(_) => (
+                        true,
+                        Some(format!(
+                            "Breakpoint on function '{}' at memory address: 0x{:08x}",
+                            bp.name, location
+                        )),
+                    ),
+                    Err(err) => {
+                        self.log_to_console(format!(
+                            "ERROR: Could not set breakpoint on function '{}': {}",
+                            bp.name, err
+                        ));
+                        (false, Some(err.to_string()))
+                    }
+                },
+                None => (
+                    false,
+                    Some(format!("No function named '{}' found", bp.name)),
+                ),
+            };
+
+            created_breakpoints.push(Breakpoint {
+                column: None,
+                end_column: None,
+                end_line: None,
+                id: None,
+                line: None,
+                message: reason_msg,
+                source: None,
+                instruction_reference: location.map(|location| location.to_string()),
+                offset: None,
+                verified,
+            });
+        }
+
+        let breakpoint_body = SetFunctionBreakpointsResponseBody {
+            breakpoints: created_breakpoints,
+        };
+        self.send_response(&request, Ok(Some(breakpoint_body)))
+    }
+
+    pub(crate) fn set_instruction_breakpoints(
+        &mut self,
+        core_data: &mut CoreData,
+        request: &Request,
+    ) -> bool {
+        let args: SetInstructionBreakpointsArguments = match get_arguments(&request) {
+            Ok(arguments) => arguments,
+            Err(error) => {
+                return self.send_response::<()>(
+                    &request,
+                    Err(DebuggerError::Other(anyhow!(
+                        "Could not read arguments : {}",
+                        error
+                    ))),
+                )
+            }
+        };
+
+        if let Err(error) = self.clear_breakpoints(core_data, &BreakpointSource::Instruction) {
+            return self.send_response::<()>(
+                &request,
+                Err(DebuggerError::Other(anyhow!(
+                    "Failed to clear existing breakpoints before setting new ones : {}",
+                    error
+                ))),
+            );
+        }
+
+        let mut created_breakpoints: Vec<Breakpoint> = Vec::new();
+
+        for bp in &args.breakpoints {
+            let location = parse::<i64>(&bp.instruction_reference)
+                .ok()
+                .and_then(|address| address.checked_add(bp.offset.unwrap_or(0)))
+                .filter(|address| u32::try_from(*address).is_ok());
+
+            let (verified, reason_msg) = match location {
+                Some(location) => match self.add_breakpoint(
+                    core_data,
+                    BreakpointSource::Instruction,
+                    location as u32,
+                    bp.condition.as_deref(),
+                    bp.hit_condition.as_deref(),
+                ) {
+                    Ok(_) => (
+                        true,
+                        Some(format!("Breakpoint at memory address: 0x{:08x}", location)),
+                    ),
+                    Err(err) => {
+                        self.log_to_console(format!(
+                            "ERROR: Could not set breakpoint at memory address: 0x{:08x}: {}",
+                            location, err
+                        ));
+                        (false, Some(err.to_string()))
+                    }
+                },
+                None => (
+                    false,
+                    Some(format!(
+                        "Invalid instruction reference '{}'",
+                        bp.instruction_reference
+                    )),
+                ),
+            };
+
+            created_breakpoints.push(Breakpoint {
+                column: None,
+                end_column: None,
+                end_line: None,
+                id: None,
+                line: None,
+                message: reason_msg,
+                source: None,
+                instruction_reference: location.map(|location| format!("0x{:08x}", location)),
+                offset: None,
+                verified,
+            });
+        }
+
+        let breakpoint_body = SetInstructionBreakpointsResponseBody {
+            breakpoints: created_breakpoints,
+        };
+        self.send_response(&request, Ok(Some(breakpoint_body)))
+    }
+
+    /// Halt the core on the selected exceptions, with the vector catch of Cortex-M cores.
+    pub(crate) fn set_exception_breakpoints(
+        &mut self,
+        core_data: &mut CoreData,
+        request: &Request,
+    ) -> bool {
+        let args: SetExceptionBreakpointsArguments = match get_arguments(&request) {
+            Ok(arguments) => arguments,
+            Err(error) => {
+                return self.send_response::<()>(
+                    &request,
+                    Err(DebuggerError::Other(anyhow!(
+                        "Could not read arguments : {}",
+                        error
+                    ))),
+                )
+            }
+        };
+
+        let filters: Vec<Option<ExceptionFilter>> = args
+            .filters
+            .iter()
+            .map(|filter| ExceptionFilter::from_id(filter))
+            .collect();
+
+        let result =
+            exceptions::supported_filters(&mut core_data.target_core).and_then(|supported| {
+                let enabled_filters: Vec<ExceptionFilter> = filters
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .filter(|filter| supported.contains(filter))
+                    .collect();
+
+                exceptions::set_vector_catch(&mut core_data.target_core, &enabled_filters)?;
+                self.exception_filters
+                    .insert(core_data.target_core.id(), enabled_filters);
+
+                Ok(supported)
+            });
+        if let Err(error) = &result {
+            self.log_to_console(format!(
+                "ERROR: Could not set exception breakpoints: {}",
+                error
+            ));
+        }
+
+        let breakpoints = args
+            .filters
+            .iter()
+            .zip(filters)
+            .map(|(id, filter)| {
+                let (message, verified) = match (filter, &result) {
+                    (None, _) => (format!("Unknown exception filter '{}'", id), false),
+                    (Some(_), Err(error)) => (error.to_string(), false),
+                    (Some(filter), Ok(supported)) if !supported.contains(&filter) => (
+                        format!("{} is not supported by this core", filter.label()),
+                        false,
+                    ),
+                    (Some(filter), Ok(_)) => (format!("Halting on {}", filter.label()), true),
+                };
+
+                Breakpoint {
+                    column: None,
+                    end_column: None,
+                    end_line: None,
+                    id: None,
+                    line: None,
+                    message: Some(message),
+                    source: None,
+                    instruction_reference: None,
+                    offset: None,
+                    verified,
+                }
+            })
+            .collect();
+
+        self.send_response(
+            &request,
+            Ok(Some(SetExceptionBreakpointsResponseBody {
+                breakpoints: Some(breakpoints),
+            })),
+        )
+    }
+
+    /// Set the vector catch of the exception breakpoints again, after a reset of the core cleared the reset vector catch.
+    fn restore_vector_catch(&mut self, core_data: &mut CoreData) {
+        let filters = match self.exception_filters.get(&core_data.target_core.id()) {
+            Some(filters) if !filters.is_empty() => filters.clone(),
+            _ => return,
+        };
+
+        if let Err(error) = exceptions::set_vector_catch(&mut core_data.target_core, &filters) {
+            self.log_to_console(format!(
+                "ERROR: Could not restore exception breakpoints after the reset: {}",
+                error
+            ));
+        }
+    }
+
+    /// The explanation of the fault which halted the core, for the `text` of the stopped event.
+    /// The explanation is also written to the console, as not all clients show the `text`.
+    pub(crate) fn fault_description(
+        &mut self,
+        core_data: &mut CoreData,
+        status: CoreStatus,
+    ) -> Option<String> {
+        if status != CoreStatus::Halted(HaltReason::Exception) {
+            return None;
+        }
+
+        let description = exceptions::describe_fault(&mut core_data.target_core)?;
+        self.log_to_console(format!(
+            "Core {} halted on an exception: {}",
+            core_data.target_core.id(),
+            description
+        ));
+        Some(description)
+    }
+
+    /// Disassemble the code around a memory reference, annotated with the function names and
+    /// source locations from the debug information.
+    pub(crate) fn disassemble(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
+        let arguments: DisassembleArguments = match self.adapter_type {
+            DebugAdapterType::CommandLine => match request.arguments.as_ref().unwrap().try_into() {
+                Ok(arguments) => arguments,
+                Err(error) => return self.send_response::<()>(request, Err(error)),
+            },
+            DebugAdapterType::DapClient => match get_arguments(&request) {
+                Ok(arguments) => arguments,
+                Err(error) => return self.send_response::<()>(&request, Err(error)),
+            },
+        };
+
+        let address = match memory_address(&arguments.memory_reference, arguments.offset) {
+            Ok(address) => u64::from(address),
+            Err(error) => return self.send_response::<()>(&request, Err(error)),
+        };
+
+        let instructions = match disassembly::disassemble(
+            &mut core_data.target_core,
+            address,
+            arguments.instruction_offset.unwrap_or(0),
+            arguments.instruction_count.max(0) as usize,
+        ) {
+            Ok(instructions) => instructions,
+            Err(error) => return self.send_response::<()>(&request, Err(error)),
+        };
+
+        let resolve_symbols = arguments.resolve_symbols.unwrap_or(true);
+
+        // The symbol and the source are only reported when they change, the client uses the
+        // previous ones for the following instructions.
+        let mut previous_symbol = None;
+        let mut previous_source = None;
+
+        let instructions: Vec<DisassembledInstruction> = instructions
+            .into_iter()
+            .map(|instruction| {
+                let debug_info = core_data
+                    .debug_info
+                    .filter(|_| !instruction.bytes.is_empty());
+
+                let symbol = debug_info
+                    .filter(|_| resolve_symbols)
+                    .and_then(|debug_info| debug_info.function_name(instruction.address, false));
+
+                let source_location = debug_info
+                    .and_then(|debug_info| debug_info.get_source_location(instruction.address));
+
+                let source = source_location.as_ref().map(get_dap_source);
+
+                let (line, column) = match &source_location {
+                    Some(source_location) => (
+                        source_location.line.map(|line| line as i64),
+                        source_location.column.map(|column| match column {
+                            ColumnType::LeftEdge => 0,
+                            ColumnType::Column(c) => c as i64,
+                        }),
+                    ),
+                    None => (None, None),
+                };
+
+                let location = if source.is_some()
+                    && source.as_ref().map(|source| &source.path) != previous_source.as_ref()
+                {
+                    previous_source = source.as_ref().map(|source| source.path.clone());
+                    source
+                } else {
+                    None
+                };
+
+                let symbol = if symbol.is_some() && symbol != previous_symbol {
+                    previous_symbol = symbol.clone();
+                    symbol
+                } else {
+                    None
+                };
+
+                DisassembledInstruction {
+                    address: format!("0x{:08x}", instruction.address),
+                    instruction_bytes: Some(
+                        instruction
+                            .bytes
+                            .iter()
+                            .map(|byte| format!("{:02x}", byte))
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    ),
+                    instruction: instruction.text,
+                    symbol,
+                    location,
+                    line,
+                    column,
+                    end_line: None,
+                    end_column: None,
+                }
+            })
+            .collect();
+
+        match self.adapter_type {
+            DebugAdapterType::CommandLine => {
+                let mut response = String::new();
+
+                for instruction in &instructions {
+                    if let Some(symbol) = &instruction.symbol {
+                        response.push_str(&format!("{}:\n", symbol));
+                    }
+
+                    response.push_str(&format!(
+                        "{}: {:<12} {}\n",
+                        instruction.address,
+                        instruction.instruction_bytes.as_deref().unwrap_or_default(),
+                        instruction.instruction
+                    ));
+                }
+
+                self.send_response(&request, Ok(Some(response)))
+            }
+            DebugAdapterType::DapClient => {
+                self.send_response(&request, Ok(Some(DisassembleResponseBody { instructions })))
+            }
+        }
+    }
+
+    /// Set a hardware breakpoint for the client, with optional conditions.
+    fn add_breakpoint(
+        &mut self,
+        core_data: &mut CoreData,
+        source: BreakpointSource,
+        address: u32,
+        condition: Option<&str>,
+        hit_condition: Option<&str>,
+    ) -> Result<(), DebuggerError> {
+        let breakpoint = ActiveBreakpoint::new(source, address, condition, hit_condition)?;
+
+        core_data.target_core.set_hw_breakpoint(address)?;
+
+        self.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    /// Remove all breakpoints which were set by the given source. Breakpoints at the same address set by another source stay active.
+    fn clear_breakpoints(
+        &mut self,
+        core_data: &mut CoreData,
+        source: &BreakpointSource,
+    ) -> Result<(), probe_rs::Error> {
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.breakpoints)
+            .into_iter()
+            .partition(|breakpoint| &breakpoint.source == source);
+
+        self.breakpoints = kept;
+
+        let mut cleared_addresses = Vec::new();
+
+        for breakpoint in removed {
+            if cleared_addresses.contains(&breakpoint.address)
+                || self
+                    .breakpoints
+                    .iter()
+                    .any(|other| other.address == breakpoint.address)
+            {
+                continue;
+            }
+
+            core_data
+                .target_core
+                .clear_hw_breakpoint(breakpoint.address)?;
+            cleared_addresses.push(breakpoint.address);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate the conditions of the breakpoints at the address where the core halted. If none of them are met, the core is resumed, and `true` is returned.
+    pub(crate) fn skip_breakpoint(&mut self, core_data: &mut CoreData) -> bool {
+        let pc = match core_data
+            .target_core
+            .read_core_reg(core_data.target_core.registers().program_counter())
+        {
+            Ok(pc) => pc,
+            Err(_) => return false,
+        };
+
+        // Breakpoints which were not set by the client, e.g. from the CLI, always halt.
+        if !self.breakpoints.iter().any(|bp| bp.address == pc) {
+            return false;
+        }
+
+        let mut halt = false;
+        let mut errors = Vec::new();
+
+        for breakpoint in self.breakpoints.iter_mut().filter(|bp| bp.address == pc) {
+            let condition_met = match &breakpoint.condition {
+                Some(condition) => match condition
+                    .evaluate_on_target(core_data, None)
+                    .and_then(|value| value.as_integer())
+                {
+                    Ok(value) => value != 0,
+                    Err(error) => {
+                        // Halt, so the user can fix the condition.
+                        errors.push(format!(
+                            "ERROR: Failed to evaluate breakpoint condition at 0x{:08x}: {}",
+                            pc, error
+                        ));
+                        true
+                    }
+                },
+                None => true,
+            };
+
+            if condition_met {
+                breakpoint.hit_count += 1;
+
+                halt |= breakpoint
+                    .hit_condition
+                    .map(|hit_condition| hit_condition.is_met(breakpoint.hit_count))
+                    .unwrap_or(true);
+            }
+        }
+
+        for error in errors {
+            self.log_to_console(error);
+        }
+
+        if halt {
+            return false;
+        }
+
+        // Step off the breakpoint first, otherwise the core would halt on it again right away.
+        match core_data
+            .target_core
+            .step()
+            .and_then(|_| core_data.target_core.run())
+        {
+            Ok(()) => {
+                self.set_last_known_status(core_data.target_core.id(), CoreStatus::Running);
+                true
+            }
+            Err(error) => {
+                self.log_to_console(format!(
+                    "ERROR: Failed to resume after breakpoint at 0x{:08x}: {}",
+                    pc, error
+                ));
+                false
+            }
+        }
     }
 
     pub(crate) fn stack_trace(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
@@ -618,7 +1321,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
             },
         };
 
-        if let Some(debug_info) = core_data.debug_info.as_ref() {
+        if let Some(debug_info) = core_data.debug_info {
             //Evaluate the static scoped variables.
             let static_variables =
                 match debug_info.get_stack_statics(&mut core_data.target_core, u64::from(pc)) {
@@ -632,9 +1335,17 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                     }
                 };
 
+            let core_index = core_data.target_core.id();
+
             //Store the static variables for later calls to `variables()` to retrieve
             let (static_scope_reference, named_static_variables_cnt, indexed_static_variables_cnt) =
-                self.create_variable_map(&static_variables);
+                self.create_variable_map(&static_variables, core_index);
+
+            //Store the peripherals of the SVD file, their registers are read later by `variables()`
+            let (peripheral_scope_reference, peripheral_cnt) = match core_data.peripherals {
+                Some(peripherals) => self.create_peripheral_map(peripherals, core_index),
+                None => (0, 0),
+            };
 
             let current_stackframes =
                 debug_info.try_unwind(&mut core_data.target_core, u64::from(pc));
@@ -650,7 +1361,9 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                 }
                 DebugAdapterType::DapClient => {
                     let mut frame_list: Vec<StackFrame> = current_stackframes
-                        .map(|frame| {
+                        .enumerate()
+                        .map(|(frame_index, frame)| {
+                            let frame_id = self.new_frame_id(core_index, frame_index);
                             let column = frame
                                 .source_location
                                 .as_ref()
@@ -662,32 +1375,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                                 .unwrap_or(0);
 
                             let source = if let Some(source_location) = &frame.source_location {
-                                let path: Option<PathBuf> =
-                                    source_location.directory.as_ref().map(|path| {
-                                        let mut path = if path.is_relative() {
-                                            std::env::current_dir().unwrap().join(path)
-                                        } else {
-                                            path.to_owned()
-                                        };
-
-                                        if let Some(file) = &source_location.file {
-                                            path.push(file);
-                                        }
-
-                                        path
-                                    });
-
-                                //TODO: Consider implementing RTIC's expanded source access. Might also do a general macro expansion if that makes sense.
-                                Some(Source {
-                                    name: source_location.file.clone(),
-                                    path: path.map(|p| p.to_string_lossy().to_string()),
-                                    source_reference: None,
-                                    presentation_hint: None,
-                                    origin: None,
-                                    sources: None,
-                                    adapter_data: None,
-                                    checksums: None,
-                                })
+                                Some(get_dap_source(source_location))
                             } else {
                                 log::debug!("No source location present for frame!");
                                 None
@@ -705,8 +1393,13 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
                             //Build the locals scope
                             //Extract all the variables from the StackFrame for later MS DAP calls to retrieve
+                            let first_variable_map_key = self.variable_map_key_seq + 1;
                             let (variables_reference, named_variables_cnt, indexed_variables_cnt) =
-                                self.create_variable_map(&frame.variables);
+                                self.create_variable_map(&frame.variables, core_index);
+                            if frame_index > 0 {
+                                self.outer_frame_references
+                                    .extend(first_variable_map_key..=self.variable_map_key_seq);
+                            }
                             scopes.push(Scope {
                                 line: Some(line),
                                 column: frame.source_location.as_ref().and_then(|l| {
@@ -750,7 +1443,10 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
                             //Build the registers scope and add it's variables
                             //TODO: Consider expanding beyond core register to add other architectue registers
-                            let register_scope_reference = self.new_variable_map_key();
+                            let register_scope_reference = self.new_variable_map_key(core_index);
+                            self.register_scope_references
+                                .entry(core_index)
+                                .or_insert(register_scope_reference);
                             let mut register_count: i64 = 0;
                             self.variable_map.insert(
                                 register_scope_reference,
@@ -804,12 +1500,29 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                                 },
                             });
 
+                            //The peripherals are mapped before iterating the frames, and are the same for all frames.
+                            if peripheral_scope_reference != 0 {
+                                scopes.push(Scope {
+                                    line: None,
+                                    column: None,
+                                    end_column: None,
+                                    end_line: None,
+                                    expensive: true,
+                                    indexed_variables: Some(0),
+                                    name: "Peripherals".to_string(),
+                                    presentation_hint: None,
+                                    named_variables: Some(peripheral_cnt),
+                                    source: None,
+                                    variables_reference: peripheral_scope_reference,
+                                });
+                            }
+
                             //Finally, store the scopes for this frame
-                            self.scope_map.insert(frame.id as i64, scopes);
+                            self.scope_map.insert(frame_id, scopes);
 
                             //TODO: Can we add more meaningful info to module_id, etc.
                             StackFrame {
-                                id: frame.id as i64,
+                                id: frame_id,
                                 name: frame.function_name.clone(),
                                 source,
                                 line,
@@ -921,11 +1634,20 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
         self.send_response(&request, result)
     }
-    pub(crate) fn variables(&mut self, _core_data: &mut CoreData, request: &Request) -> bool {
+    pub(crate) fn variables(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
         let arguments: VariablesArguments = match get_arguments(&request) {
             Ok(arguments) => arguments,
             Err(error) => return self.send_response::<()>(request, Err(error)),
         };
+        if let Some(peripheral) = self
+            .peripheral_references
+            .remove(&arguments.variables_reference)
+            .and_then(|index| core_data.peripherals?.peripherals.get(index))
+        {
+            let registers = self.read_peripheral(core_data, peripheral);
+            self.variable_map
+                .insert(arguments.variables_reference, registers);
+        }
         return self.send_response(
             &request,
             match self
@@ -962,37 +1684,300 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         );
     }
 
+    pub(crate) fn set_variable(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
+        let arguments: SetVariableArguments = match get_arguments(&request) {
+            Ok(arguments) => arguments,
+            Err(error) => return self.send_response::<()>(request, Err(error)),
+        };
+
+        let result = self
+            .write_variable(
+                core_data,
+                arguments.variables_reference,
+                &arguments.name,
+                &arguments.value,
+            )
+            .map(|variable| {
+                Some(SetVariableResponseBody {
+                    value: variable.value,
+                    type_: variable.type_,
+                    variables_reference: Some(variable.variables_reference),
+                    named_variables: variable.named_variables,
+                    indexed_variables: variable.indexed_variables,
+                })
+            });
+
+        self.send_response(&request, result)
+    }
+
+    /// Change the value of a variable or register, which is identified by its name. Registers can also be prefixed with `$`.
+    pub(crate) fn set_expression(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
+        let arguments: SetExpressionArguments = match get_arguments(&request) {
+            Ok(arguments) => arguments,
+            Err(error) => return self.send_response::<()>(request, Err(error)),
+        };
+
+        let name = arguments.expression.trim();
+
+        // Without a frame, only the statics are searched. They are the same for all frames.
+        let scopes = arguments
+            .frame_id
+            .and_then(|frame_id| self.scope_map.get(&frame_id))
+            .or_else(|| self.scope_map.values().next())
+            .cloned()
+            .unwrap_or_default();
+
+        let variables_reference = scopes
+            .iter()
+            .filter(|scope| {
+                arguments.frame_id.is_some()
+                    || scope.presentation_hint.as_deref() == Some("statics")
+            })
+            .map(|scope| scope.variables_reference)
+            .find(|variables_reference| {
+                self.find_variable(*variables_reference, name.trim_start_matches('$'))
+                    .is_some()
+            });
+
+        let result = match variables_reference {
+            Some(variables_reference) => self
+                .write_variable(
+                    core_data,
+                    variables_reference,
+                    name.trim_start_matches('$'),
+                    &arguments.value,
+                )
+                .map(|variable| {
+                    Some(SetExpressionResponseBody {
+                        value: variable.value,
+                        type_: variable.type_,
+                        presentation_hint: None,
+                        variables_reference: Some(variable.variables_reference),
+                        named_variables: variable.named_variables,
+                        indexed_variables: variable.indexed_variables,
+                    })
+                }),
+            None => Err(DebuggerError::Other(anyhow!(
+                "No variable or register named '{}' found",
+                name
+            ))),
+        };
+
+        self.send_response(&request, result)
+    }
+
+    /// Evaluate an expression in the context of a stack frame, for the debug console, watch
+    /// expressions and hovers. See [`crate::expression`] for the syntax.
+    pub(crate) fn evaluate(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
+        let arguments: EvaluateArguments = match self.adapter_type {
+            DebugAdapterType::CommandLine => EvaluateArguments {
+                context: Some("repl".to_string()),
+                expression: request
+                    .arguments
+                    .as_ref()
+                    .and_then(|arguments| arguments.as_array())
+                    .map(|arguments| {
+                        arguments
+                            .iter()
+                            .filter_map(|argument| argument.as_str())
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .unwrap_or_default(),
+                format: None,
+                frame_id: None,
+            },
+            DebugAdapterType::DapClient => match get_arguments(&request) {
+                Ok(arguments) => arguments,
+                Err(error) => return self.send_response::<()>(&request, Err(error)),
+            },
+        };
+
+        let frame_index = arguments
+            .frame_id
+            .and_then(|frame_id| self.frame_indices.get(&frame_id).copied());
+        let value = match Expression::parse(&arguments.expression)
+            .and_then(|expression| expression.evaluate_on_target(core_data, frame_index))
+        {
+            Ok(value) => value,
+            Err(error) => return self.send_response::<()>(&request, Err(error)),
+        };
+
+        match self.adapter_type {
+            DebugAdapterType::CommandLine => {
+                self.send_response(&request, Ok(Some(value.to_string())))
+            }
+            DebugAdapterType::DapClient => {
+                let result = value.to_string();
+
+                let body = match value {
+                    Value::Variable(variable) => {
+                        let (variables_reference, named_variables, indexed_variables) =
+                            match &variable.children {
+                                Some(children) => {
+                                    self.create_variable_map(children, core_data.target_core.id())
+                                }
+                                None => (0, 0, 0),
+                            };
+
+                        EvaluateResponseBody {
+                            result,
+                            type_: Some(variable.type_name.clone()),
+                            presentation_hint: None,
+                            variables_reference,
+                            named_variables: Some(named_variables),
+                            indexed_variables: Some(indexed_variables),
+                            memory_reference: variable_memory_reference(&variable),
+                        }
+                    }
+                    Value::Pointer { address, type_name } => EvaluateResponseBody {
+                        result,
+                        type_: Some(format!("*const {}", type_name)),
+                        presentation_hint: None,
+                        variables_reference: 0,
+                        named_variables: None,
+                        indexed_variables: None,
+                        memory_reference: Some(format!("0x{:08x}", address)),
+                    },
+                    Value::Integer(_) | Value::Float(_) => EvaluateResponseBody {
+                        result,
+                        type_: None,
+                        presentation_hint: None,
+                        variables_reference: 0,
+                        named_variables: None,
+                        indexed_variables: None,
+                        memory_reference: None,
+                    },
+                };
+
+                self.send_response(&request, Ok(Some(body)))
+            }
+        }
+    }
+
+    /// Find the position of a variable in the container with the given variable_map key.
+    fn find_variable(&self, variables_reference: i64, name: &str) -> Option<usize> {
+        let variables = self.variable_map.get(&variables_reference)?;
+
+        if self.is_register_scope(variables_reference) {
+            // The register names can have a description, e.g. "R7: THUMB Frame Pointer".
+            variables.iter().position(|variable| {
+                variable
+                    .name
+                    .split(':')
+                    .next()
+                    .map(|register_name| register_name.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+            })
+        } else {
+            variables.iter().position(|variable| variable.name == name)
+        }
+    }
+
+    /// Write a new value to a variable or register of the target, and return the updated MS DAP Variable.
+    fn write_variable(
+        &mut self,
+        core_data: &mut CoreData,
+        variables_reference: i64,
+        name: &str,
+        value: &str,
+    ) -> Result<Variable, DebuggerError> {
+        let position = self
+            .find_variable(variables_reference, name)
+            .ok_or_else(|| DebuggerError::Other(anyhow!("No variable named '{}' found", name)))?;
+
+        let new_value = if self.is_register_scope(variables_reference) {
+            let register_value: u32 = parse(value.trim()).map_err(|error| {
+                DebuggerError::Other(anyhow!("Invalid register value '{}': {}", value, error))
+            })?;
+
+            // The registers are listed in the order of their register numbers, which is also the order of the platform registers of the core.
+            let register_address = core_data
+                .target_core
+                .registers()
+                .get_platform_register(position)
+                .map(CoreRegisterAddress::from)
+                .ok_or_else(|| {
+                    DebuggerError::Other(anyhow!("The register '{}' can not be changed", name))
+                })?;
+
+            core_data
+                .target_core
+                .write_core_reg(register_address, register_value)?;
+
+            format!("0x{:08x}", register_value)
+        } else {
+            let variable = self
+                .variable_values
+                .get_mut(&variables_reference)
+                .and_then(|variables| variables.get_mut(position))
+                .ok_or_else(|| {
+                    DebuggerError::Other(anyhow!("The variable '{}' can not be changed", name))
+                })?;
+
+            if variable.register.is_some()
+                && self.outer_frame_references.contains(&variables_reference)
+            {
+                return Err(DebuggerError::Other(anyhow!(
+                    "The variable '{}' is stored in a register, and can only be changed in the top stack frame",
+                    name
+                )));
+            }
+
+            variable
+                .update_value(&mut core_data.target_core, value)
+                .map_err(|error| DebuggerError::Other(error.into()))?;
+
+            variable.get_value()
+        };
+
+        // `find_variable` ensured that the variable exists.
+        let dap_variable = &mut self.variable_map.get_mut(&variables_reference).unwrap()[position];
+        dap_variable.value = new_value;
+
+        Ok(dap_variable.clone())
+    }
+
     pub(crate) fn r#continue(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
         // let args: ContinueArguments = get_arguments(&request)?;
         match core_data.target_core.run() {
             Ok(_) => {
-                self.last_known_status = core_data
-                    .target_core
-                    .status()
-                    .unwrap_or(CoreStatus::Unknown);
+                self.set_last_known_status(
+                    core_data.target_core.id(),
+                    core_data
+                        .target_core
+                        .status()
+                        .unwrap_or(CoreStatus::Unknown),
+                );
                 match self.adapter_type {
                     DebugAdapterType::CommandLine => self.send_response(
                         &request,
-                        Ok(Some(self.last_known_status.short_long_status().1)),
+                        Ok(Some(
+                            self.last_known_status(core_data.target_core.id())
+                                .short_long_status()
+                                .1,
+                        )),
                     ),
                     DebugAdapterType::DapClient => {
                         self.send_response(
                             &request,
                             Ok(Some(ContinueResponseBody {
-                                all_threads_continued: if self.last_known_status
-                                    == CoreStatus::Running
-                                {
-                                    Some(true)
-                                } else {
-                                    Some(false)
-                                },
+                                all_threads_continued: Some(false),
                             })),
                         );
                         //We have to consider the fact that sometimes the `run()` is successfull, but "immediately" after the MCU hits a breakpoint or exception. So we have to check the status again to be sure.
                         thread::sleep(Duration::from_millis(100)); //small delay to make sure the MCU hits user breakpoints early in main()
                         let core_status = match core_data.target_core.status() {
                             Ok(new_status) => match new_status {
+                                // Semihosting requests are serviced by `process_next_request`, the target is still considered to be running.
+                                CoreStatus::Halted(HaltReason::Semihosting) => CoreStatus::Running,
+                                CoreStatus::Halted(HaltReason::Breakpoint)
+                                    if self.skip_breakpoint(core_data) =>
+                                {
+                                    CoreStatus::Running
+                                }
                                 CoreStatus::Halted(_) => {
+                                    let text = self.fault_description(core_data, new_status);
                                     let event_body = Some(StoppedEventBody {
                                         reason: new_status.short_long_status().0.to_owned(),
                                         description: Some(
@@ -1000,8 +1985,8 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                                         ),
                                         thread_id: Some(core_data.target_core.id() as i64),
                                         preserve_focus_hint: None,
-                                        text: None,
-                                        all_threads_stopped: Some(true),
+                                        text,
+                                        all_threads_stopped: Some(false),
                                         hit_breakpoint_ids: None,
                                     });
                                     self.send_event("stopped", event_body);
@@ -1011,36 +1996,106 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                             },
                             Err(_) => CoreStatus::Unknown,
                         };
-                        self.last_known_status = core_status;
+                        self.set_last_known_status(core_data.target_core.id(), core_status);
                         true
                     }
                 }
             }
             Err(error) => {
-                self.last_known_status = CoreStatus::Halted(HaltReason::Unknown);
+                self.set_last_known_status(
+                    core_data.target_core.id(),
+                    CoreStatus::Halted(HaltReason::Unknown),
+                );
                 self.send_response::<()>(&request, Err(DebuggerError::Other(anyhow!("{}", error))))
             }
         }
     }
 
-    /// Steps at 'instruction' granularity ONLY.
+    /// Service a semihosting request of the target, and forward its console output to the client.
+    /// Returns `false` if the target application exited, or the request could not be serviced, which ends the debug session.
+    pub(crate) fn semihosting(&mut self, core_data: &mut CoreData) -> bool {
+        match self.semihosting.service(&mut core_data.target_core) {
+            Ok(SemihostingOutcome::Resumed) => {
+                self.set_last_known_status(core_data.target_core.id(), CoreStatus::Running);
+                true
+            }
+            Ok(SemihostingOutcome::Output { stream, data }) => {
+                self.set_last_known_status(core_data.target_core.id(), CoreStatus::Running);
+                self.send_output(stream, &data)
+            }
+            Ok(SemihostingOutcome::Exit(exit_code)) => {
+                self.log_to_console(format!("Target application exited with code {}", exit_code));
+                self.send_event(
+                    "exited",
+                    Some(ExitedEventBody {
+                        exit_code: exit_code as i64,
+                    }),
+                );
+                false
+            }
+            Err(error) => {
+                self.log_to_console(format!(
+                    "ERROR: Failed to service semihosting request: {}",
+                    error
+                ));
+                false
+            }
+        }
+    }
+
+    /// Step over the current statement, or a single instruction if the client requests `instruction` granularity.
     pub(crate) fn next(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
-        //TODO: Implement 'statement' granularity, then update DAP `Capabilities` and read `NextArguments`
-        // let args: NextArguments = get_arguments(&request)?;
+        let granularity = match self.adapter_type {
+            // The CLI `step` command steps a single instruction.
+            DebugAdapterType::CommandLine => Some(SteppingGranularity::Instruction),
+            DebugAdapterType::DapClient => match get_arguments::<NextArguments>(&request) {
+                Ok(arguments) => arguments.granularity,
+                Err(error) => return self.send_response::<()>(&request, Err(error)),
+            },
+        };
 
-        match core_data.target_core.step() {
-            Ok(cpu_info) => {
-                let new_status = match core_data.target_core.status() {
-                    Ok(new_status) => new_status,
-                    Err(error) => {
-                        self.send_response::<()>(&request, Err(DebuggerError::ProbeRs(error)));
-                        return false;
-                    }
-                };
-                self.last_known_status = new_status;
+        self.step(core_data, request, SteppingMode::Over, granularity)
+    }
+
+    /// Step into the function called by the current statement.
+    pub(crate) fn step_in(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
+        let granularity = match self.adapter_type {
+            DebugAdapterType::CommandLine => None,
+            DebugAdapterType::DapClient => match get_arguments::<StepInArguments>(&request) {
+                Ok(arguments) => arguments.granularity,
+                Err(error) => return self.send_response::<()>(&request, Err(error)),
+            },
+        };
+
+        self.step(core_data, request, SteppingMode::Into, granularity)
+    }
+
+    /// Run until the current function returns to its caller. The granularity is ignored, because the step always ends at the return address.
+    pub(crate) fn step_out(&mut self, core_data: &mut CoreData, request: &Request) -> bool {
+        self.step(core_data, request, SteppingMode::Out, None)
+    }
+
+    fn step(
+        &mut self,
+        core_data: &mut CoreData,
+        request: &Request,
+        mode: SteppingMode,
+        granularity: Option<SteppingGranularity>,
+    ) -> bool {
+        let mode = match granularity {
+            Some(SteppingGranularity::Instruction) if mode != SteppingMode::Out => {
+                SteppingMode::Instruction
+            }
+            _ => mode,
+        };
+
+        match stepping::step(core_data, mode) {
+            Ok((cpu_info, halt_reason)) => {
+                let new_status = CoreStatus::Halted(halt_reason);
+                self.set_last_known_status(core_data.target_core.id(), new_status);
                 self.send_response::<()>(&request, Ok(None));
                 let event_body = Some(StoppedEventBody {
-                    reason: "step".to_owned(),
+                    reason: new_status.short_long_status().0.to_owned(),
                     description: Some(format!(
                         "{} at address 0x{:08x}",
                         new_status.short_long_status().1,
@@ -1049,7 +2104,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                     thread_id: Some(core_data.target_core.id() as i64),
                     preserve_focus_hint: None,
                     text: None,
-                    all_threads_stopped: Some(true),
+                    all_threads_stopped: Some(false),
                     hit_breakpoint_ids: None,
                 });
                 self.send_event("stopped", event_body)
@@ -1066,14 +2121,136 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
     }
 
     /// return a newly allocated id for a register scope reference
-    fn new_variable_map_key(&mut self) -> i64 {
+    fn new_variable_map_key(&mut self, core_index: usize) -> i64 {
+        self.variable_map_key_seq += 1;
+        self.variable_cores
+            .insert(self.variable_map_key_seq, core_index);
+        self.variable_map_key_seq
+    }
+
+    /// return a newly allocated id for a stack frame. The ids of the frames of all cores come from the same sequence, because MS DAP requires them to be unique across all threads.
+    fn new_frame_id(&mut self, core_index: usize, frame_index: usize) -> i64 {
         self.variable_map_key_seq += 1;
+        self.frame_cores
+            .insert(self.variable_map_key_seq, core_index);
+        self.frame_indices
+            .insert(self.variable_map_key_seq, frame_index);
         self.variable_map_key_seq
     }
 
+    /// Is the variable_map key the registers scope of the top stack frame of a core.
+    fn is_register_scope(&self, variables_reference: i64) -> bool {
+        self.register_scope_references
+            .values()
+            .any(|reference| *reference == variables_reference)
+    }
+
+    /// Add the peripherals of the SVD file to self.variable_map, without reading their registers.
+    /// returns a tuple containing the (variables_map_key, peripheral_cnt) of the "Peripherals" scope
+    fn create_peripheral_map(
+        &mut self,
+        peripherals: &SvdPeripherals,
+        core_index: usize,
+    ) -> (i64, i64) {
+        let dap_variables: Vec<Variable> = peripherals
+            .peripherals
+            .iter()
+            .enumerate()
+            .map(|(index, peripheral)| {
+                let variables_reference = if peripheral.registers.is_empty() {
+                    0
+                } else {
+                    let variables_reference = self.new_variable_map_key(core_index);
+                    self.peripheral_references
+                        .insert(variables_reference, index);
+                    variables_reference
+                };
+                Variable {
+                    name: peripheral.name.clone(),
+                    value: peripheral.description.clone().unwrap_or_default(),
+                    type_: Some("Peripheral".to_owned()),
+                    presentation_hint: None,
+                    evaluate_name: None,
+                    variables_reference,
+                    named_variables: Some(peripheral.registers.len() as i64),
+                    indexed_variables: Some(0),
+                    memory_reference: Some(format!("0x{:08x}", peripheral.base_address)),
+                }
+            })
+            .collect();
+
+        if dap_variables.is_empty() {
+            return (0, 0);
+        }
+        let peripheral_cnt = dap_variables.len() as i64;
+        let variable_map_key = self.new_variable_map_key(core_index);
+        self.variable_map.insert(variable_map_key, dap_variables);
+        (variable_map_key, peripheral_cnt)
+    }
+
+    /// Read the registers of a peripheral, and add their fields to self.variable_map.
+    /// Write-only registers are not read, and registers which can't be read are reported as unavailable.
+    fn read_peripheral(
+        &mut self,
+        core_data: &mut CoreData,
+        peripheral: &peripherals::Peripheral,
+    ) -> Vec<Variable> {
+        let core_index = core_data.target_core.id();
+        peripheral
+            .registers
+            .iter()
+            .map(|register| {
+                let register_value = if register.readable {
+                    register.read(&mut core_data.target_core)
+                } else {
+                    Err(DebuggerError::Other(anyhow!("write-only")))
+                };
+                let (value, variables_reference) = match register_value {
+                    Ok(register_value) if !register.fields.is_empty() => {
+                        let variables_reference = self.new_variable_map_key(core_index);
+                        let fields = register
+                            .fields
+                            .iter()
+                            .map(|field| Variable {
+                                name: field.name.clone(),
+                                value: field.format(register_value),
+                                type_: Some(field.bit_range()),
+                                presentation_hint: None,
+                                evaluate_name: None,
+                                variables_reference: 0,
+                                named_variables: None,
+                                indexed_variables: None,
+                                memory_reference: None,
+                            })
+                            .collect();
+                        self.variable_map.insert(variables_reference, fields);
+                        (register.format(register_value), variables_reference)
+                    }
+                    Ok(register_value) => (register.format(register_value), 0),
+                    Err(error) => (format!("<{}>", error), 0),
+                };
+                Variable {
+                    name: register.name.clone(),
+                    value,
+                    type_: Some(format!("u{}", register.size)),
+                    presentation_hint: None,
+                    evaluate_name: None,
+                    variables_reference,
+                    named_variables: Some(register.fields.len() as i64),
+                    indexed_variables: Some(0),
+                    memory_reference: Some(format!("0x{:08x}", register.address)),
+                }
+            })
+            .collect()
+    }
+
     /// recurse through each variable and add children with parent reference to self.variables_map
     /// returns a tuple containing the parent's  (variables_map_key, named_child_variables_cnt, indexed_child_variables_cnt)
-    fn create_variable_map(&mut self, variables: &[probe_rs::debug::Variable]) -> (i64, i64, i64) {
+    fn create_variable_map(
+        &mut self,
+        variables: &[probe_rs::debug::Variable],
+        core_index: usize,
+    ) -> (i64, i64, i64) {
         let mut named_child_variables_cnt = 0;
         let mut indexed_child_variables_cnt = 0;
         let dap_variables: Vec<Variable> = variables
@@ -1088,7 +2265,7 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 
                 let (variables_reference, named_variables_cnt, indexed_variables_cnt) =
                     match &variable.children {
-                        Some(children) => self.create_variable_map(children),
+                        Some(children) => self.create_variable_map(children, core_index),
                         None => (0, 0, 0),
                     };
                 Variable {
@@ -1100,15 +2277,19 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                     variables_reference,
                     named_variables: Some(named_variables_cnt),
                     indexed_variables: Some(indexed_variables_cnt),
-                    memory_reference: Some(format!("0x{:08x}", variable.memory_location)),
+                    memory_reference: variable_memory_reference(variable),
                 }
             })
             .collect();
         let variable_map_key = if named_child_variables_cnt > 0 || indexed_child_variables_cnt > 0 {
-            self.new_variable_map_key()
+            self.new_variable_map_key(core_index)
         } else {
             0
         };
+        if variable_map_key != 0 {
+            self.variable_values
+                .insert(variable_map_key, variables.to_vec());
+        }
         match self.variable_map.insert(variable_map_key, dap_variables) {
             Some(_) => (0, 0, 0), //This should never happen ... unless this module has a logic error for calculating unique variable_map_key values :)
             None => (
@@ -1493,10 +2674,18 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
                     }
                 }
                 "continued" => {
-                    println!(
-                        "{}",
-                        self.last_known_status.short_long_status().1.to_owned()
-                    );
+                    if let Some(event_body) = event_body {
+                        let event_body_struct: ContinuedEventBody = serde_json::from_value(
+                            serde_json::to_value(event_body).unwrap_or_default(),
+                        )
+                        .unwrap();
+                        println!(
+                            "{}",
+                            self.last_known_status(event_body_struct.thread_id as usize)
+                                .short_long_status()
+                                .1
+                        );
+                    }
                 }
                 other => match self.console_log_level {
                     ConsoleLog::Error => {}
@@ -1515,6 +2704,99 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
         true
     }
 
+    /// Send output of the target application (e.g. from semihosting) to the client.
+    pub fn send_output(&mut self, stream: ConsoleStream, data: &[u8]) -> bool {
+        let output = String::from_utf8_lossy(data).to_string();
+        if self.adapter_type == DebugAdapterType::DapClient {
+            let event_body = match serde_json::to_value(OutputEventBody {
+                output,
+                category: Some(
+                    match stream {
+                        ConsoleStream::Stdout => "stdout",
+                        ConsoleStream::Stderr => "stderr",
+                    }
+                    .to_owned(),
+                ),
+                variables_reference: None,
+                source: None,
+                line: None,
+                column: None,
+                data: None,
+                group: None,
+            }) {
+                Ok(event_body) => event_body,
+                Err(_) => {
+                    return false;
+                }
+            };
+            self.send_event("output", Some(event_body))
+        } else {
+            //DebugCAdapterType::CommandLine
+            match stream {
+                ConsoleStream::Stdout => print!("{}", output),
+                ConsoleStream::Stderr => eprint!("{}", output),
+            }
+            true
+        }
+    }
+
+    /// Start a progress bar in the DAP client, if it supports them, and return the id of the progress.
+    /// `request_id` is the request that the progress belongs to, if any.
+    pub(crate) fn start_progress(
+        &mut self,
+        title: &str,
+        request_id: Option<i64>,
+    ) -> Option<String> {
+        if self.adapter_type != DebugAdapterType::DapClient || !self.supports_progress_reporting {
+            return None;
+        }
+        let progress_id = format!("probe-rs-progress-{}", self.seq);
+        self.send_event(
+            "progressStart",
+            Some(ProgressStartEventBody {
+                progress_id: progress_id.clone(),
+                title: title.to_owned(),
+                request_id,
+                cancellable: Some(false),
+                message: None,
+                percentage: None,
+            }),
+        );
+        Some(progress_id)
+    }
+
+    /// Update a progress bar which was started with [DebugAdapter::start_progress].
+    pub(crate) fn update_progress(
+        &mut self,
+        progress_id: Option<&str>,
+        message: Option<String>,
+        percentage: Option<f64>,
+    ) {
+        if let Some(progress_id) = progress_id {
+            self.send_event(
+                "progressUpdate",
+                Some(ProgressUpdateEventBody {
+                    progress_id: progress_id.to_owned(),
+                    message,
+                    percentage,
+                }),
+            );
+        }
+    }
+
+    /// End a progress bar which was started with [DebugAdapter::start_progress], with an optional final message.
+    pub(crate) fn end_progress(&mut self, progress_id: Option<&str>, message: Option<String>) {
+        if let Some(progress_id) = progress_id {
+            self.send_event(
+                "progressEnd",
+                Some(ProgressEndEventBody {
+                    progress_id: progress_id.to_owned(),
+                    message,
+                }),
+            );
+        }
+    }
+
     pub fn log_to_console<S: Into<String>>(&mut self, msg: S) -> bool {
         if self.adapter_type == DebugAdapterType::DapClient {
             let event_body = match serde_json::to_value(OutputEventBody {
@@ -1542,6 +2824,55 @@ impl<R: Read, W: Write> DebugAdapter<R, W> {
 }
 
 // SECTION: Some helper functions
+/// Create the DAP source for a source location of the debug information.
+fn get_dap_source(source_location: &SourceLocation) -> Source {
+    let path: Option<PathBuf> = source_location.directory.as_ref().map(|path| {
+        let mut path = if path.is_relative() {
+            std::env::current_dir().unwrap().join(path)
+        } else {
+            path.to_owned()
+        };
+
+        if let Some(file) = &source_location.file {
+            path.push(file);
+        }
+
+        path
+    });
+
+    //TODO: Consider implementing RTIC's expanded source access. Might also do a general macro expansion if that makes sense.
+    Source {
+        name: source_location.file.clone(),
+        path: path.map(|p| p.to_string_lossy().to_string()),
+        source_reference: None,
+        presentation_hint: None,
+        origin: None,
+        sources: None,
+        adapter_data: None,
+        checksums: None,
+    }
+}
+
+/// The memory reference of a variable, for variables which are stored in memory.
+fn variable_memory_reference(variable: &probe_rs::debug::Variable) -> Option<String> {
+    match variable.memory_location {
+        0 | u64::MAX => None,
+        _ if variable.register.is_some() => None,
+        memory_location => Some(format!("0x{:08x}", memory_location)),
+    }
+}
+
+/// The address of a DAP memory reference, with an optional offset in bytes.
+fn memory_address(memory_reference: &str, offset: Option<i64>) -> Result<u32, DebuggerError> {
+    parse::<i64>(memory_reference)
+        .ok()
+        .and_then(|address| address.checked_add(offset.unwrap_or(0)))
+        .and_then(|address| u32::try_from(address).ok())
+        .ok_or_else(|| {
+            DebuggerError::Other(anyhow!("Invalid memory reference '{}'", memory_reference))
+        })
+}
+
 pub fn get_arguments<T: DeserializeOwned>(req: &Request) -> Result<T, crate::DebuggerError> {
     let value = req
         .arguments
@@ -1570,6 +2901,17 @@ mod test {
 
         assert_eq!(234, get_content_len(&header).unwrap());
     }
+
+    #[test]
+    fn memory_address_with_offset() {
+        assert_eq!(
+            memory_address("0x2000_0000", Some(-2)).unwrap(),
+            0x1fff_fffe
+        );
+        assert_eq!(memory_address("1024", None).unwrap(), 1024);
+        assert!(memory_address("0x10", Some(-0x20)).is_err());
+        assert!(memory_address("pc", None).is_err());
+    }
 }
 
 pub(crate) trait DapStatus {
@@ -1604,6 +2946,10 @@ impl DapStatus for CoreStatus {
                     "Core halted due to a user (debugger client) request",
                 ),
                 HaltReason::External => ("external", "Core halted due to an external request"),
+                HaltReason::Semihosting => (
+                    "semihosting",
+                    "Core halted due to a semihosting request of the target",
+                ),
                 _other => ("unrecognized", "Core halted: unrecognized cause"),
             },
             CoreStatus::Unknown => ("unknown", "Core status cannot be determined"),
@@ -2,23 +2,32 @@ use crate::dap_types::*;
 use crate::debug_adapter::DapStatus;
 use crate::debug_adapter::*;
 
+use crate::exceptions;
+use crate::peripherals::SvdPeripherals;
 use crate::DebuggerError;
 use anyhow::{anyhow, Result};
-use capstone::{arch::arm::ArchMode, prelude::*, Capstone, Endian};
 use probe_rs::debug::DebugInfo;
-use probe_rs::flashing::{download_file, download_file_with_options, DownloadOptions, Format};
-use probe_rs::{config::TargetSelector, ProbeCreationError};
+use probe_rs::flashing::{
+    download_file, download_file_with_options, read_flash_algorithm_from_flm, DownloadOptions,
+    FlashProgress, Format, ProgressEvent,
+};
+use probe_rs::{
+    config::{get_target_by_name, TargetSelector},
+    ProbeCreationError,
+};
 use probe_rs::{
-    Core, CoreStatus, DebugProbeError, DebugProbeSelector, MemoryInterface, Probe, Session,
-    WireProtocol,
+    Core, CoreStatus, DebugProbeError, DebugProbeSelector, HaltReason, MemoryInterface, Probe,
+    Session, WireProtocol,
 };
 use serde::Deserialize;
 use std::{
+    cell::RefCell,
     env::{current_dir, set_current_dir},
     io,
     io::{Read, Write},
     net::{Ipv4Addr, TcpListener, ToSocketAddrs},
     path::PathBuf,
+    rc::Rc,
     str::FromStr,
     thread,
     time::{Duration, Instant},
@@ -78,6 +87,10 @@ pub struct DebuggerOptions {
     #[structopt(long, parse(from_os_str), conflicts_with("dap"))]
     program_binary: Option<PathBuf>,
 
+    /// CMSIS-SVD file of the target, to show its peripheral registers. Relative to `cwd`, or fully qualified.
+    #[structopt(long, parse(from_os_str))]
+    svd_file: Option<PathBuf>,
+
     /// The number associated with the debug probe to use. Use 'list' command to see available probes
     #[structopt(
         long = "probe",
@@ -115,35 +128,88 @@ pub struct DebuggerOptions {
     #[structopt(long, requires("dap"))]
     pub(crate) port: Option<u16>,
 
+    /// Flash the target before debugging. These are options of the `debug` command, and arguments of the `launch` request for DAP clients.
+    #[structopt(skip)]
+    #[serde(skip)]
+    pub(crate) flashing_options: FlashingOptions,
+
+    /// Level of information to be logged to the debugger console (Error, Info or Debug )
+    #[structopt(long, parse(try_from_str = parse_console_log))]
+    #[serde(default = "default_console_log")]
+    pub(crate) console_log_level: Option<ConsoleLog>,
+}
+
+/// Options to flash the program to the target, and reset it, at the start of a debug session.
+#[derive(StructOpt, Clone, Deserialize, Debug, Default)]
+pub struct FlashingOptions {
     /// Flash the target before debugging
-    #[structopt(long, hidden = true)]
+    #[structopt(long, conflicts_with("dap"))]
     #[serde(default)]
     pub(crate) flashing_enabled: bool,
 
     /// Reset the target after flashing
-    #[structopt(long, hidden = true, required_if("flashing_enabled", "true"))]
+    #[structopt(long, conflicts_with("dap"), required_if("flashing_enabled", "true"))]
     #[serde(default)]
     pub(crate) reset_after_flashing: bool,
 
     /// Halt the target after reset
-    #[structopt(long, hidden = true)]
+    #[structopt(long, conflicts_with("dap"))]
     #[serde(default)]
     pub(crate) halt_after_reset: bool,
 
     /// Do a full chip erase, versus page-by-page erase
-    #[structopt(long, hidden = true, required_if("flashing_enabled", "true"))]
+    #[structopt(long, conflicts_with("dap"), required_if("flashing_enabled", "true"))]
     #[serde(default)]
     pub(crate) full_chip_erase: bool,
 
     /// Restore erased bytes that will not be rewritten from ELF
-    #[structopt(long, hidden = true, required_if("flashing_enabled", "true"))]
+    #[structopt(long, conflicts_with("dap"), required_if("flashing_enabled", "true"))]
     #[serde(default)]
     pub(crate) restore_unwritten_bytes: bool,
 
-    /// Level of information to be logged to the debugger console (Error, Info or Debug )
-    #[structopt(long, parse(try_from_str = parse_console_log))]
-    #[serde(default = "default_console_log")]
-    pub(crate) console_log_level: Option<ConsoleLog>,
+    /// Skip erasing and programming the sectors which already hold the data from the ELF
+    #[structopt(long, conflicts_with("dap"))]
+    #[serde(default)]
+    pub(crate) skip_unchanged_sectors: bool,
+
+    /// An FLM file of a CMSIS-Pack, with the flash algorithm to use instead of the one of the chip
+    #[structopt(long, parse(from_os_str), conflicts_with("dap"))]
+    pub(crate) flash_algorithm: Option<PathBuf>,
+}
+
+/// The arguments of the DAP `launch` request. Launching flashes the `program_binary` to the target, if `flashing_enabled`, and resets the target.
+#[derive(Deserialize, Debug)]
+pub(crate) struct LaunchArguments {
+    #[serde(flatten)]
+    pub(crate) debugger_options: DebuggerOptions,
+    #[serde(flatten)]
+    pub(crate) flashing_options: FlashingOptions,
+}
+
+/// The arguments of the DAP `attach` request. Attaching connects to the target as it is, without flashing or resetting it.
+#[derive(Deserialize, Debug)]
+pub(crate) struct AttachArguments {
+    #[serde(flatten)]
+    pub(crate) debugger_options: DebuggerOptions,
+}
+
+impl LaunchArguments {
+    pub(crate) fn into_options(self) -> DebuggerOptions {
+        DebuggerOptions {
+            flashing_options: self.flashing_options,
+            ..self.debugger_options
+        }
+    }
+}
+
+impl AttachArguments {
+    pub(crate) fn into_options(self) -> DebuggerOptions {
+        DebuggerOptions {
+            // Attaching must not reset the target.
+            connect_under_reset: false,
+            ..self.debugger_options
+        }
+    }
 }
 
 impl DebuggerOptions {
@@ -168,17 +234,30 @@ impl DebuggerOptions {
         &mut self,
         new_program_binary: Option<PathBuf>,
     ) {
-        self.program_binary = match new_program_binary {
-            Some(temp_path) => {
-                let mut new_path = PathBuf::new();
-                if temp_path.is_relative() {
-                    new_path.push(self.cwd.clone().unwrap());
-                }
-                new_path.push(temp_path);
-                Some(new_path)
-            }
-            None => None,
-        };
+        self.program_binary = new_program_binary.map(|path| self.qualify_path(path));
+    }
+
+    /// If the path to the SVD file is relative, we join if with the cwd.
+    pub(crate) fn qualify_and_update_svd_file(&mut self, new_svd_file: Option<PathBuf>) {
+        self.svd_file = new_svd_file.map(|path| self.qualify_path(path));
+    }
+
+    /// If the path to the FLM file of the flash algorithm is relative, we join it with the cwd.
+    pub(crate) fn qualify_and_update_flash_algorithm(
+        &mut self,
+        new_flash_algorithm: Option<PathBuf>,
+    ) {
+        self.flashing_options.flash_algorithm =
+            new_flash_algorithm.map(|path| self.qualify_path(path));
+    }
+
+    fn qualify_path(&self, temp_path: PathBuf) -> PathBuf {
+        let mut new_path = PathBuf::new();
+        if temp_path.is_relative() {
+            new_path.push(self.cwd.clone().unwrap());
+        }
+        new_path.push(temp_path);
+        new_path
     }
 }
 
@@ -198,15 +277,108 @@ pub struct Debugger {
     pub supported_commands: Vec<DebugCommand>,
 }
 
+/// Translates the [ProgressEvent]s of flashing into the message and percentage of a DAP progress bar.
+/// The percentage is the progress of the current phase (filling, erasing or programming), weighted by the number of bytes of the phase in the [probe_rs::flashing::FlashLayout] of the region being flashed.
+/// Not every phase reports its progress, e.g. the sectors are not erased one by one with a chip erase, so each phase is reported on its own, and completes when it is finished.
+#[derive(Default)]
+struct FlashingProgress {
+    fill_size: u64,
+    erase_size: u64,
+    program_size: u64,
+    phase_size: u64,
+    done_size: u64,
+    message: &'static str,
+    percentage: u8,
+}
+
+impl FlashingProgress {
+    /// Set the bytes which are filled, erased and programmed in the region of flash which is flashed next. Each region is flashed with its own flash layout.
+    fn initialize(&mut self, fill_size: u64, erase_size: u64, program_size: u64) {
+        self.fill_size = fill_size;
+        self.erase_size = erase_size;
+        self.program_size = program_size;
+    }
+
+    /// Start a new phase of flashing, with `phase_size` bytes.
+    fn start_phase(&mut self, message: &'static str, phase_size: u64) -> &'static str {
+        self.phase_size = phase_size;
+        self.done_size = 0;
+        message
+    }
+
+    /// Update the progress with an event, and return the new message and percentage, if either of them changed.
+    fn update(&mut self, event: &ProgressEvent) -> Option<(String, f64)> {
+        let message = match event {
+            ProgressEvent::Initialized { flash_layout } => {
+                let fill_size: u64 = flash_layout
+                    .fills()
+                    .iter()
+                    .map(|fill| u64::from(fill.size()))
+                    .sum();
+                let erase_size: u64 = flash_layout
+                    .sectors()
+                    .iter()
+                    .map(|sector| u64::from(sector.size()))
+                    .sum();
+                let program_size: u64 = flash_layout
+                    .pages()
+                    .iter()
+                    .map(|page| u64::from(page.size()))
+                    .sum();
+                self.initialize(fill_size, erase_size, program_size);
+                return None;
+            }
+            ProgressEvent::SectorSkipped { .. } => "Skipping unchanged sectors",
+            ProgressEvent::StartedFilling => {
+                self.start_phase("Reading the flash contents to restore", self.fill_size)
+            }
+            ProgressEvent::StartedErasing => self.start_phase("Erasing sectors", self.erase_size),
+            ProgressEvent::StartedProgramming => {
+                self.start_phase("Programming pages", self.program_size)
+            }
+            ProgressEvent::PageFilled { size, .. }
+            | ProgressEvent::SectorErased { size, .. }
+            | ProgressEvent::PageProgrammed { size, .. } => {
+                self.done_size += u64::from(*size);
+                self.message
+            }
+            ProgressEvent::FailedFilling => "Reading the flash contents failed",
+            ProgressEvent::FailedErasing => "Erasing failed",
+            ProgressEvent::FailedProgramming => "Programming failed",
+            ProgressEvent::FinishedFilling
+            | ProgressEvent::FinishedErasing
+            | ProgressEvent::FinishedProgramming => {
+                self.done_size = self.phase_size;
+                self.message
+            }
+        };
+
+        let percentage = match self.phase_size {
+            0 => 0,
+            phase_size => (self.done_size.min(phase_size) * 100 / phase_size) as u8,
+        };
+
+        if message == self.message && percentage == self.percentage {
+            return None;
+        }
+        self.message = message;
+        self.percentage = percentage;
+
+        Some((message.to_owned(), f64::from(percentage)))
+    }
+}
+
 pub struct SessionData {
     pub(crate) session: Session,
-    #[allow(dead_code)]
-    pub(crate) capstone: Capstone,
+    /// The debug information of the `program_binary`, which is loaded once per session.
+    pub(crate) debug_info: Option<DebugInfo>,
+    /// The peripherals of the target, from the `svd_file`.
+    pub(crate) peripherals: Option<SvdPeripherals>,
 }
 pub struct CoreData<'p> {
     pub(crate) target_core: Core<'p>,
-    pub(crate) target_name: String,
-    pub(crate) debug_info: Option<DebugInfo>,
+    pub(crate) debug_info: Option<&'p DebugInfo>,
+    pub(crate) peripherals: Option<&'p SvdPeripherals>,
 }
 /// Definition of commands that have been implemented in Debugger.
 #[derive(Clone, Copy)]
@@ -256,9 +428,32 @@ pub fn start_session(debugger_options: &DebuggerOptions) -> Result<SessionData,
         }
     }?;
 
-    let target_selector = match &debugger_options.chip {
-        Some(identifier) => identifier.into(),
-        None => TargetSelector::Auto,
+    let target_selector = match (
+        &debugger_options.chip,
+        &debugger_options.flashing_options.flash_algorithm,
+    ) {
+        (Some(identifier), Some(flash_algorithm)) => {
+            let mut target = get_target_by_name(identifier).map_err(|error| {
+                DebuggerError::Other(anyhow!("Unknown chip '{}': {}", identifier, error))
+            })?;
+            let flash_algorithm =
+                read_flash_algorithm_from_flm(flash_algorithm).map_err(|error| {
+                    DebuggerError::Other(anyhow!(
+                        "Could not read the flash algorithm {:?}: {}",
+                        flash_algorithm,
+                        error
+                    ))
+                })?;
+            target.add_flash_algorithm(flash_algorithm);
+            target.into()
+        }
+        (None, Some(_)) => {
+            return Err(DebuggerError::Other(anyhow!(
+                "The chip has to be given to use a flash algorithm"
+            )))
+        }
+        (Some(identifier), None) => identifier.into(),
+        (None, None) => TargetSelector::Auto,
     };
     //set the protocol
     target_probe.select_protocol(debugger_options.protocol.unwrap_or(WireProtocol::Swd))?;
@@ -296,37 +491,57 @@ pub fn start_session(debugger_options: &DebuggerOptions) -> Result<SessionData,
         })?;
     };
 
-    //Configure the Capstone
-    let capstone = Capstone::new()
-        .arm()
-        .mode(ArchMode::Thumb)
-        .endian(Endian::Little)
-        .build()
-        .unwrap();
+    //The peripherals are optional, so the session can continue without them
+    let peripherals =
+        debugger_options
+            .svd_file
+            .as_ref()
+            .and_then(|path| match SvdPeripherals::from_file(path) {
+                Ok(peripherals) => Some(peripherals),
+                Err(error) => {
+                    log::warn!("{}", error);
+                    None
+                }
+            });
+
+    //The debug information is optional too, e.g. to attach to a core without a binary
+    let debug_info = debugger_options.program_binary.as_ref().and_then(|path| {
+        match DebugInfo::from_file(path) {
+            Ok(debug_info) => Some(debug_info),
+            Err(error) => {
+                log::warn!(
+                    "Could not load the debug information from {:?}: {}",
+                    path,
+                    error
+                );
+                None
+            }
+        }
+    });
 
     //Populate the return SessionData
     Ok(SessionData {
         session: target_session,
-        capstone,
+        debug_info,
+        peripherals,
     })
 }
 
+/// The name of a core, which is shown as the name of its thread by the DAP client.
+fn core_name(core_index: usize, target_name: &str) -> String {
+    format!("{}-{}", core_index, target_name)
+}
+
 pub fn attach_core<'p>(
     session_data: &'p mut SessionData,
-    debugger_options: &DebuggerOptions,
+    core_index: usize,
 ) -> Result<CoreData<'p>, DebuggerError> {
-    //Configure the DebugInfo
-    let debug_info = debugger_options
-        .program_binary
-        .as_ref()
-        .and_then(|path| DebugInfo::from_file(path).ok());
-    let target_name = session_data.session.target().name.clone();
     //Do no-op attach to the core and return it
-    match session_data.session.core(debugger_options.core_index) {
+    match session_data.session.core(core_index) {
         Ok(target_core) => Ok(CoreData {
             target_core,
-            target_name: format!("{}-{}", debugger_options.core_index, target_name),
-            debug_info,
+            debug_info: session_data.debug_info.as_ref(),
+            peripherals: session_data.peripherals.as_ref(),
         }),
         Err(_) => Err(DebuggerError::UnableToOpenProbe(Some(
             "No core at the specified index.",
@@ -356,6 +571,18 @@ impl Debugger {
                     help_text: "Step a single instruction",
                     function_name: "next",
                 },
+                DebugCommand {
+                    dap_cmd: "stepIn",
+                    cli_cmd: "step_in",
+                    help_text: "Step into the function called by the current source line",
+                    function_name: "step_in",
+                },
+                DebugCommand {
+                    dap_cmd: "stepOut",
+                    cli_cmd: "step_out",
+                    help_text: "Run until the current function returns",
+                    function_name: "step_out",
+                },
                 DebugCommand {
                     dap_cmd: "pause",
                     cli_cmd: "halt",
@@ -363,11 +590,17 @@ impl Debugger {
                     function_name: "pause",
                 },
                 DebugCommand {
-                    dap_cmd: "read_memory",
+                    dap_cmd: "readMemory",
                     cli_cmd: "read",
                     help_text: "Read 32bit value from memory",
                     function_name: "read_memory",
                 },
+                DebugCommand {
+                    dap_cmd: "writeMemory",
+                    cli_cmd: "",
+                    help_text: "Write bytes to memory",
+                    function_name: "write_memory",
+                },
                 DebugCommand {
                     dap_cmd: "",
                     cli_cmd: "write",
@@ -435,6 +668,12 @@ impl Debugger {
                     help_text: "",
                     function_name: "set_breakpoints",
                 },
+                DebugCommand {
+                    dap_cmd: "setFunctionBreakpoints",
+                    cli_cmd: "",
+                    help_text: "",
+                    function_name: "set_function_breakpoints",
+                },
                 DebugCommand {
                     dap_cmd: "stackTrace",
                     cli_cmd: "stack",
@@ -459,6 +698,42 @@ impl Debugger {
                     help_text: "",
                     function_name: "variables",
                 },
+                DebugCommand {
+                    dap_cmd: "setVariable",
+                    cli_cmd: "",
+                    help_text: "",
+                    function_name: "set_variable",
+                },
+                DebugCommand {
+                    dap_cmd: "setInstructionBreakpoints",
+                    cli_cmd: "",
+                    help_text: "",
+                    function_name: "set_instruction_breakpoints",
+                },
+                DebugCommand {
+                    dap_cmd: "setExceptionBreakpoints",
+                    cli_cmd: "",
+                    help_text: "",
+                    function_name: "set_exception_breakpoints",
+                },
+                DebugCommand {
+                    dap_cmd: "disassemble",
+                    cli_cmd: "disassemble",
+                    help_text: "Disassemble instructions at a memory address, e.g. 'disassemble 0x08000100 10'",
+                    function_name: "disassemble",
+                },
+                DebugCommand {
+                    dap_cmd: "evaluate",
+                    cli_cmd: "print",
+                    help_text: "Evaluate an expression, e.g. 'print config.baud * 2'",
+                    function_name: "evaluate",
+                },
+                DebugCommand {
+                    dap_cmd: "setExpression",
+                    cli_cmd: "",
+                    help_text: "",
+                    function_name: "set_expression",
+                },
                 DebugCommand {
                     dap_cmd: "run",
                     cli_cmd: "continue",
@@ -470,6 +745,99 @@ impl Debugger {
         }
     }
 
+    /// Check the status of one core, and notify the DAP-Client if it changed, with the core index as the thread id.
+    /// Returns `Some(true)` if the status changed, and `None` if the debug session should end.
+    fn poll_core<R: Read, W: Write>(
+        &mut self,
+        session_data: &mut SessionData,
+        debug_adapter: &mut DebugAdapter<R, W>,
+        request: &Request,
+        core_index: usize,
+    ) -> Option<bool> {
+        let last_known_status = debug_adapter.last_known_status(core_index);
+        let mut core_data = match attach_core(session_data, core_index) {
+            Ok(core_data) => core_data,
+            Err(error) => {
+                debug_adapter.send_response::<()>(request, Err(error));
+                return None;
+            }
+        };
+        let new_status = match core_data.target_core.status() {
+            Ok(new_status) => new_status,
+            Err(error) => {
+                debug_adapter.send_response::<()>(request, Err(DebuggerError::ProbeRs(error)));
+                return None;
+            }
+        };
+
+        // Semihosting requests are serviced right away, and the core resumed, without notifying the DAP-Client of the halt.
+        if new_status == CoreStatus::Halted(HaltReason::Semihosting) {
+            return if debug_adapter.semihosting(&mut core_data) {
+                Some(true)
+            } else {
+                None
+            };
+        }
+
+        if new_status == last_known_status {
+            return Some(false);
+        };
+
+        // Breakpoints whose conditions are not met are skipped the same way. This is only done on the transition into the halt, so the conditions and hit counts are evaluated once per hit.
+        if new_status == CoreStatus::Halted(HaltReason::Breakpoint)
+            && debug_adapter.skip_breakpoint(&mut core_data)
+        {
+            return Some(true);
+        }
+
+        match new_status {
+            CoreStatus::Running | CoreStatus::Sleeping => {
+                let event_body = Some(ContinuedEventBody {
+                    all_threads_continued: Some(false),
+                    thread_id: core_index as i64,
+                });
+                debug_adapter.send_event("continued", event_body);
+            }
+            CoreStatus::Halted(_) => {
+                let text = debug_adapter.fault_description(&mut core_data, new_status);
+                let event_body = Some(StoppedEventBody {
+                    reason: new_status.short_long_status().0.to_owned(),
+                    description: Some(new_status.short_long_status().1.to_owned()),
+                    thread_id: Some(core_index as i64),
+                    preserve_focus_hint: Some(false),
+                    text,
+                    all_threads_stopped: Some(false),
+                    hit_breakpoint_ids: None,
+                });
+                debug_adapter.send_event("stopped", event_body);
+            }
+            CoreStatus::LockedUp => {
+                let event_body = Some(StoppedEventBody {
+                    reason: new_status.short_long_status().0.to_owned(),
+                    description: Some(new_status.short_long_status().1.to_owned()),
+                    thread_id: Some(core_index as i64),
+                    preserve_focus_hint: Some(false),
+                    text: None,
+                    all_threads_stopped: Some(false),
+                    hit_breakpoint_ids: None,
+                });
+                debug_adapter.send_event("stopped", event_body);
+                return None;
+            }
+            CoreStatus::Unknown => {
+                debug_adapter.send_response::<()>(
+                    request,
+                    Err(DebuggerError::Other(anyhow!(
+                        "Unknown Device status reveived from Probe-rs"
+                    ))),
+                );
+                return None;
+            }
+        };
+        debug_adapter.set_last_known_status(core_index, new_status);
+        Some(true)
+    }
+
     //SECTION: Methods to handle DAP requests
     pub fn process_next_request<R: Read, W: Write>(
         &mut self,
@@ -489,78 +857,26 @@ impl Debugger {
                 - If the `new_status` is `Running`, then we have to poll on a regular basis, until the Probe stops for good reasons like breakpoints, or bad reasons like panics. Then tell the DAP-Client.
                 - TODO: Figure out CPU/Comms overhead costs to determine optimal polling intervals
                 */
-                let last_known_status = debug_adapter.last_known_status;
-                match last_known_status {
+                match debug_adapter.last_known_status(self.debugger_options.core_index) {
                     CoreStatus::Unknown => true,
                     _other => {
-                        let mut core_data = match attach_core(session_data, &self.debugger_options)
-                        {
-                            Ok(core_data) => core_data,
-                            Err(error) => {
-                                debug_adapter.send_response::<()>(&request, Err(error));
-                                return false;
-                            }
-                        };
-                        let new_status = match core_data.target_core.status() {
-                            Ok(new_status) => new_status,
-                            Err(error) => {
-                                debug_adapter.send_response::<()>(
-                                    &request,
-                                    Err(DebuggerError::ProbeRs(error)),
-                                );
-                                return false;
+                        let core_indices: Vec<usize> = session_data
+                            .session
+                            .list_cores()
+                            .into_iter()
+                            .map(|(core_index, _)| core_index)
+                            .collect();
+                        let mut status_changed = false;
+                        for core_index in core_indices {
+                            match self.poll_core(session_data, debug_adapter, &request, core_index)
+                            {
+                                Some(changed) => status_changed |= changed,
+                                None => return false,
                             }
-                        };
-
-                        if new_status == last_known_status {
+                        }
+                        if !status_changed {
                             thread::sleep(Duration::from_millis(50)); //small delay to reduce fast looping costs
-                            return true;
-                        };
-
-                        match new_status {
-                            CoreStatus::Running | CoreStatus::Sleeping => {
-                                let event_body = Some(ContinuedEventBody {
-                                    all_threads_continued: Some(true),
-                                    thread_id: core_data.target_core.id() as i64,
-                                });
-                                debug_adapter.send_event("continued", event_body);
-                            }
-                            CoreStatus::Halted(_) => {
-                                let event_body = Some(StoppedEventBody {
-                                    reason: new_status.short_long_status().0.to_owned(),
-                                    description: Some(new_status.short_long_status().1.to_owned()),
-                                    thread_id: Some(core_data.target_core.id() as i64),
-                                    preserve_focus_hint: Some(false),
-                                    text: None,
-                                    all_threads_stopped: Some(true),
-                                    hit_breakpoint_ids: None,
-                                });
-                                debug_adapter.send_event("stopped", event_body);
-                            }
-                            CoreStatus::LockedUp => {
-                                let event_body = Some(StoppedEventBody {
-                                    reason: new_status.short_long_status().0.to_owned(),
-                                    description: Some(new_status.short_long_status().1.to_owned()),
-                                    thread_id: Some(core_data.target_core.id() as i64),
-                                    preserve_focus_hint: Some(false),
-                                    text: None,
-                                    all_threads_stopped: Some(true),
-                                    hit_breakpoint_ids: None,
-                                });
-                                debug_adapter.send_event("stopped", event_body);
-                                return false;
-                            }
-                            CoreStatus::Unknown => {
-                                debug_adapter.send_response::<()>(
-                                    &request,
-                                    Err(DebuggerError::Other(anyhow!(
-                                        "Unknown Device status reveived from Probe-rs"
-                                    ))),
-                                );
-                                return false;
-                            }
-                        };
-                        debug_adapter.last_known_status = new_status;
+                        }
                         true
                     }
                 }
@@ -583,9 +899,24 @@ impl Debugger {
                     .find(|c| c.dap_cmd == command_lookup || c.cli_cmd == command_lookup);
                 match valid_command {
                     Some(valid_command) => {
-                        //First, attach to the core
-                        let mut core_data = match attach_core(session_data, &self.debugger_options)
-                        {
+                        if valid_command.function_name == "threads" {
+                            let target_name = session_data.session.target().name.clone();
+                            let threads = session_data
+                                .session
+                                .list_cores()
+                                .into_iter()
+                                .map(|(core_index, _)| Thread {
+                                    id: core_index as i64,
+                                    name: core_name(core_index, &target_name),
+                                })
+                                .collect();
+                            return debug_adapter.threads(threads, &request);
+                        }
+                        //First, attach to the core that the request is meant for. Requests which don't refer to a core, like breakpoints, go to the core selected with `--core-index`.
+                        let core_index = debug_adapter
+                            .core_index(&request)
+                            .unwrap_or(self.debugger_options.core_index);
+                        let mut core_data = match attach_core(session_data, core_index) {
                             Ok(core_data) => core_data,
                             Err(error) => {
                                 debug_adapter.send_response::<()>(&request, Err(error));
@@ -595,9 +926,12 @@ impl Debugger {
                         match valid_command.function_name {
                             "status" => debug_adapter.status(&mut core_data, &request),
                             "next" => debug_adapter.next(&mut core_data, &request),
+                            "step_in" => debug_adapter.step_in(&mut core_data, &request),
+                            "step_out" => debug_adapter.step_out(&mut core_data, &request),
                             "pause" => debug_adapter.pause(&mut core_data, &request),
                             "read_memory" => debug_adapter.read_memory(&mut core_data, &request),
                             "write" => debug_adapter.write(&mut core_data, &request),
+                            "write_memory" => debug_adapter.write_memory(&mut core_data, &request),
                             "set_breakpoint" => {
                                 debug_adapter.set_breakpoint(&mut core_data, &request)
                             }
@@ -615,15 +949,29 @@ impl Debugger {
                             }
                             "disconnect" => debug_adapter.disconnect(&mut core_data, &request),
                             "terminate" => debug_adapter.terminate(&mut core_data, &request),
-                            "threads" => debug_adapter.threads(&mut core_data, &request),
                             "restart" => debug_adapter.restart(&mut core_data, &request),
                             "set_breakpoints" => {
                                 debug_adapter.set_breakpoints(&mut core_data, &request)
                             }
+                            "set_function_breakpoints" => {
+                                debug_adapter.set_function_breakpoints(&mut core_data, &request)
+                            }
                             "stack_trace" => debug_adapter.stack_trace(&mut core_data, &request),
                             "scopes" => debug_adapter.scopes(&mut core_data, &request),
                             "source" => debug_adapter.source(&mut core_data, &request),
                             "variables" => debug_adapter.variables(&mut core_data, &request),
+                            "set_variable" => debug_adapter.set_variable(&mut core_data, &request),
+                            "set_expression" => {
+                                debug_adapter.set_expression(&mut core_data, &request)
+                            }
+                            "evaluate" => debug_adapter.evaluate(&mut core_data, &request),
+                            "set_instruction_breakpoints" => {
+                                debug_adapter.set_instruction_breakpoints(&mut core_data, &request)
+                            }
+                            "set_exception_breakpoints" => {
+                                debug_adapter.set_exception_breakpoints(&mut core_data, &request)
+                            }
+                            "disassemble" => debug_adapter.disassemble(&mut core_data, &request),
                             "continue" => debug_adapter.r#continue(&mut core_data, &request),
                             other => {
                                 debug_adapter.send_response::<()>(
@@ -671,7 +1019,10 @@ impl Debugger {
     All requests are interpreted, actions taken, and responses formulated here. This function is self contained and returns nothing.
     The [debug_adapter::DebugAdapter] takes care of _implementing the DAP Base Protocol_ and _communicating with the DAP client_ and _probe_.
     */
-    pub fn debug_session<R: Read, W: Write>(&mut self, mut debug_adapter: DebugAdapter<R, W>) {
+    pub fn debug_session<R: Read + 'static, W: Write + 'static>(
+        &mut self,
+        mut debug_adapter: DebugAdapter<R, W>,
+    ) {
         //Filter out just the set of commands that will work for this session.
         self.supported_commands = if debug_adapter.adapter_type == DebugAdapterType::DapClient {
             self.all_commands
@@ -695,6 +1046,9 @@ impl Debugger {
             type_: "request".to_owned(),
         };
 
+        //The request that started the session, `launch` or `attach` for the DapClient. Errors during flashing and reset are reported as its response.
+        let mut startup_request = custom_request.clone();
+
         //The DapClient startup process has a specific sequence. Handle it here before starting a probe-rs session and looping through user generated requests.
         if debug_adapter.adapter_type == DebugAdapterType::DapClient {
             //Handling the initialize, and Attach/Launch requests here in this method, before entering the interactive loop that processes requests through the process_request method.
@@ -725,7 +1079,7 @@ impl Debugger {
                     }
                 };
             }
-            let _arguments: InitializeRequestArguments = match get_arguments::<
+            let arguments: InitializeRequestArguments = match get_arguments::<
                 InitializeRequestArguments,
             >(&request)
             {
@@ -743,18 +1097,27 @@ impl Debugger {
                     return;
                 }
             };
+            debug_adapter.supports_progress_reporting =
+                arguments.supports_progress_reporting.unwrap_or(false);
 
             //Reply to Initialize with Capabilities
             let capabilities = Capabilities {
                 supports_configuration_done_request: Some(true),
                 supports_read_memory_request: Some(true),
+                supports_write_memory_request: Some(true),
                 supports_restart_request: Some(false), // It is better (and cheap enough) to let the client kill and restart the debugadapter, than to try a in-process reset.
                 supports_terminate_request: Some(true),
+                supports_stepping_granularity: Some(true),
                 // supports_value_formatting_options: Some(true),
-                //supports_function_breakpoints: Some(true),
-                //TODO: Use DEMCR register to implement exception breakpoints
-                // supports_exception_options: Some(true),
-                // supports_exception_filter_options: Some (true),
+                supports_function_breakpoints: Some(true),
+                supports_conditional_breakpoints: Some(true),
+                supports_hit_conditional_breakpoints: Some(true),
+                supports_set_variable: Some(true),
+                supports_set_expression: Some(true),
+                supports_evaluate_for_hovers: Some(true),
+                supports_disassemble_request: Some(true),
+                supports_instruction_breakpoints: Some(true),
+                exception_breakpoint_filters: Some(exceptions::exception_breakpoint_filters()),
                 ..Default::default()
             };
             debug_adapter.send_response(&request, Ok(Some(capabilities)));
@@ -779,9 +1142,14 @@ impl Debugger {
                     }
                 };
             }
-            match get_arguments(&request) {
+            let arguments = if request.command == "launch" {
+                get_arguments::<LaunchArguments>(&request).map(LaunchArguments::into_options)
+            } else {
+                get_arguments::<AttachArguments>(&request).map(AttachArguments::into_options)
+            };
+            match arguments {
                 Ok(arguments) => {
-                    self.debugger_options = DebuggerOptions { ..arguments };
+                    self.debugger_options = arguments;
                     debug_adapter.console_log_level = self
                         .debugger_options
                         .console_log_level
@@ -792,6 +1160,14 @@ impl Debugger {
                     self.debugger_options.qualify_and_update_program_binary(
                         self.debugger_options.program_binary.clone(),
                     );
+                    self.debugger_options
+                        .qualify_and_update_svd_file(self.debugger_options.svd_file.clone());
+                    self.debugger_options.qualify_and_update_flash_algorithm(
+                        self.debugger_options
+                            .flashing_options
+                            .flash_algorithm
+                            .clone(),
+                    );
                     match self.debugger_options.program_binary.clone() {
                         Some(program_binary) => {
                             if !program_binary.is_file() {
@@ -815,7 +1191,6 @@ impl Debugger {
                             return;
                         }
                     }
-                    debug_adapter.send_response::<()>(&request, Ok(None));
                 }
                 Err(error) => {
                     debug_adapter.send_response::<()>(
@@ -826,6 +1201,8 @@ impl Debugger {
                     return;
                 }
             };
+            //The launch or attach request is answered once the target is ready for debugging.
+            startup_request = request;
         } else {
             //DebugAdapterType::CommandLine
             //update the cwd and program_binary
@@ -833,6 +1210,14 @@ impl Debugger {
                 .validate_and_update_cwd(self.debugger_options.cwd.clone());
             self.debugger_options
                 .qualify_and_update_program_binary(self.debugger_options.program_binary.clone());
+            self.debugger_options
+                .qualify_and_update_svd_file(self.debugger_options.svd_file.clone());
+            self.debugger_options.qualify_and_update_flash_algorithm(
+                self.debugger_options
+                    .flashing_options
+                    .flash_algorithm
+                    .clone(),
+            );
             match self.debugger_options.program_binary.clone() {
                 Some(program_binary) => {
                     if !program_binary.is_file() {
@@ -861,78 +1246,111 @@ impl Debugger {
         let mut session_data = match start_session(&self.debugger_options) {
             Ok(session_data) => session_data,
             Err(error) => {
-                debug_adapter.send_response::<()>(
-                    &Request {
-                        arguments: None,
-                        command: "probe-rs::openProbe".to_owned(),
-                        seq: debug_adapter.peek_seq(),
-                        type_: "request".to_owned(),
-                    },
-                    Err(error),
-                );
+                debug_adapter.send_response::<()>(&startup_request, Err(error));
                 debug_adapter.send_event("exited", Some(ExitedEventBody { exit_code: 1 }));
                 return;
             }
         };
-        debug_adapter.halt_after_reset = self.debugger_options.halt_after_reset;
+        let flashing_options = self.debugger_options.flashing_options.clone();
+        // An attached core is left as it is. If it is halted, it stays halted after the `configurationDone` request.
+        debug_adapter.halt_after_reset =
+            flashing_options.halt_after_reset || startup_request.command == "attach";
 
         //Do the flashing
-        {
-            if self.debugger_options.flashing_enabled {
-                let path_to_elf = self.debugger_options.program_binary.clone().unwrap();
-                debug_adapter.log_to_console(format!(
-                    "FLASHING: Starting write of {:?} to device memory",
-                    &path_to_elf
-                ));
-
-                let mut download_options = DownloadOptions::default();
-
-                download_options.keep_unwritten_bytes =
-                    self.debugger_options.restore_unwritten_bytes;
-
-                download_options.do_chip_erase = self.debugger_options.full_chip_erase;
-
-                match download_file_with_options(
-                    &mut session_data.session,
-                    path_to_elf,
-                    Format::Elf,
-                    download_options,
-                ) {
-                    Ok(_) => {
-                        debug_adapter.log_to_console(format!(
-                            "FLASHING: Completed write of {:?} to device memory",
-                            &self.debugger_options.program_binary.clone().unwrap()
-                        ));
-                    }
-                    Err(error) => {
-                        debug_adapter.send_response::<()>(
-                            &custom_request,
-                            Err(DebuggerError::FileDownload(error)),
-                        );
-                        return;
-                    }
+        if flashing_options.flashing_enabled {
+            let path_to_elf = self.debugger_options.program_binary.clone().unwrap();
+            debug_adapter.log_to_console(format!(
+                "FLASHING: Starting write of {:?} to device memory",
+                &path_to_elf
+            ));
+            let progress_id = debug_adapter.start_progress(
+                &format!("Flashing {}", path_to_elf.display()),
+                Some(startup_request.seq),
+            );
+
+            //The progress handler has to own the debug adapter to send the progress events while flashing, so we share it until the flashing is done.
+            let rc_debug_adapter = Rc::new(RefCell::new(debug_adapter));
+            let progress_debug_adapter = rc_debug_adapter.clone();
+            let progress_event_id = progress_id.clone();
+            let flashing_progress = RefCell::new(FlashingProgress::default());
+            let progress = FlashProgress::new(move |event| {
+                if let Some((message, percentage)) = flashing_progress.borrow_mut().update(&event) {
+                    progress_debug_adapter.borrow_mut().update_progress(
+                        progress_event_id.as_deref(),
+                        Some(message),
+                        Some(percentage),
+                    );
+                }
+            });
+
+            let mut download_options = DownloadOptions::default();
+
+            download_options.progress = Some(&progress);
+
+            download_options.keep_unwritten_bytes = flashing_options.restore_unwritten_bytes;
+
+            download_options.do_chip_erase = flashing_options.full_chip_erase;
+
+            download_options.skip_unchanged_sectors = flashing_options.skip_unchanged_sectors;
+
+            let result = download_file_with_options(
+                &mut session_data.session,
+                &path_to_elf,
+                Format::Elf,
+                download_options,
+            );
+
+            drop(progress);
+            debug_adapter = match Rc::try_unwrap(rc_debug_adapter) {
+                Ok(debug_adapter) => debug_adapter.into_inner(),
+                Err(_) => {
+                    log::error!("The debug adapter is still in use after flashing");
+                    return;
+                }
+            };
+
+            match result {
+                Ok(_) => {
+                    debug_adapter
+                        .end_progress(progress_id.as_deref(), Some("Flashing complete".to_owned()));
+                    debug_adapter.log_to_console(format!(
+                        "FLASHING: Completed write of {:?} to device memory",
+                        &path_to_elf
+                    ));
+                }
+                Err(error) => {
+                    debug_adapter
+                        .end_progress(progress_id.as_deref(), Some("Flashing failed".to_owned()));
+                    debug_adapter.send_response::<()>(
+                        &startup_request,
+                        Err(DebuggerError::FileDownload(error)),
+                    );
+                    return;
                 }
             }
         }
 
         //This is the first attach to the requested core. If this one works, all subsequent ones will be no-op requests for a Core reference. Do NOT hold onto this reference for the duration of the session ... that is why this code is in a block of its own.
+        let reset = flashing_options.flashing_enabled && flashing_options.reset_after_flashing;
         {
             //First, attach to the core
-            let mut core_data = match attach_core(&mut session_data, &self.debugger_options) {
-                Ok(core_data) => core_data,
-                Err(error) => {
-                    debug_adapter.send_response::<()>(&custom_request, Err(error));
-                    return;
-                }
-            };
+            let mut core_data =
+                match attach_core(&mut session_data, self.debugger_options.core_index) {
+                    Ok(core_data) => core_data,
+                    Err(error) => {
+                        debug_adapter.send_response::<()>(&startup_request, Err(error));
+                        return;
+                    }
+                };
 
-            if self.debugger_options.flashing_enabled
-                && self.debugger_options.reset_after_flashing
-                && !debug_adapter.restart(&mut core_data, &custom_request)
-            {
+            //For the DapClient, the restart answers the launch request.
+            if reset && !debug_adapter.restart(&mut core_data, &startup_request) {
                 return;
             }
         }
+        if debug_adapter.adapter_type == DebugAdapterType::DapClient && !reset {
+            debug_adapter.send_response::<()>(&startup_request, Ok(None));
+        }
 
         //After flashing and forced setup, we can signal the client that are ready to receive incoming requests
         //Send Initalized event to client
@@ -984,7 +1402,7 @@ pub fn reset_target_of_device(
     _assert: Option<bool>,
 ) -> Result<()> {
     let mut session_data = start_session(&debugger_options)?;
-    attach_core(&mut session_data, &debugger_options)
+    attach_core(&mut session_data, debugger_options.core_index)
         .unwrap()
         .target_core
         .reset()?;
@@ -993,7 +1411,7 @@ pub fn reset_target_of_device(
 
 pub fn dump_memory(debugger_options: DebuggerOptions, loc: u32, words: u32) -> Result<()> {
     let mut session_data = start_session(&debugger_options)?;
-    let mut target_core = attach_core(&mut session_data, &debugger_options)
+    let mut target_core = attach_core(&mut session_data, debugger_options.core_index)
         .unwrap()
         .target_core;
 
@@ -1039,7 +1457,7 @@ pub fn trace_u32_on_target(debugger_options: DebuggerOptions, loc: u32) -> Resul
     let start = Instant::now();
 
     let mut session_data = start_session(&debugger_options)?;
-    let mut target_core = attach_core(&mut session_data, &debugger_options)
+    let mut target_core = attach_core(&mut session_data, debugger_options.core_index)
         .unwrap()
         .target_core;
 
@@ -1072,10 +1490,13 @@ pub fn trace_u32_on_target(debugger_options: DebuggerOptions, loc: u32) -> Resul
     }
 }
 
-pub fn debug(debugger_options: DebuggerOptions, dap: bool) {
+pub fn debug(debugger_options: DebuggerOptions, flashing_options: FlashingOptions, dap: bool) {
     let program_name = structopt::clap::crate_name!();
 
-    let mut debugger = Debugger::new(debugger_options);
+    let mut debugger = Debugger::new(DebuggerOptions {
+        flashing_options,
+        ..debugger_options
+    });
 
     if !dap {
         println!(
@@ -1138,3 +1559,105 @@ pub fn debug(debugger_options: DebuggerOptions, dap: bool) {
         };
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request_arguments() -> serde_json::Value {
+        serde_json::json!({
+            "program_binary": "target/thumbv7em-none-eabihf/debug/app",
+            "chip": "STM32H745ZITx",
+            "core_index": 1,
+            "connect_under_reset": true,
+            "flashing_enabled": true,
+            "reset_after_flashing": true,
+            "halt_after_reset": true,
+            "skip_unchanged_sectors": true,
+            "flash_algorithm": "STM32H7x_2048.FLM",
+        })
+    }
+
+    #[test]
+    fn launch_arguments() {
+        let arguments: LaunchArguments = serde_json::from_value(request_arguments()).unwrap();
+        let options = arguments.into_options();
+
+        assert_eq!(options.chip.as_deref(), Some("STM32H745ZITx"));
+        assert_eq!(options.core_index, 1);
+        assert!(options.connect_under_reset);
+
+        let flashing_options = &options.flashing_options;
+        assert!(flashing_options.flashing_enabled);
+        assert!(flashing_options.reset_after_flashing);
+        assert!(flashing_options.halt_after_reset);
+        assert!(!flashing_options.full_chip_erase);
+        assert!(!flashing_options.restore_unwritten_bytes);
+        assert!(flashing_options.skip_unchanged_sectors);
+        assert_eq!(
+            flashing_options.flash_algorithm,
+            Some(PathBuf::from("STM32H7x_2048.FLM"))
+        );
+    }
+
+    #[test]
+    fn attach_arguments() {
+        let arguments: AttachArguments = serde_json::from_value(request_arguments()).unwrap();
+        let options = arguments.into_options();
+
+        assert_eq!(options.chip.as_deref(), Some("STM32H745ZITx"));
+        assert!(!options.connect_under_reset);
+        assert!(!options.flashing_options.flashing_enabled);
+        assert_eq!(options.flashing_options.flash_algorithm, None);
+    }
+
+    #[test]
+    fn flashing_progress_of_all_regions() {
+        let mut progress = FlashingProgress::default();
+        let erased = ProgressEvent::SectorErased {
+            size: 0x100,
+            time: Duration::from_millis(1),
+        };
+
+        // The pages are not filled unless the unwritten bytes are restored, the phase completes without progress events.
+        progress.initialize(0x80, 0x200, 0x200);
+        assert_eq!(
+            progress.update(&ProgressEvent::StartedFilling),
+            Some(("Reading the flash contents to restore".to_owned(), 0.0))
+        );
+        assert_eq!(
+            progress.update(&ProgressEvent::FinishedFilling),
+            Some(("Reading the flash contents to restore".to_owned(), 100.0))
+        );
+
+        assert_eq!(
+            progress.update(&ProgressEvent::StartedErasing),
+            Some(("Erasing sectors".to_owned(), 0.0))
+        );
+        assert_eq!(
+            progress.update(&erased),
+            Some(("Erasing sectors".to_owned(), 50.0))
+        );
+        assert_eq!(
+            progress.update(&erased),
+            Some(("Erasing sectors".to_owned(), 100.0))
+        );
+        assert_eq!(progress.update(&ProgressEvent::FinishedErasing), None);
+
+        // The percentage of the second region never drops while a phase is running.
+        progress.initialize(0, 0x600, 0x600);
+        assert_eq!(
+            progress.update(&ProgressEvent::StartedErasing),
+            Some(("Erasing sectors".to_owned(), 0.0))
+        );
+        let mut percentage = 0.0;
+        for _ in 0..6 {
+            let (_, new_percentage) = progress.update(&erased).unwrap();
+            assert!(new_percentage > percentage);
+            percentage = new_percentage;
+        }
+        assert_eq!(percentage, 100.0);
+        assert_eq!(progress.update(&erased), None);
+        assert_eq!(progress.update(&ProgressEvent::FinishedErasing), None);
+    }
+}
@@ -0,0 +1,323 @@
+//! Disassembly of the code on the target, for the `disassemble` request.
+//!
+//! ARM cores are disassembled as Thumb code, and RISC-V cores as RV32 code with the compressed
+//! instructions, with capstone. Bytes which are not a valid instruction are shown as raw data.
+
+use crate::DebuggerError;
+use anyhow::anyhow;
+use capstone::{
+    arch::{arm, riscv},
+    prelude::*,
+    Capstone, Endian,
+};
+use probe_rs::{Architecture, Core, MemoryInterface};
+
+/// The longest instruction of all supported architectures, in bytes.
+const MAX_INSTRUCTION_SIZE: u64 = 4;
+
+/// The shortest instruction of all supported architectures, in bytes. Instructions are aligned to
+/// this size.
+const MIN_INSTRUCTION_SIZE: u64 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Instruction {
+    pub(crate) address: u64,
+    /// The encoding of the instruction, empty if the memory could not be read.
+    pub(crate) bytes: Vec<u8>,
+    pub(crate) text: String,
+}
+
+impl Instruction {
+    /// An instruction at an address where the memory could not be read.
+    fn unavailable(address: u64) -> Self {
+        Instruction {
+            address,
+            bytes: Vec::new(),
+            text: "<unavailable>".to_string(),
+        }
+    }
+
+    /// Bytes which are not a valid instruction.
+    fn data(address: u64, bytes: &[u8]) -> Self {
+        let text = match *bytes {
+            [low, high] => format!(".short 0x{:04x}", u16::from_le_bytes([low, high])),
+            [a, b, c, d] => format!(".word 0x{:08x}", u32::from_le_bytes([a, b, c, d])),
+            _ => format!(".byte {:02x?}", bytes),
+        };
+
+        Instruction {
+            address,
+            bytes: bytes.to_vec(),
+            text,
+        }
+    }
+}
+
+pub(crate) struct Disassembler {
+    capstone: Capstone,
+    architecture: Architecture,
+}
+
+impl Disassembler {
+    pub(crate) fn new(architecture: Architecture) -> Result<Self, DebuggerError> {
+        let capstone = match architecture {
+            Architecture::Arm => Capstone::new()
+                .arm()
+                .mode(arm::ArchMode::Thumb)
+                .endian(Endian::Little)
+                .build(),
+            Architecture::Riscv => Capstone::new()
+                .riscv()
+                .mode(riscv::ArchMode::RiscV32)
+                .extra_mode(std::iter::once(riscv::ArchExtraMode::RiscVC))
+                .build(),
+        }
+        .map_err(|error| DebuggerError::Other(anyhow!("Error creating capstone: {:?}", error)))?;
+
+        Ok(Disassembler {
+            capstone,
+            architecture,
+        })
+    }
+
+    /// Decode all instructions in `code`, which is located at `address`.
+    pub(crate) fn decode(&self, code: &[u8], address: u64) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+
+        while offset < code.len() {
+            let instruction_address = address + offset as u64;
+            let remaining = &code[offset..];
+
+            let instruction = self
+                .capstone
+                .disasm_count(remaining, instruction_address, 1)
+                .ok()
+                .and_then(|decoded| {
+                    decoded.iter().next().map(|decoded| Instruction {
+                        address: instruction_address,
+                        bytes: decoded.bytes().to_vec(),
+                        text: format!(
+                            "{} {}",
+                            decoded.mnemonic().unwrap_or_default(),
+                            decoded.op_str().unwrap_or_default()
+                        )
+                        .trim()
+                        .to_string(),
+                    })
+                });
+
+            let instruction = instruction.unwrap_or_else(|| {
+                let length = match self.architecture {
+                    Architecture::Arm => MIN_INSTRUCTION_SIZE as usize,
+                    Architecture::Riscv => riscv_instruction_length(remaining),
+                };
+                let length = remaining.len().min(length);
+                Instruction::data(instruction_address, &remaining[..length])
+            });
+
+            offset += instruction.bytes.len();
+            instructions.push(instruction);
+        }
+
+        instructions
+    }
+}
+
+/// The length of a RISC-V instruction in bytes. All instructions, except for the compressed
+/// instructions, have the two lowest bits set.
+fn riscv_instruction_length(code: &[u8]) -> usize {
+    match code.first() {
+        Some(first) if first & 0b11 == 0b11 => 4,
+        _ => 2,
+    }
+}
+
+/// Disassemble `instruction_count` instructions, starting `instruction_offset` instructions
+/// before or after the instruction at `address`.
+///
+/// The code before `address` is decoded separately, so that the instruction at `address` is
+/// always decoded correctly, even if decoding the code before it gets out of step with the
+/// actual instructions.
+pub(crate) fn disassemble(
+    core: &mut Core,
+    address: u64,
+    instruction_offset: i64,
+    instruction_count: usize,
+) -> Result<Vec<Instruction>, DebuggerError> {
+    let disassembler = Disassembler::new(core.architecture())?;
+
+    let address = address - address % MIN_INSTRUCTION_SIZE;
+    let instructions_before = instruction_offset.min(0).unsigned_abs();
+    let instructions_after = instruction_offset.max(0) as u64 + instruction_count as u64;
+
+    let start = address.saturating_sub(instructions_before * MAX_INSTRUCTION_SIZE);
+    let end = address + instructions_after * MAX_INSTRUCTION_SIZE;
+
+    let mut before = read_memory(core, start, address)
+        .map(|code| disassembler.decode(&code, start))
+        .unwrap_or_default();
+
+    // The code after `address` is read up to the first address which can't be read, e.g. at the
+    // end of a memory region. The instructions after it are shown as unavailable.
+    let after = match read_readable_memory(core, address, end) {
+        code if code.is_empty() => vec![Instruction::unavailable(address)],
+        code => disassembler.decode(&code, address),
+    };
+
+    let position = before.len() as i64 + instruction_offset;
+
+    before.extend(after);
+
+    Ok(select_instructions(&before, position, instruction_count))
+}
+
+/// Select `count` instructions starting at `position`, which can be outside of the decoded
+/// instructions. Missing instructions are filled with placeholders.
+fn select_instructions(
+    instructions: &[Instruction],
+    position: i64,
+    count: usize,
+) -> Vec<Instruction> {
+    let first_address = instructions
+        .first()
+        .map(|instruction| instruction.address)
+        .unwrap_or_default();
+    let last = instructions.last();
+
+    (position..position + count as i64)
+        .map(|index| {
+            if index < 0 {
+                let distance = index.unsigned_abs() * MIN_INSTRUCTION_SIZE;
+                Instruction::unavailable(first_address.saturating_sub(distance))
+            } else if let Some(instruction) = instructions.get(index as usize) {
+                instruction.clone()
+            } else {
+                let distance =
+                    (index as u64 + 1 - instructions.len() as u64) * MIN_INSTRUCTION_SIZE;
+                // Unavailable instructions take up the minimum instruction size.
+                let end = last
+                    .map(|last| last.address + (last.bytes.len() as u64).max(MIN_INSTRUCTION_SIZE))
+                    .unwrap_or_default();
+                Instruction::unavailable(end + distance - MIN_INSTRUCTION_SIZE)
+            }
+        })
+        .collect()
+}
+
+fn read_memory(core: &mut Core, start: u64, end: u64) -> Result<Vec<u8>, DebuggerError> {
+    let mut code = vec![0u8; (end - start) as usize];
+
+    core.read_8(start as u32, &mut code)?;
+
+    Ok(code)
+}
+
+/// Read the memory from `start` to `end`, or as much of it as can be read from `start` on.
+fn read_readable_memory(core: &mut Core, start: u64, end: u64) -> Vec<u8> {
+    if let Ok(code) = read_memory(core, start, end) {
+        return code;
+    }
+
+    let mut code = Vec::new();
+    let mut chunk_start = start;
+    while chunk_start < end {
+        let chunk_end = end.min(chunk_start + MAX_INSTRUCTION_SIZE);
+        match read_memory(core, chunk_start, chunk_end) {
+            Ok(chunk) => code.extend(chunk),
+            Err(_) => break,
+        }
+        chunk_start = chunk_end;
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_thumb() {
+        let disassembler = Disassembler::new(Architecture::Arm).unwrap();
+
+        // nop, bl, bx lr, and a truncated 32 bit instruction
+        let instructions = disassembler.decode(
+            &[0x00, 0xbf, 0x00, 0xf0, 0x02, 0xf8, 0x70, 0x47, 0x00, 0xf0],
+            0x100,
+        );
+
+        let texts: Vec<_> = instructions
+            .iter()
+            .map(|instruction| (instruction.address, instruction.text.as_str()))
+            .collect();
+
+        assert_eq!(
+            texts,
+            vec![
+                (0x100, "nop"),
+                (0x102, "bl #0x10a"),
+                (0x106, "bx lr"),
+                (0x108, ".short 0xf000"),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_riscv() {
+        let disassembler = Disassembler::new(Architecture::Riscv).unwrap();
+
+        // c.nop, addi x0, x0, 0 (nop), and an invalid 32 bit instruction
+        let instructions = disassembler.decode(
+            &[0x01, 0x00, 0x13, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff],
+            0x0,
+        );
+
+        let texts: Vec<_> = instructions
+            .iter()
+            .map(|instruction| (instruction.address, instruction.text.as_str()))
+            .collect();
+
+        assert_eq!(
+            texts,
+            vec![(0x0, "c.nop"), (0x2, "nop"), (0x6, ".word 0xffffffff")]
+        );
+    }
+
+    #[test]
+    fn select_with_placeholders() {
+        let instructions = vec![
+            Instruction::data(0x100, &[0, 0]),
+            Instruction::data(0x102, &[0, 0, 0, 0]),
+        ];
+
+        let selected = select_instructions(&instructions, -1, 4);
+
+        let addresses: Vec<_> = selected
+            .iter()
+            .map(|instruction| instruction.address)
+            .collect();
+        assert_eq!(addresses, vec![0xfe, 0x100, 0x102, 0x106]);
+        assert!(selected[0].bytes.is_empty());
+        assert!(selected[3].bytes.is_empty());
+    }
+
+    #[test]
+    fn select_after_unavailable() {
+        let instructions = vec![
+            Instruction::data(0x100, &[0, 0]),
+            Instruction::unavailable(0x102),
+        ];
+
+        let selected = select_instructions(&instructions, 0, 4);
+
+        let addresses: Vec<_> = selected
+            .iter()
+            .map(|instruction| instruction.address)
+            .collect();
+        assert_eq!(addresses, vec![0x100, 0x102, 0x104, 0x106]);
+        assert!(selected[1..]
+            .iter()
+            .all(|instruction| instruction.text == "<unavailable>"));
+    }
+}
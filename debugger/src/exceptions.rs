@@ -0,0 +1,316 @@
+//! Exception breakpoints for Cortex-M cores, and the decoding of their fault status registers.
+//!
+//! The exception breakpoints use the vector catch of the core, which halts the core when it
+//! takes one of the selected exceptions. The vector catch is configured with the `VC_*` bits of
+//! the DEMCR register.
+
+use crate::dap_types::ExceptionBreakpointsFilter;
+use crate::DebuggerError;
+use anyhow::anyhow;
+use probe_rs::{architecture::arm::m4::Demcr, Architecture, Core, CoreRegister, MemoryInterface};
+
+/// CPUID Base Register
+const CPUID: u32 = 0xE000_ED00;
+/// Configurable Fault Status Register
+const CFSR: u32 = 0xE000_ED28;
+/// HardFault Status Register
+const HFSR: u32 = 0xE000_ED2C;
+/// MemManage Fault Address Register
+const MMFAR: u32 = 0xE000_ED34;
+/// BusFault Address Register
+const BFAR: u32 = 0xE000_ED38;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum ExceptionFilter {
+    HardFault,
+    MemManage,
+    BusFault,
+    UsageFault,
+    Reset,
+}
+
+impl ExceptionFilter {
+    pub(crate) const ALL: [ExceptionFilter; 5] = [
+        ExceptionFilter::HardFault,
+        ExceptionFilter::MemManage,
+        ExceptionFilter::BusFault,
+        ExceptionFilter::UsageFault,
+        ExceptionFilter::Reset,
+    ];
+
+    /// The id of the filter in the DAP `exceptionBreakpointFilters` capability.
+    pub(crate) fn id(self) -> &'static str {
+        match self {
+            ExceptionFilter::HardFault => "hard_fault",
+            ExceptionFilter::MemManage => "mem_manage",
+            ExceptionFilter::BusFault => "bus_fault",
+            ExceptionFilter::UsageFault => "usage_fault",
+            ExceptionFilter::Reset => "reset",
+        }
+    }
+
+    pub(crate) fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|filter| filter.id() == id)
+    }
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            ExceptionFilter::HardFault => "HardFault",
+            ExceptionFilter::MemManage => "MemManage fault",
+            ExceptionFilter::BusFault => "BusFault",
+            ExceptionFilter::UsageFault => "UsageFault",
+            ExceptionFilter::Reset => "Reset",
+        }
+    }
+
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            ExceptionFilter::HardFault => {
+                "Halt on a HardFault, or a fault during exception entry or return"
+            }
+            ExceptionFilter::MemManage => "Halt on a MemManage fault (ARMv7-M and later)",
+            ExceptionFilter::BusFault => "Halt on a BusFault (ARMv7-M and later)",
+            ExceptionFilter::UsageFault => "Halt on a UsageFault (ARMv7-M and later)",
+            ExceptionFilter::Reset => "Halt at the reset vector, after a reset of the core",
+        }
+    }
+
+    /// ARMv6-M cores, like the Cortex-M0, only have the vector catch of HardFaults and resets.
+    fn is_supported(self, armv6m: bool) -> bool {
+        !armv6m || self == ExceptionFilter::HardFault || self == ExceptionFilter::Reset
+    }
+
+    /// Set or clear the vector catch bits of the exception.
+    fn set_vector_catch(self, demcr: &mut Demcr, enabled: bool) {
+        match self {
+            ExceptionFilter::HardFault => {
+                demcr.set_vc_harderr(enabled);
+                demcr.set_vc_interr(enabled);
+            }
+            ExceptionFilter::MemManage => demcr.set_vc_mmerr(enabled),
+            ExceptionFilter::BusFault => demcr.set_vc_buserr(enabled),
+            ExceptionFilter::UsageFault => {
+                demcr.set_vc_staterr(enabled);
+                demcr.set_vc_chkerr(enabled);
+                demcr.set_vc_nocperr(enabled);
+            }
+            ExceptionFilter::Reset => demcr.set_vc_corereset(enabled),
+        }
+    }
+}
+
+/// The exception filters for the `exceptionBreakpointFilters` capability. None of them are
+/// enabled by default, so the core behaves as without a debugger until the user selects one.
+pub(crate) fn exception_breakpoint_filters() -> Vec<ExceptionBreakpointsFilter> {
+    ExceptionFilter::ALL
+        .iter()
+        .map(|filter| ExceptionBreakpointsFilter {
+            filter: filter.id().to_string(),
+            label: filter.label().to_string(),
+            description: Some(filter.description().to_string()),
+            default: Some(false),
+            supports_condition: None,
+            condition_description: None,
+        })
+        .collect()
+}
+
+/// The exception filters which the vector catch of the core supports.
+pub(crate) fn supported_filters(core: &mut Core) -> Result<Vec<ExceptionFilter>, DebuggerError> {
+    if core.architecture() != Architecture::Arm {
+        return Err(DebuggerError::Other(anyhow!(
+            "Exception breakpoints are only supported on ARM cores"
+        )));
+    }
+
+    let armv6m = is_armv6m(core.read_word_32(CPUID)?);
+
+    Ok(ExceptionFilter::ALL
+        .iter()
+        .copied()
+        .filter(|filter| filter.is_supported(armv6m))
+        .collect())
+}
+
+/// The architecture field of the CPUID register is `0xC` for ARMv6-M, and `0xF` for ARMv7-M and ARMv8-M.
+fn is_armv6m(cpuid: u32) -> bool {
+    (cpuid >> 16) & 0xf == 0xc
+}
+
+/// Enable the vector catch for the given exceptions, and disable it for all others.
+pub(crate) fn set_vector_catch(
+    core: &mut Core,
+    filters: &[ExceptionFilter],
+) -> Result<(), DebuggerError> {
+    if core.architecture() != Architecture::Arm {
+        return Err(DebuggerError::Other(anyhow!(
+            "Exception breakpoints are only supported on ARM cores"
+        )));
+    }
+
+    let mut demcr = Demcr(core.read_word_32(Demcr::ADDRESS)?);
+    for filter in ExceptionFilter::ALL.iter() {
+        filter.set_vector_catch(&mut demcr, filters.contains(filter));
+    }
+    core.write_word_32(Demcr::ADDRESS, demcr.into())?;
+
+    Ok(())
+}
+
+/// Read the fault status registers of a core which halted on an exception, and explain the fault.
+/// Returns `None` if no fault is recorded, e.g. after a halt at the reset vector.
+pub(crate) fn describe_fault(core: &mut Core) -> Option<String> {
+    if core.architecture() != Architecture::Arm {
+        return None;
+    }
+
+    let cfsr = core.read_word_32(CFSR).ok()?;
+    let hfsr = core.read_word_32(HFSR).ok()?;
+    // The fault address registers are only read when they are valid, as some cores share them.
+    let mmfar = if cfsr & (1 << 7) != 0 {
+        core.read_word_32(MMFAR).ok()
+    } else {
+        None
+    };
+    let bfar = if cfsr & (1 << 15) != 0 {
+        core.read_word_32(BFAR).ok()
+    } else {
+        None
+    };
+
+    let faults = decode_fault_status(cfsr, hfsr, mmfar, bfar);
+    if faults.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "{} (CFSR: 0x{:08x}, HFSR: 0x{:08x})",
+            faults.join(", "),
+            cfsr,
+            hfsr
+        ))
+    }
+}
+
+/// Explain the bits which are set in the CFSR and HFSR, with the fault addresses from MMFAR and BFAR.
+fn decode_fault_status(cfsr: u32, hfsr: u32, mmfar: Option<u32>, bfar: Option<u32>) -> Vec<String> {
+    const HFSR_BITS: [(u32, &str); 3] = [
+        (1, "HardFault: vector table read error"),
+        (30, "HardFault: escalated from a configurable fault"),
+        (31, "HardFault: debug event"),
+    ];
+    const CFSR_BITS: [(u32, &str); 17] = [
+        // MemManage Fault Status Register
+        (0, "MemManage: instruction access violation"),
+        (1, "MemManage: data access violation"),
+        (
+            3,
+            "MemManage: fault on unstacking for a return from exception",
+        ),
+        (4, "MemManage: fault on stacking for exception entry"),
+        (
+            5,
+            "MemManage: fault during floating-point lazy state preservation",
+        ),
+        // BusFault Status Register
+        (8, "BusFault: instruction bus error"),
+        (9, "BusFault: precise data bus error"),
+        (10, "BusFault: imprecise data bus error"),
+        (
+            11,
+            "BusFault: fault on unstacking for a return from exception",
+        ),
+        (12, "BusFault: fault on stacking for exception entry"),
+        (
+            13,
+            "BusFault: fault during floating-point lazy state preservation",
+        ),
+        // UsageFault Status Register
+        (16, "UsageFault: undefined instruction"),
+        (
+            17,
+            "UsageFault: invalid state, e.g. an attempt to switch to ARM state",
+        ),
+        (18, "UsageFault: invalid PC load on exception return"),
+        (19, "UsageFault: no coprocessor"),
+        (24, "UsageFault: unaligned access"),
+        (25, "UsageFault: divide by zero"),
+    ];
+
+    let mut faults: Vec<String> = HFSR_BITS
+        .iter()
+        .filter(|(bit, _)| hfsr & (1 << bit) != 0)
+        .map(|(_, fault)| fault.to_string())
+        .collect();
+
+    for (bit, fault) in CFSR_BITS.iter() {
+        if cfsr & (1 << bit) != 0 {
+            let address = match bit {
+                1 => mmfar,
+                9 => bfar,
+                _ => None,
+            };
+            faults.push(match address {
+                Some(address) => format!("{} at address 0x{:08x}", fault, address),
+                None => fault.to_string(),
+            });
+        }
+    }
+
+    faults
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_escalated_bus_fault() {
+        let faults = decode_fault_status(0x0000_8200, 0x4000_0000, None, Some(0x2004_0000));
+
+        assert_eq!(
+            faults,
+            vec![
+                "HardFault: escalated from a configurable fault",
+                "BusFault: precise data bus error at address 0x20040000",
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_usage_fault() {
+        let faults = decode_fault_status(0x0200_0000, 0, None, None);
+
+        assert_eq!(faults, vec!["UsageFault: divide by zero"]);
+    }
+
+    #[test]
+    fn filter_vector_catch_bits() {
+        let mut demcr = Demcr(0x0100_0000);
+        ExceptionFilter::UsageFault.set_vector_catch(&mut demcr, true);
+        ExceptionFilter::Reset.set_vector_catch(&mut demcr, true);
+
+        assert_eq!(u32::from(demcr), 0x0100_00e1);
+        assert_eq!(
+            ExceptionFilter::from_id("usage_fault"),
+            Some(ExceptionFilter::UsageFault)
+        );
+    }
+
+    #[test]
+    fn armv6m_filters() {
+        // Cortex-M0+ and Cortex-M4
+        assert!(is_armv6m(0x410c_c601));
+        assert!(!is_armv6m(0x410f_c241));
+
+        let armv6m_filters: Vec<_> = ExceptionFilter::ALL
+            .iter()
+            .copied()
+            .filter(|filter| filter.is_supported(true))
+            .collect();
+        assert_eq!(
+            armv6m_filters,
+            vec![ExceptionFilter::HardFault, ExceptionFilter::Reset]
+        );
+        assert!(ExceptionFilter::UsageFault.is_supported(false));
+    }
+}
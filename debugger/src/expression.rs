@@ -0,0 +1,1147 @@
+//! A small expression language, used for the conditions of breakpoints and for the `evaluate`
+//! request of the debug console and watch expressions.
+//!
+//! Expressions consist of integer literals, names of variables and CPU registers, and operators
+//! with the precedence they have in Rust:
+//!
+//! - field access `config.baud` (or `pair.0` for tuples), indexing `buffer[3]`,
+//! - the unary operators `-`, `!`, dereference `*pointer` and address-of `&variable`,
+//! - casts `0x2000_0000 as *const u32`, to a base type or to any type of the debug information,
+//! - arithmetic `*`, `/`, `%`, `+`, `-`, shifts `<<`, `>>`, and bitwise `&`, `^`, `|`,
+//! - comparisons `==`, `!=`, `<`, `<=`, `>`, `>=`, and the logical operators `&&` and `||`,
+//!
+//! e.g. `counter > 10 && $r0 == 0x20`. Names prefixed with `$` always refer to a register, other
+//! names are first looked up in the variables of the current stack frame, then in the statics,
+//! and then in the registers. Statics can also be qualified with their module, e.g.
+//! `blinky::COUNTER`.
+//!
+//! Conditions are true if they evaluate to a value other than zero.
+
+use crate::{debugger::CoreData, DebuggerError};
+use anyhow::anyhow;
+use parse_int::parse;
+use probe_rs::{
+    debug::{Registers, Variable, VariableKind},
+    CoreRegisterAddress,
+};
+use std::{convert::TryFrom, fmt, iter::Peekable, str::Chars};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UnaryOperator {
+    Not,
+    Negate,
+    Dereference,
+    AddressOf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl BinaryOperator {
+    /// Operators with a higher precedence bind more tightly.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::Less
+            | BinaryOperator::LessOrEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterOrEqual => 3,
+            BinaryOperator::BitOr => 4,
+            BinaryOperator::BitXor => 5,
+            BinaryOperator::BitAnd => 6,
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => 7,
+            BinaryOperator::Add | BinaryOperator::Subtract => 8,
+            BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Remainder => 9,
+        }
+    }
+
+    fn apply(self, left: i128, right: i128) -> Result<i128, DebuggerError> {
+        let result = match self {
+            BinaryOperator::Or => (left != 0 || right != 0) as i128,
+            BinaryOperator::And => (left != 0 && right != 0) as i128,
+            BinaryOperator::Equal => (left == right) as i128,
+            BinaryOperator::NotEqual => (left != right) as i128,
+            BinaryOperator::Less => (left < right) as i128,
+            BinaryOperator::LessOrEqual => (left <= right) as i128,
+            BinaryOperator::Greater => (left > right) as i128,
+            BinaryOperator::GreaterOrEqual => (left >= right) as i128,
+            BinaryOperator::BitOr => left | right,
+            BinaryOperator::BitXor => left ^ right,
+            BinaryOperator::BitAnd => left & right,
+            BinaryOperator::ShiftLeft => u32::try_from(right)
+                .ok()
+                .and_then(|right| left.checked_shl(right))
+                .ok_or_else(|| overflow(self))?,
+            BinaryOperator::ShiftRight => u32::try_from(right)
+                .ok()
+                .and_then(|right| left.checked_shr(right))
+                .ok_or_else(|| overflow(self))?,
+            BinaryOperator::Add => left.checked_add(right).ok_or_else(|| overflow(self))?,
+            BinaryOperator::Subtract => left.checked_sub(right).ok_or_else(|| overflow(self))?,
+            BinaryOperator::Multiply => left.checked_mul(right).ok_or_else(|| overflow(self))?,
+            BinaryOperator::Divide => left
+                .checked_div(right)
+                .ok_or_else(|| DebuggerError::Other(anyhow!("Division by zero")))?,
+            BinaryOperator::Remainder => left
+                .checked_rem(right)
+                .ok_or_else(|| DebuggerError::Other(anyhow!("Division by zero")))?,
+        };
+
+        Ok(result)
+    }
+
+    fn apply_float(self, left: f64, right: f64) -> Result<Value, DebuggerError> {
+        let result = match self {
+            BinaryOperator::Equal => Value::Integer((left == right) as i128),
+            BinaryOperator::NotEqual => Value::Integer((left != right) as i128),
+            BinaryOperator::Less => Value::Integer((left < right) as i128),
+            BinaryOperator::LessOrEqual => Value::Integer((left <= right) as i128),
+            BinaryOperator::Greater => Value::Integer((left > right) as i128),
+            BinaryOperator::GreaterOrEqual => Value::Integer((left >= right) as i128),
+            BinaryOperator::Add => Value::Float(left + right),
+            BinaryOperator::Subtract => Value::Float(left - right),
+            BinaryOperator::Multiply => Value::Float(left * right),
+            BinaryOperator::Divide => Value::Float(left / right),
+            BinaryOperator::Remainder => Value::Float(left % right),
+            other => {
+                return Err(DebuggerError::Other(anyhow!(
+                    "The operator {:?} can't be applied to floating point values",
+                    other
+                )))
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+fn overflow(operator: BinaryOperator) -> DebuggerError {
+    DebuggerError::Other(anyhow!("Overflow in operation {:?}", operator))
+}
+
+/// The type of a cast, e.g. `u32` or `*const Config`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CastType {
+    Value(String),
+    Pointer(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Integer(i128),
+    Name(String),
+    Unary(UnaryOperator),
+    Binary(BinaryOperator),
+    OpenParenthesis,
+    CloseParenthesis,
+    OpenBracket,
+    CloseBracket,
+    Dot,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expression {
+    Integer(i128),
+    /// The name of a variable, or of a register if it starts with `$`.
+    Name(String),
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Field(Box<Expression>, String),
+    Index(Box<Expression>, Box<Expression>),
+    Cast(Box<Expression>, CastType),
+}
+
+/// The result of evaluating an expression.
+#[derive(Debug, Clone)]
+pub(crate) enum Value {
+    Integer(i128),
+    Float(f64),
+    /// A variable of the target, which can also have a structured type.
+    Variable(Variable),
+    /// The result of a cast to a pointer type, which can be dereferenced.
+    Pointer {
+        address: u64,
+        type_name: String,
+    },
+}
+
+impl Value {
+    /// Convert the value to an integer, for use in arithmetic and conditions.
+    pub(crate) fn as_integer(&self) -> Result<i128, DebuggerError> {
+        match self {
+            Value::Integer(value) => Ok(*value),
+            Value::Float(value) => Ok(*value as i128),
+            Value::Pointer { address, .. } => Ok(i128::from(*address)),
+            Value::Variable(variable) => match variable_value(variable)? {
+                Value::Float(value) => Ok(value as i128),
+                value => value.as_integer(),
+            },
+        }
+    }
+
+    /// Integers and floats of variables, for arithmetic. Pointers are converted to their address.
+    fn as_number(&self) -> Result<Value, DebuggerError> {
+        match self {
+            Value::Variable(variable) => variable_value(variable),
+            Value::Pointer { address, .. } => Ok(Value::Integer(i128::from(*address))),
+            other => Ok(other.clone()),
+        }
+    }
+
+    fn as_float(&self) -> Result<f64, DebuggerError> {
+        match self.as_number()? {
+            Value::Float(value) => Ok(value),
+            other => Ok(other.as_integer()? as f64),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::Variable(variable) => write!(f, "{}", variable.get_value()),
+            Value::Pointer { address, .. } => write!(f, "0x{:08x}", address),
+        }
+    }
+}
+
+/// Resolves the names and memory accesses of an expression.
+pub(crate) trait Scope {
+    /// Get the value of a variable or register.
+    fn resolve(&mut self, name: &str) -> Result<Value, DebuggerError>;
+
+    /// Read a value of the named type from memory.
+    fn read(&mut self, type_name: &str, address: u64) -> Result<Value, DebuggerError>;
+}
+
+impl Expression {
+    pub(crate) fn parse(input: &str) -> Result<Expression, DebuggerError> {
+        let tokens = tokenize(input)?;
+        let mut tokens = tokens.into_iter().peekable();
+
+        let expression = parse_binary(&mut tokens, 0)?;
+
+        match tokens.next() {
+            None => Ok(expression),
+            Some(token) => Err(DebuggerError::Other(anyhow!(
+                "Unexpected {:?} in expression '{}'",
+                token,
+                input
+            ))),
+        }
+    }
+
+    /// Evaluate the expression, using `scope` to get the values of names.
+    pub(crate) fn evaluate(&self, scope: &mut dyn Scope) -> Result<Value, DebuggerError> {
+        match self {
+            Expression::Integer(value) => Ok(Value::Integer(*value)),
+            Expression::Name(name) => scope.resolve(name),
+            Expression::Unary(operator, operand) => {
+                let operand = operand.evaluate(scope)?;
+                evaluate_unary(*operator, operand, scope)
+            }
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(scope)?;
+
+                // Short-circuit, so that the right side can guard against names which can't be resolved.
+                match operator {
+                    BinaryOperator::And if left.as_integer()? == 0 => Ok(Value::Integer(0)),
+                    BinaryOperator::Or if left.as_integer()? != 0 => Ok(Value::Integer(1)),
+                    BinaryOperator::And | BinaryOperator::Or => Ok(Value::Integer(
+                        (right.evaluate(scope)?.as_integer()? != 0) as i128,
+                    )),
+                    _ => {
+                        let left = left.as_number()?;
+                        let right = right.evaluate(scope)?.as_number()?;
+
+                        match (&left, &right) {
+                            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                                operator.apply_float(left.as_float()?, right.as_float()?)
+                            }
+                            _ => Ok(Value::Integer(
+                                operator.apply(left.as_integer()?, right.as_integer()?)?,
+                            )),
+                        }
+                    }
+                }
+            }
+            Expression::Field(operand, field) => match operand.evaluate(scope)? {
+                Value::Variable(variable) => child(&variable, field),
+                other => Err(DebuggerError::Other(anyhow!(
+                    "The value {} has no field '{}'",
+                    other,
+                    field
+                ))),
+            },
+            Expression::Index(operand, index) => {
+                let operand = operand.evaluate(scope)?;
+                let index = index.evaluate(scope)?.as_integer()?;
+
+                match operand {
+                    Value::Variable(variable) => child(&variable, &format!("__{}", index)),
+                    Value::Pointer { address, type_name } => {
+                        let element = scope.read(&type_name, address)?;
+                        let element_size = match &element {
+                            Value::Variable(variable) => variable.byte_size,
+                            _ => 0,
+                        };
+
+                        i64::try_from(index)
+                            .ok()
+                            .and_then(|index| {
+                                address.checked_add((index as u64).wrapping_mul(element_size))
+                            })
+                            .ok_or_else(|| {
+                                DebuggerError::Other(anyhow!("Index {} is out of range", index))
+                            })
+                            .and_then(|address| scope.read(&type_name, address))
+                    }
+                    other => Err(DebuggerError::Other(anyhow!(
+                        "The value {} can't be indexed",
+                        other
+                    ))),
+                }
+            }
+            Expression::Cast(operand, cast_type) => {
+                let operand = operand.evaluate(scope)?;
+                cast(operand, cast_type, scope)
+            }
+        }
+    }
+
+    /// Evaluate the expression in the context of the halted core, using the variables of the stack
+    /// frame at the given position in the stack trace, or of the top stack frame if it is `None`.
+    pub(crate) fn evaluate_on_target(
+        &self,
+        core_data: &mut CoreData,
+        frame_index: Option<usize>,
+    ) -> Result<Value, DebuggerError> {
+        let mut scope = TargetScope {
+            core_data,
+            frame_index,
+            variables: None,
+        };
+
+        self.evaluate(&mut scope)
+    }
+}
+
+fn evaluate_unary(
+    operator: UnaryOperator,
+    operand: Value,
+    scope: &mut dyn Scope,
+) -> Result<Value, DebuggerError> {
+    match operator {
+        UnaryOperator::Not => Ok(Value::Integer((operand.as_integer()? == 0) as i128)),
+        UnaryOperator::Negate => match operand.as_number()? {
+            Value::Float(value) => Ok(Value::Float(-value)),
+            value => Ok(Value::Integer(-value.as_integer()?)),
+        },
+        UnaryOperator::Dereference => match operand {
+            Value::Pointer { address, type_name } => scope.read(&type_name, address),
+            Value::Variable(variable) => variable
+                .children
+                .iter()
+                .flatten()
+                .find(|child| child.kind == VariableKind::Referenced)
+                .cloned()
+                .map(Value::Variable)
+                .ok_or_else(|| {
+                    DebuggerError::Other(anyhow!(
+                        "The variable '{}' of type {} is not a pointer",
+                        variable.name,
+                        variable.type_name
+                    ))
+                }),
+            other => Err(DebuggerError::Other(anyhow!(
+                "The value {} has no type, cast it to a pointer type first, e.g. `*({} as *const u32)`",
+                other,
+                other
+            ))),
+        },
+        UnaryOperator::AddressOf => match operand {
+            Value::Variable(variable) if variable.register.is_none() => {
+                Ok(Value::Pointer {
+                    address: variable.memory_location,
+                    type_name: variable.type_name,
+                })
+            }
+            other => Err(DebuggerError::Other(anyhow!(
+                "The value {} is not stored in memory",
+                other
+            ))),
+        },
+    }
+}
+
+fn cast(
+    operand: Value,
+    cast_type: &CastType,
+    scope: &mut dyn Scope,
+) -> Result<Value, DebuggerError> {
+    let type_name = match cast_type {
+        CastType::Pointer(type_name) => {
+            let address = u64::try_from(operand.as_integer()?)
+                .map_err(|_| DebuggerError::Other(anyhow!("Invalid address {}", operand)))?;
+
+            return Ok(Value::Pointer {
+                address,
+                type_name: type_name.clone(),
+            });
+        }
+        CastType::Value(type_name) => type_name.as_str(),
+    };
+
+    let value = match (type_name, operand.as_number()) {
+        ("f32", Ok(value)) | ("f64", Ok(value)) => return Ok(Value::Float(value.as_float()?)),
+        (_, Ok(Value::Float(value))) => value as i128,
+        (_, Ok(value)) => value.as_integer()?,
+        (_, Err(error)) => return Err(error),
+    };
+
+    let truncated = match type_name {
+        "bool" => (value != 0) as i128,
+        "u8" => i128::from(value as u8),
+        "u16" => i128::from(value as u16),
+        "u32" | "usize" => i128::from(value as u32),
+        "u64" => i128::from(value as u64),
+        "u128" => value,
+        "i8" => i128::from(value as i8),
+        "i16" => i128::from(value as i16),
+        "i32" | "isize" => i128::from(value as i32),
+        "i64" => i128::from(value as i64),
+        "i128" => value,
+        // Any other type is read from memory, at the address given by the operand.
+        other => {
+            let address = u64::try_from(value)
+                .map_err(|_| DebuggerError::Other(anyhow!("Invalid address {}", value)))?;
+
+            return scope.read(other, address);
+        }
+    };
+
+    Ok(Value::Integer(truncated))
+}
+
+/// Find the child of a structured variable, e.g. a field of a struct or an element of an array.
+/// The fields of a struct which is behind a pointer can be accessed directly.
+fn child(variable: &Variable, name: &str) -> Result<Value, DebuggerError> {
+    // Tuple fields are named `__0`, `__1`, ...
+    let indexed_name = if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("__{}", name)
+    } else {
+        name.to_owned()
+    };
+
+    let children = variable.children.iter().flatten();
+
+    if let Some(child) = children.clone().find(|child| child.name == indexed_name) {
+        return Ok(Value::Variable(child.clone()));
+    }
+
+    if let Some(referenced) = children
+        .clone()
+        .find(|child| child.kind == VariableKind::Referenced)
+    {
+        return child(referenced, name);
+    }
+
+    Err(DebuggerError::Other(anyhow!(
+        "'{}' of type {} has no field or element '{}'",
+        variable.name,
+        variable.type_name,
+        name.trim_start_matches("__")
+    )))
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+/// Parse binary operations with at least the given precedence, using precedence climbing.
+fn parse_binary(tokens: &mut Tokens, min_precedence: u8) -> Result<Expression, DebuggerError> {
+    let mut left = parse_cast(tokens)?;
+
+    while let Some(Token::Binary(operator)) = tokens.peek().cloned() {
+        if operator.precedence() < min_precedence {
+            break;
+        }
+
+        tokens.next();
+
+        let right = parse_binary(tokens, operator.precedence() + 1)?;
+
+        left = Expression::Binary(operator, Box::new(left), Box::new(right));
+    }
+
+    Ok(left)
+}
+
+/// Parse a unary expression, followed by any number of `as` casts.
+fn parse_cast(tokens: &mut Tokens) -> Result<Expression, DebuggerError> {
+    let mut expression = parse_unary(tokens)?;
+
+    while tokens.peek() == Some(&Token::Name("as".to_string())) {
+        tokens.next();
+
+        expression = Expression::Cast(Box::new(expression), parse_type(tokens)?);
+    }
+
+    Ok(expression)
+}
+
+/// Parse a type, either a plain name or a raw pointer or reference to a type.
+fn parse_type(tokens: &mut Tokens) -> Result<CastType, DebuggerError> {
+    let pointer = match tokens.peek() {
+        Some(Token::Binary(BinaryOperator::Multiply)) => {
+            tokens.next();
+
+            match tokens.next() {
+                Some(Token::Name(qualifier)) if qualifier == "const" || qualifier == "mut" => {}
+                _ => {
+                    return Err(DebuggerError::Other(anyhow!(
+                        "Expected 'const' or 'mut' after '*' in a pointer type"
+                    )))
+                }
+            }
+
+            true
+        }
+        Some(Token::Binary(BinaryOperator::BitAnd)) => {
+            tokens.next();
+
+            if tokens.peek() == Some(&Token::Name("mut".to_string())) {
+                tokens.next();
+            }
+
+            true
+        }
+        _ => false,
+    };
+
+    match tokens.next() {
+        Some(Token::Name(type_name)) if pointer => Ok(CastType::Pointer(type_name)),
+        Some(Token::Name(type_name)) => Ok(CastType::Value(type_name)),
+        _ => Err(DebuggerError::Other(anyhow!("Expected a type after 'as'"))),
+    }
+}
+
+fn parse_unary(tokens: &mut Tokens) -> Result<Expression, DebuggerError> {
+    let operator = match tokens.peek() {
+        Some(Token::Unary(operator)) => Some(*operator),
+        Some(Token::Binary(BinaryOperator::Subtract)) => Some(UnaryOperator::Negate),
+        Some(Token::Binary(BinaryOperator::Multiply)) => Some(UnaryOperator::Dereference),
+        Some(Token::Binary(BinaryOperator::BitAnd)) => Some(UnaryOperator::AddressOf),
+        _ => None,
+    };
+
+    match operator {
+        Some(operator) => {
+            tokens.next();
+            Ok(Expression::Unary(operator, Box::new(parse_unary(tokens)?)))
+        }
+        None => parse_postfix(tokens),
+    }
+}
+
+/// Parse an operand, followed by any number of field accesses and indexing operations.
+fn parse_postfix(tokens: &mut Tokens) -> Result<Expression, DebuggerError> {
+    let mut expression = parse_operand(tokens)?;
+
+    loop {
+        expression = match tokens.peek() {
+            Some(Token::Dot) => {
+                tokens.next();
+
+                match tokens.next() {
+                    Some(Token::Name(field)) => Expression::Field(Box::new(expression), field),
+                    Some(Token::Integer(field)) => {
+                        Expression::Field(Box::new(expression), field.to_string())
+                    }
+                    _ => return Err(DebuggerError::Other(anyhow!("Expected a field after '.'"))),
+                }
+            }
+            Some(Token::OpenBracket) => {
+                tokens.next();
+
+                let index = parse_binary(tokens, 0)?;
+
+                match tokens.next() {
+                    Some(Token::CloseBracket) => {
+                        Expression::Index(Box::new(expression), Box::new(index))
+                    }
+                    _ => return Err(DebuggerError::Other(anyhow!("Expected ']'"))),
+                }
+            }
+            _ => return Ok(expression),
+        };
+    }
+}
+
+fn parse_operand(tokens: &mut Tokens) -> Result<Expression, DebuggerError> {
+    match tokens.next() {
+        Some(Token::Integer(value)) => Ok(Expression::Integer(value)),
+        Some(Token::Name(name)) => Ok(Expression::Name(name)),
+        Some(Token::OpenParenthesis) => {
+            let expression = parse_binary(tokens, 0)?;
+
+            match tokens.next() {
+                Some(Token::CloseParenthesis) => Ok(expression),
+                _ => Err(DebuggerError::Other(anyhow!("Expected ')'"))),
+            }
+        }
+        Some(token) => Err(DebuggerError::Other(anyhow!(
+            "Unexpected {:?} in expression",
+            token
+        ))),
+        None => Err(DebuggerError::Other(anyhow!(
+            "Unexpected end of expression"
+        ))),
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, DebuggerError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        let token =
+            match c {
+                c if c.is_whitespace() => {
+                    chars.next();
+                    continue;
+                }
+                '0'..='9' => {
+                    let literal = take_word(&mut chars);
+
+                    Token::Integer(parse::<i128>(&literal.replace('_', "")).map_err(|_| {
+                        DebuggerError::Other(anyhow!("Invalid integer '{}'", literal))
+                    })?)
+                }
+                c if c == '$' || c == '_' || c.is_alphabetic() => {
+                    let mut name = take_word(&mut chars);
+
+                    // Names of statics can be qualified with their module, e.g. `blinky::COUNTER`.
+                    while chars.peek() == Some(&':') {
+                        chars.next();
+
+                        if chars.next() != Some(':') {
+                            return Err(DebuggerError::Other(anyhow!(
+                                "Expected '::' in name '{}'",
+                                name
+                            )));
+                        }
+
+                        name.push_str("::");
+                        name.push_str(&take_word(&mut chars));
+                    }
+
+                    Token::Name(name)
+                }
+                _ => {
+                    chars.next();
+
+                    let next = chars.peek().copied();
+
+                    let (token, length) = match (c, next) {
+                        ('(', _) => (Token::OpenParenthesis, 1),
+                        (')', _) => (Token::CloseParenthesis, 1),
+                        ('[', _) => (Token::OpenBracket, 1),
+                        (']', _) => (Token::CloseBracket, 1),
+                        ('.', _) => (Token::Dot, 1),
+                        ('=', Some('=')) => (Token::Binary(BinaryOperator::Equal), 2),
+                        ('!', Some('=')) => (Token::Binary(BinaryOperator::NotEqual), 2),
+                        ('<', Some('=')) => (Token::Binary(BinaryOperator::LessOrEqual), 2),
+                        ('>', Some('=')) => (Token::Binary(BinaryOperator::GreaterOrEqual), 2),
+                        ('<', Some('<')) => (Token::Binary(BinaryOperator::ShiftLeft), 2),
+                        ('>', Some('>')) => (Token::Binary(BinaryOperator::ShiftRight), 2),
+                        ('&', Some('&')) => (Token::Binary(BinaryOperator::And), 2),
+                        ('|', Some('|')) => (Token::Binary(BinaryOperator::Or), 2),
+                        ('!', _) => (Token::Unary(UnaryOperator::Not), 1),
+                        ('<', _) => (Token::Binary(BinaryOperator::Less), 1),
+                        ('>', _) => (Token::Binary(BinaryOperator::Greater), 1),
+                        ('&', _) => (Token::Binary(BinaryOperator::BitAnd), 1),
+                        ('|', _) => (Token::Binary(BinaryOperator::BitOr), 1),
+                        ('^', _) => (Token::Binary(BinaryOperator::BitXor), 1),
+                        ('+', _) => (Token::Binary(BinaryOperator::Add), 1),
+                        ('-', _) => (Token::Binary(BinaryOperator::Subtract), 1),
+                        ('*', _) => (Token::Binary(BinaryOperator::Multiply), 1),
+                        ('/', _) => (Token::Binary(BinaryOperator::Divide), 1),
+                        ('%', _) => (Token::Binary(BinaryOperator::Remainder), 1),
+                        _ => {
+                            return Err(DebuggerError::Other(anyhow!(
+                                "Unexpected character '{}' in expression",
+                                c
+                            )))
+                        }
+                    };
+
+                    if length == 2 {
+                        chars.next();
+                    }
+
+                    token
+                }
+            };
+
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Take characters which can be part of a name or an integer literal.
+fn take_word(chars: &mut Peekable<Chars>) -> String {
+    let mut word = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if c == '$' || c == '_' || c.is_alphanumeric() {
+            word.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+
+    word
+}
+
+/// Resolves names to the values of variables and registers of a halted core.
+struct TargetScope<'a, 'p> {
+    core_data: &'a mut CoreData<'p>,
+    /// The position in the stack trace of the stack frame whose variables are used, or `None` for the top frame.
+    frame_index: Option<usize>,
+    /// The variables of the stack frame, followed by the statics.
+    /// They are only read when a name is not a register, because unwinding the stack is slow.
+    variables: Option<Vec<Variable>>,
+}
+
+impl Scope for TargetScope<'_, '_> {
+    fn resolve(&mut self, name: &str) -> Result<Value, DebuggerError> {
+        if let Some(register_name) = name.strip_prefix('$') {
+            return self.register_value(register_name).unwrap_or_else(|| {
+                Err(DebuggerError::Other(anyhow!(
+                    "Unknown register '{}'",
+                    register_name
+                )))
+            });
+        }
+
+        if let Some(variable) = find_variable(self.variables()?, name) {
+            return Ok(Value::Variable(variable.clone()));
+        }
+
+        self.register_value(name).unwrap_or_else(|| {
+            Err(DebuggerError::Other(anyhow!(
+                "Unknown variable or register '{}'",
+                name
+            )))
+        })
+    }
+
+    fn read(&mut self, type_name: &str, address: u64) -> Result<Value, DebuggerError> {
+        let CoreData {
+            target_core: core,
+            debug_info,
+            ..
+        } = &mut *self.core_data;
+
+        let debug_info = debug_info
+            .ok_or_else(|| DebuggerError::Other(anyhow!("No debug information available")))?;
+
+        debug_info
+            .get_variable_of_type(core, type_name, &format!("0x{:08x}", address), address)
+            .map_err(|error| DebuggerError::Other(error.into()))?
+            .map(Value::Variable)
+            .ok_or_else(|| DebuggerError::Other(anyhow!("Unknown type '{}'", type_name)))
+    }
+}
+
+impl TargetScope<'_, '_> {
+    fn register_value(&mut self, name: &str) -> Option<Result<Value, DebuggerError>> {
+        let core = &mut self.core_data.target_core;
+        let registers = core.registers();
+
+        let register = registers
+            .registers()
+            .chain(vec![
+                registers.program_counter(),
+                registers.stack_pointer(),
+                registers.return_address(),
+            ])
+            .find(|register| register.name().eq_ignore_ascii_case(name))?;
+
+        // In the other stack frames, the values of the registers are unwound from the stack.
+        // The position of a register in the platform registers is its DWARF register number.
+        let dwarf_register = registers.registers().position(|platform_register| {
+            CoreRegisterAddress::from(platform_register) == CoreRegisterAddress::from(register)
+        });
+        if let (Some(frame_index), Some(dwarf_register)) = (self.frame_index, dwarf_register) {
+            match self.frame_registers(frame_index) {
+                Ok(Some(frame_registers)) => {
+                    let value = frame_registers.get_dwarf_register(dwarf_register as u16);
+                    return Some(
+                        value
+                            .map(|value| Value::Integer(i128::from(value)))
+                            .ok_or_else(|| {
+                                DebuggerError::Other(anyhow!(
+                                    "Register '{}' is not available",
+                                    name
+                                ))
+                            }),
+                    );
+                }
+                Ok(None) => {}
+                Err(error) => return Some(Err(error)),
+            }
+        }
+
+        let core = &mut self.core_data.target_core;
+        Some(
+            core.read_core_reg(register)
+                .map(|value| Value::Integer(i128::from(value)))
+                .map_err(DebuggerError::ProbeRs),
+        )
+    }
+
+    /// The registers of the stack frame at `frame_index`, or `None` if it is the top frame, whose registers are read from the core.
+    fn frame_registers(&mut self, frame_index: usize) -> Result<Option<Registers>, DebuggerError> {
+        if frame_index == 0 {
+            return Ok(None);
+        }
+
+        let core = &mut self.core_data.target_core;
+        let debug_info = self
+            .core_data
+            .debug_info
+            .ok_or_else(|| DebuggerError::Other(anyhow!("No debug information available")))?;
+
+        let pc = u64::from(core.read_core_reg(core.registers().program_counter())?);
+
+        // The stack pointer of a frame is the call frame address of the frame it called.
+        let mut stack_pointer = None;
+        for (index, frame) in debug_info.try_unwind(core, pc).enumerate() {
+            if index == frame_index {
+                let mut registers = frame.registers;
+                registers[13] = stack_pointer;
+                return Ok(Some(registers));
+            }
+            stack_pointer = frame.registers.get_call_frame_address();
+        }
+
+        Err(DebuggerError::Other(anyhow!(
+            "The stack frame {} was not found",
+            frame_index
+        )))
+    }
+
+    fn variables(&mut self) -> Result<&[Variable], DebuggerError> {
+        if self.variables.is_none() {
+            let core = &mut self.core_data.target_core;
+            let debug_info = self
+                .core_data
+                .debug_info
+                .ok_or_else(|| DebuggerError::Other(anyhow!("No debug information available")))?;
+
+            let pc = u64::from(core.read_core_reg(core.registers().program_counter())?);
+
+            let mut variables = debug_info
+                .try_unwind(core, pc)
+                .nth(self.frame_index.unwrap_or(0))
+                .map(|frame| frame.variables)
+                .unwrap_or_default();
+
+            variables.extend(
+                debug_info
+                    .get_stack_statics(core, pc)
+                    .map_err(|error| DebuggerError::Other(error.into()))?,
+            );
+
+            self.variables = Some(variables);
+        }
+
+        Ok(self.variables.as_deref().unwrap_or_default())
+    }
+}
+
+/// Find a variable by name. The statics are grouped by their module, in variables of type
+/// `namespace`, and can be found by their plain or their qualified name.
+fn find_variable<'v>(variables: &'v [Variable], name: &str) -> Option<&'v Variable> {
+    find_in_namespace(variables, name, "")
+}
+
+fn find_in_namespace<'v>(
+    variables: &'v [Variable],
+    name: &str,
+    namespace_path: &str,
+) -> Option<&'v Variable> {
+    let mut namespaces = Vec::new();
+
+    for variable in variables {
+        if variable.type_name == "namespace" {
+            namespaces.push(variable);
+        } else if variable.name == name
+            || (!namespace_path.is_empty()
+                && format!("{}::{}", namespace_path, variable.name) == name)
+        {
+            return Some(variable);
+        }
+    }
+
+    namespaces.into_iter().find_map(|namespace| {
+        // Nested namespaces are named either by their full path, or by their own name only.
+        let path = if namespace_path.is_empty()
+            || namespace.name.starts_with(&format!("{}::", namespace_path))
+        {
+            namespace.name.clone()
+        } else {
+            format!("{}::{}", namespace_path, namespace.name)
+        };
+
+        find_in_namespace(
+            namespace.children.as_deref().unwrap_or_default(),
+            name,
+            &path,
+        )
+    })
+}
+
+/// Convert the displayed value of a variable back to a number.
+fn variable_value(variable: &Variable) -> Result<Value, DebuggerError> {
+    let value = variable.get_value();
+
+    match value.as_str() {
+        "true" => Ok(Value::Integer(1)),
+        "false" => Ok(Value::Integer(0)),
+        value => {
+            let value = value.replace('_', "");
+
+            if variable.type_name == "char" && value.chars().count() == 1 {
+                return Ok(Value::Integer(
+                    value.chars().next().unwrap_or_default() as i128
+                ));
+            }
+
+            if let Ok(integer) = parse::<i128>(&value) {
+                return Ok(Value::Integer(integer));
+            }
+
+            if variable.type_name.starts_with('f') {
+                if let Ok(float) = value.parse::<f64>() {
+                    return Ok(Value::Float(float));
+                }
+            }
+
+            // Pointers and references are converted to the address they point to.
+            if let Some(referenced) = variable
+                .children
+                .iter()
+                .flatten()
+                .find(|child| child.kind == VariableKind::Referenced)
+            {
+                return Ok(Value::Integer(i128::from(referenced.memory_location)));
+            }
+
+            Err(DebuggerError::Other(anyhow!(
+                "Variable '{}' of type {} has no numeric value: {}",
+                variable.name,
+                variable.type_name,
+                value
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A scope with a few variables, which reads memory as the address plus one.
+    struct TestScope;
+
+    impl Scope for TestScope {
+        fn resolve(&mut self, name: &str) -> Result<Value, DebuggerError> {
+            match name {
+                "counter" => Ok(Value::Integer(12)),
+                "$r0" => Ok(Value::Integer(0x20)),
+                "config" => {
+                    let mut baud = Variable::new();
+                    baud.name = "baud".to_string();
+                    baud.type_name = "u32".to_string();
+                    baud.set_value("115_200".to_string());
+
+                    let mut config = Variable::new();
+                    config.name = "config".to_string();
+                    config.type_name = "Config".to_string();
+                    config.children = Some(vec![baud]);
+
+                    Ok(Value::Variable(config))
+                }
+                other => Err(DebuggerError::Other(anyhow!("Unknown name {}", other))),
+            }
+        }
+
+        fn read(&mut self, type_name: &str, address: u64) -> Result<Value, DebuggerError> {
+            let mut variable = Variable::new();
+            variable.type_name = type_name.to_string();
+            variable.byte_size = 4;
+            variable.set_value((address + 1).to_string());
+
+            Ok(Value::Variable(variable))
+        }
+    }
+
+    fn evaluate(input: &str) -> i128 {
+        Expression::parse(input)
+            .unwrap()
+            .evaluate(&mut TestScope)
+            .unwrap()
+            .as_integer()
+            .unwrap()
+    }
+
+    #[test]
+    fn evaluate_comparisons() {
+        assert_eq!(evaluate("counter == 12"), 1);
+        assert_eq!(evaluate("counter != 12"), 0);
+        assert_eq!(evaluate("counter > 1_000"), 0);
+        assert_eq!(evaluate("$r0 <= 0x20"), 1);
+        assert_eq!(evaluate("counter"), 12);
+    }
+
+    #[test]
+    fn evaluate_logical_operators() {
+        assert_eq!(evaluate("counter > 10 && $r0 == 0x20"), 1);
+        assert_eq!(evaluate("!(counter > 10) || $r0 < 0x20"), 0);
+        assert_eq!(evaluate("0 && unknown"), 0);
+        assert_eq!(evaluate("1 || unknown"), 1);
+    }
+
+    #[test]
+    fn evaluate_arithmetic() {
+        assert_eq!(evaluate("counter + 3 * 2"), 18);
+        assert_eq!(evaluate("(counter + 3) * 2"), 30);
+        assert_eq!(evaluate("-counter % 5"), -2);
+        assert_eq!(evaluate("1 << 4 | 1"), 17);
+        assert_eq!(evaluate("$r0 & 0x30 ^ 0x1"), 0x21);
+        assert_eq!(evaluate("counter / 5 == 2"), 1);
+        assert!(Expression::parse("counter / 0")
+            .unwrap()
+            .evaluate(&mut TestScope)
+            .is_err());
+    }
+
+    #[test]
+    fn evaluate_fields_and_casts() {
+        assert_eq!(evaluate("config.baud"), 115_200);
+        assert_eq!(evaluate("config.baud / 100"), 1152);
+        assert_eq!(evaluate("300 as u8"), 44);
+        assert_eq!(evaluate("-1 as u16"), 0xffff);
+        assert_eq!(evaluate("*(0x2000_0000 as *const u32)"), 0x2000_0001);
+        assert_eq!(evaluate("(0x2000_0000 as *const u32)[2]"), 0x2000_0009);
+        assert_eq!(evaluate("0x2000_0000 as Config"), 0x2000_0001);
+        assert!(Expression::parse("config.parity")
+            .unwrap()
+            .evaluate(&mut TestScope)
+            .is_err());
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(
+            Expression::parse("a || b && c == 1").unwrap(),
+            Expression::Binary(
+                BinaryOperator::Or,
+                Box::new(Expression::Name("a".to_string())),
+                Box::new(Expression::Binary(
+                    BinaryOperator::And,
+                    Box::new(Expression::Name("b".to_string())),
+                    Box::new(Expression::Binary(
+                        BinaryOperator::Equal,
+                        Box::new(Expression::Name("c".to_string())),
+                        Box::new(Expression::Integer(1))
+                    ))
+                ))
+            )
+        );
+
+        assert_eq!(
+            Expression::parse("*p.next[1] as u8").unwrap(),
+            Expression::Cast(
+                Box::new(Expression::Unary(
+                    UnaryOperator::Dereference,
+                    Box::new(Expression::Index(
+                        Box::new(Expression::Field(
+                            Box::new(Expression::Name("p".to_string())),
+                            "next".to_string()
+                        )),
+                        Box::new(Expression::Integer(1))
+                    ))
+                )),
+                CastType::Value("u8".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn find_qualified_statics() {
+        let mut counter = Variable::new();
+        counter.name = "COUNTER".to_string();
+
+        let mut module = Variable::new();
+        module.name = "blinky::leds".to_string();
+        module.type_name = "namespace".to_string();
+        module.children = Some(vec![counter.clone()]);
+
+        let mut krate = Variable::new();
+        krate.name = "blinky".to_string();
+        krate.type_name = "namespace".to_string();
+        krate.children = Some(vec![module]);
+
+        let mut driver = Variable::new();
+        driver.name = "driver".to_string();
+        driver.type_name = "namespace".to_string();
+        driver.children = Some(vec![counter.clone()]);
+        krate.children.as_mut().unwrap().push(driver);
+
+        let variables = [krate];
+
+        assert!(find_variable(&variables, "COUNTER").is_some());
+        assert!(find_variable(&variables, "blinky::leds::COUNTER").is_some());
+        assert!(find_variable(&variables, "blinky::driver::COUNTER").is_some());
+        assert!(find_variable(&variables, "blinky::COUNTER").is_none());
+        assert!(find_variable(&variables, "LEDS").is_none());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Expression::parse("counter ==").is_err());
+        assert!(Expression::parse("(counter").is_err());
+        assert!(Expression::parse("counter = 1").is_err());
+        assert!(Expression::parse("counter 1").is_err());
+        assert!(Expression::parse("buffer[1").is_err());
+        assert!(Expression::parse("1 as *u32").is_err());
+    }
+}
@@ -1,12 +1,18 @@
+mod breakpoint;
 mod dap_types; //Uses Schemafy to generate DAP types from Json
 mod debug_adapter;
 mod debugger; //The probe-rs debugger.
+mod disassembly;
+mod exceptions;
+mod expression;
 mod info;
+mod peripherals;
+mod stepping;
 
 use anyhow::Result;
 use debugger::{
     debug, download_program_fast, dump_memory, list_connected_devices, reset_target_of_device,
-    trace_u32_on_target, DebuggerOptions,
+    trace_u32_on_target, DebuggerOptions, FlashingOptions,
 };
 use log::error;
 use probe_rs::architecture::arm::ap::AccessPortError;
@@ -104,6 +110,9 @@ enum CliCommands {
         #[structopt(flatten)]
         debugger_options: DebuggerOptions,
 
+        #[structopt(flatten)]
+        flashing_options: FlashingOptions,
+
         //TODO: Implement multi-session --server choices
         /// Switch from using CLI to DAP Protocol debug commands. By default, the DAP communication for the first session is via STDIN and STDOUT. Adding the additional --port property will run as an IP server, listening to connections on the specified port.
         #[structopt(long)]
@@ -164,10 +173,11 @@ fn main() -> Result<()> {
         } => reset_target_of_device(debugger_options, assert)?,
         CliCommands::Debug {
             debugger_options,
+            flashing_options,
             // program_binary,
             // port,
             dap,
-        } => debug(debugger_options, dap),
+        } => debug(debugger_options, flashing_options, dap),
         CliCommands::Dump {
             debugger_options,
             loc,
@@ -0,0 +1,390 @@
+//! Peripheral registers from CMSIS-SVD files, for the "Peripherals" scope of the debugger.
+//!
+//! The SVD file is parsed once, when the session starts, into a flat list of peripherals, with
+//! their registers and fields. Clusters and register arrays are expanded, and `derivedFrom`
+//! peripherals are resolved. The registers are only read from the target when the client expands
+//! a peripheral.
+
+use crate::DebuggerError;
+use anyhow::anyhow;
+use probe_rs::{Core, MemoryInterface};
+use std::{fs, path::Path};
+use svd_parser::svd::{
+    self, Access, DeriveFrom, RegisterCluster, RegisterInfo, RegisterProperties,
+};
+
+/// The size of registers which don't have a size in the SVD file, in bits.
+const DEFAULT_REGISTER_SIZE: u32 = 32;
+
+#[derive(Debug)]
+pub(crate) struct SvdPeripherals {
+    pub(crate) peripherals: Vec<Peripheral>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Peripheral {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) base_address: u32,
+    pub(crate) registers: Vec<Register>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Register {
+    pub(crate) name: String,
+    pub(crate) address: u32,
+    /// The size of the register in bits.
+    pub(crate) size: u32,
+    /// Write-only registers are not read, because reading them can have side effects.
+    pub(crate) readable: bool,
+    pub(crate) fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Field {
+    pub(crate) name: String,
+    /// The position of the lowest bit of the field in the register.
+    pub(crate) offset: u32,
+    pub(crate) width: u32,
+    /// The names of the enumerated values of the field.
+    pub(crate) values: Vec<(String, u64)>,
+}
+
+impl SvdPeripherals {
+    pub(crate) fn from_file(path: &Path) -> Result<Self, DebuggerError> {
+        let xml = fs::read_to_string(path).map_err(|error| {
+            DebuggerError::Other(anyhow!("Could not read SVD file {:?}: {}", path, error))
+        })?;
+
+        Self::parse(&xml).map_err(|error| {
+            DebuggerError::Other(anyhow!("Could not parse SVD file {:?}: {}", path, error))
+        })
+    }
+
+    pub(crate) fn parse(xml: &str) -> Result<Self, DebuggerError> {
+        let device = svd_parser::parse(xml).map_err(DebuggerError::Other)?;
+
+        let peripherals = device
+            .peripherals
+            .iter()
+            .map(|peripheral| {
+                let peripheral = match &peripheral.derived_from {
+                    Some(derived_from) => device
+                        .peripherals
+                        .iter()
+                        .find(|other| &other.name == derived_from)
+                        .map(|other| peripheral.derive_from(other))
+                        .ok_or_else(|| {
+                            DebuggerError::Other(anyhow!(
+                                "Peripheral '{}' is derived from the unknown peripheral '{}'",
+                                peripheral.name,
+                                derived_from
+                            ))
+                        })?,
+                    None => peripheral.clone(),
+                };
+
+                let properties = peripheral
+                    .default_register_properties
+                    .derive_from(&device.default_register_properties);
+                let base_address = peripheral.base_address as u32;
+
+                let mut registers = Vec::new();
+                for register_cluster in peripheral.registers.iter().flatten() {
+                    add_registers(
+                        &mut registers,
+                        register_cluster,
+                        "",
+                        base_address,
+                        &properties,
+                    );
+                }
+                registers.sort_by_key(|register| register.address);
+
+                Ok(Peripheral {
+                    name: peripheral.name.clone(),
+                    description: peripheral.description.clone(),
+                    base_address,
+                    registers,
+                })
+            })
+            .collect::<Result<Vec<_>, DebuggerError>>()?;
+
+        Ok(SvdPeripherals { peripherals })
+    }
+}
+
+/// Add the registers of a register or cluster, and of all the elements of register and cluster
+/// arrays, located at `base_address`. The names of registers in clusters are prefixed with the
+/// name of the cluster.
+fn add_registers(
+    registers: &mut Vec<Register>,
+    register_cluster: &RegisterCluster,
+    prefix: &str,
+    base_address: u32,
+    properties: &RegisterProperties,
+) {
+    match register_cluster {
+        RegisterCluster::Register(svd::Register::Single(info)) => {
+            registers.push(Register::new(
+                info,
+                prefix,
+                &info.name,
+                base_address,
+                properties,
+            ));
+        }
+        RegisterCluster::Register(svd::Register::Array(info, dim)) => {
+            for (index, dim_index) in dim.indexes().enumerate() {
+                let name = info
+                    .name
+                    .replace("[%s]", &dim_index)
+                    .replace("%s", &dim_index);
+                let address = base_address + index as u32 * dim.dim_increment;
+                registers.push(Register::new(info, prefix, &name, address, properties));
+            }
+        }
+        RegisterCluster::Cluster(cluster) => {
+            let properties = cluster.default_register_properties.derive_from(properties);
+            let elements: Vec<(String, u32)> = match cluster {
+                svd::Cluster::Single(info) => vec![(info.name.clone(), info.address_offset)],
+                svd::Cluster::Array(info, dim) => dim
+                    .indexes()
+                    .enumerate()
+                    .map(|(index, dim_index)| {
+                        (
+                            info.name
+                                .replace("[%s]", &dim_index)
+                                .replace("%s", &dim_index),
+                            info.address_offset + index as u32 * dim.dim_increment,
+                        )
+                    })
+                    .collect(),
+            };
+
+            for (name, address_offset) in elements {
+                let prefix = format!("{}{}.", prefix, name);
+                for child in &cluster.children {
+                    add_registers(
+                        registers,
+                        child,
+                        &prefix,
+                        base_address + address_offset,
+                        &properties,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Register {
+    fn new(
+        info: &RegisterInfo,
+        prefix: &str,
+        name: &str,
+        base_address: u32,
+        properties: &RegisterProperties,
+    ) -> Self {
+        let properties = info.properties.derive_from(properties);
+
+        Register {
+            name: format!("{}{}", prefix, name),
+            address: base_address + info.address_offset,
+            size: properties.size.unwrap_or(DEFAULT_REGISTER_SIZE),
+            readable: properties.access.map(Access::can_read).unwrap_or(true),
+            fields: info
+                .fields
+                .iter()
+                .flatten()
+                .map(|field| Field {
+                    name: field.name.clone(),
+                    offset: field.bit_range.offset,
+                    width: field.bit_range.width,
+                    values: field
+                        .enumerated_values
+                        .iter()
+                        .flat_map(|enumerated_values| enumerated_values.values.iter())
+                        .filter_map(|value| value.value.map(|number| (value.name.clone(), number)))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Read the value of the register from the target, with an access of the size of the
+    /// register, where possible.
+    pub(crate) fn read(&self, core: &mut Core) -> Result<u64, DebuggerError> {
+        let value = match self.size {
+            8 => u64::from(core.read_word_8(self.address)?),
+            32 => u64::from(core.read_word_32(self.address)?),
+            64 => {
+                let mut words = [0u32; 2];
+                core.read_32(self.address, &mut words)?;
+                u64::from(words[0]) | u64::from(words[1]) << 32
+            }
+            size => {
+                let mut bytes = [0u8; 8];
+                let length = (size as usize + 7) / 8;
+                let bytes_read = bytes.get_mut(..length).ok_or_else(|| {
+                    DebuggerError::Other(anyhow!(
+                        "Register {} has an unsupported size of {} bits",
+                        self.name,
+                        size
+                    ))
+                })?;
+                core.read_8(self.address, bytes_read)?;
+                u64::from_le_bytes(bytes)
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Format a value of the register as a hexadecimal number with all its digits.
+    pub(crate) fn format(&self, value: u64) -> String {
+        format!("0x{:0width$x}", value, width = (self.size as usize + 3) / 4)
+    }
+}
+
+impl Field {
+    /// Extract the value of the field from the value of its register.
+    pub(crate) fn extract(&self, register_value: u64) -> u64 {
+        let mask = match self.width {
+            64 => u64::MAX,
+            width => (1 << width) - 1,
+        };
+        register_value.checked_shr(self.offset).unwrap_or(0) & mask
+    }
+
+    /// Format the value of the field, with the name of the enumerated value, if there is one.
+    pub(crate) fn format(&self, register_value: u64) -> String {
+        let value = self.extract(register_value);
+        let number = if self.width == 1 {
+            value.to_string()
+        } else {
+            format!("0x{:x}", value)
+        };
+
+        match self
+            .values
+            .iter()
+            .find(|(_, enumerated)| *enumerated == value)
+        {
+            Some((name, _)) => format!("{} ({})", name, number),
+            None => number,
+        }
+    }
+
+    /// The bits of the register which contain the field, e.g. `[7:4]`.
+    pub(crate) fn bit_range(&self) -> String {
+        if self.width == 1 {
+            format!("[{}]", self.offset)
+        } else {
+            format!("[{}:{}]", self.offset + self.width - 1, self.offset)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SVD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device schemaVersion="1.1" xmlns:xs="http://www.w3.org/2001/XMLSchema-instance">
+  <name>TEST</name>
+  <version>1.0</version>
+  <description>Test device</description>
+  <addressUnitBits>8</addressUnitBits>
+  <width>32</width>
+  <size>32</size>
+  <access>read-write</access>
+  <peripherals>
+    <peripheral>
+      <name>TIMER0</name>
+      <description>Timer</description>
+      <baseAddress>0x40008000</baseAddress>
+      <registers>
+        <register>
+          <name>CTRL</name>
+          <addressOffset>0x4</addressOffset>
+          <fields>
+            <field>
+              <name>ENABLE</name>
+              <bitOffset>0</bitOffset>
+              <bitWidth>1</bitWidth>
+            </field>
+            <field>
+              <name>MODE</name>
+              <bitOffset>4</bitOffset>
+              <bitWidth>2</bitWidth>
+              <enumeratedValues>
+                <enumeratedValue><name>Timer</name><value>0</value></enumeratedValue>
+                <enumeratedValue><name>Counter</name><value>1</value></enumeratedValue>
+              </enumeratedValues>
+            </field>
+          </fields>
+        </register>
+        <register>
+          <dim>2</dim>
+          <dimIncrement>4</dimIncrement>
+          <name>CC[%s]</name>
+          <addressOffset>0x10</addressOffset>
+        </register>
+        <register>
+          <name>TASK</name>
+          <addressOffset>0x0</addressOffset>
+          <access>write-only</access>
+        </register>
+      </registers>
+    </peripheral>
+    <peripheral derivedFrom="TIMER0">
+      <name>TIMER1</name>
+      <baseAddress>0x40009000</baseAddress>
+    </peripheral>
+  </peripherals>
+</device>"#;
+
+    #[test]
+    fn parse_peripherals() {
+        let svd = SvdPeripherals::parse(SVD).unwrap();
+
+        assert_eq!(svd.peripherals.len(), 2);
+
+        let registers: Vec<_> = svd.peripherals[0]
+            .registers
+            .iter()
+            .map(|register| (register.name.as_str(), register.address, register.readable))
+            .collect();
+        assert_eq!(
+            registers,
+            vec![
+                ("TASK", 0x4000_8000, false),
+                ("CTRL", 0x4000_8004, true),
+                ("CC0", 0x4000_8010, true),
+                ("CC1", 0x4000_8014, true),
+            ]
+        );
+
+        let derived = &svd.peripherals[1];
+        assert_eq!(derived.name, "TIMER1");
+        assert_eq!(derived.description.as_deref(), Some("Timer"));
+        assert_eq!(derived.registers[1].address, 0x4000_9004);
+    }
+
+    #[test]
+    fn decode_fields() {
+        let svd = SvdPeripherals::parse(SVD).unwrap();
+        let control = &svd.peripherals[0].registers[1];
+
+        assert_eq!(control.format(0x11), "0x00000011");
+
+        let enable = &control.fields[0];
+        let mode = &control.fields[1];
+        assert_eq!(enable.format(0x11), "1");
+        assert_eq!(enable.bit_range(), "[0]");
+        assert_eq!(mode.format(0x11), "Counter (0x1)");
+        assert_eq!(mode.format(0x31), "0x3");
+        assert_eq!(mode.bit_range(), "[5:4]");
+    }
+}
@@ -0,0 +1,291 @@
+//! Stepping at source statement granularity.
+//!
+//! Statements are identified by the line program of the debug information: a step ends as soon as
+//! the core reaches an address which belongs to a different source line. Function calls are
+//! detected by a change of the return address register, and stepped over by running to the
+//! return address with a temporary hardware breakpoint. Stepping out uses the call frame
+//! information of the unwinder to find the return address of the current function.
+
+use crate::{debugger::CoreData, DebuggerError};
+use anyhow::anyhow;
+use probe_rs::{
+    debug::{DebugInfo, SourceLocation},
+    Core, CoreInformation, CoreStatus, HaltReason,
+};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Upper limit for the number of instructions stepped for a single statement, so that a step on a
+/// line with a long running loop does not block the debugger forever.
+const MAX_STATEMENT_STEPS: usize = 1000;
+
+/// How long to wait for the core to return from a function, before it is halted wherever it is.
+const RUN_TO_ADDRESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SteppingMode {
+    /// Step a single instruction.
+    Instruction,
+    /// Run to the next statement, stepping over function calls.
+    Over,
+    /// Run to the next statement, stepping into function calls which have debug information.
+    Into,
+    /// Run until the current function returns to its caller.
+    Out,
+}
+
+/// Execute a step, and return the state of the core afterwards.
+///
+/// The returned [`HaltReason`] is [`HaltReason::Step`] if the step completed, or the reason
+/// why the core halted before, e.g. a user breakpoint inside a function which was stepped over.
+pub(crate) fn step(
+    core_data: &mut CoreData,
+    mode: SteppingMode,
+) -> Result<(CoreInformation, HaltReason), DebuggerError> {
+    let CoreData {
+        target_core: core,
+        debug_info,
+        ..
+    } = core_data;
+
+    let debug_info = match *debug_info {
+        Some(debug_info) if mode != SteppingMode::Instruction => debug_info,
+        _ if mode == SteppingMode::Out => {
+            return Err(DebuggerError::Other(anyhow!(
+                "Stepping out of a function requires debug information"
+            )))
+        }
+        _ => return Ok((core.step()?, HaltReason::Step)),
+    };
+
+    match mode {
+        SteppingMode::Out => step_out(core, debug_info),
+        SteppingMode::Into => step_statement(core, debug_info, true),
+        _ => step_statement(core, debug_info, false),
+    }
+}
+
+/// Step instructions until the core reaches a different source line.
+fn step_statement(
+    core: &mut Core,
+    debug_info: &DebugInfo,
+    into_calls: bool,
+) -> Result<(CoreInformation, HaltReason), DebuggerError> {
+    let registers = core.registers();
+
+    let mut program_counter = core.read_core_reg(registers.program_counter())?;
+
+    let start_line = match debug_info
+        .get_source_location(u64::from(program_counter))
+        .filter(has_line)
+    {
+        Some(location) => location,
+        // Without a source line to start from, statements can't be detected.
+        None => return Ok((core.step()?, HaltReason::Step)),
+    };
+
+    for _ in 0..MAX_STATEMENT_STEPS {
+        let return_address = core.read_core_reg(registers.return_address())?;
+        let stack_pointer = core.read_core_reg(registers.stack_pointer())?;
+
+        let mut cpu_info = core.step()?;
+
+        let new_return_address = core.read_core_reg(registers.return_address())?;
+
+        if new_return_address != return_address
+            && is_call(program_counter, cpu_info.pc, new_return_address & !1)
+        {
+            let step_into = into_calls
+                && debug_info
+                    .get_source_location(u64::from(cpu_info.pc))
+                    .filter(has_line)
+                    .is_some();
+
+            if !step_into {
+                let (new_cpu_info, reason) =
+                    run_to_address(core, new_return_address & !1, stack_pointer)?;
+
+                if reason != HaltReason::Step {
+                    return Ok((new_cpu_info, reason));
+                }
+
+                cpu_info = new_cpu_info;
+            }
+        }
+
+        program_counter = cpu_info.pc;
+
+        if let Some(location) = debug_info.get_source_location(u64::from(program_counter)) {
+            if has_line(&location) && !is_same_line(&location, &start_line) {
+                return Ok((cpu_info, HaltReason::Step));
+            }
+        }
+    }
+
+    log::warn!(
+        "Stopped stepping after {} instructions without reaching a new statement",
+        MAX_STATEMENT_STEPS
+    );
+
+    Ok((
+        CoreInformation {
+            pc: program_counter,
+        },
+        HaltReason::Step,
+    ))
+}
+
+/// Run until the current function returns to its caller.
+fn step_out(
+    core: &mut Core,
+    debug_info: &DebugInfo,
+) -> Result<(CoreInformation, HaltReason), DebuggerError> {
+    let program_counter = core.read_core_reg(core.registers().program_counter())?;
+
+    // Inlined functions have an additional frame at the same address for their call site, which
+    // is skipped. The canonical frame address of the last frame at the current address is the
+    // value of the stack pointer once the function returned.
+    let mut frame_address = None;
+    let mut return_address = None;
+
+    for frame in debug_info.try_unwind(core, u64::from(program_counter)) {
+        if frame.pc != program_counter {
+            return_address = Some(frame.pc);
+            break;
+        }
+
+        frame_address = frame.registers.get_call_frame_address().or(frame_address);
+    }
+
+    match (return_address, frame_address) {
+        (Some(return_address), Some(frame_address)) => {
+            run_to_address(core, return_address, frame_address)
+        }
+        _ => Err(DebuggerError::Other(anyhow!(
+            "Unable to determine the caller of the function at {:#010x}",
+            program_counter
+        ))),
+    }
+}
+
+/// Run the core until it reaches `address` with a stack pointer of at least `stack_pointer`,
+/// i.e. in the frame of the caller and not in a recursive call of the function.
+fn run_to_address(
+    core: &mut Core,
+    address: u32,
+    stack_pointer: u32,
+) -> Result<(CoreInformation, HaltReason), DebuggerError> {
+    // Keep breakpoints which were set by the user at the same address.
+    let temporary_breakpoint = !core.hw_breakpoints()?.contains(&Some(address));
+
+    if temporary_breakpoint {
+        core.set_hw_breakpoint(address)?;
+    }
+
+    let result = run_to_breakpoint(core, address, stack_pointer);
+
+    if temporary_breakpoint {
+        core.clear_hw_breakpoint(address)?;
+    }
+
+    result
+}
+
+fn run_to_breakpoint(
+    core: &mut Core,
+    address: u32,
+    stack_pointer: u32,
+) -> Result<(CoreInformation, HaltReason), DebuggerError> {
+    let registers = core.registers();
+
+    // Step off the current instruction first, a breakpoint on it would halt the core right away.
+    let mut cpu_info = core.step()?;
+    let mut reason = HaltReason::Step;
+
+    loop {
+        if cpu_info.pc == address {
+            if core.read_core_reg(registers.stack_pointer())? >= stack_pointer {
+                return Ok((cpu_info, HaltReason::Step));
+            }
+
+            // In a recursive call, the core is still at the breakpoint. Not every core steps off
+            // a breakpoint when it is resumed, so step off it before running again.
+            cpu_info = core.step()?;
+            reason = HaltReason::Step;
+            continue;
+        } else if reason != HaltReason::Step {
+            // Halted somewhere else, e.g. at a breakpoint set by the user.
+            return Ok((cpu_info, reason));
+        }
+
+        core.run()?;
+        reason = wait_for_halt(core)?;
+        cpu_info = CoreInformation {
+            pc: core.read_core_reg(registers.program_counter())?,
+        };
+    }
+}
+
+/// Wait until the core halts, and return the reason. If it does not halt within
+/// [`RUN_TO_ADDRESS_TIMEOUT`], it is halted with a request.
+fn wait_for_halt(core: &mut Core) -> Result<HaltReason, DebuggerError> {
+    let start = Instant::now();
+
+    loop {
+        match core.status()? {
+            CoreStatus::Halted(reason) => return Ok(reason),
+            CoreStatus::LockedUp => {
+                return Err(DebuggerError::Other(anyhow!(
+                    "Core locked up while stepping"
+                )))
+            }
+            _ => {}
+        }
+
+        if start.elapsed() > RUN_TO_ADDRESS_TIMEOUT {
+            log::warn!("Step did not complete in time, halting the core");
+            core.halt(Duration::from_millis(100))?;
+            return Ok(HaltReason::Request);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Check if a step from `previous_pc` to `pc` was a function call, which set the return address
+/// to the instruction following the call.
+fn is_call(previous_pc: u32, pc: u32, return_address: u32) -> bool {
+    let next_instruction = previous_pc + 1..=previous_pc + 4;
+
+    next_instruction.contains(&return_address) && !next_instruction.contains(&pc)
+}
+
+/// Line number zero is used for code which can't be attributed to a source line.
+fn has_line(location: &SourceLocation) -> bool {
+    matches!(location.line, Some(line) if line != 0)
+}
+
+fn is_same_line(location: &SourceLocation, other: &SourceLocation) -> bool {
+    location.line == other.line
+        && location.file == other.file
+        && location.directory == other.directory
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_call;
+
+    #[test]
+    fn detect_call() {
+        // Thumb `bl` is four bytes long, the return address is the following instruction.
+        assert!(is_call(0x0800_0100, 0x0800_0400, 0x0800_0104));
+        // 16-bit `blx`
+        assert!(is_call(0x0800_0100, 0x0800_0400, 0x0800_0102));
+        // The return address was loaded by an instruction which didn't branch.
+        assert!(!is_call(0x0800_0100, 0x0800_0102, 0x0800_0102));
+        // Return from a function
+        assert!(!is_call(0x0800_0400, 0x0800_0104, 0x0800_0104));
+    }
+}
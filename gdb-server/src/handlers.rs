@@ -1,10 +1,44 @@
 use crate::architecture::{GdbArchitectureExt, GdbTargetExt};
-use probe_rs::{Core, CoreStatus, MemoryInterface, Session};
+use crate::parser::{v_packet::Action, BreakpointType, ThreadId, ThreadOperation};
+use probe_rs::config::MemoryRegion;
+use probe_rs::flashing::{DownloadOptions, FlashError, FlashLoader};
+use probe_rs::{
+    Architecture, Core, CoreStatus, Error, MemoryInterface, Session, Watchpoint, WatchpointKind,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Range;
 use std::time::Duration;
 
+/// A breakpoint inserted with a `Z0` packet.
+///
+/// The index of the core the breakpoint was inserted on is kept, so it is removed on the
+/// same core, even if GDB selected another thread in the meantime.
+pub(crate) enum SoftwareBreakpoint {
+    /// Breakpoint instruction written to RAM, with the replaced instruction.
+    Ram {
+        core_index: usize,
+        original: Vec<u8>,
+    },
+    /// Breakpoint instruction in flash, with the replaced instruction.
+    ///
+    /// The flash is only written when the cores are resumed, see [`write_flash_breakpoints`].
+    Flash {
+        original: Vec<u8>,
+        instruction: &'static [u8],
+    },
+}
+
+/// The GDB thread ID of a core.
+///
+/// Each core is represented as a GDB thread. Thread IDs start at 1, because 0 means any thread.
+fn thread_id(core_index: usize) -> u32 {
+    core_index as u32 + 1
+}
+
 pub(crate) fn q_supported() -> Option<String> {
     Some(
-        "PacketSize=2048;swbreak-;hwbreak+;vContSupported+;qXfer:features:read+;qXfer:memory-map:read+"
+        "PacketSize=2048;swbreak+;hwbreak+;vContSupported+;qXfer:features:read+;qXfer:memory-map:read+"
             .into(),
     )
 }
@@ -21,8 +55,79 @@ pub(crate) fn q_attached() -> Option<String> {
     Some("1".into())
 }
 
-pub(crate) fn halt_reason() -> Option<String> {
-    Some("S05".into())
+pub(crate) fn halt_reason(core_index: usize) -> Option<String> {
+    Some(format!("T05thread:{:x};", thread_id(core_index)))
+}
+
+pub(crate) fn current_thread(core_index: usize) -> Option<String> {
+    Some(format!("QC{:x}", thread_id(core_index)))
+}
+
+pub(crate) fn first_thread_info(session: &Session) -> Option<String> {
+    let threads = session
+        .list_cores()
+        .iter()
+        .map(|(core_index, _)| format!("{:x}", thread_id(*core_index)))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    Some(format!("m{}", threads))
+}
+
+pub(crate) fn subsequent_thread_info() -> Option<String> {
+    // All threads are already listed in the reply to `qfThreadInfo`.
+    Some("l".into())
+}
+
+pub(crate) fn thread_extra_info(session: &Session, thread: ThreadId) -> Option<String> {
+    let core = session
+        .list_cores()
+        .into_iter()
+        .find(|(core_index, _)| thread.matches(thread_id(*core_index)));
+
+    match core {
+        Some((core_index, core_type)) => Some(hex::encode(format!(
+            "Core {} ({:?})",
+            core_index, core_type
+        ))),
+        None => Some("E22".to_string()),
+    }
+}
+
+pub(crate) fn thread_alive(session: &Session, thread: ThreadId) -> Option<String> {
+    let num_cores = session.list_cores().len() as u32;
+
+    match thread {
+        ThreadId::Id(id) if id > num_cores => Some("E22".to_string()),
+        _ => reply_ok(),
+    }
+}
+
+pub(crate) fn select_thread(
+    session: &Session,
+    operation: ThreadOperation,
+    thread: ThreadId,
+    general_core: &mut usize,
+    continue_thread: &mut ThreadId,
+) -> Option<String> {
+    let num_cores = session.list_cores().len() as u32;
+
+    match (operation, thread) {
+        (_, ThreadId::Id(id)) if id > num_cores => {
+            log::warn!(
+                "Thread {} selected, but there are only {} cores",
+                id,
+                num_cores
+            );
+            return Some("E22".to_string());
+        }
+        (ThreadOperation::General, ThreadId::Id(id)) => *general_core = id as usize - 1,
+        // Register and memory accesses stay on the current core.
+        (ThreadOperation::General, _) => (),
+        (ThreadOperation::Continue, thread) => *continue_thread = thread,
+    }
+
+    reply_ok()
 }
 
 pub(crate) fn read_general_registers(mut core: Core) -> Option<String> {
@@ -236,11 +341,16 @@ pub(crate) fn write_register(register: u32, hex_value: &str, mut core: Core) ->
     reply_ok()
 }
 
-pub(crate) fn read_memory(address: u32, length: u32, mut core: Core) -> Option<String> {
+pub(crate) fn read_memory(
+    address: u32,
+    length: u32,
+    mut core: Core,
+    flash_breakpoints: &HashMap<u32, Vec<u8>>,
+) -> Option<String> {
     let mut readback_data = vec![0u8; length as usize];
     match core.read_8(address, &mut readback_data) {
         Ok(_) => Some(
-            readback_data
+            hide_flash_breakpoints(address, &mut readback_data, flash_breakpoints)
                 .iter()
                 .map(|s| format!("{:02x?}", s))
                 .collect::<Vec<String>>()
@@ -268,22 +378,126 @@ pub(crate) fn host_info() -> Option<String> {
     Some("cputype:12;cpusubtype:14;triple:armv6m--none-eabi;endian:litte;ptrsize:4".to_string())
 }
 
-pub(crate) fn run(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    core.run().unwrap();
-    *awaits_halt = true;
-    None
+/// Replace the breakpoint instructions in flash within the `data` read from `address`
+/// with the original instructions.
+///
+/// GDB expects to read the original instructions of breakpoints it removed, which are
+/// only restored in the flash when the cores are resumed.
+fn hide_flash_breakpoints<'data>(
+    address: u32,
+    data: &'data mut [u8],
+    flash_breakpoints: &HashMap<u32, Vec<u8>>,
+) -> &'data [u8] {
+    let end = address as u64 + data.len() as u64;
+
+    for (breakpoint_address, original) in flash_breakpoints {
+        for (byte_address, byte) in (*breakpoint_address as u64..).zip(original) {
+            if byte_address >= address as u64 && byte_address < end {
+                data[(byte_address - address as u64) as usize] = *byte;
+            }
+        }
+    }
+
+    data
 }
 
-pub(crate) fn stop(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    core.halt(Duration::from_millis(100)).unwrap();
-    *awaits_halt = false;
-    Some("OK".into())
+/// Apply the actions of a `vCont` packet to the cores.
+///
+/// Each core gets the first action which matches its thread, cores without a matching action
+/// are left alone. When a core is single stepped, no other cores are resumed, so the stop
+/// of the stepped core can be reported right away.
+///
+/// Breakpoints in flash are written before any core is resumed.
+pub(crate) fn resume(
+    session: &mut Session,
+    actions: &[(Action, ThreadId)],
+    running_cores: &mut Vec<usize>,
+    software_breakpoints: &HashMap<u32, SoftwareBreakpoint>,
+    flash_breakpoints: &mut HashMap<u32, Vec<u8>>,
+) -> Result<Option<String>, Error> {
+    let core_indices: Vec<usize> = session
+        .list_cores()
+        .into_iter()
+        .map(|(core_index, _)| core_index)
+        .collect();
+
+    let mut step_cores = Vec::new();
+    let mut run_cores = Vec::new();
+
+    for (core_index, action) in core_actions(&core_indices, actions) {
+        match action {
+            Action::Step => step_cores.push(core_index),
+            Action::Continue => run_cores.push(core_index),
+            Action::Stop => {
+                session.core(core_index)?.halt(Duration::from_millis(100))?;
+                running_cores.retain(|running_core| *running_core != core_index);
+            }
+            other => log::warn!("vCont with action {:?} not supported", other),
+        }
+    }
+
+    if step_cores.is_empty() && run_cores.is_empty() {
+        return Ok(reply_ok());
+    }
+
+    if let Err(e) = write_flash_breakpoints(session, software_breakpoints, flash_breakpoints) {
+        log::error!("Unable to write the breakpoints to flash: {}", e);
+        return Ok(Some("E14".to_string()));
+    }
+
+    if step_cores.is_empty() {
+        for core_index in run_cores {
+            session.core(core_index)?.run()?;
+            running_cores.push(core_index);
+        }
+
+        return Ok(None);
+    }
+
+    // GDB expects all threads to be stopped once the step is done, so the other cores only run
+    // while the stepped cores execute their instruction.
+    for &core_index in &run_cores {
+        session.core(core_index)?.run()?;
+    }
+    for &core_index in &step_cores {
+        session.core(core_index)?.step()?;
+    }
+    for core_index in run_cores {
+        session.core(core_index)?.halt(Duration::from_millis(100))?;
+    }
+
+    Ok(Some(format!("T05thread:{:x};", thread_id(step_cores[0]))))
+}
+
+/// The action of a `vCont` packet for each core.
+///
+/// The first action whose thread ID matches the thread of a core is applied to it,
+/// cores without a matching action are left as they are.
+fn core_actions<'a>(
+    core_indices: &[usize],
+    actions: &'a [(Action, ThreadId)],
+) -> Vec<(usize, &'a Action)> {
+    core_indices
+        .iter()
+        .filter_map(|&core_index| {
+            actions
+                .iter()
+                .find(|(_, thread)| thread.matches(thread_id(core_index)))
+                .map(|(action, _)| (core_index, action))
+        })
+        .collect()
 }
 
-pub(crate) fn step(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    core.step().unwrap();
-    *awaits_halt = false;
-    Some("S05".into())
+/// Halt all cores which are still running.
+pub(crate) fn halt_cores(
+    session: &mut Session,
+    running_cores: &mut Vec<usize>,
+) -> Result<(), Error> {
+    for core_index in running_cores.drain(..) {
+        session.core(core_index)?.halt(Duration::from_millis(100))?;
+    }
+
+    Ok(())
 }
 
 pub(crate) fn insert_hardware_break(address: u32, _kind: u32, mut core: Core) -> Option<String> {
@@ -296,22 +510,487 @@ pub(crate) fn remove_hardware_break(address: u32, _kind: u32, mut core: Core) ->
     Some("OK".into())
 }
 
+/// The breakpoint instruction replacing an instruction of the given `kind`,
+/// as sent by GDB in the `Z0` packet.
+fn breakpoint_instruction(architecture: Architecture, kind: u32) -> Option<&'static [u8]> {
+    match (architecture, kind) {
+        // `BKPT`, only the first halfword of a 32-bit Thumb-2 instruction is replaced
+        (Architecture::Arm, 2) | (Architecture::Arm, 3) => Some(&[0x00, 0xbe]),
+        // `c.ebreak`
+        (Architecture::Riscv, 2) => Some(&[0x02, 0x90]),
+        // `ebreak`
+        (Architecture::Riscv, 4) => Some(&[0x73, 0x00, 0x10, 0x00]),
+        _ => None,
+    }
+}
+
+fn is_flash(session: &Session, address: u32) -> bool {
+    session
+        .target()
+        .memory_map
+        .iter()
+        .any(|region| match region {
+            MemoryRegion::Nvm(region) => region.range.contains(&address),
+            _ => false,
+        })
+}
+
+fn try_insert_software_break(
+    session: &mut Session,
+    core_index: usize,
+    flash_breakpoints: &HashMap<u32, Vec<u8>>,
+    address: u32,
+    kind: u32,
+) -> anyhow::Result<SoftwareBreakpoint> {
+    let in_flash = is_flash(session, address);
+
+    let mut core = session.core(core_index)?;
+
+    let instruction = breakpoint_instruction(core.architecture(), kind).ok_or_else(|| {
+        anyhow::anyhow!("Unsupported breakpoint kind {} for this architecture", kind)
+    })?;
+
+    let mut original = vec![0; instruction.len()];
+
+    // A breakpoint which is still in the flash was removed, and is inserted again
+    // before the flash was restored.
+    match flash_breakpoints.get(&address) {
+        Some(flash_original) if flash_original.len() == original.len() => {
+            original.copy_from_slice(flash_original)
+        }
+        _ => core.read_8(address, &mut original)?,
+    }
+
+    core.debug_on_sw_breakpoint(true)?;
+
+    if in_flash {
+        return Ok(SoftwareBreakpoint::Flash {
+            original,
+            instruction,
+        });
+    }
+
+    core.write_8(address, instruction)?;
+
+    Ok(SoftwareBreakpoint::Ram {
+        core_index,
+        original,
+    })
+}
+
+fn try_remove_software_break(
+    session: &mut Session,
+    address: u32,
+    breakpoint: SoftwareBreakpoint,
+) -> anyhow::Result<()> {
+    match breakpoint {
+        SoftwareBreakpoint::Ram {
+            core_index,
+            original,
+        } => session.core(core_index)?.write_8(address, &original)?,
+        // Restored with the next call of `write_flash_breakpoints`.
+        SoftwareBreakpoint::Flash { .. } => (),
+    }
+
+    Ok(())
+}
+
+/// Bring the flash in line with the inserted breakpoints, by writing the breakpoint
+/// instructions of new breakpoints, and restoring the original instructions of removed ones.
+///
+/// GDB inserts all breakpoints before resuming and removes them after every stop. Writing
+/// the flash for every `Z0` and `z0` packet would be slow, so this is only done when the
+/// cores are resumed or GDB detaches. Breakpoints which are removed and inserted again in
+/// between don't change the flash at all.
+///
+/// The flash is written with the flash algorithm of the target, without resetting the cores,
+/// see [`DownloadOptions::keep_core_state`]. Only the registers of the cores and the RAM used by
+/// the algorithm are restored, the peripherals it changes, like the clocks, are not.
+///
+/// `flash_breakpoints` holds the original instructions of the breakpoints currently in flash.
+fn write_flash_breakpoints(
+    session: &mut Session,
+    software_breakpoints: &HashMap<u32, SoftwareBreakpoint>,
+    flash_breakpoints: &mut HashMap<u32, Vec<u8>>,
+) -> Result<(), FlashError> {
+    let mut flash_loader = session.target().flash_loader();
+    let mut changed = false;
+
+    for (address, breakpoint) in software_breakpoints {
+        if let SoftwareBreakpoint::Flash { instruction, .. } = breakpoint {
+            if !flash_breakpoints.contains_key(address) {
+                flash_loader.add_data(*address, instruction)?;
+                changed = true;
+            }
+        }
+    }
+
+    for (address, original) in flash_breakpoints.iter() {
+        if !matches!(
+            software_breakpoints.get(address),
+            Some(SoftwareBreakpoint::Flash { .. })
+        ) {
+            flash_loader.add_data(*address, original)?;
+            changed = true;
+        }
+    }
+
+    if !changed {
+        return Ok(());
+    }
+
+    // The rest of the sectors has to be kept, and the program continues where it was halted.
+    let mut options = DownloadOptions::new();
+    options.keep_unwritten_bytes = true;
+    options.keep_core_state = true;
+
+    flash_loader.commit(session, options)?;
+
+    *flash_breakpoints = software_breakpoints
+        .iter()
+        .filter_map(|(address, breakpoint)| match breakpoint {
+            SoftwareBreakpoint::Flash { original, .. } => Some((*address, original.clone())),
+            SoftwareBreakpoint::Ram { .. } => None,
+        })
+        .collect();
+
+    Ok(())
+}
+
+pub(crate) fn insert_software_break(
+    session: &mut Session,
+    core_index: usize,
+    software_breakpoints: &mut HashMap<u32, SoftwareBreakpoint>,
+    flash_breakpoints: &HashMap<u32, Vec<u8>>,
+    address: u32,
+    kind: u32,
+) -> Option<String> {
+    if software_breakpoints.contains_key(&address) {
+        return reply_ok();
+    }
+
+    match try_insert_software_break(session, core_index, flash_breakpoints, address, kind) {
+        Ok(breakpoint) => {
+            software_breakpoints.insert(address, breakpoint);
+            reply_ok()
+        }
+        Err(e) => {
+            log::warn!("Unable to set breakpoint at {:#010x}: {}", address, e);
+            Some("E14".to_string())
+        }
+    }
+}
+
+pub(crate) fn remove_software_break(
+    session: &mut Session,
+    software_breakpoints: &mut HashMap<u32, SoftwareBreakpoint>,
+    address: u32,
+) -> Option<String> {
+    let breakpoint = match software_breakpoints.remove(&address) {
+        Some(breakpoint) => breakpoint,
+        None => return reply_ok(),
+    };
+
+    match try_remove_software_break(session, address, breakpoint) {
+        Ok(()) => reply_ok(),
+        Err(e) => {
+            log::warn!("Unable to remove breakpoint at {:#010x}: {}", address, e);
+            Some("E14".to_string())
+        }
+    }
+}
+
+/// Restore the original instructions of all software breakpoints, e.g. when GDB detaches.
+pub(crate) fn remove_software_breaks(
+    session: &mut Session,
+    software_breakpoints: &mut HashMap<u32, SoftwareBreakpoint>,
+    flash_breakpoints: &mut HashMap<u32, Vec<u8>>,
+) {
+    for (address, breakpoint) in software_breakpoints.drain() {
+        if let Err(e) = try_remove_software_break(session, address, breakpoint) {
+            log::warn!("Unable to remove breakpoint at {:#010x}: {}", address, e);
+        }
+    }
+
+    if let Err(e) = write_flash_breakpoints(session, software_breakpoints, flash_breakpoints) {
+        log::warn!("Unable to remove the breakpoints from flash: {}", e);
+    }
+}
+
+/// The kind of access which triggers the watchpoint of a `Z2`, `Z3` or `Z4` packet.
+/// The breakpoint types of `Z0` and `Z1` are never passed here.
+pub(crate) fn watchpoint_kind(breakpoint_type: &BreakpointType) -> WatchpointKind {
+    match breakpoint_type {
+        BreakpointType::WriteWatchpoint => WatchpointKind::Write,
+        BreakpointType::ReadWatchpoint => WatchpointKind::Read,
+        _ => WatchpointKind::Access,
+    }
+}
+
+pub(crate) fn insert_watchpoint(
+    address: u32,
+    length: u32,
+    kind: WatchpointKind,
+    mut core: Core,
+) -> Option<String> {
+    match core.set_hw_watchpoint(address, length, kind) {
+        Ok(()) => Some("OK".into()),
+        Err(e) => {
+            log::warn!(
+                "Unable to set {:?} watchpoint at {:#010x} with length {}: {}",
+                kind,
+                address,
+                length,
+                e
+            );
+            Some("E22".to_string())
+        }
+    }
+}
+
+pub(crate) fn remove_watchpoint(
+    address: u32,
+    length: u32,
+    kind: WatchpointKind,
+    mut core: Core,
+) -> Option<String> {
+    match core.clear_hw_watchpoint(address, length, kind) {
+        Ok(()) => Some("OK".into()),
+        Err(e) => {
+            log::warn!(
+                "Unable to remove {:?} watchpoint at {:#010x} with length {}: {}",
+                kind,
+                address,
+                length,
+                e
+            );
+            Some("E22".to_string())
+        }
+    }
+}
+
+/// The stop reply sent to GDB after the core halted.
+///
+/// Watchpoint hits are reported with the watched address, so GDB can tell which
+/// watchpoint triggered. Halts on a breakpoint inserted with `Z0` are reported as
+/// software breakpoints, everything else is reported as a hardware breakpoint.
+pub(crate) fn stop_reply(
+    core: &mut Core,
+    core_index: usize,
+    software_breakpoints: &HashMap<u32, SoftwareBreakpoint>,
+) -> String {
+    let watchpoint = match core.hit_watchpoint() {
+        Ok(watchpoint) => watchpoint,
+        Err(e) => {
+            log::warn!("Unable to determine which watchpoint was hit: {}", e);
+            None
+        }
+    };
+
+    let pc = match watchpoint {
+        Some(_) => None,
+        None => core.read_core_reg(core.registers().program_counter()).ok(),
+    };
+
+    format_stop_reply(core_index, watchpoint, pc, software_breakpoints)
+}
+
+fn format_stop_reply(
+    core_index: usize,
+    watchpoint: Option<Watchpoint>,
+    pc: Option<u32>,
+    software_breakpoints: &HashMap<u32, SoftwareBreakpoint>,
+) -> String {
+    match watchpoint {
+        Some(watchpoint) => {
+            let reason = match watchpoint.kind {
+                WatchpointKind::Write => "watch",
+                WatchpointKind::Read => "rwatch",
+                WatchpointKind::Access => "awatch",
+            };
+
+            format!(
+                "T05thread:{:x};{}:{:x};",
+                thread_id(core_index),
+                reason,
+                watchpoint.address
+            )
+        }
+        None => {
+            let reason = if pc.map(|pc| software_breakpoints.contains_key(&pc)) == Some(true) {
+                "swbreak"
+            } else {
+                "hwbreak"
+            };
+
+            format!("T05thread:{:x};{}:;", thread_id(core_index), reason)
+        }
+    }
+}
+
 pub(crate) fn write_memory(address: u32, data: &[u8], mut core: Core) -> Option<String> {
     core.write_8(address, data).unwrap();
 
     Some("OK".into())
 }
 
+pub(crate) fn flash_erase(
+    erased_ranges: &mut Vec<Range<u32>>,
+    address: u32,
+    length: u32,
+) -> Option<String> {
+    let end = match address.checked_add(length) {
+        Some(end) => end,
+        None => {
+            log::warn!(
+                "Unable to erase {:#x} bytes of flash at {:#010x}, beyond the address space",
+                length,
+                address
+            );
+            return Some("E00".to_string());
+        }
+    };
+
+    // The flash loader only erases the sectors which are written, so the erased ranges are
+    // kept until `vFlashDone`, to erase the sectors which are not written as well.
+    log::debug!("Erasing flash from {:#010x} to {:#010x}", address, end);
+    erased_ranges.push(address..end);
+
+    reply_ok()
+}
+
+pub(crate) fn flash_write(
+    session: &Session,
+    flash_loader: &mut Option<FlashLoader>,
+    written_ranges: &mut Vec<Range<u32>>,
+    address: u32,
+    data: &[u8],
+) -> Option<String> {
+    let end = match u32::try_from(data.len())
+        .ok()
+        .and_then(|length| address.checked_add(length))
+    {
+        Some(end) => end,
+        None => {
+            log::warn!(
+                "Unable to write {:#x} bytes of flash at {:#010x}, beyond the address space",
+                data.len(),
+                address
+            );
+            return Some("E00".to_string());
+        }
+    };
+
+    // All writes are buffered until GDB sends `vFlashDone`.
+    let flash_loader = flash_loader.get_or_insert_with(|| session.target().flash_loader());
+
+    match flash_loader.add_data(address, data) {
+        Ok(()) => {
+            written_ranges.push(address..end);
+            reply_ok()
+        }
+        Err(e) => {
+            log::warn!("Unable to write flash at {:#010x}: {}", address, e);
+            Some("E00".to_string())
+        }
+    }
+}
+
+pub(crate) fn flash_done(
+    session: &mut Session,
+    flash_loader: &mut Option<FlashLoader>,
+    erased_ranges: &mut Vec<Range<u32>>,
+    written_ranges: &mut Vec<Range<u32>>,
+) -> Option<String> {
+    let erased_ranges = std::mem::take(erased_ranges);
+    let written_ranges = std::mem::take(written_ranges);
+
+    let mut flash_loader = match flash_loader.take() {
+        Some(flash_loader) => flash_loader,
+        // Nothing was erased or written
+        None if erased_ranges.is_empty() => return reply_ok(),
+        None => session.target().flash_loader(),
+    };
+
+    // The erased ranges which are not written are filled with the erased byte value,
+    // so the flash loader erases their sectors too.
+    for erased_range in &erased_ranges {
+        for range in unwritten_ranges(erased_range, &written_ranges) {
+            let data = vec![erased_byte_value(session, range.start); range.len()];
+            if let Err(e) = flash_loader.add_data(range.start, &data) {
+                log::warn!("Unable to erase flash at {:#010x}: {}", range.start, e);
+                return Some("E00".to_string());
+            }
+        }
+    }
+
+    match flash_loader.commit(session, DownloadOptions::new()) {
+        Ok(()) => reply_ok(),
+        Err(e) => {
+            log::error!("Flashing failed: {}", e);
+            Some("E00".to_string())
+        }
+    }
+}
+
+/// The parts of `erased_range` which are not covered by any of the `written_ranges`.
+fn unwritten_ranges(erased_range: &Range<u32>, written_ranges: &[Range<u32>]) -> Vec<Range<u32>> {
+    let mut written_ranges: Vec<&Range<u32>> = written_ranges
+        .iter()
+        .filter(|range| range.start < erased_range.end && range.end > erased_range.start)
+        .collect();
+    written_ranges.sort_by_key(|range| range.start);
+
+    let mut unwritten_ranges = Vec::new();
+    let mut start = erased_range.start;
+    for range in written_ranges {
+        if range.start > start {
+            unwritten_ranges.push(start..range.start);
+        }
+        start = start.max(range.end);
+    }
+    if start < erased_range.end {
+        unwritten_ranges.push(start..erased_range.end);
+    }
+
+    unwritten_ranges
+}
+
+/// The value of erased bytes at `address`, according to the flash algorithm of the flash.
+fn erased_byte_value(session: &Session, address: u32) -> u8 {
+    let mut algorithms = session
+        .target()
+        .flash_algorithms
+        .iter()
+        .filter(|algorithm| algorithm.flash_properties.address_range.contains(&address));
+
+    algorithms
+        .clone()
+        .find(|algorithm| algorithm.default)
+        .or_else(|| algorithms.next())
+        .map_or(0xff, |algorithm| {
+            algorithm.flash_properties.erased_byte_value
+        })
+}
+
 pub(crate) fn get_memory_map(session: &Session) -> Option<String> {
     let memory_map = session.target().gdb_memory_map();
 
     Some(String::from_utf8(gdb_sanitize_file(memory_map.as_bytes(), 0, 1000)).unwrap())
 }
 
-pub(crate) fn user_halt(mut core: Core, awaits_halt: &mut bool) -> Option<String> {
-    let _ = core.halt(Duration::from_millis(100));
-    *awaits_halt = false;
-    Some("T02".into())
+pub(crate) fn user_halt(
+    session: &mut Session,
+    running_cores: &mut Vec<usize>,
+    general_core: &mut usize,
+) -> Result<Option<String>, Error> {
+    if let Some(core_index) = running_cores.first() {
+        *general_core = *core_index;
+    }
+
+    halt_cores(session, running_cores)?;
+
+    Ok(Some(format!("T02thread:{:x};", thread_id(*general_core))))
 }
 
 pub(crate) fn detach(break_due: &mut bool) -> Option<String> {
@@ -319,11 +998,6 @@ pub(crate) fn detach(break_due: &mut bool) -> Option<String> {
     Some("OK".into())
 }
 
-pub(crate) fn reset_halt(mut core: Core) -> Option<String> {
-    let _cpu_info = core.reset_and_halt(Duration::from_millis(400));
-    Some("OK".into())
-}
-
 fn gdb_sanitize_file(data: &[u8], offset: u32, len: u32) -> Vec<u8> {
     let offset = offset as usize;
     let len = len as usize;
@@ -355,3 +1029,185 @@ pub(crate) fn read_target_description(session: &Session, annex: &[u8]) -> Option
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unwritten_parts_of_erased_flash() {
+        let erased = 0x0800_0000..0x0800_1000;
+
+        assert_eq!(unwritten_ranges(&erased, &[]), vec![erased.clone()]);
+        assert_eq!(
+            unwritten_ranges(&erased, std::slice::from_ref(&erased)),
+            vec![]
+        );
+
+        // Writes outside of the erased range are ignored, the order of the writes doesn't matter.
+        let written = [
+            0x0800_0800..0x0800_0900,
+            0x0800_0f00..0x0800_1100,
+            0x0800_0000..0x0800_0100,
+            0x0800_2000..0x0800_2100,
+        ];
+        assert_eq!(
+            unwritten_ranges(&erased, &written),
+            vec![0x0800_0100..0x0800_0800, 0x0800_0900..0x0800_0f00]
+        );
+    }
+
+    #[test]
+    fn erase_flash_beyond_address_space() {
+        let mut erased_ranges = Vec::new();
+
+        assert_eq!(
+            flash_erase(&mut erased_ranges, 0xffff_f000, 0x2000),
+            Some("E00".to_string())
+        );
+        assert!(erased_ranges.is_empty());
+
+        assert_eq!(
+            flash_erase(&mut erased_ranges, 0xffff_e000, 0x1000),
+            reply_ok()
+        );
+        assert_eq!(erased_ranges, vec![0xffff_e000..0xffff_f000]);
+    }
+
+    #[test]
+    fn vcont_actions_for_each_thread() {
+        // `vCont;s:2;c`: the second core is stepped, the first one is continued.
+        let actions = [
+            (Action::Step, ThreadId::Id(2)),
+            (Action::Continue, ThreadId::All),
+        ];
+        assert_eq!(
+            core_actions(&[0, 1], &actions),
+            vec![(0, &Action::Continue), (1, &Action::Step)]
+        );
+
+        // `vCont;c:1;s:2`
+        let actions = [
+            (Action::Continue, ThreadId::Id(1)),
+            (Action::Step, ThreadId::Id(2)),
+        ];
+        assert_eq!(
+            core_actions(&[0, 1], &actions),
+            vec![(0, &Action::Continue), (1, &Action::Step)]
+        );
+
+        // `vCont;s:1`: the second core is left as it is.
+        let actions = [(Action::Step, ThreadId::Id(1))];
+        assert_eq!(core_actions(&[0, 1], &actions), vec![(0, &Action::Step)]);
+    }
+
+    #[test]
+    fn breakpoint_instructions() {
+        assert_eq!(
+            breakpoint_instruction(Architecture::Arm, 2),
+            Some(&[0x00, 0xbe][..])
+        );
+        assert_eq!(
+            breakpoint_instruction(Architecture::Arm, 3),
+            Some(&[0x00, 0xbe][..])
+        );
+        assert_eq!(
+            breakpoint_instruction(Architecture::Riscv, 4),
+            Some(&[0x73, 0x00, 0x10, 0x00][..])
+        );
+        assert_eq!(breakpoint_instruction(Architecture::Arm, 4), None);
+    }
+
+    #[test]
+    fn flash_breakpoints_are_hidden() {
+        let mut flash_breakpoints = HashMap::new();
+        flash_breakpoints.insert(0x0800_0002, vec![0x70, 0x47]);
+        flash_breakpoints.insert(0x0800_0007, vec![0x11, 0x22]);
+        flash_breakpoints.insert(0x0800_0100, vec![0x33, 0x44]);
+
+        let mut data = [0x00, 0xbf, 0x00, 0xbe, 0x00, 0xbf, 0x00, 0x00];
+
+        // The second breakpoint is only partially in the data read.
+        assert_eq!(
+            hide_flash_breakpoints(0x0800_0000, &mut data, &flash_breakpoints),
+            &[0x00, 0xbf, 0x70, 0x47, 0x00, 0xbf, 0x00, 0x11]
+        );
+    }
+
+    #[test]
+    fn watchpoint_kinds() {
+        assert_eq!(
+            watchpoint_kind(&BreakpointType::WriteWatchpoint),
+            WatchpointKind::Write
+        );
+        assert_eq!(
+            watchpoint_kind(&BreakpointType::ReadWatchpoint),
+            WatchpointKind::Read
+        );
+        assert_eq!(
+            watchpoint_kind(&BreakpointType::AccessWatchpoint),
+            WatchpointKind::Access
+        );
+    }
+
+    #[test]
+    fn watchpoint_stop_replies() {
+        let watchpoint = |kind| {
+            Some(Watchpoint {
+                address: 0x2000_0100,
+                size: 4,
+                kind,
+            })
+        };
+
+        assert_eq!(
+            format_stop_reply(0, watchpoint(WatchpointKind::Write), None, &HashMap::new()),
+            "T05thread:1;watch:20000100;"
+        );
+        assert_eq!(
+            format_stop_reply(1, watchpoint(WatchpointKind::Read), None, &HashMap::new()),
+            "T05thread:2;rwatch:20000100;"
+        );
+        assert_eq!(
+            format_stop_reply(0, watchpoint(WatchpointKind::Access), None, &HashMap::new()),
+            "T05thread:1;awatch:20000100;"
+        );
+    }
+
+    #[test]
+    fn breakpoint_stop_replies() {
+        let mut software_breakpoints = HashMap::new();
+        software_breakpoints.insert(
+            0x100,
+            SoftwareBreakpoint::Ram {
+                core_index: 0,
+                original: vec![0x00, 0xbf],
+            },
+        );
+        software_breakpoints.insert(
+            0x0800_0200,
+            SoftwareBreakpoint::Flash {
+                original: vec![0x70, 0x47],
+                instruction: &[0x00, 0xbe],
+            },
+        );
+
+        // Breakpoints of `Z0` packets are software breakpoints, in RAM and in flash.
+        assert_eq!(
+            format_stop_reply(0, None, Some(0x100), &software_breakpoints),
+            "T05thread:1;swbreak:;"
+        );
+        assert_eq!(
+            format_stop_reply(0, None, Some(0x0800_0200), &software_breakpoints),
+            "T05thread:1;swbreak:;"
+        );
+        assert_eq!(
+            format_stop_reply(2, None, Some(0x0800_0300), &software_breakpoints),
+            "T05thread:3;hwbreak:;"
+        );
+        assert_eq!(
+            format_stop_reply(2, None, None, &software_breakpoints),
+            "T05thread:3;hwbreak:;"
+        );
+    }
+}
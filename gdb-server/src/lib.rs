@@ -3,6 +3,7 @@
 mod architecture;
 mod gdb_server_async;
 mod handlers;
+mod monitor;
 mod parser;
 mod reader;
 mod worker;
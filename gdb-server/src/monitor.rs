@@ -0,0 +1,316 @@
+//! Monitor commands, which GDB sends in `qRcmd` packets, e.g. for `monitor reset halt`.
+//!
+//! The output of a command is shown in the GDB console.
+
+use anyhow::{anyhow, Context, Result};
+use probe_rs::architecture::arm::SwoConfig;
+use probe_rs::flashing::erase_all;
+use probe_rs::{MemoryInterface, Session};
+use std::time::Duration;
+
+pub(crate) struct MonitorCommands {
+    commands: Vec<Command>,
+}
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help_text: &'static str,
+
+    /// Execute the command on the given core, returning the output of the command.
+    function: fn(&mut Session, core_index: usize, args: &[&str]) -> Result<String>,
+}
+
+/// Parse the argument at the given index, either as decimal or as hex number with `0x` prefix.
+fn get_int_argument(args: &[&str], index: usize) -> Result<u32> {
+    let arg = args.get(index).ok_or_else(|| anyhow!("Missing argument"))?;
+
+    let value = if let Some(hex) = arg.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        arg.parse()
+    };
+
+    value.with_context(|| format!("Invalid number '{}'", arg))
+}
+
+/// The largest number of words read by a single `mdw` command.
+const MAX_MDW_COUNT: u32 = 0x1000;
+
+/// Parse the address and the optional word count of the `mdw` command.
+fn get_mdw_arguments(args: &[&str]) -> Result<(u32, usize)> {
+    let address = get_int_argument(args, 0)?;
+    let count = if args.len() > 1 {
+        get_int_argument(args, 1)?
+    } else {
+        1
+    };
+
+    if count == 0 || count > MAX_MDW_COUNT {
+        return Err(anyhow!(
+            "The count must be between 1 and {}, not {}",
+            MAX_MDW_COUNT,
+            count
+        ));
+    }
+    if address.checked_add((count - 1) * 4).is_none() {
+        return Err(anyhow!(
+            "Reading {} words at {:#010x} exceeds the address space",
+            count,
+            address
+        ));
+    }
+
+    Ok((address, count as usize))
+}
+
+/// Format words read from memory, with four words and their address on each line.
+fn format_words(address: u32, words: &[u32]) -> String {
+    let mut output = String::new();
+    for (line, chunk) in words.chunks(4).enumerate() {
+        output += &format!("{:#010x}:", address.wrapping_add(line as u32 * 16));
+
+        for word in chunk {
+            output += &format!(" {:08x}", word);
+        }
+
+        output += "\n";
+    }
+
+    output
+}
+
+impl MonitorCommands {
+    pub(crate) fn new() -> Self {
+        let mut monitor = MonitorCommands {
+            commands: Vec::new(),
+        };
+
+        monitor.add_command(Command {
+            name: "reset",
+            usage: "reset [halt|run]",
+            help_text: "Reset the target, and halt it (default) or let it run",
+
+            function: |session, core_index, args| {
+                let mut core = session.core(core_index)?;
+
+                match args.first().copied() {
+                    None | Some("halt") => {
+                        let cpu_info = core.reset_and_halt(Duration::from_millis(400))?;
+                        Ok(format!("Target halted at {:#010x}\n", cpu_info.pc))
+                    }
+                    Some("run") => {
+                        core.reset()?;
+                        Ok("Target reset\n".to_string())
+                    }
+                    Some(other) => Err(anyhow!("Unknown reset mode '{}'", other)),
+                }
+            },
+        });
+
+        monitor.add_command(Command {
+            name: "halt",
+            usage: "halt",
+            help_text: "Halt the target",
+
+            function: |session, core_index, _args| {
+                let cpu_info = session.core(core_index)?.halt(Duration::from_millis(100))?;
+
+                Ok(format!("Target halted at {:#010x}\n", cpu_info.pc))
+            },
+        });
+
+        monitor.add_command(Command {
+            name: "mdw",
+            usage: "mdw <address> [count]",
+            help_text: "Read 32-bit words from memory",
+
+            function: |session, core_index, args| {
+                let (address, count) = get_mdw_arguments(args)?;
+
+                let mut words = vec![0u32; count];
+                session.core(core_index)?.read_32(address, &mut words)?;
+
+                Ok(format_words(address, &words))
+            },
+        });
+
+        monitor.add_command(Command {
+            name: "mww",
+            usage: "mww <address> <value>",
+            help_text: "Write a 32-bit word to memory",
+
+            function: |session, core_index, args| {
+                let address = get_int_argument(args, 0)?;
+                let value = get_int_argument(args, 1)?;
+
+                session.core(core_index)?.write_word_32(address, value)?;
+
+                Ok(String::new())
+            },
+        });
+
+        monitor.add_command(Command {
+            name: "erase",
+            usage: "erase",
+            help_text: "Erase all flash memory of the target",
+
+            function: |session, _core_index, _args| {
+                erase_all(session)?;
+
+                Ok("Flash erased\n".to_string())
+            },
+        });
+
+        monitor.add_command(Command {
+            name: "speed",
+            usage: "speed <kHz>",
+            help_text: "Change the protocol speed of the probe",
+
+            function: |session, _core_index, args| {
+                let speed_khz = get_int_argument(args, 0)?;
+
+                let actual_speed_khz = session.set_speed(speed_khz)?;
+
+                Ok(format!("Protocol speed set to {} kHz\n", actual_speed_khz))
+            },
+        });
+
+        monitor.add_command(Command {
+            name: "swo",
+            usage: "swo start <TPIU clock in Hz> [baud] | swo stop",
+            help_text: "Configure the target and probe for SWO tracing",
+
+            function: |session, core_index, args| match args.first().copied() {
+                Some("start") => {
+                    let mut config = SwoConfig::new(get_int_argument(args, 1)?);
+
+                    if args.len() > 2 {
+                        config = config.set_baud(get_int_argument(args, 2)?);
+                    }
+
+                    session.setup_swv(core_index, &config)?;
+
+                    Ok("SWO started\n".to_string())
+                }
+                Some("stop") => {
+                    session.disable_swv(core_index)?;
+
+                    Ok("SWO stopped\n".to_string())
+                }
+                _ => Err(anyhow!("Expected 'start' or 'stop'")),
+            },
+        });
+
+        monitor
+    }
+
+    fn add_command(&mut self, command: Command) {
+        self.commands.push(command)
+    }
+
+    /// Execute a command line, returning the output of the command.
+    pub(crate) fn execute(
+        &self,
+        session: &mut Session,
+        core_index: usize,
+        line: &str,
+    ) -> Result<String> {
+        let mut command_parts = line.split_whitespace();
+
+        match command_parts.next() {
+            None | Some("help") => Ok(self.help()),
+            Some(name) => {
+                let command = self
+                    .commands
+                    .iter()
+                    .find(|command| command.name == name)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Unknown monitor command '{}', use 'monitor help' for a list of commands",
+                            name
+                        )
+                    })?;
+
+                let args: Vec<&str> = command_parts.collect();
+
+                (command.function)(session, core_index, &args)
+                    .map_err(|e| anyhow!("{:#}\nUsage: monitor {}", e, command.usage))
+            }
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut help = "The following monitor commands are available:\n".to_string();
+
+        for command in &self.commands {
+            help += &format!("  {:<50} {}\n", command.usage, command.help_text);
+        }
+
+        help += &format!("  {:<50} {}\n", "help", "Show this list of commands");
+
+        help
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_words, get_int_argument, get_mdw_arguments, MonitorCommands};
+
+    #[test]
+    fn parse_int_argument() {
+        assert!(get_int_argument(&["0x2000_0000"], 0).is_err());
+        assert_eq!(get_int_argument(&["0x20000000"], 0).unwrap(), 0x2000_0000);
+        assert_eq!(get_int_argument(&["1000"], 0).unwrap(), 1000);
+        assert!(get_int_argument(&["1000"], 1).is_err());
+    }
+
+    #[test]
+    fn parse_mdw_arguments() {
+        assert_eq!(
+            get_mdw_arguments(&["0x20000000"]).unwrap(),
+            (0x2000_0000, 1)
+        );
+        assert_eq!(
+            get_mdw_arguments(&["0x20000000", "6"]).unwrap(),
+            (0x2000_0000, 6)
+        );
+        assert_eq!(
+            get_mdw_arguments(&["0xfffffffc", "1"]).unwrap(),
+            (0xffff_fffc, 1)
+        );
+        assert!(get_mdw_arguments(&[]).is_err());
+        assert!(get_mdw_arguments(&["0x20000000", "0"]).is_err());
+        assert!(get_mdw_arguments(&["0x20000000", "4294967295"]).is_err());
+        assert!(get_mdw_arguments(&["0xfffffffc", "2"]).is_err());
+    }
+
+    #[test]
+    fn format_memory_words() {
+        assert_eq!(
+            format_words(0x2000_0000, &[1, 2, 3, 4, 0xdead_beef]),
+            "0x20000000: 00000001 00000002 00000003 00000004\n0x20000010: deadbeef\n"
+        );
+        assert_eq!(format_words(0xffff_fffc, &[0x10]), "0xfffffffc: 00000010\n");
+    }
+
+    #[test]
+    fn help_lists_all_commands() {
+        let help = MonitorCommands::new().help();
+
+        assert!(help.starts_with("The following monitor commands are available:\n"));
+        for usage in &[
+            "reset [halt|run]",
+            "mdw <address> [count]",
+            "speed <kHz>",
+            "help",
+        ] {
+            assert!(
+                help.lines()
+                    .any(|line| line.trim_start().starts_with(usage)),
+                "{} is missing from the help",
+                usage
+            );
+        }
+    }
+}
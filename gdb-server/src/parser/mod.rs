@@ -62,7 +62,10 @@ pub enum Packet {
         reg_values: String,
     },
     /// Packet `H`
-    SelectThread,
+    SelectThread {
+        operation: ThreadOperation,
+        thread: ThreadId,
+    },
     /// Packet `i`
     StepClockCycle,
     /// Packet `I`
@@ -98,7 +101,7 @@ pub enum Packet {
     // Packet 't'
     SearchBackwards,
     // Packet 'T'
-    ThreadInfo,
+    ThreadInfo(ThreadId),
     // Packet 'v'
     V(VPacket),
     // Packet 'X'
@@ -131,6 +134,35 @@ pub enum BreakpointType {
     AccessWatchpoint,
 }
 
+/// The operations a thread can be selected for with the `H` packet.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ThreadOperation {
+    /// `Hg`, used for register and memory accesses
+    General,
+    /// `Hc`, used for the `c` and `s` packets
+    Continue,
+}
+
+/// A thread ID as sent by GDB.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ThreadId {
+    /// `-1`, all threads
+    All,
+    /// `0`, an arbitrary thread
+    Any,
+    Id(u32),
+}
+
+impl ThreadId {
+    /// Check if `id` is one of the threads specified by this thread ID.
+    pub fn matches(self, id: u32) -> bool {
+        match self {
+            ThreadId::All | ThreadId::Any => true,
+            ThreadId::Id(thread) => thread == id,
+        }
+    }
+}
+
 pub fn parse_packet(input: &[u8]) -> Result<Packet> {
     let parse_result = alt((
         extended_mode,
@@ -148,6 +180,8 @@ pub fn parse_packet(input: &[u8]) -> Result<Packet> {
         continue_packet,
         write_register,
         write_register_hex,
+        select_thread,
+        thread_alive,
     ))(input);
 
     match parse_result {
@@ -215,6 +249,43 @@ fn write_register_hex(input: &[u8]) -> IResult<&[u8], Packet> {
     ))
 }
 
+/// Parse a thread ID, which is either `-1` or a hex number.
+fn thread_id(input: &[u8]) -> IResult<&[u8], ThreadId> {
+    alt((value(ThreadId::All, tag("-1")), positive_thread_id))(input)
+}
+
+fn positive_thread_id(input: &[u8]) -> IResult<&[u8], ThreadId> {
+    let (input, id) = hex_u32(input)?;
+
+    let thread_id = match id {
+        0 => ThreadId::Any,
+        id => ThreadId::Id(id),
+    };
+
+    Ok((input, thread_id))
+}
+
+fn select_thread(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, _) = char('H')(input)?;
+
+    let (input, operation) = alt((
+        value(ThreadOperation::General, char('g')),
+        value(ThreadOperation::Continue, char('c')),
+    ))(input)?;
+
+    let (input, thread) = thread_id(input)?;
+
+    Ok((input, Packet::SelectThread { operation, thread }))
+}
+
+fn thread_alive(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, _) = char('T')(input)?;
+
+    let (input, thread) = thread_id(input)?;
+
+    Ok((input, Packet::ThreadInfo(thread)))
+}
+
 fn query(input: &[u8]) -> IResult<&[u8], Packet> {
     let (input, _) = char('q')(input)?;
     let (input, packet) = query_packet(input)?;
@@ -438,6 +509,41 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_select_thread() {
+        assert_eq!(
+            parse_packet(b"Hg2").unwrap(),
+            Packet::SelectThread {
+                operation: ThreadOperation::General,
+                thread: ThreadId::Id(2),
+            }
+        );
+
+        assert_eq!(
+            parse_packet(b"Hc-1").unwrap(),
+            Packet::SelectThread {
+                operation: ThreadOperation::Continue,
+                thread: ThreadId::All,
+            }
+        );
+
+        assert_eq!(
+            parse_packet(b"Hg0").unwrap(),
+            Packet::SelectThread {
+                operation: ThreadOperation::General,
+                thread: ThreadId::Any,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_thread_alive() {
+        assert_eq!(
+            parse_packet(b"Ta").unwrap(),
+            Packet::ThreadInfo(ThreadId::Id(10))
+        );
+    }
+
     #[test]
     fn parse_write_memory_binary() {
         assert_eq!(
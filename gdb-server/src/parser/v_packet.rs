@@ -1,14 +1,32 @@
-use super::{query::pid, Pid};
+use super::{query::pid, thread_id, Pid, ThreadId};
 use nom::{
-    branch::alt, bytes::complete::tag, character::complete::char, combinator::value, IResult,
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::char,
+    combinator::{opt, value},
+    multi::many1,
+    number::complete::hex_u32,
+    sequence::preceded,
+    IResult,
 };
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum VPacket {
     Attach(Pid),
-    Continue(Action),
+    /// The actions of a `vCont` packet, together with the threads they apply to.
+    /// Actions without a thread apply to all threads.
+    Continue(Vec<(Action, ThreadId)>),
     Unknown(Vec<u8>),
     QueryContSupport,
+    FlashErase {
+        address: u32,
+        length: u32,
+    },
+    FlashWrite {
+        address: u32,
+        data: Vec<u8>,
+    },
+    FlashDone,
 }
 
 #[allow(dead_code)]
@@ -23,7 +41,14 @@ pub enum Action {
 }
 
 pub fn v_packet(input: &[u8]) -> IResult<&[u8], VPacket> {
-    let parse_result = alt((v_attach, v_cont_support, v_cont))(input);
+    let parse_result = alt((
+        v_attach,
+        v_cont_support,
+        v_cont,
+        v_flash_erase,
+        v_flash_write,
+        v_flash_done,
+    ))(input);
 
     match parse_result {
         Ok((input, packet)) => Ok((input, packet)),
@@ -51,11 +76,48 @@ fn v_cont_support(input: &[u8]) -> IResult<&[u8], VPacket> {
 }
 
 fn v_cont(input: &[u8]) -> IResult<&[u8], VPacket> {
-    let (input, _) = tag("Cont;")(input)?;
+    let (input, _) = tag("Cont")(input)?;
 
+    let (input, actions) = many1(preceded(char(';'), v_cont_thread_action))(input)?;
+
+    Ok((input, VPacket::Continue(actions)))
+}
+
+fn v_cont_thread_action(input: &[u8]) -> IResult<&[u8], (Action, ThreadId)> {
     let (input, action) = v_cont_action(input)?;
 
-    Ok((input, VPacket::Continue(action)))
+    let (input, thread) = opt(preceded(char(':'), thread_id))(input)?;
+
+    Ok((input, (action, thread.unwrap_or(ThreadId::All))))
+}
+
+fn v_flash_erase(input: &[u8]) -> IResult<&[u8], VPacket> {
+    let (input, _) = tag("FlashErase:")(input)?;
+
+    let (input, address) = hex_u32(input)?;
+    let (input, _) = char(',')(input)?;
+    let (input, length) = hex_u32(input)?;
+
+    Ok((input, VPacket::FlashErase { address, length }))
+}
+
+fn v_flash_write(input: &[u8]) -> IResult<&[u8], VPacket> {
+    let (input, _) = tag("FlashWrite:")(input)?;
+
+    let (input, address) = hex_u32(input)?;
+    let (data, _) = char(':')(input)?;
+
+    Ok((
+        &[],
+        VPacket::FlashWrite {
+            address,
+            data: data.to_owned(),
+        },
+    ))
+}
+
+fn v_flash_done(input: &[u8]) -> IResult<&[u8], VPacket> {
+    value(VPacket::FlashDone, tag("FlashDone"))(input)
 }
 
 fn v_cont_action(input: &[u8]) -> IResult<&[u8], Action> {
@@ -89,7 +151,10 @@ mod test {
     fn parse_v_cont_cont() {
         assert_eq!(
             v_packet(b"Cont;c").unwrap(),
-            (EMPTY, VPacket::Continue(Action::Continue))
+            (
+                EMPTY,
+                VPacket::Continue(vec![(Action::Continue, ThreadId::All)])
+            )
         );
     }
 
@@ -97,7 +162,24 @@ mod test {
     fn parse_v_cont_step() {
         assert_eq!(
             v_packet(b"Cont;s").unwrap(),
-            (EMPTY, VPacket::Continue(Action::Step))
+            (
+                EMPTY,
+                VPacket::Continue(vec![(Action::Step, ThreadId::All)])
+            )
+        );
+    }
+
+    #[test]
+    fn parse_v_cont_threads() {
+        assert_eq!(
+            v_packet(b"Cont;s:2;c").unwrap(),
+            (
+                EMPTY,
+                VPacket::Continue(vec![
+                    (Action::Step, ThreadId::Id(2)),
+                    (Action::Continue, ThreadId::All)
+                ])
+            )
         );
     }
 
@@ -105,7 +187,43 @@ mod test {
     fn parse_v_cont_stop() {
         assert_eq!(
             v_packet(b"Cont;t").unwrap(),
-            (EMPTY, VPacket::Continue(Action::Stop))
+            (
+                EMPTY,
+                VPacket::Continue(vec![(Action::Stop, ThreadId::All)])
+            )
+        );
+    }
+
+    #[test]
+    fn parse_v_flash_erase() {
+        assert_eq!(
+            v_packet(b"FlashErase:08000000,4000").unwrap(),
+            (
+                EMPTY,
+                VPacket::FlashErase {
+                    address: 0x0800_0000,
+                    length: 0x4000
+                }
+            )
         );
     }
+
+    #[test]
+    fn parse_v_flash_write() {
+        assert_eq!(
+            v_packet(b"FlashWrite:08000000:\x00\x01:").unwrap(),
+            (
+                EMPTY,
+                VPacket::FlashWrite {
+                    address: 0x0800_0000,
+                    data: vec![0x00, 0x01, b':']
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_v_flash_done() {
+        assert_eq!(v_packet(b"FlashDone").unwrap(), (EMPTY, VPacket::FlashDone));
+    }
 }
@@ -4,55 +4,110 @@ use futures::channel::mpsc;
 use futures::future::FutureExt;
 use futures::select;
 use gdb_protocol::packet::{CheckedPacket, Kind as PacketKind};
-use probe_rs::Session;
+use probe_rs::flashing::FlashLoader;
+use probe_rs::semihosting::{Semihosting, SemihostingOutcome};
+use probe_rs::{CoreStatus, HaltReason, Session};
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::ops::Range;
 use std::{sync::Mutex, time::Duration};
 
-use crate::parser::parse_packet;
+use crate::parser::{parse_packet, ThreadId};
 
-use crate::handlers;
+use crate::handlers::{self, SoftwareBreakpoint};
+use crate::monitor::MonitorCommands;
 
 type ServerResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 type Sender<T> = mpsc::UnboundedSender<T>;
 type Receiver<T> = mpsc::UnboundedReceiver<T>;
 
+/// State of a GDB connection, which is kept between packets.
+///
+/// Every core of the target is represented as a GDB thread.
+pub struct ConnectionState {
+    /// Cores which were resumed, and for which GDB awaits a stop reply.
+    running_cores: Vec<usize>,
+    /// Core used for register and memory accesses, selected with `Hg`.
+    general_core: usize,
+    /// Threads resumed by the `c` packet, selected with `Hc`.
+    continue_thread: ThreadId,
+    /// Data written with `vFlashWrite`, which is flashed on `vFlashDone`.
+    flash_loader: Option<FlashLoader>,
+    /// Flash ranges erased with `vFlashErase`, which are erased on `vFlashDone`.
+    flash_erased_ranges: Vec<Range<u32>>,
+    /// Flash ranges written with `vFlashWrite`.
+    flash_written_ranges: Vec<Range<u32>>,
+    /// Breakpoints inserted with `Z0`, by address.
+    software_breakpoints: HashMap<u32, SoftwareBreakpoint>,
+    /// Original instructions of the breakpoints currently written to flash, by address.
+    flash_breakpoints: HashMap<u32, Vec<u8>>,
+}
+
+impl ConnectionState {
+    fn new() -> Self {
+        Self {
+            running_cores: Vec::new(),
+            general_core: 0,
+            continue_thread: ThreadId::All,
+            flash_loader: None,
+            flash_erased_ranges: Vec::new(),
+            flash_written_ranges: Vec::new(),
+            software_breakpoints: HashMap::new(),
+            flash_breakpoints: HashMap::new(),
+        }
+    }
+}
+
 pub async fn worker(
     mut input_stream: Receiver<CheckedPacket>,
     output_stream: Sender<CheckedPacket>,
     session: &Mutex<Session>,
 ) -> ServerResult<()> {
-    // When we first attach to the core, GDB expects us to halt the core, so we do this here when a new client connects.
-    // If the core is already halted, nothing happens if we issue a halt command again, so we always do this no matter of core state.
-    session
-        .lock()
-        .unwrap()
-        .core(0)?
-        .halt(Duration::from_millis(100))?;
+    // When we first attach to the cores, GDB expects us to halt them, so we do this here when a new client connects.
+    // If a core is already halted, nothing happens if we issue a halt command again, so we always do this no matter of core state.
+    {
+        let mut session = session.lock().unwrap();
 
-    let mut awaits_halt = false;
+        for (core_index, _) in session.list_cores() {
+            session.core(core_index)?.halt(Duration::from_millis(100))?;
+        }
+    }
+
+    let mut state = ConnectionState::new();
+    let mut semihosting = Semihosting::new();
+    let monitor_commands = MonitorCommands::new();
 
     loop {
         select! {
             potential_packet = input_stream.next().fuse() => {
                 if let Some(packet) = potential_packet {
                     log::warn!("WORKING {}", String::from_utf8_lossy(&packet.data));
-                    if handler(&session, &output_stream, &mut awaits_halt, packet).await? {
+                    if handler(&session, &output_stream, &mut state, &monitor_commands, packet).await? {
                         break;
                     }
                 } else {
                     break
                 }
             },
-            _ = await_halt(session, &output_stream, &mut awaits_halt).fuse() => {}
+            _ = await_halt(session, &output_stream, &mut state, &mut semihosting).fuse() => {}
         }
     }
+
+    // The original instructions have to be restored, because GDB won't do it after detaching.
+    handlers::remove_software_breaks(
+        &mut session.lock().unwrap(),
+        &mut state.software_breakpoints,
+        &mut state.flash_breakpoints,
+    );
+
     Ok(())
 }
 
 pub async fn handler(
     session: &Mutex<Session>,
     output_stream: &Sender<CheckedPacket>,
-    awaits_halt: &mut bool,
+    state: &mut ConnectionState,
+    monitor_commands: &MonitorCommands,
     packet: CheckedPacket,
 ) -> ServerResult<bool> {
     let parsed_packet = parse_packet(&packet.data);
@@ -69,80 +124,153 @@ pub async fn handler(
             log::debug!("Parsed packet: {:?}", parsed_packet);
             let mut session = session.lock().expect("Poisoned Mutex");
             match parsed_packet {
-                HaltReason => handlers::halt_reason(),
-                Continue => handlers::run(session.core(0)?, awaits_halt),
+                HaltReason => handlers::halt_reason(state.general_core),
+                Continue => handlers::resume(
+                    &mut session,
+                    &[(Action::Continue, state.continue_thread)],
+                    &mut state.running_cores,
+                    &state.software_breakpoints,
+                    &mut state.flash_breakpoints,
+                )?,
                 V(VPacket::QueryContSupport) => handlers::vcont_supported(),
+                V(VPacket::FlashErase { address, length }) => {
+                    handlers::flash_erase(&mut state.flash_erased_ranges, address, length)
+                }
+                V(VPacket::FlashWrite { address, data }) => handlers::flash_write(
+                    &session,
+                    &mut state.flash_loader,
+                    &mut state.flash_written_ranges,
+                    address,
+                    &data,
+                ),
+                V(VPacket::FlashDone) => handlers::flash_done(
+                    &mut session,
+                    &mut state.flash_loader,
+                    &mut state.flash_erased_ranges,
+                    &mut state.flash_written_ranges,
+                ),
                 Query(QueryPacket::Supported { .. }) => handlers::q_supported(),
                 Query(QueryPacket::Attached { .. }) => handlers::q_attached(),
-                Query(QueryPacket::Command(cmd)) => {
-                    if cmd == b"reset" {
-                        handlers::reset_halt(session.core(0)?)
-                    } else {
-                        log::debug!("Unknown monitor command: '{:?}'", cmd);
-                        Some(hex::encode(
-                            "Unknown monitor command\nOnly 'reset' is currently supported\n"
-                                .as_bytes(),
-                        ))
+                Query(QueryPacket::Command(command)) => {
+                    let command = String::from_utf8_lossy(&command);
+
+                    match monitor_commands.execute(&mut session, state.general_core, &command) {
+                        Ok(output) => {
+                            send_console_output(output_stream, &output)?;
+                            handlers::reply_ok()
+                        }
+                        Err(e) => {
+                            log::warn!("Monitor command '{}' failed: {:#}", command, e);
+                            send_console_output(output_stream, &format!("{}\n", e))?;
+                            Some("E01".to_string())
+                        }
                     }
                 }
                 Query(QueryPacket::HostInfo) => handlers::host_info(),
-                ReadGeneralRegister => handlers::read_general_registers(session.core(0)?),
-                ReadRegisterHex(register) => handlers::read_register(register, session.core(0)?),
-                WriteGeneralRegister { reg_values } => {
-                    handlers::write_general_registers(&reg_values, session.core(0)?)
+                Query(QueryPacket::ThreadId) => handlers::current_thread(state.general_core),
+                Query(QueryPacket::FirstThreadInfo) => handlers::first_thread_info(&session),
+                Query(QueryPacket::SubsequentThreadInfo) => handlers::subsequent_thread_info(),
+                Query(QueryPacket::ThreadExtraInfo(thread)) => {
+                    handlers::thread_extra_info(&session, thread)
+                }
+                SelectThread { operation, thread } => handlers::select_thread(
+                    &session,
+                    operation,
+                    thread,
+                    &mut state.general_core,
+                    &mut state.continue_thread,
+                ),
+                ThreadInfo(thread) => handlers::thread_alive(&session, thread),
+                ReadGeneralRegister => {
+                    handlers::read_general_registers(session.core(state.general_core)?)
                 }
+                ReadRegisterHex(register) => {
+                    handlers::read_register(register, session.core(state.general_core)?)
+                }
+                WriteGeneralRegister { reg_values } => handlers::write_general_registers(
+                    &reg_values,
+                    session.core(state.general_core)?,
+                ),
                 WriteRegisterHex { address, value } => {
-                    handlers::write_register(address, &value, session.core(0)?)
+                    handlers::write_register(address, &value, session.core(state.general_core)?)
                 }
                 ReadMemory { address, length } => {
                     // LLDB will send 64 bit addresses, which are not supported by probe-rs
                     // yet.
 
                     if let Ok(address) = u32::try_from(address) {
-                        handlers::read_memory(address, length, session.core(0)?)
+                        handlers::read_memory(
+                            address,
+                            length,
+                            session.core(state.general_core)?,
+                            &state.flash_breakpoints,
+                        )
                     } else {
                         //
                         handlers::reply_empty()
                     }
                 }
                 Detach => handlers::detach(&mut break_due),
-                V(VPacket::Continue(action)) => match action {
-                    Action::Continue => handlers::run(session.core(0)?, awaits_halt),
-                    Action::Stop => handlers::stop(session.core(0)?, awaits_halt),
-                    Action::Step => handlers::step(session.core(0)?, awaits_halt),
-                    other => {
-                        log::warn!("vCont with action {:?} not supported", other);
-                        handlers::reply_empty()
-                    }
-                },
+                V(VPacket::Continue(actions)) => handlers::resume(
+                    &mut session,
+                    &actions,
+                    &mut state.running_cores,
+                    &state.software_breakpoints,
+                    &mut state.flash_breakpoints,
+                )?,
                 InsertBreakpoint {
                     breakpoint_type,
                     address,
                     kind,
                 } => match breakpoint_type {
-                    BreakpointType::Hardware => {
-                        handlers::insert_hardware_break(address, kind, session.core(0)?)
-                    }
-                    other => {
-                        log::warn!("Breakpoint type {:?} is not supported.", other);
-                        handlers::reply_empty()
-                    }
+                    BreakpointType::Software => handlers::insert_software_break(
+                        &mut session,
+                        state.general_core,
+                        &mut state.software_breakpoints,
+                        &state.flash_breakpoints,
+                        address,
+                        kind,
+                    ),
+                    BreakpointType::Hardware => handlers::insert_hardware_break(
+                        address,
+                        kind,
+                        session.core(state.general_core)?,
+                    ),
+                    BreakpointType::WriteWatchpoint
+                    | BreakpointType::ReadWatchpoint
+                    | BreakpointType::AccessWatchpoint => handlers::insert_watchpoint(
+                        address,
+                        kind,
+                        handlers::watchpoint_kind(&breakpoint_type),
+                        session.core(state.general_core)?,
+                    ),
                 },
                 RemoveBreakpoint {
                     breakpoint_type,
                     address,
                     kind,
                 } => match breakpoint_type {
-                    BreakpointType::Hardware => {
-                        handlers::remove_hardware_break(address, kind, session.core(0)?)
-                    }
-                    other => {
-                        log::warn!("Breakpoint type {:?} is not supported.", other);
-                        handlers::reply_empty()
-                    }
+                    BreakpointType::Software => handlers::remove_software_break(
+                        &mut session,
+                        &mut state.software_breakpoints,
+                        address,
+                    ),
+                    BreakpointType::Hardware => handlers::remove_hardware_break(
+                        address,
+                        kind,
+                        session.core(state.general_core)?,
+                    ),
+                    BreakpointType::WriteWatchpoint
+                    | BreakpointType::ReadWatchpoint
+                    | BreakpointType::AccessWatchpoint => handlers::remove_watchpoint(
+                        address,
+                        kind,
+                        handlers::watchpoint_kind(&breakpoint_type),
+                        session.core(state.general_core)?,
+                    ),
                 },
                 WriteMemoryBinary { address, data } => {
-                    handlers::write_memory(address, &data, session.core(0)?)
+                    handlers::write_memory(address, &data, session.core(state.general_core)?)
                 }
                 Query(QueryPacket::Transfer { object, operation }) => {
                     use crate::parser::query::TransferOperation;
@@ -176,7 +304,11 @@ pub async fn handler(
                         }
                     }
                 }
-                Interrupt => handlers::user_halt(session.core(0)?, awaits_halt),
+                Interrupt => handlers::user_halt(
+                    &mut session,
+                    &mut state.running_cores,
+                    &mut state.general_core,
+                )?,
                 other => {
                     log::warn!("Unknown command: '{:?}'", other);
 
@@ -211,20 +343,72 @@ pub async fn handler(
     Ok(break_due)
 }
 
+/// Send output to the GDB console, split into `O` packets.
+fn send_console_output(output_stream: &Sender<CheckedPacket>, output: &str) -> ServerResult<()> {
+    // Each byte is sent as two hex digits, this keeps packets below the maximum packet size.
+    for chunk in output.as_bytes().chunks(512) {
+        let packet = format!("O{}", hex::encode(chunk));
+
+        output_stream.unbounded_send(CheckedPacket::from_data(
+            PacketKind::Packet,
+            packet.into_bytes(),
+        ))?;
+    }
+
+    Ok(())
+}
+
 pub async fn await_halt(
     session: &Mutex<Session>,
     output_stream: &Sender<CheckedPacket>,
-    await_halt: &mut bool,
+    state: &mut ConnectionState,
+    semihosting: &mut Semihosting,
 ) -> ServerResult<()> {
     task::sleep(Duration::from_millis(10)).await;
-    if *await_halt {
+    if !state.running_cores.is_empty() {
         let mut session = session.lock().expect("Poisoned Mutex");
-        if session.core(0)?.core_halted().unwrap() {
-            let response = CheckedPacket::from_data(PacketKind::Packet, b"T05hwbreak:;".to_vec());
+
+        let mut halted_core = None;
+        for core_index in &state.running_cores {
+            if session.core(*core_index)?.core_halted()? {
+                halted_core = Some(*core_index);
+                break;
+            }
+        }
+
+        if let Some(core_index) = halted_core {
+            let mut core = session.core(core_index)?;
+
+            let (response, stopped) =
+                if core.status()? == CoreStatus::Halted(HaltReason::Semihosting) {
+                    // Semihosting requests are serviced here, GDB only sees
+                    // the console output and the exit of the program.
+                    match semihosting.service(&mut core)? {
+                        SemihostingOutcome::Resumed => return Ok(()),
+                        SemihostingOutcome::Output { data, .. } => {
+                            (format!("O{}", hex::encode(data)), false)
+                        }
+                        SemihostingOutcome::Exit(code) => (format!("W{:02x}", code as u8), true),
+                    }
+                } else {
+                    state.general_core = core_index;
+                    (
+                        handlers::stop_reply(&mut core, core_index, &state.software_breakpoints),
+                        true,
+                    )
+                };
+
+            drop(core);
+
+            // In all-stop mode, the other cores are stopped as well when one core halts.
+            if stopped {
+                handlers::halt_cores(&mut session, &mut state.running_cores)?;
+            }
+
+            let response = CheckedPacket::from_data(PacketKind::Packet, response.into_bytes());
 
             let mut bytes = Vec::new();
             response.encode(&mut bytes).unwrap();
-            *await_halt = false;
 
             let _ = output_stream.unbounded_send(response);
         }
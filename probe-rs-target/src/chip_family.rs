@@ -1,6 +1,9 @@
 use super::chip::Chip;
 use super::flash_algorithm::RawFlashAlgorithm;
+use super::memory::{MemoryRange, MemoryRegion};
 use jep106::JEP106Code;
+use std::fmt;
+use std::ops::Range;
 
 use serde::{Deserialize, Serialize};
 
@@ -130,4 +133,320 @@ impl ChipFamily {
         let name = name.as_ref();
         self.flash_algorithms.iter().find(|elem| elem.name == name)
     }
+
+    /// Check the target description for mistakes which serde can not detect, and which
+    /// would otherwise only show up when flashing. All the problems which are found are
+    /// collected in the returned [ValidationError].
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut problems = Vec::new();
+
+        for algorithm in &self.flash_algorithms {
+            validate_flash_properties(algorithm, &mut problems);
+        }
+
+        for chip in &self.variants {
+            for region in &chip.memory_map {
+                let (range, cores) = region_range_and_cores(region);
+                check_cores(
+                    chip,
+                    cores,
+                    &format!("the memory region {:#010x?}", range),
+                    &mut problems,
+                );
+            }
+
+            let mut algorithms = Vec::new();
+            for name in &chip.flash_algorithms {
+                match self.get_algorithm(name) {
+                    Some(algorithm) => {
+                        check_cores(
+                            chip,
+                            &algorithm.cores,
+                            &format!("the flash algorithm '{}'", algorithm.name),
+                            &mut problems,
+                        );
+                        algorithms.push(algorithm);
+                    }
+                    None => problems.push(format!(
+                        "{}: the flash algorithm '{}' does not exist",
+                        chip.name, name
+                    )),
+                }
+            }
+
+            for (index, region) in chip.memory_map.iter().enumerate() {
+                let (range, _) = region_range_and_cores(region);
+                for other in &chip.memory_map[index + 1..] {
+                    let (other_range, _) = region_range_and_cores(other);
+                    if range.start < other_range.end && other_range.start < range.end {
+                        problems.push(format!(
+                            "{}: the memory regions {:#010x?} and {:#010x?} overlap",
+                            chip.name, range, other_range
+                        ));
+                    }
+                }
+            }
+
+            for region in chip.memory_map.iter().filter_map(|region| match region {
+                MemoryRegion::Nvm(region) => Some(region),
+                _ => None,
+            }) {
+                let intersecting: Vec<_> = algorithms
+                    .iter()
+                    .filter(|algorithm| {
+                        algorithm
+                            .flash_properties
+                            .address_range
+                            .intersects_range(&region.range)
+                    })
+                    .collect();
+                let covering: Vec<_> = intersecting
+                    .iter()
+                    .filter(|algorithm| {
+                        algorithm
+                            .flash_properties
+                            .address_range
+                            .contains_range(&region.range)
+                    })
+                    .collect();
+
+                if covering.is_empty() {
+                    for algorithm in intersecting {
+                        problems.push(format!(
+                            "{}: the flash algorithm '{}' for {:#010x?} does not cover the whole NVM region {:#010x?}",
+                            chip.name,
+                            algorithm.name,
+                            algorithm.flash_properties.address_range,
+                            region.range
+                        ));
+                    }
+                } else if covering.len() > 1 && !covering.iter().any(|algorithm| algorithm.default)
+                {
+                    problems.push(format!(
+                        "{}: none of the flash algorithms for the NVM region {:#010x?} is the default",
+                        chip.name, region.range
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError {
+                family: self.name.clone(),
+                problems,
+            })
+        }
+    }
+}
+
+/// Check that the sectors of a flash algorithm start at the beginning of its flash, are ordered,
+/// and that each group of sectors starts at a page boundary. Sectors can be smaller than a page,
+/// so only their start is checked.
+fn validate_flash_properties(algorithm: &RawFlashAlgorithm, problems: &mut Vec<String>) {
+    let properties = &algorithm.flash_properties;
+    let range = &properties.address_range;
+
+    if range.start >= range.end {
+        problems.push(format!(
+            "flash algorithm '{}': the flash range {:#010x?} is empty",
+            algorithm.name, range
+        ));
+        return;
+    }
+    if properties.page_size == 0 {
+        problems.push(format!(
+            "flash algorithm '{}': the page size is 0",
+            algorithm.name
+        ));
+        return;
+    }
+
+    match properties.sectors.first() {
+        None => problems.push(format!(
+            "flash algorithm '{}': there are no sectors",
+            algorithm.name
+        )),
+        Some(sector) if sector.address != 0 => problems.push(format!(
+            "flash algorithm '{}': the first sectors start at {:#x} instead of the beginning of the flash",
+            algorithm.name, sector.address
+        )),
+        Some(_) => {}
+    }
+
+    let flash_size = range.end - range.start;
+    for (index, sector) in properties.sectors.iter().enumerate() {
+        if sector.size == 0 {
+            problems.push(format!(
+                "flash algorithm '{}': the sectors at {:#x} have a size of 0",
+                algorithm.name, sector.address
+            ));
+        }
+        if sector.address % properties.page_size != 0 {
+            problems.push(format!(
+                "flash algorithm '{}': the sectors at {:#x} are not aligned to the page size of {:#x} bytes",
+                algorithm.name, sector.address, properties.page_size
+            ));
+        }
+        if sector.address >= flash_size {
+            problems.push(format!(
+                "flash algorithm '{}': the sectors at {:#x} start behind the end of the flash at {:#x}",
+                algorithm.name, sector.address, flash_size
+            ));
+        }
+        if index > 0 && sector.address <= properties.sectors[index - 1].address {
+            problems.push(format!(
+                "flash algorithm '{}': the sectors at {:#x} are not in ascending order",
+                algorithm.name, sector.address
+            ));
+        }
+    }
+}
+
+/// Check that the cores which are referenced by `what` exist on the chip.
+fn check_cores(chip: &Chip, cores: &[String], what: &str, problems: &mut Vec<String>) {
+    for core in cores {
+        if !chip.cores.iter().any(|other| &other.name == core) {
+            problems.push(format!(
+                "{}: {} references the unknown core '{}'",
+                chip.name, what, core
+            ));
+        }
+    }
+}
+
+fn region_range_and_cores(region: &MemoryRegion) -> (&Range<u32>, &[String]) {
+    match region {
+        MemoryRegion::Ram(region) => (&region.range, &region.cores),
+        MemoryRegion::Generic(region) => (&region.range, &region.cores),
+        MemoryRegion::Nvm(region) => (&region.range, &region.cores),
+    }
+}
+
+/// The problems which [ChipFamily::validate] found in a target description.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// The name of the chip family.
+    pub family: String,
+    /// A description of each problem, starting with the chip or flash algorithm it was found in.
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The target description of {} has {} problem(s):",
+            self.family,
+            self.problems.len()
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ArmCoreAccessOptions, Core, CoreAccessOptions, FlashProperties, NvmRegion, RamRegion,
+        SectorDescription,
+    };
+
+    fn family() -> ChipFamily {
+        let algorithm = |name: &str, address_range: Range<u32>| RawFlashAlgorithm {
+            name: name.to_string(),
+            flash_properties: FlashProperties {
+                address_range,
+                page_size: 0x400,
+                sectors: vec![SectorDescription {
+                    size: 0x800,
+                    address: 0,
+                }],
+                ..Default::default()
+            },
+            cores: vec!["main".to_string()],
+            ..Default::default()
+        };
+
+        ChipFamily {
+            name: "TEST Series".to_string(),
+            manufacturer: None,
+            variants: vec![Chip {
+                name: "TEST1".to_string(),
+                part: None,
+                cores: vec![Core {
+                    name: "main".to_string(),
+                    core_type: CoreType::M4,
+                    core_access_options: CoreAccessOptions::Arm(ArmCoreAccessOptions {
+                        ap: 0,
+                        psel: 0,
+                    }),
+                }],
+                memory_map: vec![
+                    MemoryRegion::Nvm(NvmRegion {
+                        range: 0x0800_0000..0x0810_0000,
+                        is_boot_memory: true,
+                        cores: vec!["main".to_string()],
+                    }),
+                    MemoryRegion::Ram(RamRegion {
+                        range: 0x2000_0000..0x2002_0000,
+                        is_boot_memory: false,
+                        cores: vec!["main".to_string()],
+                    }),
+                ],
+                flash_algorithms: vec!["test_1m".to_string(), "test_2m".to_string()],
+            }],
+            flash_algorithms: vec![
+                algorithm("test_1m", 0x0800_0000..0x0810_0000),
+                algorithm("test_2m", 0x0800_0000..0x0820_0000),
+            ],
+            source: TargetDescriptionSource::BuiltIn,
+        }
+    }
+
+    #[test]
+    fn missing_default_algorithm() {
+        let mut family = family();
+        assert_eq!(
+            family.validate().unwrap_err().problems,
+            vec!["TEST1: none of the flash algorithms for the NVM region 0x08000000..0x08100000 is the default"]
+        );
+
+        family.flash_algorithms[1].default = true;
+        assert_eq!(family.validate(), Ok(()));
+    }
+
+    #[test]
+    fn collect_all_problems() {
+        let mut family = family();
+        family.flash_algorithms[0].default = true;
+        family.flash_algorithms[1].flash_properties.address_range = 0x0800_0000..0x0808_0000;
+        family.flash_algorithms[1].flash_properties.sectors[0].address = 0x200;
+        family.flash_algorithms[1].cores.push("secure".to_string());
+        family.variants[0].memory_map[1] = MemoryRegion::Ram(RamRegion {
+            range: 0x080f_0000..0x0811_0000,
+            is_boot_memory: false,
+            cores: vec!["main".to_string()],
+        });
+        family.variants[0]
+            .flash_algorithms
+            .push("test_otp".to_string());
+
+        assert_eq!(
+            family.validate().unwrap_err().problems,
+            vec![
+                "flash algorithm 'test_2m': the first sectors start at 0x200 instead of the beginning of the flash",
+                "flash algorithm 'test_2m': the sectors at 0x200 are not aligned to the page size of 0x400 bytes",
+                "TEST1: the flash algorithm 'test_2m' references the unknown core 'secure'",
+                "TEST1: the flash algorithm 'test_otp' does not exist",
+                "TEST1: the memory regions 0x08000000..0x08100000 and 0x080f0000..0x08110000 overlap",
+            ]
+        );
+    }
 }
@@ -17,6 +17,7 @@ fn main() {
     }
 
     let mut families: Vec<ChipFamily> = Vec::new();
+    let mut invalid_families = Vec::new();
 
     let mut files = vec![];
     visit_dirs(&Path::new("targets"), &mut files).unwrap();
@@ -28,11 +29,24 @@ fn main() {
         let yaml: Result<ChipFamily, _> = serde_yaml::from_str(&string);
 
         match yaml {
-            Ok(familiy) => families.push(familiy),
+            Ok(familiy) => {
+                if let Err(e) = familiy.validate() {
+                    invalid_families.push(format!("{:?}: {}", file, e));
+                }
+                families.push(familiy)
+            }
             Err(e) => panic!("Failed to parse target file: {:?} because:\n{}", file, e),
         }
     }
 
+    if !invalid_families.is_empty() {
+        panic!(
+            "Found problems in {} target file(s):\n{}",
+            invalid_families.len(),
+            invalid_families.join("\n")
+        );
+    }
+
     let families_bin =
         bincode::serialize(&families).expect("Failed to serialize families as bincode");
 
@@ -62,6 +62,12 @@ pub trait ArmProbeInterface: DapAccess + SwoAccess + Debug + Send {
     /// See also [`Probe::target_reset_deassert`].
     fn target_reset_deassert(&mut self) -> Result<(), ProbeRsError>;
 
+    /// Change the protocol speed of the probe while attached.
+    ///
+    /// Returns the speed which was actually set, in kHz, or [`DebugProbeError::Attached`]
+    /// if the probe can't change its speed while attached.
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, ProbeRsError>;
+
     fn close(self: Box<Self>) -> Probe;
 }
 
@@ -224,6 +230,14 @@ impl ArmProbeInterface for ArmCommunicationInterface {
         Ok(())
     }
 
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, ProbeRsError> {
+        if !self.probe.can_set_speed_while_attached() {
+            return Err(DebugProbeError::Attached.into());
+        }
+
+        Ok(self.probe.set_speed(speed_khz)?)
+    }
+
     fn close(self: Box<Self>) -> Probe {
         Probe::from_attached_probe(self.probe.into_probe())
     }
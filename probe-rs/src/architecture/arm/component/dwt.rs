@@ -5,12 +5,38 @@
 //!
 //! See ARMv7-M architecture reference manual C1.8 for some additional
 //! info about this stuff.
-
+//!
+//! The comparators are also used for hardware watchpoints. The DWT is located at the
+//! same address on all M-profile cores, so the watchpoint functions access it directly
+//! through the memory of the core. ARMv6-M and ARMv7-M share the encoding of the
+//! comparator registers, and use the MASK register to watch a range of addresses.
+//! ARMv8-M has no MASK register and encodes the match type differently, see the
+//! ARMv8-M Architecture Reference Manual, B13.2.
+
+use anyhow::anyhow;
 use bitfield::bitfield;
 
+use super::super::core::m4::Demcr;
 use super::super::memory::romtable::Component;
 use super::DebugRegister;
-use crate::{Core, Error};
+use crate::core::{CoreRegister, Watchpoint, WatchpointKind};
+use crate::{Core, Error, MemoryInterface};
+
+/// The architectural base address of the DWT on M-profile cores.
+const DWT_ADDRESS: u32 = 0xE000_1000;
+
+/// `DWT_FUNCTION` values of ARMv6-M and ARMv7-M for a watchpoint on read, write or any access.
+const FUNCTION_V7_READ: u8 = 0b0101;
+const FUNCTION_V7_WRITE: u8 = 0b0110;
+const FUNCTION_V7_ACCESS: u8 = 0b0111;
+
+/// `DWT_FUNCTION.MATCH` values of ARMv8-M for a data address on any access, write or read.
+const MATCH_V8_ACCESS: u8 = 0b0100;
+const MATCH_V8_WRITE: u8 = 0b0101;
+const MATCH_V8_READ: u8 = 0b0110;
+
+/// `DWT_FUNCTION.ACTION` of ARMv8-M which generates a debug event, i.e. halts the core.
+const ACTION_V8_DEBUG_EVENT: u8 = 0b01;
 
 /// A struct representing a DWT unit on target.
 pub struct Dwt<'probe: 'core, 'core> {
@@ -302,7 +328,10 @@ bitfield! {
     pub datavmatch, set_datavmatch: 8;
     pub cycmatch, set_cycmatch: 7;
     pub emitrange, set_emitrange: 5;
-    pub function, set_function: 3, 0;
+    /// ARMv8-M only, in place of EMITRANGE: the action on a match.
+    pub u8, action, set_action: 5, 4;
+    /// The MATCH field on ARMv8-M.
+    pub u8, function, set_function: 3, 0;
 }
 
 impl Default for Function {
@@ -327,3 +356,263 @@ impl DebugRegister for Function {
     const ADDRESS: u32 = 0x28;
     const NAME: &'static str = "DWT/FUNCTION";
 }
+
+/// The DWT version, which determines the encoding of the comparator registers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum DwtVersion {
+    /// ARMv6-M and ARMv7-M
+    V7,
+    /// ARMv8-M
+    V8,
+}
+
+fn load_unit<R: DebugRegister>(
+    memory: &mut impl MemoryInterface,
+    unit_index: usize,
+) -> Result<R, Error> {
+    Ok(R::from(memory.read_word_32(
+        DWT_ADDRESS + R::ADDRESS + 16 * unit_index as u32,
+    )?))
+}
+
+fn store_unit<R: DebugRegister>(
+    memory: &mut impl MemoryInterface,
+    unit_index: usize,
+    register: R,
+) -> Result<(), Error> {
+    memory.write_word_32(
+        DWT_ADDRESS + R::ADDRESS + 16 * unit_index as u32,
+        register.into(),
+    )
+}
+
+pub(crate) fn get_available_watchpoint_units(
+    memory: &mut impl MemoryInterface,
+) -> Result<u32, Error> {
+    let ctrl = Ctrl::from(memory.read_word_32(DWT_ADDRESS + Ctrl::ADDRESS)?);
+
+    Ok(ctrl.numcomp().into())
+}
+
+/// Read the watchpoints from all DWT comparators. Comparators which are not configured
+/// as a halting data address watchpoint are returned as `None`.
+pub(crate) fn get_hw_watchpoints(
+    memory: &mut impl MemoryInterface,
+    version: DwtVersion,
+) -> Result<Vec<Option<Watchpoint>>, Error> {
+    let num_units = get_available_watchpoint_units(memory)? as usize;
+
+    let mut watchpoints = Vec::with_capacity(num_units);
+
+    for unit_index in 0..num_units {
+        let function: Function = load_unit(memory, unit_index)?;
+
+        let kind_and_size = match version {
+            DwtVersion::V7 => {
+                let kind = match function.function() {
+                    FUNCTION_V7_READ => Some(WatchpointKind::Read),
+                    FUNCTION_V7_WRITE => Some(WatchpointKind::Write),
+                    FUNCTION_V7_ACCESS => Some(WatchpointKind::Access),
+                    _ => None,
+                };
+
+                match kind {
+                    Some(kind) => {
+                        let mask: Mask = load_unit(memory, unit_index)?;
+                        Some((kind, 1 << mask.mask()))
+                    }
+                    None => None,
+                }
+            }
+            DwtVersion::V8 => {
+                let kind = match function.function() {
+                    MATCH_V8_READ => Some(WatchpointKind::Read),
+                    MATCH_V8_WRITE => Some(WatchpointKind::Write),
+                    MATCH_V8_ACCESS => Some(WatchpointKind::Access),
+                    _ => None,
+                };
+
+                kind.filter(|_| function.action() == ACTION_V8_DEBUG_EVENT)
+                    .map(|kind| (kind, 1 << function.datavsize()))
+            }
+        };
+
+        let watchpoint = match kind_and_size {
+            Some((kind, size)) => {
+                let comp: Comp = load_unit(memory, unit_index)?;
+
+                Some(Watchpoint {
+                    address: comp.comp(),
+                    size,
+                    kind,
+                })
+            }
+            None => None,
+        };
+
+        watchpoints.push(watchpoint);
+    }
+
+    Ok(watchpoints)
+}
+
+pub(crate) fn set_hw_watchpoint(
+    memory: &mut impl MemoryInterface,
+    version: DwtVersion,
+    unit_index: usize,
+    watchpoint: Watchpoint,
+) -> Result<(), Error> {
+    // The DWT only works if it is enabled through DEMCR.
+    let mut demcr = Demcr::from(memory.read_word_32(Demcr::ADDRESS)?);
+    if !demcr.trcena() {
+        demcr.set_trcena(true);
+        memory.write_word_32(Demcr::ADDRESS, demcr.into())?;
+    }
+
+    let size_log2 = watchpoint.size.trailing_zeros();
+
+    let mut function = Function::default();
+    match version {
+        DwtVersion::V7 => {
+            let mut mask = Mask::default();
+            mask.set_mask(size_log2);
+            store_unit(memory, unit_index, mask)?;
+
+            function.set_function(match watchpoint.kind {
+                WatchpointKind::Read => FUNCTION_V7_READ,
+                WatchpointKind::Write => FUNCTION_V7_WRITE,
+                WatchpointKind::Access => FUNCTION_V7_ACCESS,
+            });
+        }
+        DwtVersion::V8 => {
+            if size_log2 > 2 {
+                return Err(Error::Other(anyhow!(
+                    "Watchpoints on ARMv8-M can watch at most 4 bytes, but {} were requested",
+                    watchpoint.size
+                )));
+            }
+
+            function.set_datavsize(size_log2 as u8);
+            function.set_action(ACTION_V8_DEBUG_EVENT);
+            function.set_function(match watchpoint.kind {
+                WatchpointKind::Read => MATCH_V8_READ,
+                WatchpointKind::Write => MATCH_V8_WRITE,
+                WatchpointKind::Access => MATCH_V8_ACCESS,
+            });
+        }
+    };
+
+    store_unit(memory, unit_index, Comp::from(watchpoint.address))?;
+    store_unit(memory, unit_index, function)?;
+    memory.flush()?;
+
+    Ok(())
+}
+
+/// Find the first comparator which matched since its `DWT_FUNCTION` register was last read.
+///
+/// Reading `DWT_FUNCTION` clears the `MATCHED` bit, so this only works once after a halt.
+pub(crate) fn get_hit_watchpoint_unit(
+    memory: &mut impl MemoryInterface,
+) -> Result<Option<usize>, Error> {
+    let num_units = get_available_watchpoint_units(memory)? as usize;
+
+    for unit_index in 0..num_units {
+        // MATCHED is at the same position in all versions.
+        let function: Function = load_unit(memory, unit_index)?;
+
+        if function.matched() {
+            return Ok(Some(unit_index));
+        }
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn clear_hw_watchpoint(
+    memory: &mut impl MemoryInterface,
+    unit_index: usize,
+) -> Result<(), Error> {
+    // A function of zero disables the comparator on all versions.
+    store_unit(memory, unit_index, Function::default())?;
+    store_unit(memory, unit_index, Comp::default())?;
+    memory.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::mock::MockMemory;
+
+    /// A mock of the DWT with two comparators, and of DEMCR.
+    fn mock_dwt() -> MockMemory {
+        let mut mock = MockMemory::new(DWT_ADDRESS, 0xE000_EE00 - DWT_ADDRESS);
+        mock.put_u32(DWT_ADDRESS + Ctrl::ADDRESS, 2 << 28);
+        mock
+    }
+
+    #[test]
+    fn set_and_clear_watchpoint_v7() {
+        let mut mock = mock_dwt();
+        let watchpoint = Watchpoint {
+            address: 0x2000_0100,
+            size: 4,
+            kind: WatchpointKind::Write,
+        };
+
+        set_hw_watchpoint(&mut mock, DwtVersion::V7, 1, watchpoint).unwrap();
+
+        assert_eq!(mock.read_word_32(0xE000_1030).unwrap(), 0x2000_0100);
+        assert_eq!(mock.read_word_32(0xE000_1034).unwrap(), 2);
+        assert_eq!(mock.read_word_32(0xE000_1038).unwrap(), 0b0110);
+        assert!(Demcr::from(mock.read_word_32(Demcr::ADDRESS).unwrap()).trcena());
+        assert_eq!(
+            get_hw_watchpoints(&mut mock, DwtVersion::V7).unwrap(),
+            vec![None, Some(watchpoint)]
+        );
+
+        clear_hw_watchpoint(&mut mock, 1).unwrap();
+
+        assert_eq!(mock.read_word_32(0xE000_1038).unwrap(), 0);
+        assert_eq!(
+            get_hw_watchpoints(&mut mock, DwtVersion::V7).unwrap(),
+            vec![None, None]
+        );
+    }
+
+    #[test]
+    fn set_watchpoint_v8() {
+        let mut mock = mock_dwt();
+        let watchpoint = Watchpoint {
+            address: 0x2000_0200,
+            size: 2,
+            kind: WatchpointKind::Read,
+        };
+
+        set_hw_watchpoint(&mut mock, DwtVersion::V8, 0, watchpoint).unwrap();
+
+        assert_eq!(mock.read_word_32(0xE000_1020).unwrap(), 0x2000_0200);
+        assert_eq!(mock.read_word_32(0xE000_1028).unwrap(), 0b0100_0001_0110);
+        assert_eq!(
+            get_hw_watchpoints(&mut mock, DwtVersion::V8).unwrap(),
+            vec![Some(watchpoint), None]
+        );
+
+        let too_large = Watchpoint {
+            size: 8,
+            ..watchpoint
+        };
+        assert!(set_hw_watchpoint(&mut mock, DwtVersion::V8, 1, too_large).is_err());
+    }
+
+    #[test]
+    fn hit_watchpoint_unit() {
+        let mut mock = mock_dwt();
+        assert_eq!(get_hit_watchpoint_unit(&mut mock).unwrap(), None);
+
+        mock.put_u32(0xE000_1038, 1 << 24);
+        assert_eq!(get_hit_watchpoint_unit(&mut mock).unwrap(), Some(1));
+    }
+}
@@ -1,4 +1,4 @@
-mod dwt;
+pub(crate) mod dwt;
 mod itm;
 mod tpiu;
 
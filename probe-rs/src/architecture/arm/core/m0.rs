@@ -1,7 +1,10 @@
-use super::{reset_catch_clear, reset_catch_set, CortexState, Dfsr, ARM_REGISTER_FILE};
+use super::{
+    check_for_semihosting, reset_catch_clear, reset_catch_set, CortexState, Dfsr, ARM_REGISTER_FILE,
+};
+use crate::architecture::arm::component::dwt::{self, DwtVersion};
 use crate::core::{
     Architecture, CoreInformation, CoreInterface, CoreRegister, CoreRegisterAddress,
-    RegisterDescription, RegisterFile, RegisterKind,
+    RegisterDescription, RegisterFile, RegisterKind, Watchpoint,
 };
 use crate::error::Error;
 use crate::memory::Memory;
@@ -294,7 +297,7 @@ impl<'probe> M0<'probe> {
             } else if dhcsr.s_halt() {
                 let dfsr = Dfsr(memory.read_word_32(Dfsr::ADDRESS)?);
 
-                let reason = dfsr.halt_reason();
+                let reason = check_for_semihosting(&mut memory, dfsr.halt_reason())?;
 
                 log::debug!("Core was halted when connecting, reason: {:?}", reason);
 
@@ -524,6 +527,35 @@ impl<'probe> CoreInterface for M0<'probe> {
         self.state.hw_breakpoints_enabled
     }
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, Error> {
+        dwt::get_available_watchpoint_units(self)
+    }
+
+    fn get_hw_watchpoints(&mut self) -> Result<Vec<Option<Watchpoint>>, Error> {
+        dwt::get_hw_watchpoints(self, DwtVersion::V7)
+    }
+
+    fn set_hw_watchpoint(
+        &mut self,
+        unit_index: usize,
+        watchpoint: Watchpoint,
+    ) -> Result<(), Error> {
+        dwt::set_hw_watchpoint(self, DwtVersion::V7, unit_index, watchpoint)
+    }
+
+    fn clear_hw_watchpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+        dwt::clear_hw_watchpoint(self, unit_index)
+    }
+
+    fn get_hit_watchpoint_unit(&mut self) -> Result<Option<usize>, Error> {
+        dwt::get_hit_watchpoint_unit(self)
+    }
+
+    fn debug_on_sw_breakpoint(&mut self, _enabled: bool) -> Result<(), Error> {
+        // A `BKPT` instruction always halts the core while halting debug is enabled.
+        Ok(())
+    }
+
     fn architecture(&self) -> Architecture {
         Architecture::Arm
     }
@@ -555,7 +587,7 @@ impl<'probe> CoreInterface for M0<'probe> {
         if dhcsr.s_halt() {
             let dfsr = Dfsr(self.memory.read_word_32(Dfsr::ADDRESS)?);
 
-            let reason = dfsr.halt_reason();
+            let reason = check_for_semihosting(&mut self.memory, dfsr.halt_reason())?;
 
             // Clear bits from Dfsr register
             self.memory
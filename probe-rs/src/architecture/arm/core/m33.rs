@@ -6,7 +6,7 @@ use crate::memory::Memory;
 use crate::{
     core::{
         Architecture, CoreInformation, CoreInterface, CoreRegister, CoreRegisterAddress,
-        RegisterFile,
+        RegisterFile, Watchpoint,
     },
     CoreStatus, DebugProbeError, HaltReason,
 };
@@ -16,7 +16,10 @@ use crate::{architecture::arm::core::register, MemoryInterface};
 
 use bitfield::bitfield;
 
-use super::{reset_catch_clear, reset_catch_set, CortexState, Dfsr, ARM_REGISTER_FILE};
+use super::{
+    check_for_semihosting, reset_catch_clear, reset_catch_set, CortexState, Dfsr, ARM_REGISTER_FILE,
+};
+use crate::architecture::arm::component::dwt::{self, DwtVersion};
 use std::{
     mem::size_of,
     time::{Duration, Instant},
@@ -42,7 +45,7 @@ impl<'probe> M33<'probe> {
             } else if dhcsr.s_halt() {
                 let dfsr = Dfsr(memory.read_word_32(Dfsr::ADDRESS)?);
 
-                let reason = dfsr.halt_reason();
+                let reason = check_for_semihosting(&mut memory, dfsr.halt_reason())?;
 
                 log::debug!("Core was halted when connecting, reason: {:?}", reason);
 
@@ -271,6 +274,35 @@ impl<'probe> CoreInterface for M33<'probe> {
         self.state.hw_breakpoints_enabled
     }
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, Error> {
+        dwt::get_available_watchpoint_units(self)
+    }
+
+    fn get_hw_watchpoints(&mut self) -> Result<Vec<Option<Watchpoint>>, Error> {
+        dwt::get_hw_watchpoints(self, DwtVersion::V8)
+    }
+
+    fn set_hw_watchpoint(
+        &mut self,
+        unit_index: usize,
+        watchpoint: Watchpoint,
+    ) -> Result<(), Error> {
+        dwt::set_hw_watchpoint(self, DwtVersion::V8, unit_index, watchpoint)
+    }
+
+    fn clear_hw_watchpoint(&mut self, unit_index: usize) -> Result<(), Error> {
+        dwt::clear_hw_watchpoint(self, unit_index)
+    }
+
+    fn get_hit_watchpoint_unit(&mut self) -> Result<Option<usize>, Error> {
+        dwt::get_hit_watchpoint_unit(self)
+    }
+
+    fn debug_on_sw_breakpoint(&mut self, _enabled: bool) -> Result<(), Error> {
+        // A `BKPT` instruction always halts the core while halting debug is enabled.
+        Ok(())
+    }
+
     fn architecture(&self) -> Architecture {
         Architecture::Arm
     }
@@ -302,7 +334,7 @@ impl<'probe> CoreInterface for M33<'probe> {
         if dhcsr.s_halt() {
             let dfsr = Dfsr(self.memory.read_word_32(Dfsr::ADDRESS)?);
 
-            let reason = dfsr.halt_reason();
+            let reason = check_for_semihosting(&mut self.memory, dfsr.halt_reason())?;
 
             // Clear bits from Dfsr register
             self.memory
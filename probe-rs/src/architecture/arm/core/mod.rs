@@ -1,5 +1,6 @@
 use crate::{
     core::{CoreRegister, CoreRegisterAddress, RegisterDescription, RegisterFile, RegisterKind},
+    memory::Memory,
     CoreStatus, Error, HaltReason, MemoryInterface,
 };
 
@@ -61,6 +62,34 @@ pub(crate) fn reset_catch_clear(core: &mut impl MemoryInterface) -> Result<(), E
     Ok(())
 }
 
+/// Encoding of the `BKPT 0xAB` instruction, which the target executes
+/// to request a semihosting operation from the debugger.
+const BKPT_SEMIHOSTING: u16 = 0xBEAB;
+
+/// Check if a core halted on a breakpoint is actually requesting
+/// a semihosting operation, by looking at the instruction at the current PC.
+///
+/// Any other halt reason is returned unchanged.
+pub(crate) fn check_for_semihosting(
+    memory: &mut Memory,
+    reason: HaltReason,
+) -> Result<HaltReason, Error> {
+    if reason != HaltReason::Breakpoint {
+        return Ok(reason);
+    }
+
+    let pc = memory.read_core_reg(register::PC.address)?;
+
+    let mut instruction = [0u8; 2];
+    memory.read_8(pc & !1, &mut instruction)?;
+
+    if u16::from_le_bytes(instruction) == BKPT_SEMIHOSTING {
+        Ok(HaltReason::Semihosting)
+    } else {
+        Ok(reason)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CortexDump {
     pub regs: [u32; 16],
@@ -109,7 +138,7 @@ pub(crate) mod register {
     };
 }
 
-static ARM_REGISTER_FILE: RegisterFile = RegisterFile {
+pub(crate) static ARM_REGISTER_FILE: RegisterFile = RegisterFile {
     platform_registers: &[
         RegisterDescription {
             name: "R0",
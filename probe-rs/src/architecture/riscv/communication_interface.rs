@@ -1201,6 +1201,18 @@ impl<'probe> RiscvCommunicationInterface {
         Ok(())
     }
 
+    /// Change the protocol speed of the probe while attached.
+    ///
+    /// Returns the speed which was actually set, in kHz, or [`DebugProbeError::Attached`]
+    /// if the probe can't change its speed while attached.
+    pub fn set_speed(&mut self, speed_khz: u32) -> Result<u32, ProbeRsError> {
+        if !self.dtm.probe.can_set_speed_while_attached() {
+            return Err(DebugProbeError::Attached.into());
+        }
+
+        Ok(self.dtm.probe.set_speed(speed_khz)?)
+    }
+
     pub fn close(self) -> Probe {
         Probe::from_attached_probe(self.dtm.probe.into_probe())
     }
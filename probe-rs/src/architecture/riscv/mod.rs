@@ -9,7 +9,7 @@ use communication_interface::{
     AbstractCommandErrorKind, DebugRegister, RiscvCommunicationInterface, RiscvError,
 };
 
-use crate::core::{CoreInformation, RegisterFile};
+use crate::core::{CoreInformation, RegisterFile, Watchpoint, WatchpointKind};
 use crate::{CoreRegisterAddress, CoreStatus, Error, HaltReason, MemoryInterface};
 use bitfield::bitfield;
 use register::RISCV_REGISTERS;
@@ -431,6 +431,166 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
         Ok(())
     }
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, crate::Error> {
+        // Watchpoints use the same triggers as breakpoints
+        self.get_available_breakpoint_units()
+    }
+
+    /// See docs on the [`CoreInterface::get_hw_watchpoints`] trait
+    /// NOTE: For riscv, only load and store triggers are returned, execution triggers are breakpoints.
+    fn get_hw_watchpoints(&mut self) -> Result<Vec<Option<Watchpoint>>, Error> {
+        let tselect = 0x7a0;
+        let tdata1 = 0x7a1;
+        let tdata2 = 0x7a2;
+
+        let mut watchpoints = vec![];
+        let num_triggers = self.get_available_watchpoint_units()? as usize;
+        for unit_index in 0..num_triggers {
+            //select the trigger
+            self.write_csr(tselect, unit_index as u32)?;
+
+            //Read the trigger "configuration" data
+            let tdata_value = Mcontrol(self.read_csr(tdata1)?);
+
+            let kind = match (tdata_value.load(), tdata_value.store()) {
+                (true, true) => Some(WatchpointKind::Access),
+                (true, false) => Some(WatchpointKind::Read),
+                (false, true) => Some(WatchpointKind::Write),
+                (false, false) => None,
+            };
+
+            let watchpoint = match kind {
+                Some(kind)
+                    if tdata_value.type_() == 0b10
+                        && tdata_value.action() == 1
+                        && !tdata_value.execute() =>
+                {
+                    let tdata2_value = self.read_csr(tdata2)?;
+
+                    match tdata_value.match_() {
+                        // Exact address
+                        0 => Some(Watchpoint {
+                            address: tdata2_value,
+                            size: 1,
+                            kind,
+                        }),
+                        // NAPOT range, the trailing ones in tdata2 encode the size
+                        1 => {
+                            let size = 2 << tdata2_value.trailing_ones();
+                            Some(Watchpoint {
+                                address: tdata2_value & !(size - 1),
+                                size,
+                                kind,
+                            })
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+
+            watchpoints.push(watchpoint);
+        }
+
+        Ok(watchpoints)
+    }
+
+    fn set_hw_watchpoint(
+        &mut self,
+        unit_index: usize,
+        watchpoint: Watchpoint,
+    ) -> Result<(), crate::Error> {
+        let tselect = 0x7a0;
+        let tdata1 = 0x7a1;
+        let tdata2 = 0x7a2;
+
+        self.write_csr(tselect, unit_index as u32)?;
+
+        // verify the trigger has the correct type
+        let trigger_type = Mcontrol(self.read_csr(tdata1)?).type_();
+        if trigger_type != 0b10 {
+            return Err(RiscvError::UnexpectedTriggerType(trigger_type).into());
+        }
+
+        let mut data_trigger = Mcontrol(0);
+
+        // Enter debug mode
+        data_trigger.set_action(1);
+
+        data_trigger.set_m(true);
+        data_trigger.set_s(true);
+        data_trigger.set_u(true);
+
+        match watchpoint.kind {
+            WatchpointKind::Read => data_trigger.set_load(true),
+            WatchpointKind::Write => data_trigger.set_store(true),
+            WatchpointKind::Access => {
+                data_trigger.set_load(true);
+                data_trigger.set_store(true);
+            }
+        }
+
+        data_trigger.set_dmode(true);
+
+        // Match address
+        data_trigger.set_select(false);
+
+        // A single byte is matched exactly, larger ranges are matched as NAPOT
+        // (naturally aligned power-of-two), encoded in the low bits of tdata2.
+        let tdata2_value = if watchpoint.size == 1 {
+            data_trigger.set_match(0);
+            watchpoint.address
+        } else {
+            data_trigger.set_match(1);
+            watchpoint.address | ((watchpoint.size >> 1) - 1)
+        };
+
+        self.write_csr(tdata1, data_trigger.0)?;
+        self.write_csr(tdata2, tdata2_value)?;
+
+        Ok(())
+    }
+
+    fn clear_hw_watchpoint(&mut self, unit_index: usize) -> Result<(), crate::Error> {
+        // Watchpoints and breakpoints are cleared in the same way
+        self.clear_hw_breakpoint(unit_index)
+    }
+
+    fn debug_on_sw_breakpoint(&mut self, enabled: bool) -> Result<(), crate::Error> {
+        let mut dcsr = Dcsr(self.read_core_reg(CoreRegisterAddress(0x7b0))?);
+
+        dcsr.set_ebreakm(enabled);
+        dcsr.set_ebreaks(enabled);
+        dcsr.set_ebreaku(enabled);
+
+        self.write_csr(0x7b0, dcsr.0).map_err(|e| e.into())
+    }
+
+    fn get_hit_watchpoint_unit(&mut self) -> Result<Option<usize>, crate::Error> {
+        let tselect = 0x7a0;
+        let tdata1 = 0x7a1;
+
+        let num_triggers = self.get_available_watchpoint_units()? as usize;
+        for unit_index in 0..num_triggers {
+            self.write_csr(tselect, unit_index as u32)?;
+
+            let mut tdata_value = Mcontrol(self.read_csr(tdata1)?);
+
+            // The hit bit is optional, and has to be cleared by the debugger.
+            if tdata_value.type_() == 0b10
+                && tdata_value.hit()
+                && (tdata_value.load() || tdata_value.store())
+            {
+                tdata_value.set_hit(false);
+                self.write_csr(tdata1, tdata_value.0)?;
+
+                return Ok(Some(unit_index));
+            }
+        }
+
+        Ok(None)
+    }
+
     fn registers(&self) -> &'static RegisterFile {
         &RISCV_REGISTERS
     }
@@ -502,15 +662,13 @@ impl<'probe> CoreInterface for Riscv32<'probe> {
             // The trigger must be active in at least a single mode
             let trigger_any_mode_active = tdata_value.m() || tdata_value.s() || tdata_value.u();
 
-            let trigger_any_action_enabled =
-                tdata_value.execute() || tdata_value.store() || tdata_value.load();
-
-            //Only return if the trigger if it is for an execution debug action in all modes
+            //Only return if the trigger if it is for an execution debug action in all modes.
+            //Load and store triggers are used for watchpoints, see `get_hw_watchpoints`.
             if tdata_value.type_() == 0b10
                 && tdata_value.action() == 1
                 && tdata_value.match_() == 0
                 && trigger_any_mode_active
-                && trigger_any_action_enabled
+                && tdata_value.execute()
             {
                 let breakpoint = self.read_csr(tdata2)?;
                 breakpoints.push(Some(breakpoint));
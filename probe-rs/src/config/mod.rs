@@ -19,7 +19,8 @@
 //! ## Adding targets at runtime
 //!
 //! To add a target at runtime, the [add_target_from_yaml] file can
-//! be used to read targets from a YAML file.
+//! be used to read targets from a YAML file. Like the built-in targets, the targets
+//! are checked for problems with [ChipFamily::validate] before they are added.
 //!
 
 mod chip_info;
@@ -29,6 +30,7 @@ mod target;
 pub use probe_rs_target::{
     Chip, ChipFamily, Core, CoreType, FlashProperties, MemoryRange, MemoryRegion, NvmRegion,
     PageInfo, RamRegion, RawFlashAlgorithm, SectorDescription, SectorInfo, TargetDescriptionSource,
+    ValidationError,
 };
 
 pub use registry::{
@@ -32,6 +32,9 @@ pub enum RegistryError {
     /// An error occured while deserializing a YAML target description file.
     #[error("Deserializing the yaml encountered an error")]
     Yaml(#[from] serde_yaml::Error),
+    /// A target description file has problems which would break flashing.
+    #[error("The target description is invalid. {0}")]
+    InvalidTargetDescription(#[from] probe_rs_target::ValidationError),
     /// Unable to lock the registry.
     #[error("Unable to lock registry")]
     LockUnavailable,
@@ -312,6 +315,7 @@ impl Registry {
     fn add_target_from_yaml(&mut self, path_to_yaml: &Path) -> Result<(), RegistryError> {
         let file = File::open(path_to_yaml)?;
         let chip: ChipFamily = serde_yaml::from_reader(file)?;
+        chip.validate()?;
 
         let index = self
             .families
@@ -343,6 +347,9 @@ pub(crate) fn get_target_by_chip_info(chip_info: ChipInfo) -> Result<Target, Reg
 
 /// Parse a target description file and add the contained targets
 /// to the internal target registry.
+///
+/// The targets are checked with [`ChipFamily::validate`], and are not
+/// added if they have any problems.
 pub fn add_target_from_yaml(path_to_yaml: &Path) -> Result<(), RegistryError> {
     REGISTRY.try_lock()?.add_target_from_yaml(path_to_yaml)
 }
@@ -1,4 +1,6 @@
-use super::{Chip, Core, CoreType, MemoryRegion, RawFlashAlgorithm, TargetDescriptionSource};
+use super::{
+    Chip, Core, CoreType, MemoryRange, MemoryRegion, RawFlashAlgorithm, TargetDescriptionSource,
+};
 use crate::{core::Architecture, flashing::FlashLoader};
 
 /// This describes a complete target with a fixed chip model and variant.
@@ -88,6 +90,31 @@ impl Target {
         FlashLoader::new(self.memory_map.clone(), self.source.clone())
     }
 
+    /// Adds a flash algorithm, e.g. one read from an FLM file with
+    /// [read_flash_algorithm_from_flm](crate::flashing::read_flash_algorithm_from_flm).
+    ///
+    /// The algorithm becomes the default algorithm for its flash, and can be used by all cores
+    /// if it doesn't name any cores. It replaces an algorithm of the target with the same name.
+    pub fn add_flash_algorithm(&mut self, mut algorithm: RawFlashAlgorithm) {
+        if algorithm.cores.is_empty() {
+            algorithm.cores = self.cores.iter().map(|core| core.name.clone()).collect();
+        }
+
+        // The algorithms are looked up by their name when flashing.
+        self.flash_algorithms
+            .retain(|other| other.name != algorithm.name);
+
+        let range = &algorithm.flash_properties.address_range;
+        for other in &mut self.flash_algorithms {
+            if other.flash_properties.address_range.intersects_range(range) {
+                other.default = false;
+            }
+        }
+        algorithm.default = true;
+
+        self.flash_algorithms.push(algorithm);
+    }
+
     /// Gets a [RawFlashAlgorithm] by name.
     pub(crate) fn flash_algorithm_by_name(&self, name: &str) -> Option<&RawFlashAlgorithm> {
         self.flash_algorithms.iter().find(|a| a.name == name)
@@ -153,3 +180,34 @@ impl From<Target> for TargetSelector {
         TargetSelector::Specified(target)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_flash_algorithm_with_existing_name() {
+        let mut target = crate::config::get_target_by_name("nrf51822_xxaa").unwrap();
+        let existing = target.flash_algorithms[0].clone();
+
+        let algorithm = RawFlashAlgorithm {
+            name: existing.name.clone(),
+            pc_init: Some(0x1234),
+            flash_properties: existing.flash_properties.clone(),
+            ..Default::default()
+        };
+        target.add_flash_algorithm(algorithm);
+
+        let algorithms: Vec<_> = target
+            .flash_algorithms
+            .iter()
+            .filter(|algorithm| algorithm.name == existing.name)
+            .collect();
+        assert_eq!(algorithms.len(), 1);
+
+        let added = target.flash_algorithm_by_name(&existing.name).unwrap();
+        assert_eq!(added.pc_init, Some(0x1234));
+        assert!(added.default);
+        assert!(!added.cores.is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+//! A fake core for unit tests of code which accesses the target through a [Core](super::Core).
+
+use super::{
+    Architecture, CoreInformation, CoreInterface, CoreRegisterAddress, CoreStatus, RegisterFile,
+    Watchpoint,
+};
+use crate::architecture::arm::component::dwt::{self, DwtVersion};
+use crate::architecture::arm::core::ARM_REGISTER_FILE;
+use crate::memory::mock::MockMemory;
+use crate::{error, Error, MemoryInterface};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A fake ARM core, which only supports accessing its registers and memory, and the
+/// watchpoints of an ARMv7-M DWT in its memory.
+///
+/// The registers are zero until they are written.
+pub(crate) struct MockCore {
+    memory: MockMemory,
+    registers: HashMap<CoreRegisterAddress, u32>,
+}
+
+impl MockCore {
+    pub fn new(memory: MockMemory) -> Self {
+        Self {
+            memory,
+            registers: HashMap::new(),
+        }
+    }
+}
+
+impl MemoryInterface for MockCore {
+    fn read_word_32(&mut self, address: u32) -> Result<u32, Error> {
+        self.memory.read_word_32(address)
+    }
+
+    fn read_word_8(&mut self, address: u32) -> Result<u8, Error> {
+        self.memory.read_word_8(address)
+    }
+
+    fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), Error> {
+        self.memory.read_32(address, data)
+    }
+
+    fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), Error> {
+        self.memory.read_8(address, data)
+    }
+
+    fn write_word_32(&mut self, addr: u32, data: u32) -> Result<(), Error> {
+        self.memory.write_word_32(addr, data)
+    }
+
+    fn write_word_8(&mut self, addr: u32, data: u8) -> Result<(), Error> {
+        self.memory.write_word_8(addr, data)
+    }
+
+    fn write_32(&mut self, addr: u32, data: &[u32]) -> Result<(), Error> {
+        self.memory.write_32(addr, data)
+    }
+
+    fn write_8(&mut self, addr: u32, data: &[u8]) -> Result<(), Error> {
+        self.memory.write_8(addr, data)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.memory.flush()
+    }
+}
+
+/// The error of the functions which the mock core does not support.
+fn unsupported() -> error::Error {
+    error::Error::Other(anyhow!("Not supported by the mock core"))
+}
+
+impl CoreInterface for MockCore {
+    fn wait_for_core_halted(&mut self, _timeout: Duration) -> Result<(), error::Error> {
+        Err(unsupported())
+    }
+
+    fn core_halted(&mut self) -> Result<bool, error::Error> {
+        Err(unsupported())
+    }
+
+    fn status(&mut self) -> Result<CoreStatus, error::Error> {
+        Err(unsupported())
+    }
+
+    fn halt(&mut self, _timeout: Duration) -> Result<CoreInformation, error::Error> {
+        Err(unsupported())
+    }
+
+    fn run(&mut self) -> Result<(), error::Error> {
+        Err(unsupported())
+    }
+
+    fn reset(&mut self) -> Result<(), error::Error> {
+        Err(unsupported())
+    }
+
+    fn reset_and_halt(&mut self, _timeout: Duration) -> Result<CoreInformation, error::Error> {
+        Err(unsupported())
+    }
+
+    fn step(&mut self) -> Result<CoreInformation, error::Error> {
+        Err(unsupported())
+    }
+
+    fn read_core_reg(&mut self, address: CoreRegisterAddress) -> Result<u32, error::Error> {
+        Ok(self.registers.get(&address).copied().unwrap_or(0))
+    }
+
+    fn write_core_reg(&mut self, address: CoreRegisterAddress, value: u32) -> Result<()> {
+        self.registers.insert(address, value);
+        Ok(())
+    }
+
+    fn get_available_breakpoint_units(&mut self) -> Result<u32, error::Error> {
+        Err(unsupported())
+    }
+
+    fn get_hw_breakpoints(&mut self) -> Result<Vec<Option<u32>>, error::Error> {
+        Err(unsupported())
+    }
+
+    fn enable_breakpoints(&mut self, _state: bool) -> Result<(), error::Error> {
+        Err(unsupported())
+    }
+
+    fn set_hw_breakpoint(&mut self, _unit_index: usize, _addr: u32) -> Result<(), error::Error> {
+        Err(unsupported())
+    }
+
+    fn clear_hw_breakpoint(&mut self, _unit_index: usize) -> Result<(), error::Error> {
+        Err(unsupported())
+    }
+
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, error::Error> {
+        dwt::get_available_watchpoint_units(self)
+    }
+
+    fn get_hw_watchpoints(&mut self) -> Result<Vec<Option<Watchpoint>>, error::Error> {
+        dwt::get_hw_watchpoints(self, DwtVersion::V7)
+    }
+
+    fn set_hw_watchpoint(
+        &mut self,
+        unit_index: usize,
+        watchpoint: Watchpoint,
+    ) -> Result<(), error::Error> {
+        dwt::set_hw_watchpoint(self, DwtVersion::V7, unit_index, watchpoint)
+    }
+
+    fn clear_hw_watchpoint(&mut self, unit_index: usize) -> Result<(), error::Error> {
+        dwt::clear_hw_watchpoint(self, unit_index)
+    }
+
+    fn get_hit_watchpoint_unit(&mut self) -> Result<Option<usize>, error::Error> {
+        dwt::get_hit_watchpoint_unit(self)
+    }
+
+    fn debug_on_sw_breakpoint(&mut self, _enabled: bool) -> Result<(), error::Error> {
+        Err(unsupported())
+    }
+
+    fn registers(&self) -> &'static RegisterFile {
+        &ARM_REGISTER_FILE
+    }
+
+    fn hw_breakpoints_enabled(&self) -> bool {
+        false
+    }
+
+    fn architecture(&self) -> Architecture {
+        Architecture::Arm
+    }
+}
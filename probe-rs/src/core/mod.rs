@@ -1,4 +1,6 @@
 pub(crate) mod communication_interface;
+#[cfg(test)]
+pub(crate) mod mock;
 
 pub use communication_interface::CommunicationInterface;
 
@@ -172,6 +174,28 @@ pub trait CoreInterface: MemoryInterface {
 
     fn clear_hw_breakpoint(&mut self, unit_index: usize) -> Result<(), error::Error>;
 
+    fn get_available_watchpoint_units(&mut self) -> Result<u32, error::Error>;
+
+    /// Read the hardware watchpoints from the watchpoint units, and adds them to the Result Vector.
+    /// A value of None in any position of the Vector indicates that the position is unset/available.
+    fn get_hw_watchpoints(&mut self) -> Result<Vec<Option<Watchpoint>>, error::Error>;
+
+    fn set_hw_watchpoint(
+        &mut self,
+        unit_index: usize,
+        watchpoint: Watchpoint,
+    ) -> Result<(), error::Error>;
+
+    fn clear_hw_watchpoint(&mut self, unit_index: usize) -> Result<(), error::Error>;
+
+    /// Find the watchpoint unit which matched, causing the core to halt.
+    /// Returns `None` if no unit matched, or if the hardware doesn't record it.
+    fn get_hit_watchpoint_unit(&mut self) -> Result<Option<usize>, error::Error>;
+
+    /// Configure whether software breakpoint instructions (`BKPT` on ARM,
+    /// `ebreak` on RISC-V) halt the core, instead of raising an exception.
+    fn debug_on_sw_breakpoint(&mut self, enabled: bool) -> Result<(), error::Error>;
+
     fn registers(&self) -> &'static RegisterFile;
 
     fn hw_breakpoints_enabled(&self) -> bool;
@@ -403,9 +427,13 @@ impl<'probe> Core<'probe> {
 
     /// Find the index of the next available HW breakpoint comparator.
     fn find_free_breakpoint_comparator_index(&mut self) -> Result<usize, error::Error> {
+        let in_use_by_watchpoints = self.units_in_use_by_watchpoints()?;
+
         let mut next_available_hw_breakpoint = 0;
         for breakpoint in self.inner.get_hw_breakpoints()? {
-            if breakpoint.is_none() {
+            if breakpoint.is_none()
+                && !in_use_by_watchpoints.contains(&next_available_hw_breakpoint)
+            {
                 return Ok(next_available_hw_breakpoint);
             } else {
                 next_available_hw_breakpoint += 1;
@@ -416,6 +444,51 @@ impl<'probe> Core<'probe> {
         )))
     }
 
+    /// Find the index of the next available HW watchpoint unit.
+    fn find_free_watchpoint_unit_index(&mut self) -> Result<usize, error::Error> {
+        let in_use_by_breakpoints = self.units_in_use_by_breakpoints()?;
+
+        self.inner
+            .get_hw_watchpoints()?
+            .iter()
+            .enumerate()
+            .position(|(index, watchpoint)| {
+                watchpoint.is_none() && !in_use_by_breakpoints.contains(&index)
+            })
+            .ok_or_else(|| error::Error::Other(anyhow!("No available hardware watchpoints")))
+    }
+
+    /// On RISC-V, breakpoints and watchpoints share the same trigger units,
+    /// so a unit used for one of them is not available for the other.
+    fn units_in_use_by_watchpoints(&mut self) -> Result<Vec<usize>, error::Error> {
+        if self.architecture() != Architecture::Riscv {
+            return Ok(vec![]);
+        }
+
+        Ok(self
+            .inner
+            .get_hw_watchpoints()?
+            .iter()
+            .enumerate()
+            .filter_map(|(index, watchpoint)| watchpoint.map(|_| index))
+            .collect())
+    }
+
+    /// See [`Core::units_in_use_by_watchpoints`].
+    fn units_in_use_by_breakpoints(&mut self) -> Result<Vec<usize>, error::Error> {
+        if self.architecture() != Architecture::Riscv {
+            return Ok(vec![]);
+        }
+
+        Ok(self
+            .inner
+            .get_hw_breakpoints()?
+            .iter()
+            .enumerate()
+            .filter_map(|(index, breakpoint)| breakpoint.map(|_| index))
+            .collect())
+    }
+
     /// Set a hardware breakpoint
     ///
     /// This function will try to set a hardware breakpoint. The amount
@@ -449,6 +522,12 @@ impl<'probe> Core<'probe> {
         Ok(())
     }
 
+    /// Read the addresses of all hardware breakpoints. A value of `None` indicates that
+    /// the comparator at this position is unused.
+    pub fn hw_breakpoints(&mut self) -> Result<Vec<Option<u32>>, error::Error> {
+        self.inner.get_hw_breakpoints()
+    }
+
     pub fn clear_hw_breakpoint(&mut self, address: u32) -> Result<(), error::Error> {
         let bp_position = self
             .inner
@@ -486,6 +565,144 @@ impl<'probe> Core<'probe> {
         Ok(())
     }
 
+    pub fn get_available_watchpoint_units(&mut self) -> Result<u32, error::Error> {
+        self.inner.get_available_watchpoint_units()
+    }
+
+    /// Set a hardware watchpoint
+    ///
+    /// The core halts with [`HaltReason::Watchpoint`] when `size` bytes at `address`
+    /// are accessed as specified by `kind`. `size` has to be a power of two, and
+    /// `address` has to be aligned to it. The amount of hardware watchpoints which
+    /// are supported is chip specific, and can be queried using the
+    /// `get_available_watchpoint_units` function.
+    pub fn set_hw_watchpoint(
+        &mut self,
+        address: u32,
+        size: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), error::Error> {
+        if !size.is_power_of_two() || address & (size - 1) != 0 {
+            return Err(error::Error::Other(anyhow!(
+                "A watchpoint of {} bytes at address {:#010x} is not supported. The size has to be a power of two, and the address aligned to it.",
+                size,
+                address
+            )));
+        }
+
+        let watchpoint = Watchpoint {
+            address,
+            size,
+            kind,
+        };
+
+        // If this watchpoint is set already, re-use its unit, else find the next free one.
+        // Watchpoints of another size or kind at the same address need a unit of their own.
+        let unit_index = match self
+            .inner
+            .get_hw_watchpoints()?
+            .iter()
+            .position(|wp| *wp == Some(watchpoint))
+        {
+            Some(unit_index) => unit_index,
+            None => self.find_free_watchpoint_unit_index()?,
+        };
+
+        log::debug!(
+            "Trying to set HW watchpoint #{}: {:x?}",
+            unit_index,
+            watchpoint
+        );
+
+        self.inner.set_hw_watchpoint(unit_index, watchpoint)
+    }
+
+    /// Clear a hardware watchpoint
+    ///
+    /// Only the watchpoint set with the same `address`, `size` and `kind` is cleared,
+    /// other watchpoints at the same address are kept.
+    pub fn clear_hw_watchpoint(
+        &mut self,
+        address: u32,
+        size: u32,
+        kind: WatchpointKind,
+    ) -> Result<(), error::Error> {
+        let watchpoint = Watchpoint {
+            address,
+            size,
+            kind,
+        };
+
+        let wp_position = self
+            .inner
+            .get_hw_watchpoints()?
+            .iter()
+            .position(|wp| *wp == Some(watchpoint));
+
+        match wp_position {
+            Some(wp_position) => {
+                log::debug!(
+                    "Will clear HW watchpoint #{}: {:x?}",
+                    wp_position,
+                    watchpoint
+                );
+                self.inner.clear_hw_watchpoint(wp_position)
+            }
+            None => Err(error::Error::Other(anyhow!(
+                "No {:?} watchpoint of {} bytes found at address {:#010x}",
+                kind,
+                size,
+                address
+            ))),
+        }
+    }
+
+    /// Configure whether software breakpoint instructions (`BKPT` on ARM,
+    /// `ebreak` on RISC-V) halt the core, instead of raising an exception.
+    pub fn debug_on_sw_breakpoint(&mut self, enabled: bool) -> Result<(), error::Error> {
+        self.inner.debug_on_sw_breakpoint(enabled)
+    }
+
+    /// Determine the watchpoint which caused the core to halt.
+    ///
+    /// If the hardware doesn't record which watchpoint matched, but the core halted with
+    /// [`HaltReason::Watchpoint`] and only a single watchpoint is set, that one is returned.
+    pub fn hit_watchpoint(&mut self) -> Result<Option<Watchpoint>, error::Error> {
+        // This has to be checked first, because reading the watchpoints
+        // can clear the information which one matched.
+        let hit_unit = self.inner.get_hit_watchpoint_unit()?;
+
+        let watchpoints = self.inner.get_hw_watchpoints()?;
+
+        if let Some(hit_unit) = hit_unit {
+            return Ok(watchpoints.get(hit_unit).copied().flatten());
+        }
+
+        if self.status()? == CoreStatus::Halted(HaltReason::Watchpoint) {
+            let mut watchpoints = watchpoints.into_iter().flatten();
+
+            if let (Some(watchpoint), None) = (watchpoints.next(), watchpoints.next()) {
+                return Ok(Some(watchpoint));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Clear all hardware watchpoints
+    ///
+    /// This function will clear all HW watchpoints which are configured on the target,
+    /// regardless if they are set by probe-rs.
+    /// Also used as a helper function in [`Session::drop`].
+    pub fn clear_all_hw_watchpoints(&mut self) -> Result<(), error::Error> {
+        for (unit_index, watchpoint) in self.inner.get_hw_watchpoints()?.into_iter().enumerate() {
+            if watchpoint.is_some() {
+                self.inner.clear_hw_watchpoint(unit_index)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn architecture(&self) -> Architecture {
         self.inner.architecture()
     }
@@ -521,6 +738,28 @@ pub struct Breakpoint {
     register_hw: usize,
 }
 
+/// The kind of memory access which triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointKind {
+    /// Halt when the memory is read.
+    Read,
+    /// Halt when the memory is written.
+    Write,
+    /// Halt on any access to the memory.
+    Access,
+}
+
+/// A hardware watchpoint, as configured in a watchpoint unit of the core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// The start address of the watched memory.
+    pub address: u32,
+    /// The size of the watched memory in bytes, always a power of two.
+    pub size: u32,
+    /// The kind of access which triggers the watchpoint.
+    pub kind: WatchpointKind,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Architecture {
     Arm,
@@ -565,8 +804,66 @@ pub enum HaltReason {
     Request,
     /// External halt request
     External,
+    /// Core halted on a `BKPT 0xAB` instruction, requesting
+    /// a semihosting operation. See [`crate::semihosting`].
+    Semihosting,
     /// Unknown reason for halt.
     ///
     /// This can happen for example when the core is already halted when we connect.
     Unknown,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockCore;
+    use super::*;
+    use crate::memory::mock::MockMemory;
+
+    #[test]
+    fn watchpoints_of_different_kinds_at_the_same_address() {
+        // A DWT of an ARMv7-M core with two comparators, and DEMCR.
+        let mut memory = MockMemory::new(0xE000_1000, 0xE000_EE00 - 0xE000_1000);
+        memory.put_u32(0xE000_1000, 2 << 28);
+        let mut state = CoreState::new(0);
+        let mut core = Core::new(MockCore::new(memory), &mut state);
+
+        let write = Watchpoint {
+            address: 0x2000_0100,
+            size: 4,
+            kind: WatchpointKind::Write,
+        };
+        let read = Watchpoint {
+            kind: WatchpointKind::Read,
+            ..write
+        };
+
+        // Like the `Z2` and `Z3` packets of GDB.
+        core.set_hw_watchpoint(0x2000_0100, 4, WatchpointKind::Write)
+            .unwrap();
+        core.set_hw_watchpoint(0x2000_0100, 4, WatchpointKind::Read)
+            .unwrap();
+        assert_eq!(
+            core.inner.get_hw_watchpoints().unwrap(),
+            vec![Some(write), Some(read)]
+        );
+
+        // Setting a watchpoint again reuses its unit.
+        core.set_hw_watchpoint(0x2000_0100, 4, WatchpointKind::Read)
+            .unwrap();
+
+        // Like the `z2` packet.
+        core.clear_hw_watchpoint(0x2000_0100, 4, WatchpointKind::Write)
+            .unwrap();
+        assert_eq!(
+            core.inner.get_hw_watchpoints().unwrap(),
+            vec![None, Some(read)]
+        );
+
+        assert!(core
+            .clear_hw_watchpoint(0x2000_0100, 2, WatchpointKind::Read)
+            .is_err());
+        core.clear_hw_watchpoint(0x2000_0100, 4, WatchpointKind::Read)
+            .unwrap();
+        assert_eq!(core.inner.get_hw_watchpoints().unwrap(), vec![None, None]);
+    }
+}
@@ -5,7 +5,7 @@
 
 mod variable;
 
-use crate::{core::Core, MemoryInterface};
+use crate::{core::Core, Architecture, CoreRegisterAddress, MemoryInterface};
 use num_traits::Zero;
 pub use variable::{Variable, VariableInclusion, VariableKind, VariantRole};
 
@@ -120,18 +120,25 @@ fn variable_recurse(
 
     ret
 }
-#[derive(Debug, Clone)]
+/// The values of the registers of a stack frame, by DWARF register number.
+#[derive(Debug, Clone, Default)]
 pub struct Registers([Option<u32>; 16]);
 
 impl Registers {
     pub fn from_core(core: &mut Core) -> Self {
         let mut registers = Registers([None; 16]);
-        for i in 0..16 {
-            registers[i as usize] = core.read_core_reg(i).ok();
+        // The platform registers are in the order of the DWARF register numbers.
+        for (i, register) in core.registers().registers().take(16).enumerate() {
+            registers[i] = core.read_core_reg(register).ok();
         }
         registers
     }
 
+    /// The value of a register, by its DWARF register number, if it is known.
+    pub fn get_dwarf_register(&self, register: u16) -> Option<u32> {
+        self.0.get(register as usize).copied().flatten()
+    }
+
     pub fn get_call_frame_address(&self) -> Option<u32> {
         self.0[13]
     }
@@ -191,6 +198,35 @@ pub struct SourceLocation {
     pub directory: Option<PathBuf>,
 }
 
+/// The DCRSR register selector of the process stack pointer (PSP) on ARMv7-M and ARMv8-M.
+const PSP: CoreRegisterAddress = CoreRegisterAddress(0b1_0010);
+
+/// EXC_RETURN bit which is set if the exception frame was stacked on the process stack (PSP).
+const EXC_RETURN_SPSEL: u32 = 1 << 2;
+/// EXC_RETURN bit which is cleared if the exception frame includes the floating-point context.
+const EXC_RETURN_FTYPE: u32 = 1 << 4;
+/// xPSR bit which is set if the stack was realigned to 8 bytes when the exception frame was stacked.
+const XPSR_STACK_ALIGN: u32 = 1 << 9;
+
+/// Check if a value in the LR is an EXC_RETURN value, which returns from an exception handler
+/// to the interrupted code.
+fn is_exc_return(lr: u32) -> bool {
+    lr & 0xff00_0000 == 0xff00_0000
+}
+
+/// The size of an exception frame in bytes: R0-R3, R12, LR, PC and xPSR, followed by S0-S15,
+/// FPSCR and a reserved word in an extended frame, and the padding for the stack alignment.
+fn exception_frame_size(exc_return: u32, xpsr: u32) -> u32 {
+    let mut size = 8 * 4;
+    if exc_return & EXC_RETURN_FTYPE == 0 {
+        size += 18 * 4;
+    }
+    if xpsr & XPSR_STACK_ALIGN != 0 {
+        size += 4;
+    }
+    size
+}
+
 #[derive(Debug, Clone)]
 enum InlineFunctionState {
     /// We are at the state where the function was inlined.
@@ -211,6 +247,8 @@ pub struct StackFrameIterator<'debuginfo, 'probe, 'core> {
     pc: Option<u64>,
     registers: Registers,
     inlining_state: InlineFunctionState,
+    /// The EXC_RETURN value, if the next frame is the exception frame of an exception handler.
+    exception_return: Option<u32>,
 }
 
 impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
@@ -228,15 +266,96 @@ impl<'debuginfo, 'probe, 'core> StackFrameIterator<'debuginfo, 'probe, 'core> {
             pc: Some(pc),
             registers,
             inlining_state: InlineFunctionState::NoInlining,
+            exception_return: None,
+        }
+    }
+
+    /// Return a synthetic frame for the exception frame which the core stacked when it entered
+    /// an exception handler, and restore the registers of the interrupted code from it, so that
+    /// unwinding continues in the interrupted function.
+    fn unwind_exception_frame(&mut self, exc_return: u32) -> StackFrame {
+        let frame = StackFrame {
+            id: self.frame_count,
+            function_name: "<exception frame>".to_owned(),
+            source_location: None,
+            registers: self.registers.clone(),
+            pc: exc_return,
+            variables: vec![],
+        };
+        self.frame_count += 1;
+        self.pc = None;
+
+        // Exception handlers always run on the main stack, so the CFA of the handler is the
+        // location of the exception frame, unless the frame was stacked on the process stack.
+        let frame_address = if exc_return & EXC_RETURN_SPSEL != 0 {
+            self.core.read_core_reg(PSP).ok()
+        } else {
+            self.registers.get_call_frame_address()
+        };
+        let frame_address = match frame_address {
+            Some(frame_address) => frame_address,
+            None => {
+                log::warn!("Unable to unwind exception frame: Missing stack pointer");
+                return frame;
+            }
+        };
+
+        if let Err(e) =
+            restore_exception_frame(&mut self.registers, self.core, frame_address, exc_return)
+        {
+            log::info!(
+                "Failed to read exception frame at address {:#010x}: {}",
+                frame_address,
+                e
+            );
+            return frame;
         }
+
+        self.pc = self.registers[15].map(|pc| u64::from(pc & !1));
+
+        frame
     }
 }
 
+/// Restore the registers which the core stacked in the exception frame at `frame_address`, when it
+/// entered the exception handler which returns with `exc_return`.
+fn restore_exception_frame(
+    registers: &mut Registers,
+    memory: &mut impl MemoryInterface,
+    frame_address: u32,
+    exc_return: u32,
+) -> Result<(), crate::Error> {
+    let mut stacked = [0u32; 8];
+    memory.read_32(frame_address, &mut stacked)?;
+    let [r0, r1, r2, r3, r12, lr, pc, xpsr] = stacked;
+
+    debug!(
+        "Exception frame at {:#010x}: pc={:#010x}, lr={:#010x}, xpsr={:#010x}",
+        frame_address, pc, lr, xpsr
+    );
+
+    registers[0] = Some(r0);
+    registers[1] = Some(r1);
+    registers[2] = Some(r2);
+    registers[3] = Some(r3);
+    registers[12] = Some(r12);
+    registers[14] = Some(lr);
+    registers[15] = Some(pc);
+    registers.set_call_frame_address(Some(frame_address + exception_frame_size(exc_return, xpsr)));
+
+    Ok(())
+}
+
 impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'probe, 'core> {
     type Item = StackFrame;
 
     fn next(&mut self) -> Option<Self::Item> {
         use gimli::UnwindSection;
+
+        if let Some(exc_return) = self.exception_return.take() {
+            return Some(self.unwind_exception_frame(exc_return));
+        }
+
         let mut ctx = gimli::UninitializedUnwindContext::new();
         let bases = gimli::BaseAddresses::default();
 
@@ -250,6 +369,9 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
 
         log::debug!("StackFrame: Unwinding at address {:#010x}", pc);
 
+        // The registers of this frame, before the registers of its caller are unwound.
+        let frame_registers = self.registers.clone();
+
         // Find function information, to check if we are in an inlined function.
 
         let inline_call_site = match self.inlining_state {
@@ -442,11 +564,17 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
             self.registers.set_call_frame_address(current_cfa);
         }
 
+        // The frame gets the values of its own registers, e.g. for the variables which are kept
+        // in registers. Only the call frame address is taken from the unwound registers.
+        let mut registers = frame_registers;
+        registers.set_call_frame_address(self.registers.get_call_frame_address());
+        registers[15] = Some(pc as u32);
+
         let return_frame = match self.debug_info.get_stackframe_info(
             &mut self.core,
             pc,
             self.frame_count,
-            self.registers.clone(),
+            registers,
             in_inlined_function,
         ) {
             Ok(mut frame) => {
@@ -492,6 +620,15 @@ impl<'debuginfo, 'probe, 'core> Iterator for StackFrameIterator<'debuginfo, 'pro
             // a backtrace, not the next instruction to be executed.
             self.pc = self.registers[14].map(|pc| u64::from(pc & !1));
 
+            // An EXC_RETURN value in the LR means that this function is an exception handler,
+            // which was called by the core, and not by another function.
+            if let Some(lr) = self.registers[14] {
+                if is_exc_return(lr) && self.core.architecture() == Architecture::Arm {
+                    debug!("Called from exception, EXC_RETURN={:#010x}", lr);
+                    self.exception_return = Some(lr);
+                }
+            }
+
             log::debug!("Called from pc={:#010x?}", self.pc);
         }
 
@@ -719,12 +856,15 @@ impl DebugInfo {
 
                 log::debug!("Function name: {}", function_name);
 
-                let variables = unit_info.get_function_variables(
+                let mut variables = unit_info.get_function_variables(
                     core,
                     die_cursor_state,
                     u64::from(registers.get_call_frame_address().unwrap_or(0)),
                     u64::from(registers.get_frame_program_counter().unwrap_or(0)),
                 )?;
+                for variable in &mut variables {
+                    variable.extract_register_value(&registers);
+                }
                 // dbg!(&variables);
                 //Ready to go ...
                 return Ok(StackFrame {
@@ -857,6 +997,168 @@ impl DebugInfo {
         }
     }
 
+    /// Find the program counter where a breakpoint should be set for a function.
+    ///
+    /// The name is either the plain name of the function, e.g. `main`, or the name qualified by
+    /// the enclosing namespaces, e.g. `blinky::main`. The breakpoint location is the end of the
+    /// function prologue, if the line program marks it, otherwise the start of the function.
+    pub fn get_function_breakpoint_location(&self, name: &str) -> Result<Option<u64>, DebugError> {
+        let mut unit_iter = self.dwarf.units();
+
+        while let Some(unit_header) = unit_iter.next()? {
+            let unit = self.dwarf.unit(unit_header)?;
+
+            // Names of the namespaces enclosing the current entry, with their depth.
+            let mut namespaces: Vec<(isize, String)> = Vec::new();
+            let mut depth = 0;
+
+            let mut entries = unit.entries();
+
+            while let Some((delta_depth, entry)) = entries.next_dfs()? {
+                depth += delta_depth;
+
+                while namespaces
+                    .last()
+                    .map(|(namespace_depth, _)| *namespace_depth >= depth)
+                    .unwrap_or(false)
+                {
+                    namespaces.pop();
+                }
+
+                let entry_name = match entry.attr_value(gimli::DW_AT_name)? {
+                    Some(value) => match self.dwarf.attr_string(&unit, value) {
+                        Ok(entry_name) => from_utf8(&entry_name)?.to_owned(),
+                        Err(_) => continue,
+                    },
+                    None => continue,
+                };
+
+                match entry.tag() {
+                    gimli::DW_TAG_namespace => namespaces.push((depth, entry_name)),
+                    gimli::DW_TAG_subprogram => {
+                        let qualified_name = namespaces
+                            .iter()
+                            .map(|(_, namespace)| namespace.as_str())
+                            .chain(std::iter::once(entry_name.as_str()))
+                            .collect::<Vec<_>>()
+                            .join("::");
+
+                        let matches = qualified_name == name
+                            || qualified_name.ends_with(&format!("::{}", name));
+
+                        if !matches {
+                            continue;
+                        }
+
+                        // Declarations and abstract instances of inlined functions have no code.
+                        let low_pc = match entry.attr_value(gimli::DW_AT_low_pc)? {
+                            Some(gimli::AttributeValue::Addr(low_pc)) => low_pc,
+                            _ => continue,
+                        };
+
+                        let high_pc = match entry.attr_value(gimli::DW_AT_high_pc)? {
+                            Some(gimli::AttributeValue::Addr(high_pc)) => high_pc,
+                            Some(gimli::AttributeValue::Udata(length)) => low_pc + length,
+                            _ => low_pc + 1,
+                        };
+
+                        debug!(
+                            "Found function {} at {:#010x}..{:#010x}",
+                            qualified_name, low_pc, high_pc
+                        );
+
+                        if let Some(ref line_program) = unit.line_program {
+                            let mut rows = line_program.clone().rows();
+
+                            while let Some((_, row)) = rows.next_row()? {
+                                if (low_pc..high_pc).contains(&row.address()) && row.prologue_end()
+                                {
+                                    return Ok(Some(row.address()));
+                                }
+                            }
+                        }
+
+                        return Ok(Some(low_pc));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Interpret the memory at `address` as a value of the type with the given name, e.g. `u32`
+    /// or the name of a struct, and return it as a `Variable` with the name `name`.
+    ///
+    /// Returns `None` if no type with this name is found in the debug information.
+    pub fn get_variable_of_type(
+        &self,
+        core: &mut Core<'_>,
+        type_name: &str,
+        name: &str,
+        address: u64,
+    ) -> Result<Option<Variable>, DebugError> {
+        let mut units = self.get_units();
+
+        while let Some(unit_info) = self.get_next_unit_info(&mut units) {
+            let mut type_offset = None;
+            let mut entries = unit_info.unit.entries();
+
+            while let Some((_, entry)) = entries.next_dfs()? {
+                let is_type = matches!(
+                    entry.tag(),
+                    gimli::DW_TAG_base_type
+                        | gimli::DW_TAG_structure_type
+                        | gimli::DW_TAG_enumeration_type
+                        | gimli::DW_TAG_union_type
+                );
+
+                // Declarations don't describe the layout of the type.
+                if !is_type || entry.attr_value(gimli::DW_AT_declaration)?.is_some() {
+                    continue;
+                }
+
+                if let Some(value) = entry.attr_value(gimli::DW_AT_name)? {
+                    if let Ok(entry_name) = self.dwarf.attr_string(&unit_info.unit, value) {
+                        if from_utf8(&entry_name) == Ok(type_name) {
+                            type_offset = Some(entry.offset());
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if let Some(type_offset) = type_offset {
+                let mut type_tree = unit_info
+                    .unit
+                    .header
+                    .entries_tree(&unit_info.unit.abbreviations, Some(type_offset))?;
+
+                let mut parent_variable = Variable::new();
+                parent_variable.memory_location = address;
+
+                let mut variable = Variable::new();
+                variable.name = name.to_owned();
+                variable.memory_location = address;
+
+                unit_info.extract_type(
+                    type_tree.root()?,
+                    &mut parent_variable,
+                    &mut variable,
+                    core,
+                    0,
+                    0,
+                )?;
+                variable.extract_value(core);
+
+                return Ok(Some(variable));
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Get the absolute path for an entry in a line program header
     fn get_path(
         &self,
@@ -2030,13 +2332,10 @@ impl<'debuginfo> UnitInfo<'debuginfo> {
                                             child_variable.set_value(value.to_string());
                                         }
                                     },
-                                    Location::Register { register: _ } => {
-                                        //TODO: I commented the below, because it needs work to read the correct register, NOT just 0 // match core.read_core_reg(register.0)
-                                        // let val = core
-                                        //     .read_core_reg(register.0 as u16)
-                                        //     .expect("Failed to read register from target");
+                                    Location::Register { register } => {
+                                        // The value is read from the registers of the frame by `Variable::extract_register_value()`.
                                         child_variable.memory_location = u64::MAX;
-                                        child_variable.set_value("extract_location() found a register address as the location".to_owned());
+                                        child_variable.register = Some(register.0);
                                     }
                                     l => {
                                         child_variable.memory_location = u64::MAX;
@@ -2266,3 +2565,55 @@ pub(crate) fn _print_all_attributes(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::mock::MockMemory;
+
+    #[test]
+    fn exception_frame_sizes() {
+        assert!(is_exc_return(0xffff_fffd));
+        assert!(!is_exc_return(0x0800_1235));
+
+        // Basic frame on the process stack
+        assert_eq!(exception_frame_size(0xffff_fffd, 0x6100_0000), 0x20);
+        // Extended frame with the floating-point context, and padding for the alignment
+        assert_eq!(exception_frame_size(0xffff_ffe9, 0x0100_0200), 0x6c);
+    }
+
+    #[test]
+    fn unwind_exception_frame() {
+        let frame_address = 0x2000_0f00;
+        let mut memory = MockMemory::new(0x2000_0000, 0x1000);
+        // R0-R3, R12, LR, PC and xPSR, stacked with padding for the alignment
+        let stacked = [0, 1, 2, 3, 12, 0x0800_0123, 0x0800_0456, 0x6100_0200];
+        for (offset, value) in stacked.iter().enumerate() {
+            memory.put_u32(frame_address + offset as u32 * 4, *value);
+        }
+
+        let mut registers = Registers::default();
+        registers[4] = Some(4);
+        registers[14] = Some(0xffff_fff9);
+
+        restore_exception_frame(&mut registers, &mut memory, frame_address, 0xffff_fff9).unwrap();
+
+        assert_eq!(registers[0..4], [Some(0), Some(1), Some(2), Some(3)]);
+        assert_eq!(registers[12], Some(12));
+        assert_eq!(registers[14], Some(0x0800_0123));
+        assert_eq!(registers.get_frame_program_counter(), Some(0x0800_0456));
+        assert_eq!(
+            registers.get_call_frame_address(),
+            Some(frame_address + 0x24)
+        );
+        // The callee-saved registers are not part of the exception frame.
+        assert_eq!(registers[4], Some(4));
+
+        // An exception frame which can't be read leaves the registers as they are.
+        let mut unchanged = registers.clone();
+        assert!(
+            restore_exception_frame(&mut unchanged, &mut memory, 0x1000_0000, 0xffff_fff9).is_err()
+        );
+        assert_eq!(unchanged[15], registers[15]);
+    }
+}
@@ -2,7 +2,12 @@ use num_traits::Zero;
 use thousands::Separable;
 
 use super::*;
-use std::{convert::TryInto, fmt};
+use crate::core::CoreRegisterAddress;
+use anyhow::anyhow;
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+};
 
 /// VariableKind is a tag used to differentiate the nature of a variable. The DAP protocol requires a differentiation between 'Named' and 'Indexed'. We've added some flags to control when variables require unique handling or decoding the value during runtimeprocessing.
 #[derive(Debug, Clone, PartialEq)]
@@ -64,6 +69,8 @@ pub struct Variable {
     pub type_name: String,
     /// The starting location/address in memory where this Variable's value is stored.
     pub memory_location: u64,
+    /// The DWARF register number of the register which holds the value of this Variable, if it is not stored in memory.
+    pub register: Option<u16>,
     pub byte_size: u64,
     /// If  this is a subrange (array, vector, etc.), is the ordinal position of this variable in that range
     pub(crate) member_index: Option<i64>,
@@ -112,6 +119,10 @@ impl Variable {
         } else {
             self.kind = VariableKind::Named
         }
+        //Variables which live in a register are decoded from the registers of their stack frame, see `extract_register_value()`
+        if self.register.is_some() {
+            return;
+        }
         //Quick exit if we don't really need to do much more
         if self.memory_location == u64::MAX// the value was set by get_location(), so just leave it as is
         || !self.value.is_empty()// the value was set elsewhere in this library - probably because of an error - so just leave it as is
@@ -210,6 +221,183 @@ impl Variable {
         self.value = string_value;
     }
 
+    /// Evaluate the value of a Variable which is stored in a register, from the register values of its stack frame.
+    /// The registers of the core are only used for the top frame, in the other frames they have to be unwound from the stack.
+    pub(crate) fn extract_register_value(&mut self, registers: &Registers) {
+        if let Some(register) = self.register {
+            if self.value.is_empty() {
+                self.value = match registers.get_dwarf_register(register) {
+                    Some(raw_value) => self.register_value(raw_value),
+                    None => "<not available>".to_string(),
+                };
+            }
+        }
+
+        if let Some(children) = &mut self.children {
+            for child in children {
+                child.extract_register_value(registers);
+            }
+        }
+    }
+
+    /// Decode the value of a Variable which is stored in a register, according to its type.
+    fn register_value(&self, raw_value: u32) -> String {
+        match self.type_name.as_str() {
+            "bool" => (raw_value & 0xff != 0).to_string(),
+            "char" => char::try_from(raw_value)
+                .map_or_else(|err| format!("ERROR: {:?}", err), |value| value.to_string()),
+            "i8" => (raw_value as i8).to_string(),
+            "i16" => (raw_value as i16).separate_with_underscores(),
+            "i32" | "isize" => (raw_value as i32).separate_with_underscores(),
+            "u8" => (raw_value as u8).to_string(),
+            "u16" => (raw_value as u16).separate_with_underscores(),
+            "u32" | "usize" => raw_value.separate_with_underscores(),
+            "f32" => f32::from_bits(raw_value).separate_with_underscores(),
+            other => format!(
+                "UNIMPLEMENTED: Evaluate type {} of ({} bytes) in register {}",
+                other,
+                self.byte_size,
+                self.register.unwrap_or_default()
+            ),
+        }
+    }
+
+    /// Encode `new_value` according to the type of the Variable, and write it to the target, either to `memory_location`, or to the register which holds the Variable.
+    /// Only Variables of base types (integers, floating point numbers, `bool` and `char`) can be changed.
+    /// Registers are written to the core, so Variables in registers can only be changed in the top stack frame.
+    pub fn update_value(&mut self, core: &mut Core<'_>, new_value: &str) -> Result<(), DebugError> {
+        let new_value = new_value.trim();
+
+        // The pointer sized types depend on the target.
+        let type_name = match (self.type_name.as_str(), self.byte_size) {
+            ("isize", 8) => "i64",
+            ("isize", _) => "i32",
+            ("usize", 8) => "u64",
+            ("usize", _) => "u32",
+            (type_name, _) => type_name,
+        };
+
+        let bytes = match type_name {
+            "bool" => match new_value {
+                "true" => vec![1],
+                "false" => vec![0],
+                other => {
+                    return Err(DebugError::Other(anyhow!(
+                        "Invalid value '{}' for type bool, expected 'true' or 'false'",
+                        other
+                    )))
+                }
+            },
+            "char" => {
+                let mut chars = new_value.trim_matches('\'').chars();
+                match (chars.next(), chars.next()) {
+                    (Some(value), None) => (value as u32).to_le_bytes().to_vec(),
+                    _ => {
+                        return Err(DebugError::Other(anyhow!(
+                            "Invalid value '{}' for type char, expected a single character",
+                            new_value
+                        )))
+                    }
+                }
+            }
+            "i8" => i8::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "i16" => i16::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "i32" => i32::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "i64" => i64::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "i128" => parse_integer(new_value)?.to_le_bytes().to_vec(),
+            "u8" => u8::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "u16" => u16::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "u32" => u32::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "u64" => u64::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "u128" => u128::try_from(parse_integer(new_value)?)?
+                .to_le_bytes()
+                .to_vec(),
+            "f32" => parse_float::<f32>(new_value)?.to_le_bytes().to_vec(),
+            "f64" => parse_float::<f64>(new_value)?.to_le_bytes().to_vec(),
+            other => {
+                return Err(DebugError::Other(anyhow!(
+                    "Variables of type {} can not be changed",
+                    other
+                )))
+            }
+        };
+
+        if self.byte_size != 0 && self.byte_size != bytes.len() as u64 {
+            return Err(DebugError::Other(anyhow!(
+                "The variable has a size of {} bytes, but the value of type {} has {} bytes",
+                self.byte_size,
+                self.type_name,
+                bytes.len()
+            )));
+        }
+
+        match self.register {
+            Some(register) => {
+                if bytes.len() > 4 {
+                    return Err(DebugError::Other(anyhow!(
+                        "A value of {} bytes does not fit into register {}",
+                        bytes.len(),
+                        register
+                    )));
+                }
+
+                // Signed values are sign extended to the full register.
+                let negative = type_name.starts_with('i') && bytes[bytes.len() - 1] & 0x80 != 0;
+                let mut raw_value = if negative { [0xff; 4] } else { [0; 4] };
+                raw_value[..bytes.len()].copy_from_slice(&bytes);
+
+                // The platform registers are in the order of the DWARF register numbers.
+                let register_address = core
+                    .registers()
+                    .get_platform_register(register as usize)
+                    .map(CoreRegisterAddress::from)
+                    .ok_or_else(|| {
+                        DebugError::Other(anyhow!(
+                            "The register {} of the variable '{}' is not supported",
+                            register,
+                            self.name
+                        ))
+                    })?;
+
+                core.write_core_reg(register_address, u32::from_le_bytes(raw_value))?;
+
+                // Read back the new value.
+                self.value = self.register_value(core.read_core_reg(register_address)?);
+
+                return Ok(());
+            }
+            None if self.memory_location == u64::MAX || self.memory_location.is_zero() => {
+                return Err(DebugError::Other(anyhow!(
+                    "The variable '{}' has no memory location",
+                    self.name
+                )));
+            }
+            None => core.write_8(self.memory_location as u32, &bytes)?,
+        }
+
+        // Read back the new value.
+        self.value = String::new();
+        self.extract_value(core);
+
+        Ok(())
+    }
+
     /// Instead of just pushing to Variable.children, do some intelligent selection/addition of new Variables.
     /// Primarily this is to force late-as-possible(before parent) call of `extract_value()` on child variables, and to determine which of the processed DWARF nodes are included in the final variable tree
     pub fn add_child_variable(&mut self, child_variable: &mut Variable, core: &mut Core<'_>) {
@@ -291,6 +479,34 @@ impl fmt::Display for Variable {
         }
     }
 }
+/// Parse an integer, either in decimal, or in hexadecimal or binary with a `0x` or `0b` prefix.
+fn parse_integer(value: &str) -> Result<i128, DebugError> {
+    let digits = value.replace('_', "");
+
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits.as_str()),
+    };
+
+    let magnitude = if let Some(hex) = digits.strip_prefix("0x") {
+        i128::from_str_radix(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i128::from_str_radix(binary, 2)
+    } else {
+        digits.parse()
+    }
+    .map_err(|_| DebugError::Other(anyhow!("Invalid integer value '{}'", value)))?;
+
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+fn parse_float<T: std::str::FromStr>(value: &str) -> Result<T, DebugError> {
+    value
+        .replace('_', "")
+        .parse()
+        .map_err(|_| DebugError::Other(anyhow!("Invalid floating point value '{}'", value)))
+}
+
 /// Traits and Impl's to read from memory and decode the Variable value based on Variable::typ and Variable::location. The MS DAP protocol passes the value as a string, so these are here only to provide the memory read logic before returning it as a string.
 trait Value {
     fn get_value(variable: &Variable, core: &mut Core<'_>) -> Result<Self, DebugError>
@@ -471,3 +687,37 @@ impl Value for f64 {
         Ok(ret_value)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{parse_integer, Registers, Variable};
+
+    #[test]
+    fn parse_integer_values() {
+        assert_eq!(parse_integer("1_000").unwrap(), 1000);
+        assert_eq!(parse_integer("-42").unwrap(), -42);
+        assert_eq!(parse_integer("0x2000_0000").unwrap(), 0x2000_0000);
+        assert_eq!(parse_integer("-0x10").unwrap(), -16);
+        assert_eq!(parse_integer("0b101").unwrap(), 5);
+        assert!(parse_integer("twelve").is_err());
+    }
+
+    #[test]
+    fn register_values_of_the_frame() {
+        let mut registers = Registers::default();
+        registers[4] = Some(0xffff_fffe);
+
+        let mut variable = Variable::new();
+        variable.type_name = "i32".to_string();
+        variable.register = Some(4);
+        variable.extract_register_value(&registers);
+        assert_eq!(variable.get_value(), "-2");
+
+        // Registers which could not be unwound have no value.
+        let mut variable = Variable::new();
+        variable.type_name = "u32".to_string();
+        variable.register = Some(5);
+        variable.extract_register_value(&registers);
+        assert_eq!(variable.get_value(), "<not available>");
+    }
+}
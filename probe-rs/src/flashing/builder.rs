@@ -134,6 +134,86 @@ impl FlashLayout {
     pub fn visualize(&self) -> FlashVisualizer {
         FlashVisualizer::new(&self)
     }
+
+    /// Returns the contents of `sector` after flashing. Bytes which are restored to their
+    /// current value, if `restore_unwritten_bytes` is set, are `None`, as they always match.
+    ///
+    /// Returns `None` if a page of the layout extends beyond the sector, because such a sector
+    /// can't be skipped without skipping parts of another sector.
+    pub(super) fn sector_contents(
+        &self,
+        sector: &FlashSector,
+        erased_byte_value: u8,
+        restore_unwritten_bytes: bool,
+    ) -> Option<Vec<Option<u8>>> {
+        let sector_range = sector.address..sector.address + sector.size;
+        let mut contents = vec![Some(erased_byte_value); sector.size as usize];
+
+        for (page_index, page) in self.pages.iter().enumerate() {
+            let page_range = page.address..page.address + page.size();
+            if !sector_range.intersects_range(&page_range) {
+                continue;
+            }
+            if !sector_range.contains_range(&page_range) {
+                return None;
+            }
+
+            let offset = (page.address - sector.address) as usize;
+            for (byte, value) in contents[offset..].iter_mut().zip(&page.data) {
+                *byte = Some(*value);
+            }
+
+            if restore_unwritten_bytes {
+                for fill in self
+                    .fills
+                    .iter()
+                    .filter(|fill| fill.page_index == page_index)
+                {
+                    let offset = (fill.address - sector.address) as usize;
+                    for byte in &mut contents[offset..offset + fill.size as usize] {
+                        *byte = None;
+                    }
+                }
+            }
+        }
+
+        Some(contents)
+    }
+
+    /// Removes `sectors` from the layout, together with the pages and fills in them, so that
+    /// they are neither erased nor programmed.
+    pub(super) fn remove_sectors(&mut self, sectors: &[FlashSector]) {
+        let in_removed_sector = |address: u32| {
+            sectors
+                .iter()
+                .any(|sector| (sector.address..sector.address + sector.size).contains(&address))
+        };
+
+        self.sectors.retain(|sector| !sectors.contains(sector));
+
+        let mut page_indices = Vec::with_capacity(self.pages.len());
+        let mut kept_pages = 0;
+        for page in &self.pages {
+            if in_removed_sector(page.address) {
+                page_indices.push(None);
+            } else {
+                page_indices.push(Some(kept_pages));
+                kept_pages += 1;
+            }
+        }
+
+        self.pages.retain(|page| !in_removed_sector(page.address));
+        self.fills = self
+            .fills
+            .iter()
+            .filter_map(|fill| {
+                page_indices[fill.page_index].map(|page_index| FlashFill {
+                    page_index,
+                    ..fill.clone()
+                })
+            })
+            .collect();
+    }
 }
 
 /// A block of data that is to be written to flash.
@@ -1131,4 +1211,46 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn remove_unchanged_sector() {
+        let (region, flash_algorithm) = assemble_demo_flash1();
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x0FFE, &[42; 4]).unwrap();
+        let mut flash_layout = flash_builder
+            .build_sectors_and_pages(&region, &flash_algorithm, true)
+            .unwrap();
+
+        let erased_byte_value = flash_algorithm.flash_properties.erased_byte_value;
+        let first_sector = flash_layout.sectors()[0].clone();
+
+        let contents = flash_layout
+            .sector_contents(&first_sector, erased_byte_value, false)
+            .unwrap();
+        assert_eq!(contents[0x0FFD], Some(erased_byte_value));
+        assert_eq!(contents[0x0FFE], Some(42));
+
+        let contents = flash_layout
+            .sector_contents(&first_sector, erased_byte_value, true)
+            .unwrap();
+        assert_eq!(contents[0x0FFD], None);
+        assert_eq!(contents[0x0FFE], Some(42));
+
+        flash_layout.remove_sectors(&[first_sector]);
+
+        assert_eq!(
+            flash_layout.sectors(),
+            &[FlashSector {
+                address: 0x1000,
+                size: 0x1000,
+            }]
+        );
+        assert_eq!(flash_layout.pages().len(), 4);
+        assert_eq!(flash_layout.pages()[0].address(), 0x1000);
+        assert!(flash_layout
+            .fills()
+            .iter()
+            .all(|fill| fill.address >= 0x1000));
+        assert_eq!(flash_layout.fills()[0].page_index(), 0);
+    }
 }
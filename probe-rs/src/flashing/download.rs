@@ -111,7 +111,28 @@ pub struct DownloadOptions<'progress> {
     /// It may be useful for mass production.
     pub skip_erase: bool,
     /// After flashing, read back all the flashed data to verify it has been written correctly.
+    ///
+    /// Data in flash is verified with a CRC32 routine on the target instead, one sector at a time,
+    /// if there is enough RAM for the routine next to the flash algorithm.
     pub verify: bool,
+    /// Read the contents of every sector before flashing, and skip erasing and programming the
+    /// sectors which already hold the data to be flashed.
+    ///
+    /// This makes flashing a slightly changed program much faster. It has no effect if
+    /// `do_chip_erase` is set, because a chip erase erases all sectors anyway.
+    pub skip_unchanged_sectors: bool,
+    /// Don't reset the cores before flashing, and restore their registers and the RAM used by
+    /// the flash algorithm afterwards.
+    ///
+    /// This allows changing the flash while a program is being debugged, e.g. to write breakpoint
+    /// instructions. The program can be resumed where it was halted, as long as it does not use
+    /// the flash being written.
+    ///
+    /// Only the core registers and the RAM used by the flash algorithm are restored. The flash
+    /// algorithm, starting with its `Init` function, runs on the halted program, and can clobber
+    /// any other state the program relies on, e.g. the clock configuration, the flash controller
+    /// or other peripherals, and the RAM beyond the algorithm.
+    pub keep_core_state: bool,
 }
 
 impl<'progress> DownloadOptions<'progress> {
@@ -45,7 +45,7 @@ pub fn erase_all(session: &mut Session) -> Result<(), FlashError> {
         let algo = algo.unwrap().clone();
 
         let core_index = session.target().core_index_by_name(&core_name).unwrap();
-        let mut flasher = Flasher::new(session, core_index, &algo)?;
+        let mut flasher = Flasher::new(session, core_index, &algo, false)?;
 
         if flasher.is_chip_erase_supported() {
             log::debug!("     -- chip erase supported, doing it.");
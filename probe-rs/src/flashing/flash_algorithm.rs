@@ -41,6 +41,9 @@ pub struct FlashAlgorithm {
     /// least as large as the region's `page_size` attribute. If at least 2 buffers are included in
     /// the list, then double buffered programming will be enabled.
     pub page_buffers: Vec<u32>,
+    /// Address of the built-in CRC32 routine, which is loaded behind the page buffers if the RAM
+    /// is large enough. It is used to verify the flash contents without reading them back.
+    pub pc_crc32: Option<u32>,
 
     /// The properties of the flash on the device.
     pub flash_properties: FlashProperties,
@@ -177,6 +180,92 @@ impl FlashAlgorithm {
         }
     }
 
+    // A position-independent CRC32 routine (IEEE 802.3, reflected, without a table) for
+    // Thumb, including ARMv6-M. It takes the address in R0 and the length in R1, and returns
+    // the CRC in R0.
+    //
+    //          push {r4, r5}
+    //          movs r2, #0
+    //          mvns r2, r2
+    //          ldr  r3, =0xEDB88320
+    //  byte:   cmp  r1, #0
+    //          beq  done
+    //          ldrb r4, [r0]
+    //          eors r2, r4
+    //          movs r5, #8
+    //  bit:    lsrs r2, r2, #1
+    //          bcc  next
+    //          eors r2, r3
+    //  next:   subs r5, #1
+    //          bne  bit
+    //          adds r0, #1
+    //          subs r1, #1
+    //          b    byte
+    //  done:   mvns r0, r2
+    //          pop  {r4, r5}
+    //          bx   lr
+    const ARM_CRC32_ROUTINE: [u32; 11] = [
+        0x2200_B430,
+        0x4B08_43D2,
+        0xD00A_2900,
+        0x4062_7804,
+        0x0852_2508,
+        0x405A_D300,
+        0xD1FA_3D01,
+        0x3901_3001,
+        0x43D0_E7F2,
+        0x4770_BC30,
+        0xEDB8_8320,
+    ];
+
+    // The same CRC32 routine for RV32I, with the address in a0 and the length in a1.
+    //
+    //          li   a2, -1
+    //          li   a3, 0xEDB88320
+    //  byte:   beqz a1, done
+    //          lbu  a4, 0(a0)
+    //          xor  a2, a2, a4
+    //          li   a5, 8
+    //  bit:    andi t0, a2, 1
+    //          srli a2, a2, 1
+    //          beqz t0, next
+    //          xor  a2, a2, a3
+    //  next:   addi a5, a5, -1
+    //          bnez a5, bit
+    //          addi a0, a0, 1
+    //          addi a1, a1, -1
+    //          j    byte
+    //  done:   not  a0, a2
+    //          ret
+    const RISCV_CRC32_ROUTINE: [u32; 18] = [
+        0xFFF0_0613,
+        0xEDB8_86B7,
+        0x3206_8693,
+        0x0205_8A63,
+        0x0005_4703,
+        0x00E6_4633,
+        0x0080_0793,
+        0x0016_7293,
+        0x0016_5613,
+        0x0002_8463,
+        0x00D6_4633,
+        0xFFF7_8793,
+        0xFE07_96E3,
+        0x0015_0513,
+        0xFFF5_8593,
+        0xFD1F_F06F,
+        0xFFF6_4513,
+        0x0000_8067,
+    ];
+
+    /// The built-in CRC32 routine for the architecture, which is loaded at `pc_crc32`.
+    pub(super) fn crc32_routine(architecture: Architecture) -> &'static [u32] {
+        match architecture {
+            Architecture::Arm => &Self::ARM_CRC32_ROUTINE,
+            Architecture::Riscv => &Self::RISCV_CRC32_ROUTINE,
+        }
+    }
+
     /// Constructs a complete flash algorithm, tailored to the flash and RAM sizes given.
     pub fn assemble_from_raw(
         raw: &RawFlashAlgorithm,
@@ -237,6 +326,16 @@ impl FlashAlgorithm {
             vec![addr_data]
         };
 
+        // The CRC32 routine goes behind the last page buffer, if it fits into the RAM.
+        let crc32_size = std::mem::size_of_val(Self::crc32_routine(target.architecture())) as u32;
+        let addr_crc32 = page_buffers[page_buffers.len() - 1] + raw.flash_properties.page_size;
+        let addr_crc32 = (addr_crc32 + 3) & !3;
+        let pc_crc32 = if addr_crc32 + crc32_size <= ram_region.range.end {
+            Some(addr_crc32)
+        } else {
+            None
+        };
+
         let code_start = addr_load + (header.len() * size_of::<u32>()) as u32;
 
         let name = raw.name.clone();
@@ -255,6 +354,7 @@ impl FlashAlgorithm {
             begin_stack: addr_stack,
             begin_data: page_buffers[0],
             page_buffers: page_buffers.clone(),
+            pc_crc32,
             flash_properties: raw.flash_properties.clone(),
         })
     }
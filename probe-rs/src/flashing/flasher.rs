@@ -3,10 +3,12 @@ use probe_rs_target::{MemoryRegion, RawFlashAlgorithm};
 use super::{
     FlashAlgorithm, FlashBuilder, FlashError, FlashFill, FlashLayout, FlashPage, FlashProgress,
 };
+use crate::architecture::arm::core::register;
 use crate::config::NvmRegion;
 use crate::memory::MemoryInterface;
 use crate::{
     core::{Architecture, RegisterFile},
+    error,
     session::Session,
     Core, CoreRegisterAddress,
 };
@@ -55,13 +57,71 @@ pub(super) struct Flasher<'session> {
     session: &'session mut Session,
     core_index: usize,
     flash_algorithm: FlashAlgorithm,
+    /// The state of the core before the flash algorithm was loaded, if it has to be restored.
+    saved_state: Option<SavedCoreState>,
+}
+
+/// The registers of a core and the contents of the RAM used by the flash algorithm,
+/// which are restored after flashing.
+struct SavedCoreState {
+    registers: Vec<(CoreRegisterAddress, u32)>,
+    ram_address: u32,
+    ram: Vec<u8>,
+}
+
+impl SavedCoreState {
+    /// Save the registers of a halted core, and its RAM from `ram_start` to `ram_end`.
+    fn save(core: &mut Core, ram_start: u32, ram_end: u32) -> Result<Self, error::Error> {
+        // The program counter is not one of the platform registers on every architecture.
+        let register_file = core.registers();
+        let mut addresses: Vec<CoreRegisterAddress> = register_file
+            .registers()
+            .map(|register| register.address)
+            .collect();
+        if !addresses.contains(&register_file.program_counter().address) {
+            addresses.push(register_file.program_counter().address);
+        }
+        if core.architecture() == Architecture::Arm {
+            addresses.push(register::XPSR.address);
+        }
+
+        let mut registers = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            registers.push((address, core.read_core_reg(address)?));
+        }
+
+        let mut ram = vec![0; (ram_end - ram_start) as usize];
+        core.read_8(ram_start, &mut ram)?;
+
+        Ok(Self {
+            registers,
+            ram_address: ram_start,
+            ram,
+        })
+    }
+
+    /// Write the saved RAM and registers back to the core.
+    fn restore(self, core: &mut Core) -> Result<(), error::Error> {
+        core.write_8(self.ram_address, &self.ram)?;
+
+        for (address, value) in self.registers {
+            core.write_core_reg(address, value)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl<'session> Flasher<'session> {
+    /// Load the flash algorithm into the RAM of the core.
+    ///
+    /// If `keep_core_state` is `true`, the core is not reset, and its registers and the RAM used
+    /// by the flash algorithm are saved, to be restored with [`Flasher::restore_core_state`].
     pub(super) fn new(
         session: &'session mut Session,
         core_index: usize,
         raw_flash_algorithm: &RawFlashAlgorithm,
+        keep_core_state: bool,
     ) -> Result<Self, FlashError> {
         let target = session.target();
 
@@ -86,17 +146,82 @@ impl<'session> Flasher<'session> {
 
         let flash_algorithm = FlashAlgorithm::assemble_from_raw(raw_flash_algorithm, ram, target)?;
 
+        // The stack of the flash algorithm is at the beginning of the RAM, followed by the code,
+        // the page buffers and the CRC32 routine.
+        let ram_start = ram.range.start;
+
         let mut this = Self {
             session,
             core_index,
             flash_algorithm,
+            saved_state: None,
         };
 
+        if keep_core_state {
+            this.save_core_state(ram_start)?;
+        }
+
         this.load()?;
 
         Ok(this)
     }
 
+    fn save_core_state(&mut self, ram_start: u32) -> Result<(), FlashError> {
+        let algo = &self.flash_algorithm;
+
+        let page_buffers_end = algo
+            .page_buffers
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or(algo.begin_data)
+            + algo.flash_properties.page_size;
+
+        let mut core = self
+            .session
+            .core(self.core_index)
+            .map_err(FlashError::Core)?;
+
+        let crc32_end = algo.pc_crc32.map_or(0, |pc_crc32| {
+            pc_crc32
+                + std::mem::size_of_val(FlashAlgorithm::crc32_routine(core.architecture())) as u32
+        });
+
+        core.halt(Duration::from_millis(100))
+            .map_err(FlashError::Core)?;
+
+        let saved_state =
+            SavedCoreState::save(&mut core, ram_start, page_buffers_end.max(crc32_end))
+                .map_err(FlashError::Core)?;
+
+        log::debug!(
+            "Saved {} registers and {} bytes of RAM at {:#010x}",
+            saved_state.registers.len(),
+            saved_state.ram.len(),
+            ram_start
+        );
+
+        self.saved_state = Some(saved_state);
+
+        Ok(())
+    }
+
+    /// Restore the registers and the RAM saved when the flasher was created,
+    /// if `keep_core_state` was set.
+    pub(super) fn restore_core_state(&mut self) -> Result<(), FlashError> {
+        let saved_state = match self.saved_state.take() {
+            Some(saved_state) => saved_state,
+            None => return Ok(()),
+        };
+
+        let mut core = self
+            .session
+            .core(self.core_index)
+            .map_err(FlashError::Core)?;
+
+        saved_state.restore(&mut core).map_err(FlashError::Core)
+    }
+
     pub(super) fn flash_algorithm(&self) -> &FlashAlgorithm {
         &self.flash_algorithm
     }
@@ -121,9 +246,13 @@ impl<'session> Flasher<'session> {
             .halt(Duration::from_millis(100))
             .map_err(FlashError::Core)?;
         log::debug!("PC = 0x{:08x}", cpu_info.pc);
-        log::debug!("Reset and halt");
-        core.reset_and_halt(Duration::from_millis(500))
-            .map_err(FlashError::Core)?;
+
+        // Without a reset, the program can continue where it was halted after flashing.
+        if self.saved_state.is_none() {
+            log::debug!("Reset and halt");
+            core.reset_and_halt(Duration::from_millis(500))
+                .map_err(FlashError::Core)?;
+        }
 
         // TODO: Possible special preparation of the target such as enabling faster clocks for the flash e.g.
 
@@ -159,6 +288,15 @@ impl<'session> Flasher<'session> {
 
         log::debug!("RAM contents match flashing algo blob.");
 
+        if let Some(pc_crc32) = algo.pc_crc32 {
+            log::debug!(
+                "Loading CRC32 routine into RAM at address 0x{:08x}",
+                pc_crc32
+            );
+            let routine = FlashAlgorithm::crc32_routine(core.architecture());
+            core.write_32(pc_crc32, routine).map_err(FlashError::Core)?;
+        }
+
         Ok(())
     }
 
@@ -226,6 +364,10 @@ impl<'session> Flasher<'session> {
     /// If `restore_unwritten_bytes` is `true`, all bytes of a sector,
     /// that are not to be written during flashing will be read from the flash first
     /// and written again once the sector is erased.
+    ///
+    /// If `skip_unchanged_sectors` is `true`, sectors which already hold their contents
+    /// are neither erased nor programmed.
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn program(
         &mut self,
         region: &NvmRegion,
@@ -233,6 +375,7 @@ impl<'session> Flasher<'session> {
         restore_unwritten_bytes: bool,
         enable_double_buffering: bool,
         skip_erasing: bool,
+        skip_unchanged_sectors: bool,
         progress: &FlashProgress,
     ) -> Result<(), FlashError> {
         log::debug!("Starting program procedure.");
@@ -243,6 +386,10 @@ impl<'session> Flasher<'session> {
             restore_unwritten_bytes,
         )?;
 
+        if skip_unchanged_sectors {
+            self.skip_unchanged_sectors(&mut flash_layout, restore_unwritten_bytes, progress)?;
+        }
+
         progress.initialized(flash_layout.clone());
 
         log::debug!("Double Buffering enabled: {:?}", enable_double_buffering);
@@ -290,6 +437,117 @@ impl<'session> Flasher<'session> {
         Ok(())
     }
 
+    /// Removes the sectors from `flash_layout` whose contents in the flash already match the
+    /// contents they would have after flashing.
+    fn skip_unchanged_sectors(
+        &mut self,
+        flash_layout: &mut FlashLayout,
+        restore_unwritten_bytes: bool,
+        progress: &FlashProgress,
+    ) -> Result<(), FlashError> {
+        let erased_byte_value = self.flash_algorithm.flash_properties.erased_byte_value;
+        let layout = &*flash_layout;
+
+        let unchanged_sectors = self.run_verify(|active| {
+            let mut unchanged_sectors = Vec::new();
+
+            for sector in layout.sectors() {
+                let expected = match layout.sector_contents(
+                    sector,
+                    erased_byte_value,
+                    restore_unwritten_bytes,
+                ) {
+                    Some(expected) => expected,
+                    None => continue,
+                };
+
+                let mut contents = vec![0; sector.size() as usize];
+                active
+                    .core
+                    .read_8(sector.address(), &mut contents)
+                    .map_err(FlashError::Core)?;
+
+                let unchanged = expected
+                    .iter()
+                    .zip(&contents)
+                    .all(|(expected, actual)| expected.is_none() || *expected == Some(*actual));
+                if unchanged {
+                    unchanged_sectors.push(sector.clone());
+                }
+            }
+
+            Ok(unchanged_sectors)
+        })?;
+
+        log::debug!(
+            "Skipping {} of {} sectors, which hold their data already.",
+            unchanged_sectors.len(),
+            flash_layout.sectors().len()
+        );
+
+        for sector in &unchanged_sectors {
+            progress.sector_skipped(sector.address(), sector.size());
+        }
+        flash_layout.remove_sectors(&unchanged_sectors);
+
+        Ok(())
+    }
+
+    /// Verifies the data of `flash_builder` in `region` with the CRC32 routine on the target,
+    /// one sector at a time, which is much faster than reading the data back.
+    ///
+    /// Returns `false` if there is not enough RAM for the CRC32 routine, in which case the data
+    /// has to be read back instead.
+    pub(super) fn verify_crc32(
+        &mut self,
+        region: &NvmRegion,
+        flash_builder: &FlashBuilder,
+    ) -> Result<bool, FlashError> {
+        let pc_crc32 = match self.flash_algorithm.pc_crc32 {
+            Some(pc_crc32) => pc_crc32,
+            None => {
+                log::debug!("Not enough RAM for the CRC32 routine, verifying by reading back.");
+                return Ok(false);
+            }
+        };
+
+        // Split the data at the sector boundaries.
+        let mut blocks = Vec::new();
+        for (mut address, mut data) in flash_builder.data_in_range(&region.range) {
+            while !data.is_empty() {
+                let size = match self.flash_algorithm.sector_info(address) {
+                    Some(sector) => (sector.base_address + sector.size - address) as usize,
+                    None => data.len(),
+                };
+                let (block, rest) = data.split_at(size.min(data.len()));
+                blocks.push((address, block));
+                address += block.len() as u32;
+                data = rest;
+            }
+        }
+
+        self.run_verify(|active| {
+            for (address, data) in blocks {
+                let crc = active.crc32(pc_crc32, address, data.len() as u32)?;
+                let expected = crc32fast::hash(data);
+
+                log::debug!(
+                    "CRC32 of {:08x}-{:08x}: {:08x}, expected {:08x}",
+                    address,
+                    address + data.len() as u32,
+                    crc,
+                    expected
+                );
+
+                if crc != expected {
+                    return Err(FlashError::Verify);
+                }
+            }
+
+            Ok(true)
+        })
+    }
+
     /// Fills all the bytes of `current_page`.
     ///
     /// If `restore_unwritten_bytes` is `true`, all bytes of the page,
@@ -709,6 +967,30 @@ impl<'probe> ActiveFlasher<'probe, Erase> {
     }
 }
 
+impl<'probe> ActiveFlasher<'probe, Verify> {
+    /// Calculates the CRC32 of `size` bytes at `address` with the CRC32 routine at `pc_crc32`.
+    pub(super) fn crc32(
+        &mut self,
+        pc_crc32: u32,
+        address: u32,
+        size: u32,
+    ) -> Result<u32, FlashError> {
+        self.call_function_and_wait(
+            &Registers {
+                pc: pc_crc32,
+                r0: Some(address),
+                r1: Some(size),
+                r2: None,
+                r3: None,
+            },
+            true,
+            // The routine needs less than 100 cycles per byte, even on slow cores.
+            Duration::from_millis(1000 + u64::from(size) / 64),
+        )
+        .map_err(FlashError::Core)
+    }
+}
+
 impl<'p> ActiveFlasher<'p, Program> {
     pub(super) fn program_page(&mut self, address: u32, bytes: &[u8]) -> Result<(), FlashError> {
         let t1 = std::time::Instant::now();
@@ -814,3 +1096,45 @@ impl<'p> ActiveFlasher<'p, Program> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{mock::MockCore, CoreState};
+    use crate::memory::mock::MockMemory;
+
+    #[test]
+    fn core_state_is_restored() {
+        let mut memory = MockMemory::new(0x2000_0000, 0x400);
+        memory.put_bytes(0x2000_0000, &[1, 2, 3, 4]);
+        let mut state = CoreState::new(0);
+        let mut core = Core::new(MockCore::new(memory), &mut state);
+
+        let program_counter = core.registers().program_counter().address;
+        core.write_core_reg(program_counter, 0x0800_0124).unwrap();
+        core.write_core_reg(CoreRegisterAddress(0), 7).unwrap();
+        core.write_core_reg(register::XPSR.address, 0x0100_0000)
+            .unwrap();
+
+        let saved_state = SavedCoreState::save(&mut core, 0x2000_0000, 0x2000_0200).unwrap();
+
+        // The flash algorithm is loaded to the RAM, and its functions are called.
+        core.write_8(0x2000_0000, &[0xff; 0x200]).unwrap();
+        core.write_core_reg(program_counter, 0x2000_0021).unwrap();
+        core.write_core_reg(CoreRegisterAddress(0), 0).unwrap();
+        core.write_core_reg(register::XPSR.address, 0).unwrap();
+
+        saved_state.restore(&mut core).unwrap();
+
+        assert_eq!(core.read_core_reg(program_counter).unwrap(), 0x0800_0124);
+        assert_eq!(core.read_core_reg(CoreRegisterAddress(0)).unwrap(), 7);
+        assert_eq!(
+            core.read_core_reg(register::XPSR.address).unwrap(),
+            0x0100_0000
+        );
+        let mut ram = [0; 0x200];
+        core.read_8(0x2000_0000, &mut ram).unwrap();
+        assert_eq!(ram[..4], [1, 2, 3, 4]);
+        assert!(ram[4..].iter().all(|byte| *byte == 0));
+    }
+}
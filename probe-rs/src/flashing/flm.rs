@@ -0,0 +1,451 @@
+//! Reading flash algorithms from the FLM files of CMSIS-Packs.
+//!
+//! An FLM file is an ELF file, which contains the position-independent code of the flash
+//! algorithm in the `PrgCode` section, its data in the `PrgData` section, and a description of
+//! the flash in the `FlashDevice` structure. The entry points are the `Init`, `UnInit`,
+//! `EraseSector`, `ProgramPage` and `EraseChip` functions.
+
+use crate::config::{FlashProperties, RawFlashAlgorithm, SectorDescription};
+use object::read::{Object, ObjectSection, ObjectSymbol};
+use object::SectionKind;
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+
+/// The size of the `FlashDevice` structure up to the list of sectors.
+const FLASH_DEVICE_HEADER_SIZE: usize = 160;
+
+/// The maximum number of sector descriptions in the `FlashDevice` structure.
+const FLASH_DEVICE_MAX_SECTORS: usize = 512;
+
+/// The marker for the end of the list of sectors in the `FlashDevice` structure.
+const SECTOR_END: u32 = 0xFFFF_FFFF;
+
+/// An error which occured while reading a flash algorithm from an FLM file.
+#[derive(Debug, thiserror::Error)]
+pub enum FlmError {
+    /// An IO error has occured while reading the FLM file.
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    /// Reading and decoding the ELF file has failed.
+    #[error("Could not read the ELF file of the flash algorithm")]
+    Elf(#[from] object::read::Error),
+    /// A section which every flash algorithm has is missing.
+    #[error("The flash algorithm has no '{0}' section")]
+    MissingSection(&'static str),
+    /// A function or structure which every flash algorithm has is missing.
+    #[error("The flash algorithm has no '{0}' symbol")]
+    MissingSymbol(&'static str),
+    /// A section or function of the flash algorithm is located before its code.
+    #[error("The '{0}' of the flash algorithm is located before its code")]
+    InvalidAddress(&'static str),
+    /// The data of the flash algorithm is located within its code.
+    #[error("The data of the flash algorithm overlaps its code")]
+    DataOverlapsCode,
+    /// The `FlashDevice` structure is too short, or its sectors are not terminated.
+    #[error("The 'FlashDevice' description of the flash algorithm is invalid")]
+    InvalidFlashDevice,
+}
+
+/// Reads a flash algorithm from an FLM file, which can be added to a target with
+/// [`Target::add_flash_algorithm`](crate::Target::add_flash_algorithm).
+///
+/// The algorithm is named after the file. It can be used by all the cores of the target.
+pub fn read_flash_algorithm_from_flm(
+    path: impl AsRef<Path>,
+) -> Result<RawFlashAlgorithm, FlmError> {
+    let path = path.as_ref();
+    let data = std::fs::read(path)?;
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    parse_flm(&name, &data)
+}
+
+/// Parses a flash algorithm from the contents of an FLM file.
+pub fn parse_flm(name: &str, data: &[u8]) -> Result<RawFlashAlgorithm, FlmError> {
+    let file = object::File::parse(data)?;
+
+    let code = file
+        .section_by_name("PrgCode")
+        .ok_or(FlmError::MissingSection("PrgCode"))?;
+    let code_start = code.address();
+
+    // The instructions are the code, followed by the data at its offset from the code, so that
+    // the algorithm finds its data relative to the static base.
+    let mut instructions = code.data()?.to_vec();
+    let data_section_offset = match file.section_by_name("PrgData") {
+        Some(data_section) => {
+            let offset = data_section
+                .address()
+                .checked_sub(code_start)
+                .and_then(|offset| usize::try_from(offset).ok())
+                .ok_or(FlmError::InvalidAddress("PrgData"))?;
+            if offset < instructions.len() {
+                return Err(FlmError::DataOverlapsCode);
+            }
+            instructions.resize(offset, 0);
+            if data_section.kind() == SectionKind::UninitializedData {
+                instructions.resize(offset + data_section.size() as usize, 0);
+            } else {
+                instructions.extend_from_slice(data_section.data()?);
+            }
+            offset as u32
+        }
+        None => instructions.len() as u32,
+    };
+    // Flash algorithms are loaded as 32 bit words.
+    instructions.resize((instructions.len() + 3) / 4 * 4, 0);
+
+    let function = |name: &'static str| -> Result<Option<u32>, FlmError> {
+        file.symbols()
+            .find(|symbol| symbol.name().ok() == Some(name))
+            .map(|symbol| {
+                symbol
+                    .address()
+                    .checked_sub(code_start)
+                    .and_then(|offset| u32::try_from(offset).ok())
+                    .ok_or(FlmError::InvalidAddress(name))
+            })
+            .transpose()
+    };
+
+    let flash_device = file
+        .symbols()
+        .find(|symbol| symbol.name().ok() == Some("FlashDevice"))
+        .ok_or(FlmError::MissingSymbol("FlashDevice"))?;
+    let flash_device_section = file
+        .sections()
+        .find(|section| {
+            (section.address()..section.address().saturating_add(section.size()))
+                .contains(&flash_device.address())
+        })
+        .ok_or(FlmError::InvalidFlashDevice)?;
+    // The section contains the symbol, so the offset is within its size. Its data is empty if
+    // the section is uninitialized.
+    let offset = (flash_device.address() - flash_device_section.address()) as usize;
+    let (description, flash_properties) = parse_flash_device(
+        flash_device_section
+            .data()?
+            .get(offset..)
+            .ok_or(FlmError::InvalidFlashDevice)?,
+    )?;
+
+    Ok(RawFlashAlgorithm {
+        name: name.to_string(),
+        description,
+        default: false,
+        instructions,
+        pc_init: function("Init")?,
+        pc_uninit: function("UnInit")?,
+        pc_program_page: function("ProgramPage")?.ok_or(FlmError::MissingSymbol("ProgramPage"))?,
+        pc_erase_sector: function("EraseSector")?.ok_or(FlmError::MissingSymbol("EraseSector"))?,
+        pc_erase_all: function("EraseChip")?,
+        data_section_offset,
+        flash_properties,
+        cores: Vec::new(),
+    })
+}
+
+/// Parses the `FlashDevice` structure into the name of the device and its flash properties.
+fn parse_flash_device(data: &[u8]) -> Result<(String, FlashProperties), FlmError> {
+    let word = |offset: usize| -> Result<u32, FlmError> {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or(FlmError::InvalidFlashDevice)
+    };
+
+    let name = data
+        .get(2..130)
+        .ok_or(FlmError::InvalidFlashDevice)?
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect();
+
+    let start = word(132)?;
+    let size = word(136)?;
+
+    let mut sectors = Vec::new();
+    for index in 0..FLASH_DEVICE_MAX_SECTORS {
+        let offset = FLASH_DEVICE_HEADER_SIZE + index * 8;
+        let sector_size = word(offset)?;
+        let address = word(offset + 4)?;
+        if sector_size == SECTOR_END && address == SECTOR_END {
+            break;
+        }
+        sectors.push(SectorDescription {
+            size: sector_size,
+            address,
+        });
+    }
+    if sectors.is_empty() || sectors.len() == FLASH_DEVICE_MAX_SECTORS {
+        return Err(FlmError::InvalidFlashDevice);
+    }
+
+    let end = start
+        .checked_add(size)
+        .ok_or(FlmError::InvalidFlashDevice)?;
+
+    let flash_properties = FlashProperties {
+        address_range: start..end,
+        page_size: word(140)?,
+        erased_byte_value: *data.get(148).ok_or(FlmError::InvalidFlashDevice)?,
+        program_page_timeout: word(152)?,
+        erase_sector_timeout: word(156)?,
+        sectors,
+    };
+
+    Ok((name, flash_properties))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A `FlashDevice` structure of a 256 kB flash with two sector sizes.
+    fn flash_device_data() -> Vec<u8> {
+        let mut data = vec![0u8; FLASH_DEVICE_HEADER_SIZE];
+        data[2..14].copy_from_slice(b"TEST 256kB\0\0");
+        data[132..136].copy_from_slice(&0x0800_0000u32.to_le_bytes());
+        data[136..140].copy_from_slice(&0x0004_0000u32.to_le_bytes());
+        data[140..144].copy_from_slice(&0x400u32.to_le_bytes());
+        data[148] = 0xFF;
+        data[152..156].copy_from_slice(&100u32.to_le_bytes());
+        data[156..160].copy_from_slice(&3000u32.to_le_bytes());
+        for word in &[0x4000u32, 0, 0x1_0000, 0x1_0000, SECTOR_END, SECTOR_END] {
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        data
+    }
+
+    const SHT_PROGBITS: u32 = 1;
+    const SHT_NOBITS: u32 = 8;
+
+    struct Section {
+        name: &'static str,
+        kind: u32,
+        address: u32,
+        data: Vec<u8>,
+    }
+
+    /// Build a minimal 32 bit ARM ELF file with the given sections, followed by the symbol
+    /// table. The symbols are given with their value and the index of their section, where
+    /// the first section has the index 1.
+    fn elf(sections: &[Section], symbols: &[(&str, u32, u16)]) -> Vec<u8> {
+        fn push_name(table: &mut Vec<u8>, name: &str) -> u32 {
+            let offset = table.len() as u32;
+            table.extend_from_slice(name.as_bytes());
+            table.push(0);
+            offset
+        }
+
+        let mut strtab = vec![0];
+        let mut symtab = vec![0u8; 16];
+        for (name, value, section) in symbols {
+            symtab.extend_from_slice(&push_name(&mut strtab, name).to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&0u32.to_le_bytes());
+            // A global function
+            symtab.extend_from_slice(&[0x12, 0]);
+            symtab.extend_from_slice(&section.to_le_bytes());
+        }
+
+        let mut shstrtab = vec![0];
+        let mut file = vec![0u8; 52];
+        let mut section_headers = vec![0u8; 40];
+        let mut add_section = |name: u32,
+                               kind: u32,
+                               address: u32,
+                               data: &[u8],
+                               size: u32,
+                               link_info_entry_size: [u32; 3]| {
+            let offset = file.len() as u32;
+            file.extend_from_slice(data);
+            file.resize((file.len() + 3) / 4 * 4, 0);
+            let [link, info, entry_size] = link_info_entry_size;
+            for word in &[
+                name, kind, 0, address, offset, size, link, info, 4, entry_size,
+            ] {
+                section_headers.extend_from_slice(&word.to_le_bytes());
+            }
+        };
+
+        for section in sections {
+            let name = push_name(&mut shstrtab, section.name);
+            let data: &[u8] = match section.kind {
+                SHT_NOBITS => &[],
+                _ => &section.data,
+            };
+            add_section(
+                name,
+                section.kind,
+                section.address,
+                data,
+                section.data.len() as u32,
+                [0; 3],
+            );
+        }
+
+        // The symbol table links to the string table after it.
+        let symtab_index = sections.len() as u32 + 1;
+        let name = push_name(&mut shstrtab, ".symtab");
+        add_section(
+            name,
+            2,
+            0,
+            &symtab,
+            symtab.len() as u32,
+            [symtab_index + 1, 1, 16],
+        );
+        let name = push_name(&mut shstrtab, ".strtab");
+        add_section(name, 3, 0, &strtab, strtab.len() as u32, [0; 3]);
+        let name = push_name(&mut shstrtab, ".shstrtab");
+        add_section(name, 3, 0, &shstrtab, shstrtab.len() as u32, [0; 3]);
+
+        let section_count = section_headers.len() as u16 / 40;
+        let section_headers_offset = file.len() as u32;
+        file.extend_from_slice(&section_headers);
+
+        file[..16].copy_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+        // An ARM executable
+        file[16..18].copy_from_slice(&2u16.to_le_bytes());
+        file[18..20].copy_from_slice(&40u16.to_le_bytes());
+        file[20..24].copy_from_slice(&1u32.to_le_bytes());
+        file[32..36].copy_from_slice(&section_headers_offset.to_le_bytes());
+        file[40..42].copy_from_slice(&52u16.to_le_bytes());
+        file[46..48].copy_from_slice(&40u16.to_le_bytes());
+        file[48..50].copy_from_slice(&section_count.to_le_bytes());
+        file[50..52].copy_from_slice(&(section_count - 1).to_le_bytes());
+
+        file
+    }
+
+    /// The sections of a flash algorithm, with the code at `code_start`.
+    fn algorithm_sections(code_start: u32, flash_device_kind: u32) -> Vec<Section> {
+        vec![
+            Section {
+                name: "PrgCode",
+                kind: SHT_PROGBITS,
+                address: code_start,
+                data: vec![0x00, 0xbf, 0x00, 0xbf, 0x70, 0x47],
+            },
+            Section {
+                name: "PrgData",
+                kind: SHT_PROGBITS,
+                address: 0x10,
+                data: vec![1, 2, 3, 4],
+            },
+            Section {
+                name: "DevDscr",
+                kind: flash_device_kind,
+                address: 0x1000,
+                data: flash_device_data(),
+            },
+        ]
+    }
+
+    const SYMBOLS: [(&str, u32, u16); 4] = [
+        ("Init", 0x1, 1),
+        ("ProgramPage", 0x3, 1),
+        ("EraseSector", 0x5, 1),
+        ("FlashDevice", 0x1000, 3),
+    ];
+
+    #[test]
+    fn flash_algorithm() {
+        let data = elf(&algorithm_sections(0, SHT_PROGBITS), &SYMBOLS);
+
+        let algorithm = parse_flm("test", &data).unwrap();
+
+        assert_eq!(algorithm.description, "TEST 256kB");
+        assert_eq!(
+            algorithm.instructions,
+            vec![0x00, 0xbf, 0x00, 0xbf, 0x70, 0x47, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4]
+        );
+        assert_eq!(algorithm.data_section_offset, 0x10);
+        assert_eq!(algorithm.pc_init, Some(0x1));
+        assert_eq!(algorithm.pc_uninit, None);
+        assert_eq!(algorithm.pc_program_page, 0x3);
+        assert_eq!(algorithm.pc_erase_sector, 0x5);
+        assert_eq!(
+            algorithm.flash_properties.address_range,
+            0x0800_0000..0x0804_0000
+        );
+    }
+
+    #[test]
+    fn invalid_flash_algorithms() {
+        // The data is located before the code.
+        let data = elf(&algorithm_sections(0x100, SHT_PROGBITS), &[]);
+        assert!(matches!(
+            parse_flm("test", &data),
+            Err(FlmError::InvalidAddress("PrgData"))
+        ));
+
+        // The data is located within the code.
+        let mut sections = algorithm_sections(0, SHT_PROGBITS);
+        sections[1].address = 0x4;
+        let data = elf(&sections, &SYMBOLS);
+        assert!(matches!(
+            parse_flm("test", &data),
+            Err(FlmError::DataOverlapsCode)
+        ));
+
+        // A function is located before the code.
+        let mut symbols = SYMBOLS.to_vec();
+        symbols[0] = ("Init", 0x1, 1);
+        let mut sections = algorithm_sections(0, SHT_PROGBITS);
+        sections[0].address = 0x8;
+        sections[1].address = 0x20;
+        let data = elf(&sections, &symbols);
+        assert!(matches!(
+            parse_flm("test", &data),
+            Err(FlmError::InvalidAddress("Init"))
+        ));
+
+        // The flash device is not initialized.
+        let mut symbols = SYMBOLS.to_vec();
+        symbols[3] = ("FlashDevice", 0x1004, 3);
+        let data = elf(&algorithm_sections(0, SHT_NOBITS), &symbols);
+        assert!(matches!(
+            parse_flm("test", &data),
+            Err(FlmError::InvalidFlashDevice)
+        ));
+    }
+
+    #[test]
+    fn flash_device() {
+        let data = flash_device_data();
+
+        let (name, flash_properties) = parse_flash_device(&data).unwrap();
+
+        assert_eq!(name, "TEST 256kB");
+        assert_eq!(flash_properties.address_range, 0x0800_0000..0x0804_0000);
+        assert_eq!(flash_properties.page_size, 0x400);
+        assert_eq!(flash_properties.erased_byte_value, 0xFF);
+        assert_eq!(flash_properties.erase_sector_timeout, 3000);
+        assert_eq!(
+            flash_properties.sectors,
+            vec![
+                SectorDescription {
+                    size: 0x4000,
+                    address: 0,
+                },
+                SectorDescription {
+                    size: 0x1_0000,
+                    address: 0x1_0000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_sectors() {
+        let mut data = vec![0u8; FLASH_DEVICE_HEADER_SIZE];
+        data.extend_from_slice(&[0u8; 16]);
+
+        assert!(parse_flash_device(&data).is_err());
+    }
+}
@@ -289,6 +289,9 @@ impl FlashLoader {
             return Ok(());
         }
 
+        // The NVM regions which were verified with CRC32 on the target, and don't have to be read back.
+        let mut verified_ranges = Vec::new();
+
         // Iterate all flash algorithms we need to use.
         for ((algo_name, core_name), regions) in algos {
             log::debug!("Flashing ranges for algo: {}", algo_name);
@@ -303,7 +306,7 @@ impl FlashLoader {
                 .iter()
                 .position(|c| c.name == core_name)
                 .unwrap();
-            let mut flasher = Flasher::new(session, core, &algo)?;
+            let mut flasher = Flasher::new(session, core, &algo, options.keep_core_state)?;
 
             let mut do_chip_erase = options.do_chip_erase;
 
@@ -319,7 +322,7 @@ impl FlashLoader {
                 flasher.run_erase(|active| active.erase_all())?;
             }
 
-            for region in regions {
+            for region in &regions {
                 log::debug!(
                     "    programming region: {:08x}-{:08x} ({} bytes)",
                     region.range.start,
@@ -329,14 +332,26 @@ impl FlashLoader {
 
                 // Program the data.
                 flasher.program(
-                    &region,
+                    region,
                     &self.builder,
                     options.keep_unwritten_bytes,
                     true,
                     options.skip_erase || do_chip_erase,
+                    options.skip_unchanged_sectors && !do_chip_erase,
                     options.progress.unwrap_or(&FlashProgress::new(|_| {})),
                 )?;
             }
+
+            // Verify now, because the CRC32 routine is gone once the RAM is written.
+            if options.verify {
+                for region in &regions {
+                    if flasher.verify_crc32(region, &self.builder)? {
+                        verified_ranges.push(region.range.clone());
+                    }
+                }
+            }
+
+            flasher.restore_core_state()?;
         }
 
         log::debug!("committing RAM!");
@@ -392,6 +407,15 @@ impl FlashLoader {
                     data.len()
                 );
 
+                let data_range = address..address + data.len() as u32;
+                if verified_ranges
+                    .iter()
+                    .any(|range: &Range<u32>| range.contains_range(&data_range))
+                {
+                    log::debug!("     -- verified with CRC32");
+                    continue;
+                }
+
                 let associated_region = session
                     .target()
                     .get_memory_region_by_address(address)
@@ -51,6 +51,7 @@ mod erase;
 mod error;
 mod flash_algorithm;
 mod flasher;
+mod flm;
 mod loader;
 mod progress;
 mod visualizer;
@@ -62,6 +63,7 @@ pub use download::*;
 pub use erase::*;
 pub use error::*;
 pub use flash_algorithm::*;
+pub use flm::*;
 pub use loader::*;
 pub use progress::*;
 pub use visualizer::*;
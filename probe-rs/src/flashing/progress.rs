@@ -36,6 +36,11 @@ impl FlashProgress {
         self.emit(ProgressEvent::Initialized { flash_layout });
     }
 
+    /// Signalize that a sector already holds its data, and is neither erased nor programmed.
+    pub(super) fn sector_skipped(&self, address: u32, size: u32) {
+        self.emit(ProgressEvent::SectorSkipped { address, size });
+    }
+
     /// Signalize that the erasing procedure started.
     pub(super) fn started_erasing(&self) {
         self.emit(ProgressEvent::StartedErasing);
@@ -102,6 +107,7 @@ impl FlashProgress {
 /// If flashing works without problems, the events will arrive in the
 /// following order:
 ///
+/// * `SectorSkipped` for every sector which already holds its data, if unchanged sectors are skipped
 /// * `Initialized`
 /// * `StartedFilling`
 /// * `PageFilled` for every page
@@ -117,6 +123,14 @@ impl FlashProgress {
 /// and no further events will be returned.
 #[derive(Debug)]
 pub enum ProgressEvent {
+    /// A sector already holds the data to be flashed, and is neither erased nor programmed.
+    /// It is not part of the flash layout of `Initialized`.
+    SectorSkipped {
+        /// The start address of the sector.
+        address: u32,
+        /// The size of the sector in bytes.
+        size: u32,
+    },
     /// The flash layout has been built and the flashing procedure was initialized.
     Initialized {
         /// The layout of the flash contents as it will be used by the flash procedure.
@@ -73,13 +73,15 @@ mod error;
 pub mod flashing;
 mod memory;
 mod probe;
+pub mod rtt;
+pub mod semihosting;
 mod session;
 
 pub use crate::config::{CoreType, Target};
 pub use crate::core::{
     Architecture, Breakpoint, BreakpointId, CommunicationInterface, Core, CoreInformation,
     CoreInterface, CoreList, CoreRegister, CoreRegisterAddress, CoreState, CoreStatus, HaltReason,
-    SpecificCoreState,
+    SpecificCoreState, Watchpoint, WatchpointKind,
 };
 pub use crate::error::Error;
 pub use crate::memory::{Memory, MemoryInterface};
@@ -0,0 +1,97 @@
+//! A fake memory for unit tests of code which accesses the target
+//! through the [MemoryInterface].
+
+use super::MemoryInterface;
+use crate::error;
+
+/// Fake target RAM, which can be accessed through the [MemoryInterface].
+///
+/// Any access outside of the configured range results in an error.
+pub(crate) struct MockMemory {
+    start: u32,
+    memory: Vec<u8>,
+}
+
+impl MockMemory {
+    pub fn new(start: u32, size: u32) -> Self {
+        Self {
+            start,
+            memory: vec![0; size as usize],
+        }
+    }
+
+    fn offset(&self, address: u32, len: usize) -> Result<usize, error::Error> {
+        let offset = address
+            .checked_sub(self.start)
+            .map(|offset| offset as usize)
+            .filter(|offset| offset + len <= self.memory.len());
+
+        offset.ok_or_else(|| {
+            error::Error::Other(anyhow::anyhow!(
+                "Access to unmapped memory at {:#010x}",
+                address
+            ))
+        })
+    }
+
+    pub fn put_u32(&mut self, address: u32, value: u32) {
+        self.write_word_32(address, value).unwrap();
+    }
+
+    pub fn put_bytes(&mut self, address: u32, data: &[u8]) {
+        self.write_8(address, data).unwrap();
+    }
+}
+
+impl MemoryInterface for MockMemory {
+    fn read_word_32(&mut self, address: u32) -> Result<u32, error::Error> {
+        let mut buff = [0];
+        self.read_32(address, &mut buff)?;
+        Ok(buff[0])
+    }
+
+    fn read_word_8(&mut self, address: u32) -> Result<u8, error::Error> {
+        let offset = self.offset(address, 1)?;
+        Ok(self.memory[offset])
+    }
+
+    fn read_32(&mut self, address: u32, data: &mut [u32]) -> Result<(), error::Error> {
+        let offset = self.offset(address, data.len() * 4)?;
+        for (value, bytes) in data.iter_mut().zip(self.memory[offset..].chunks_exact(4)) {
+            *value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        Ok(())
+    }
+
+    fn read_8(&mut self, address: u32, data: &mut [u8]) -> Result<(), error::Error> {
+        let offset = self.offset(address, data.len())?;
+        data.copy_from_slice(&self.memory[offset..offset + data.len()]);
+        Ok(())
+    }
+
+    fn write_word_32(&mut self, address: u32, data: u32) -> Result<(), error::Error> {
+        self.write_32(address, &[data])
+    }
+
+    fn write_word_8(&mut self, address: u32, data: u8) -> Result<(), error::Error> {
+        self.write_8(address, &[data])
+    }
+
+    fn write_32(&mut self, address: u32, data: &[u32]) -> Result<(), error::Error> {
+        let offset = self.offset(address, data.len() * 4)?;
+        for (value, bytes) in data.iter().zip(self.memory[offset..].chunks_exact_mut(4)) {
+            bytes.copy_from_slice(&value.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_8(&mut self, address: u32, data: &[u8]) -> Result<(), error::Error> {
+        let offset = self.offset(address, data.len())?;
+        self.memory[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), error::Error> {
+        Ok(())
+    }
+}
@@ -7,6 +7,9 @@ use crate::{
 use anyhow::anyhow;
 use anyhow::Result;
 
+#[cfg(test)]
+pub(crate) mod mock;
+
 pub trait MemoryInterface {
     /// Read a 32bit word of at `address`.
     ///
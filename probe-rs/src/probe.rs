@@ -458,6 +458,12 @@ pub trait DebugProbe: Send + fmt::Debug {
     ///
     fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError>;
 
+    /// Check if the speed can be changed with [`DebugProbe::set_speed`] while attached to the
+    /// chip. Most probes only apply the speed when attaching.
+    fn can_set_speed_while_attached(&self) -> bool {
+        false
+    }
+
     /// Attach to the chip.
     ///
     /// This should run all the necessary protocol init routines.
@@ -835,6 +841,14 @@ impl ArmProbeInterface for FakeArmInterface {
     fn target_reset_deassert(&mut self) -> Result<(), Error> {
         todo!()
     }
+
+    fn set_speed(&mut self, speed_khz: u32) -> Result<u32, Error> {
+        if !self.probe.can_set_speed_while_attached() {
+            return Err(DebugProbeError::Attached.into());
+        }
+
+        Ok(self.probe.set_speed(speed_khz)?)
+    }
 }
 
 impl DapAccess for FakeArmInterface {
@@ -410,6 +410,10 @@ impl DebugProbe for CmsisDap {
         Ok(speed_khz)
     }
 
+    fn can_set_speed_while_attached(&self) -> bool {
+        true
+    }
+
     /// Enters debug mode.
     fn attach(&mut self) -> Result<(), DebugProbeError> {
         // get information about the probe
@@ -444,6 +444,10 @@ impl DebugProbe for JLink {
         Ok(actual_speed_khz)
     }
 
+    fn can_set_speed_while_attached(&self) -> bool {
+        true
+    }
+
     fn attach(&mut self) -> Result<(), super::DebugProbeError> {
         log::debug!("Attaching to J-Link");
 
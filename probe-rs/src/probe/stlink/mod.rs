@@ -1220,6 +1220,11 @@ impl<'probe> ArmProbeInterface for StlinkArmDebug {
         Ok(())
     }
 
+    fn set_speed(&mut self, _speed_khz: u32) -> Result<u32, ProbeRsError> {
+        // The ST-Link only applies the SWD or JTAG frequency when entering debug mode.
+        Err(DebugProbeError::Attached.into())
+    }
+
     fn close(self: Box<Self>) -> Probe {
         Probe::from_attached_probe(self.probe)
     }
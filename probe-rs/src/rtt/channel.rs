@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use super::Error;
+use crate::config::MemoryRegion;
+use crate::MemoryInterface;
+
+/// Specifies what to do when a channel doesn't have enough buffer space for a complete write on the
+/// target side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ChannelMode {
+    /// Skip writing the data completely if it doesn't fit in its entirety.
+    NoBlockSkip = 0,
+
+    /// Write as much as possible of the data and ignore the rest.
+    NoBlockTrim = 1,
+
+    /// Block (spin) if the buffer is full. Note that if the application writes within a critical
+    /// section, using this mode can cause the application to freeze if the buffer becomes full and
+    /// is not read by the host.
+    BlockIfFull = 2,
+}
+
+impl TryFrom<u32> for ChannelMode {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value & ChannelMode::MASK {
+            0 => Ok(ChannelMode::NoBlockSkip),
+            1 => Ok(ChannelMode::NoBlockTrim),
+            2 => Ok(ChannelMode::BlockIfFull),
+            mode => Err(Error::ControlBlockCorrupted(format!(
+                "The channel mode {} is invalid",
+                mode
+            ))),
+        }
+    }
+}
+
+impl ChannelMode {
+    /// Bits of the channel flags which hold the mode.
+    const MASK: u32 = 0x3;
+}
+
+/// The shared part of an up or down channel, located inside the control block.
+///
+/// The layout of a channel descriptor in target memory is:
+///
+/// | Offset | Field                      |
+/// |--------|----------------------------|
+/// | 0      | Pointer to the name        |
+/// | 4      | Pointer to the buffer      |
+/// | 8      | Size of the buffer         |
+/// | 12     | Write offset               |
+/// | 16     | Read offset                |
+/// | 20     | Flags                      |
+#[derive(Debug)]
+pub(crate) struct RttChannel {
+    number: usize,
+    ptr: u32,
+    name: Option<String>,
+    buffer_ptr: u32,
+    size: u32,
+}
+
+impl RttChannel {
+    /// Size of a channel descriptor in the control block.
+    pub(crate) const SIZE: usize = 24;
+
+    const O_NAME: usize = 0;
+    const O_BUFFER_PTR: usize = 4;
+    const O_SIZE: usize = 8;
+    const O_WRITE: usize = 12;
+    const O_READ: usize = 16;
+    const O_FLAGS: usize = 20;
+
+    /// Parses a channel descriptor from the raw bytes of the control block.
+    ///
+    /// Returns `Ok(None)` if the descriptor is unused, i.e. it has no buffer.
+    pub(crate) fn from(
+        memory: &mut impl MemoryInterface,
+        number: usize,
+        memory_map: &[MemoryRegion],
+        ptr: u32,
+        mem: &[u8],
+    ) -> Result<Option<RttChannel>, Error> {
+        let buffer_ptr = read_u32(mem, Self::O_BUFFER_PTR);
+        if buffer_ptr == 0 {
+            return Ok(None);
+        }
+
+        let name_ptr = read_u32(mem, Self::O_NAME);
+        let size = read_u32(mem, Self::O_SIZE);
+
+        let name = if name_ptr == 0 {
+            None
+        } else {
+            read_c_string(memory, memory_map, name_ptr)?
+        };
+
+        Ok(Some(RttChannel {
+            number,
+            ptr,
+            name,
+            buffer_ptr,
+            size,
+        }))
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Reads the write and read offsets of the channel, validating them against the buffer size.
+    fn read_pointers(
+        &self,
+        memory: &mut impl MemoryInterface,
+        dir: &'static str,
+    ) -> Result<(u32, u32), Error> {
+        let mut block = [0u32; 2];
+        memory.read_32(self.ptr + Self::O_WRITE as u32, &mut block)?;
+
+        let write: u32 = block[0];
+        let read: u32 = block[1];
+
+        let validate = |which, value| {
+            if value >= self.size {
+                Err(Error::ControlBlockCorrupted(format!(
+                    "{} pointer is {} while buffer size is {} for {:?} channel {} ({})",
+                    which,
+                    value,
+                    self.size,
+                    dir,
+                    self.number,
+                    self.name().unwrap_or("no name"),
+                )))
+            } else {
+                Ok(())
+            }
+        };
+
+        validate("write", write)?;
+        validate("read", read)?;
+
+        Ok((write, read))
+    }
+
+    fn mode(&self, memory: &mut impl MemoryInterface) -> Result<ChannelMode, Error> {
+        let flags = memory.read_word_32(self.ptr + Self::O_FLAGS as u32)?;
+
+        ChannelMode::try_from(flags)
+    }
+
+    fn set_mode(&self, memory: &mut impl MemoryInterface, mode: ChannelMode) -> Result<(), Error> {
+        let flags = memory.read_word_32(self.ptr + Self::O_FLAGS as u32)?;
+
+        let new_flags = (flags & !ChannelMode::MASK) | mode as u32;
+        memory.write_word_32(self.ptr + Self::O_FLAGS as u32, new_flags)?;
+
+        Ok(())
+    }
+}
+
+/// RTT up (target to host) channel.
+#[derive(Debug)]
+pub struct UpChannel(pub(crate) RttChannel);
+
+impl UpChannel {
+    /// Returns the number of the channel.
+    pub fn number(&self) -> usize {
+        self.0.number
+    }
+
+    /// Returns the name of the channel or `None` if there is none.
+    pub fn name(&self) -> Option<&str> {
+        self.0.name()
+    }
+
+    /// Returns the buffer size in bytes. Note that the usable size is one byte less due to how the
+    /// ring buffer is implemented.
+    pub fn buffer_size(&self) -> usize {
+        self.0.size as usize
+    }
+
+    /// Reads the current channel mode from the target and returns it.
+    ///
+    /// See [`ChannelMode`] for more information on what the modes mean.
+    pub fn mode(&self, memory: &mut impl MemoryInterface) -> Result<ChannelMode, Error> {
+        self.0.mode(memory)
+    }
+
+    /// Changes the channel mode on the target to the specified mode.
+    ///
+    /// See [`ChannelMode`] for more information on what the modes mean.
+    pub fn set_mode(
+        &self,
+        memory: &mut impl MemoryInterface,
+        mode: ChannelMode,
+    ) -> Result<(), Error> {
+        self.0.set_mode(memory, mode)
+    }
+
+    fn read_core(
+        &self,
+        memory: &mut impl MemoryInterface,
+        mut buf: &mut [u8],
+    ) -> Result<(u32, usize), Error> {
+        let (write, mut read) = self.0.read_pointers(memory, "up")?;
+
+        let mut total = 0;
+
+        // Read while buffer contains data and output buffer has space (maximum of two iterations)
+        while !buf.is_empty() {
+            let count = std::cmp::min(self.readable_contiguous(write, read), buf.len());
+            if count == 0 {
+                break;
+            }
+
+            memory.read_8(self.0.buffer_ptr + read, &mut buf[..count])?;
+
+            total += count;
+            read += count as u32;
+
+            if read >= self.0.size {
+                // Wrap around to start
+                read = 0;
+            }
+
+            buf = &mut buf[count..];
+        }
+
+        Ok((read, total))
+    }
+
+    /// Reads some bytes from the channel to the specified buffer and returns how many bytes were
+    /// read.
+    ///
+    /// This method will not block waiting for data in the target buffer, and may read less bytes
+    /// than would fit in `buf`.
+    pub fn read(&self, memory: &mut impl MemoryInterface, buf: &mut [u8]) -> Result<usize, Error> {
+        let (read, total) = self.read_core(memory, buf)?;
+
+        if total > 0 {
+            // Write read pointer back to target if something was read
+            memory.write_word_32(self.0.ptr + RttChannel::O_READ as u32, read)?;
+        }
+
+        Ok(total)
+    }
+
+    /// Peeks at the current data in the channel buffer, copies data into the specified buffer and
+    /// returns how many bytes were read.
+    ///
+    /// The difference from [`read`](UpChannel::read) is that this does not discard the data in the
+    /// buffer.
+    pub fn peek(&self, memory: &mut impl MemoryInterface, buf: &mut [u8]) -> Result<usize, Error> {
+        Ok(self.read_core(memory, buf)?.1)
+    }
+
+    /// Calculates amount of contiguous data available for reading
+    fn readable_contiguous(&self, write: u32, read: u32) -> usize {
+        (if read > write {
+            self.0.size - read
+        } else {
+            write - read
+        }) as usize
+    }
+}
+
+/// RTT down (host to target) channel.
+#[derive(Debug)]
+pub struct DownChannel(pub(crate) RttChannel);
+
+impl DownChannel {
+    /// Returns the number of the channel.
+    pub fn number(&self) -> usize {
+        self.0.number
+    }
+
+    /// Returns the name of the channel or `None` if there is none.
+    pub fn name(&self) -> Option<&str> {
+        self.0.name()
+    }
+
+    /// Returns the buffer size in bytes. Note that the usable size is one byte less due to how the
+    /// ring buffer is implemented.
+    pub fn buffer_size(&self) -> usize {
+        self.0.size as usize
+    }
+
+    /// Writes some bytes into the channel buffer and returns the number of bytes written.
+    ///
+    /// This method will not block waiting for space to become available in the channel buffer,
+    /// and may not write all of `buf`.
+    pub fn write(&self, memory: &mut impl MemoryInterface, mut buf: &[u8]) -> Result<usize, Error> {
+        let (mut write, read) = self.0.read_pointers(memory, "down")?;
+
+        let mut total = 0;
+
+        // Write while buffer has space for data and output contains data (maximum of two iterations)
+        while !buf.is_empty() {
+            let count = std::cmp::min(self.writable_contiguous(write, read), buf.len());
+            if count == 0 {
+                break;
+            }
+
+            memory.write_8(self.0.buffer_ptr + write, &buf[..count])?;
+
+            total += count;
+            write += count as u32;
+
+            if write >= self.0.size {
+                // Wrap around to start
+                write = 0;
+            }
+
+            buf = &buf[count..];
+        }
+
+        // Write write pointer back to target
+        memory.write_word_32(self.0.ptr + RttChannel::O_WRITE as u32, write)?;
+
+        Ok(total)
+    }
+
+    /// Calculates amount of contiguous space available for writing
+    fn writable_contiguous(&self, write: u32, read: u32) -> usize {
+        (if read > write {
+            read - write - 1
+        } else if read == 0 {
+            self.0.size - write - 1
+        } else {
+            self.0.size - write
+        }) as usize
+    }
+}
+
+/// A list of up or down channels, indexed by the channel number.
+///
+/// Channels can be removed from the list with [`take`](Channels::take), which is useful to hand
+/// out individual channels to different consumers.
+#[derive(Debug)]
+pub struct Channels<T>(pub(crate) BTreeMap<usize, T>);
+
+impl<T> Channels<T> {
+    /// Returns the number of channels in the list.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if the list contains no channels.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns a reference to the channel corresponding to the number.
+    pub fn get(&self, number: usize) -> Option<&T> {
+        self.0.get(&number)
+    }
+
+    /// Removes the channel corresponding to the number from the list and returns it.
+    pub fn take(&mut self, number: usize) -> Option<T> {
+        self.0.remove(&number)
+    }
+
+    /// Gets an iterator over the channels.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.0.values()
+    }
+
+    /// Gets a mutable iterator over the channels.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.0.values_mut()
+    }
+
+    /// Consumes the list and returns a map of all the channels, indexed by the channel number.
+    pub fn drain(self) -> BTreeMap<usize, T> {
+        self.0
+    }
+}
+
+/// Maximum length of a channel name which is read from the target.
+const MAX_NAME_LENGTH: u32 = 128;
+
+/// Reads a null-terminated string from target memory.
+///
+/// To avoid reading from unmapped memory, the string is only read up to the end of the memory
+/// region it starts in. Returns `Ok(None)` if the pointer does not point into any known memory
+/// region.
+fn read_c_string(
+    memory: &mut impl MemoryInterface,
+    memory_map: &[MemoryRegion],
+    ptr: u32,
+) -> Result<Option<String>, Error> {
+    let range = memory_map
+        .iter()
+        .map(|r| match r {
+            MemoryRegion::Ram(r) => &r.range,
+            MemoryRegion::Generic(r) => &r.range,
+            MemoryRegion::Nvm(r) => &r.range,
+        })
+        .find(|r| r.contains(&ptr));
+
+    let range = match range {
+        Some(range) => range,
+        None => {
+            log::warn!(
+                "RTT channel name points to unmapped memory at {:#010x}, ignoring it",
+                ptr
+            );
+            return Ok(None);
+        }
+    };
+
+    let mut bytes = vec![0u8; std::cmp::min(MAX_NAME_LENGTH, range.end - ptr) as usize];
+    memory.read(ptr, &mut bytes)?;
+
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    Ok(Some(String::from_utf8_lossy(&bytes[..len]).into_owned()))
+}
+
+/// Reads a little endian `u32` from a byte slice.
+pub(crate) fn read_u32(mem: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&mem[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
@@ -0,0 +1,509 @@
+#![warn(missing_docs)]
+
+//! Host side implementation of the RTT (Real-Time Transfer) I/O protocol over probe-rs
+//!
+//! RTT implements input and output to/from a microcontroller using in-memory ring buffers and
+//! memory polling. This enables debug logging from the microcontroller with minimal delays and no
+//! blocking, making it usable even in real-time applications where e.g. semihosting delays cannot
+//! be tolerated.
+//!
+//! The target places a *control block* in RAM, which starts with the magic string `SEGGER RTT`
+//! and describes a number of up (target to host) and down (host to target) channels. The host
+//! locates the control block, and then reads from and writes to the channel buffers using
+//! regular memory accesses, while the target keeps running.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use probe_rs::{rtt::Rtt, Session};
+//!
+//! let mut session = Session::auto_attach("nrf52")?;
+//! let memory_map = session.target().memory_map.clone();
+//! let mut core = session.core(0)?;
+//!
+//! // Scan all RAM regions of the target for the control block.
+//! let mut rtt = Rtt::attach(&mut core, &memory_map)?;
+//!
+//! if let Some(input) = rtt.up_channels().take(0) {
+//!     let mut buf = [0u8; 1024];
+//!     let count = input.read(&mut core, &mut buf[..])?;
+//!
+//!     println!("Read data: {:?}", &buf[..count]);
+//! }
+//!
+//! if let Some(output) = rtt.down_channels().take(0) {
+//!     output.write(&mut core, b"Hello, computer!\n")?;
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+mod channel;
+
+pub use channel::{ChannelMode, Channels, DownChannel, UpChannel};
+
+use channel::{read_u32, RttChannel};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::config::MemoryRegion;
+use crate::MemoryInterface;
+
+use object::read::{Object, ObjectSymbol};
+
+/// The RTT interface.
+///
+/// Use [`Rtt::attach`] or [`Rtt::attach_region`] to attach to a probe-rs [`Core`](crate::Core)
+/// and detect the channels, as they were configured on the target. The timing of when this is
+/// called is really important, or else unexpected results can be expected.
+///
+/// ## Examples of timing issues
+///
+/// 1. If the RTT control block is not yet initialized by the target when attaching, the
+///    attach will fail, or, if the memory happens to contain a stale control block from a
+///    previous run, return stale data.
+/// 2. If the target is reset after attaching, the control block may be moved or
+///    re-initialized, and the channels have to be detected again.
+#[derive(Debug)]
+pub struct Rtt {
+    ptr: u32,
+    up_channels: Channels<UpChannel>,
+    down_channels: Channels<DownChannel>,
+}
+
+impl Rtt {
+    /// The magic string at the start of the control block.
+    const RTT_ID: [u8; 16] = *b"SEGGER RTT\0\0\0\0\0\0";
+
+    /// Name of the symbol which holds the control block in firmware using the SEGGER RTT
+    /// implementation or the `rtt-target` crate.
+    pub const CONTROL_BLOCK_SYMBOL: &'static str = "_SEGGER_RTT";
+
+    // Minimum size of the control block, excluding the channel descriptors.
+    const MIN_SIZE: usize = Self::O_CHANNEL_ARRAYS;
+
+    // Offsets of the fields in the control block header.
+    const O_ID: usize = 0;
+    const O_MAX_UP_CHANNELS: usize = 16;
+    const O_MAX_DOWN_CHANNELS: usize = 20;
+    const O_CHANNEL_ARRAYS: usize = 24;
+
+    /// Upper limit for the number of channels, to detect corrupted control blocks without reading
+    /// huge amounts of memory.
+    const MAX_CHANNELS: usize = 255;
+
+    fn from(
+        memory: &mut impl MemoryInterface,
+        memory_map: &[MemoryRegion],
+        ptr: u32,
+        mem_in: Option<&[u8]>,
+    ) -> Result<Option<Rtt>, Error> {
+        let mut mem = match mem_in {
+            Some(mem) => mem.to_vec(),
+            None => {
+                let mut mem = vec![0u8; Self::MIN_SIZE];
+                memory.read(ptr, &mut mem)?;
+                mem
+            }
+        };
+
+        if mem.len() < Self::MIN_SIZE
+            || mem[Self::O_ID..Self::O_ID + Self::RTT_ID.len()] != Self::RTT_ID
+        {
+            return Ok(None);
+        }
+
+        let max_up_channels = read_u32(&mem, Self::O_MAX_UP_CHANNELS) as usize;
+        let max_down_channels = read_u32(&mem, Self::O_MAX_DOWN_CHANNELS) as usize;
+
+        // *Very* conservative sanity check, most people only use a handful of RTT channels
+        if max_up_channels > Self::MAX_CHANNELS || max_down_channels > Self::MAX_CHANNELS {
+            return Err(Error::ControlBlockCorrupted(format!(
+                "Nonsensical array sizes at {:08x}: max_up_channels={} max_down_channels={}",
+                ptr, max_up_channels, max_down_channels
+            )));
+        }
+
+        let cb_len =
+            Self::O_CHANNEL_ARRAYS + (max_up_channels + max_down_channels) * RttChannel::SIZE;
+
+        if mem.len() < cb_len {
+            // The caller only passed the header, or a truncated control block. Read the
+            // complete control block, including the channel descriptors.
+            mem = vec![0u8; cb_len];
+            memory.read(ptr, &mut mem)?;
+        }
+
+        let mut up_channels = BTreeMap::new();
+        let mut down_channels = BTreeMap::new();
+
+        for i in 0..max_up_channels {
+            let offset = Self::O_CHANNEL_ARRAYS + i * RttChannel::SIZE;
+
+            if let Some(chan) = RttChannel::from(
+                memory,
+                i,
+                memory_map,
+                ptr + offset as u32,
+                &mem[offset..offset + RttChannel::SIZE],
+            )? {
+                up_channels.insert(i, UpChannel(chan));
+            } else {
+                log::warn!("Buffer for up channel {} not initialized", i);
+            }
+        }
+
+        for i in 0..max_down_channels {
+            let offset = Self::O_CHANNEL_ARRAYS + (max_up_channels + i) * RttChannel::SIZE;
+
+            if let Some(chan) = RttChannel::from(
+                memory,
+                i,
+                memory_map,
+                ptr + offset as u32,
+                &mem[offset..offset + RttChannel::SIZE],
+            )? {
+                down_channels.insert(i, DownChannel(chan));
+            } else {
+                log::warn!("Buffer for down channel {} not initialized", i);
+            }
+        }
+
+        Ok(Some(Rtt {
+            ptr,
+            up_channels: Channels(up_channels),
+            down_channels: Channels(down_channels),
+        }))
+    }
+
+    /// Attempts to detect an RTT control block anywhere in the target RAM and returns an instance
+    /// if a valid control block was found.
+    ///
+    /// `memory` can be any [`MemoryInterface`], usually a [`Core`](crate::Core).
+    /// `memory_map` should be the memory map of the target, as returned by
+    /// [`Target::memory_map`](crate::Target).
+    pub fn attach(
+        memory: &mut impl MemoryInterface,
+        memory_map: &[MemoryRegion],
+    ) -> Result<Rtt, Error> {
+        Self::attach_region(memory, memory_map, &ScanRegion::Ram)
+    }
+
+    /// Attempts to detect an RTT control block in the specified RAM region(s) and returns an
+    /// instance if a valid control block was found.
+    pub fn attach_region(
+        memory: &mut impl MemoryInterface,
+        memory_map: &[MemoryRegion],
+        region: &ScanRegion,
+    ) -> Result<Rtt, Error> {
+        let ranges: Vec<Range<u32>> = match region {
+            ScanRegion::Exact(addr) => {
+                log::debug!("Attaching to RTT control block at {:#010x}", addr);
+
+                return Rtt::from(memory, memory_map, *addr, None)?
+                    .ok_or(Error::ControlBlockNotFound);
+            }
+            ScanRegion::Ram => {
+                log::debug!("Scanning whole RAM for RTT control block");
+
+                memory_map
+                    .iter()
+                    .filter_map(|r| match r {
+                        MemoryRegion::Ram(r) => Some(r.range.clone()),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            ScanRegion::Range(region) => {
+                log::debug!("Scanning region {:x?} for RTT control block", region);
+
+                vec![region.clone()]
+            }
+        };
+
+        let mut instances = Vec::new();
+
+        for range in ranges {
+            if range.len() < Self::MIN_SIZE {
+                continue;
+            }
+
+            let mut mem = vec![0u8; range.len()];
+            memory.read(range.start, &mut mem)?;
+
+            for offset in
+                mem.windows(Self::RTT_ID.len())
+                    .enumerate()
+                    .filter_map(|(offset, window)| {
+                        if window == Self::RTT_ID {
+                            Some(offset)
+                        } else {
+                            None
+                        }
+                    })
+            {
+                let ptr = range.start + offset as u32;
+
+                match Rtt::from(memory, memory_map, ptr, Some(&mem[offset..])) {
+                    Ok(Some(rtt)) => instances.push(rtt),
+                    Ok(None) => (),
+                    Err(e) => {
+                        log::debug!("Ignoring invalid RTT control block at {:#010x}: {}", ptr, e)
+                    }
+                }
+            }
+        }
+
+        match instances.len() {
+            0 => Err(Error::ControlBlockNotFound),
+            1 => Ok(instances.remove(0)),
+            _ => Err(Error::MultipleControlBlocksFound(
+                instances.into_iter().map(|i| i.ptr).collect(),
+            )),
+        }
+    }
+
+    /// Returns the memory address of the control block in target memory.
+    pub fn ptr(&self) -> u32 {
+        self.ptr
+    }
+
+    /// Gets the detected up channels.
+    pub fn up_channels(&mut self) -> &mut Channels<UpChannel> {
+        &mut self.up_channels
+    }
+
+    /// Gets the detected down channels.
+    pub fn down_channels(&mut self) -> &mut Channels<DownChannel> {
+        &mut self.down_channels
+    }
+
+    /// Looks up the address of the RTT control block in an ELF file.
+    ///
+    /// The control block is expected in the symbol [`Rtt::CONTROL_BLOCK_SYMBOL`]. The returned
+    /// address can be used with [`ScanRegion::Exact`] to attach to the control block without
+    /// scanning the target memory.
+    pub fn control_block_address(elf: &[u8]) -> Option<u32> {
+        let file = object::File::parse(elf).ok()?;
+
+        file.symbols()
+            .find(|symbol| symbol.name() == Ok(Self::CONTROL_BLOCK_SYMBOL))
+            .map(|symbol| symbol.address() as u32)
+    }
+}
+
+/// Used to specify where to find the RTT control block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanRegion {
+    /// Scans for the control block in the RAM regions of the memory map.
+    Ram,
+
+    /// Limit scanning to these memory addresses in target memory. It is up to the user to ensure
+    /// that reading from this range will not read from undefined memory.
+    Range(Range<u32>),
+
+    /// Tries to find the control block starting at this exact address. It is up to the user to
+    /// ensure that reading the necessary bytes after the pointer will no read from undefined
+    /// memory.
+    Exact(u32),
+}
+
+/// Error type for RTT operations.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// RTT control block not found in target memory. Make sure RTT is initialized on the target.
+    #[error(
+        "RTT control block not found in target memory. Make sure RTT is initialized on the target."
+    )]
+    ControlBlockNotFound,
+
+    /// Multiple control blocks found in target memory. The data contains the control block
+    /// addresses (up to 5).
+    #[error("Multiple control blocks found in target memory: {}", display_list(.0))]
+    MultipleControlBlocksFound(Vec<u32>),
+
+    /// The control block has been corrupted. The data contains a detailed error.
+    #[error("Control block corrupted: {0}")]
+    ControlBlockCorrupted(String),
+
+    /// Wraps errors propagated up from probe-rs.
+    #[error("Error communicating with probe: {0}")]
+    Probe(#[from] crate::Error),
+}
+
+fn display_list(list: &[u32]) -> String {
+    list.iter()
+        .take(5)
+        .map(|ptr| format!("{:#010x}", ptr))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelMode, Error, Rtt, ScanRegion};
+    use crate::config::{MemoryRegion, RamRegion};
+    use crate::memory::mock::MockMemory;
+    use crate::MemoryInterface;
+
+    const RAM_START: u32 = 0x2000_0000;
+    const RAM_SIZE: u32 = 0x1000;
+
+    const CB_ADDRESS: u32 = RAM_START + 0x100;
+    const NAME_ADDRESS: u32 = RAM_START + 0x200;
+    const UP_BUFFER: u32 = RAM_START + 0x300;
+    const DOWN_BUFFER: u32 = RAM_START + 0x400;
+    const BUFFER_SIZE: u32 = 16;
+
+    /// Writes a control block with one up and one down channel.
+    fn with_control_block() -> MockMemory {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+
+        mock.put_bytes(CB_ADDRESS, b"SEGGER RTT\0\0\0\0\0\0");
+        mock.put_u32(CB_ADDRESS + 16, 1);
+        mock.put_u32(CB_ADDRESS + 20, 1);
+
+        mock.put_bytes(NAME_ADDRESS, b"Terminal\0");
+
+        // Up channel 0
+        let up = CB_ADDRESS + 24;
+        mock.put_u32(up, NAME_ADDRESS);
+        mock.put_u32(up + 4, UP_BUFFER);
+        mock.put_u32(up + 8, BUFFER_SIZE);
+        mock.put_u32(up + 20, ChannelMode::NoBlockTrim as u32);
+
+        // Down channel 0
+        let down = CB_ADDRESS + 48;
+        mock.put_u32(down, NAME_ADDRESS);
+        mock.put_u32(down + 4, DOWN_BUFFER);
+        mock.put_u32(down + 8, BUFFER_SIZE);
+
+        mock
+    }
+
+    fn memory_map() -> Vec<MemoryRegion> {
+        vec![MemoryRegion::Ram(RamRegion {
+            range: RAM_START..RAM_START + RAM_SIZE,
+            is_boot_memory: false,
+            cores: vec!["main".to_owned()],
+        })]
+    }
+
+    #[test]
+    fn attach_scans_ram() {
+        let mut mock = with_control_block();
+
+        let mut rtt = Rtt::attach(&mut mock, &memory_map()).unwrap();
+
+        assert_eq!(rtt.ptr(), CB_ADDRESS);
+        assert_eq!(rtt.up_channels().len(), 1);
+        assert_eq!(rtt.down_channels().len(), 1);
+
+        let up = rtt.up_channels().get(0).unwrap();
+        assert_eq!(up.name(), Some("Terminal"));
+        assert_eq!(up.buffer_size(), BUFFER_SIZE as usize);
+        assert_eq!(up.mode(&mut mock).unwrap(), ChannelMode::NoBlockTrim);
+    }
+
+    #[test]
+    fn attach_exact_address() {
+        let mut mock = with_control_block();
+
+        let rtt =
+            Rtt::attach_region(&mut mock, &memory_map(), &ScanRegion::Exact(CB_ADDRESS)).unwrap();
+        assert_eq!(rtt.ptr(), CB_ADDRESS);
+
+        let result =
+            Rtt::attach_region(&mut mock, &memory_map(), &ScanRegion::Exact(CB_ADDRESS + 4));
+        assert!(matches!(result, Err(Error::ControlBlockNotFound)));
+    }
+
+    #[test]
+    fn attach_without_control_block() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+
+        let result = Rtt::attach(&mut mock, &memory_map());
+        assert!(matches!(result, Err(Error::ControlBlockNotFound)));
+    }
+
+    #[test]
+    fn attach_multiple_control_blocks() {
+        let mut mock = with_control_block();
+        mock.put_bytes(RAM_START + 0x800, b"SEGGER RTT\0\0\0\0\0\0");
+
+        let result = Rtt::attach(&mut mock, &memory_map());
+        match result {
+            Err(Error::MultipleControlBlocksFound(ptrs)) => {
+                assert_eq!(ptrs, vec![CB_ADDRESS, RAM_START + 0x800])
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupted_control_block() {
+        let mut mock = with_control_block();
+        mock.put_u32(CB_ADDRESS + 16, 0x1000);
+
+        let result = Rtt::attach_region(&mut mock, &memory_map(), &ScanRegion::Exact(CB_ADDRESS));
+        assert!(matches!(result, Err(Error::ControlBlockCorrupted(_))));
+    }
+
+    #[test]
+    fn read_up_channel_with_wrap_around() {
+        let mut mock = with_control_block();
+        let up = CB_ADDRESS + 24;
+
+        // The target wrote "abcdefgh", starting at offset 12 of the buffer.
+        mock.put_bytes(UP_BUFFER + 12, b"abcd");
+        mock.put_bytes(UP_BUFFER, b"efgh");
+        mock.put_u32(up + 12, 4);
+        mock.put_u32(up + 16, 12);
+
+        let mut rtt = Rtt::attach(&mut mock, &memory_map()).unwrap();
+        let channel = rtt.up_channels().take(0).unwrap();
+
+        let mut buf = [0u8; 32];
+        let count = channel.peek(&mut mock, &mut buf).unwrap();
+        assert_eq!(&buf[..count], b"abcdefgh");
+        assert_eq!(mock.read_word_32(up + 16).unwrap(), 12);
+
+        let count = channel.read(&mut mock, &mut buf).unwrap();
+        assert_eq!(&buf[..count], b"abcdefgh");
+        assert_eq!(mock.read_word_32(up + 16).unwrap(), 4);
+
+        assert_eq!(channel.read(&mut mock, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn write_down_channel() {
+        let mut mock = with_control_block();
+        let down = CB_ADDRESS + 48;
+
+        let mut rtt = Rtt::attach(&mut mock, &memory_map()).unwrap();
+        let channel = rtt.down_channels().take(0).unwrap();
+
+        assert_eq!(channel.write(&mut mock, b"hello").unwrap(), 5);
+        assert_eq!(mock.read_word_32(down + 12).unwrap(), 5);
+
+        let mut buf = [0u8; 5];
+        mock.read_8(DOWN_BUFFER, &mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // Only one byte less than the buffer size can be stored in the ring buffer.
+        let written = channel.write(&mut mock, &[0x55; 32]).unwrap();
+        assert_eq!(written, BUFFER_SIZE as usize - 1 - 5);
+    }
+
+    #[test]
+    fn set_channel_mode() {
+        let mut mock = with_control_block();
+
+        let mut rtt = Rtt::attach(&mut mock, &memory_map()).unwrap();
+        let channel = rtt.up_channels().take(0).unwrap();
+
+        channel
+            .set_mode(&mut mock, ChannelMode::BlockIfFull)
+            .unwrap();
+        assert_eq!(channel.mode(&mut mock).unwrap(), ChannelMode::BlockIfFull);
+    }
+}
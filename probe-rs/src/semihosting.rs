@@ -0,0 +1,658 @@
+//! Host side of ARM semihosting.
+//!
+//! Semihosting allows a program running on the target to use resources of the host,
+//! e.g. to print to the console, to access files, or to signal that it has finished
+//! with an exit code. The target requests an operation by executing `BKPT 0xAB`, with
+//! the operation number in R0 and a parameter in R1. Cores halted on such an
+//! instruction report [`HaltReason::Semihosting`](crate::HaltReason::Semihosting).
+//!
+//! ```no_run
+//! use probe_rs::semihosting::{Semihosting, SemihostingOutcome};
+//! use probe_rs::{CoreStatus, HaltReason, Session};
+//! use std::io::Write;
+//!
+//! let mut session = Session::auto_attach("nrf52")?;
+//! let mut core = session.core(0)?;
+//! let mut semihosting = Semihosting::new();
+//!
+//! loop {
+//!     if core.status()? == CoreStatus::Halted(HaltReason::Semihosting) {
+//!         match semihosting.service(&mut core)? {
+//!             SemihostingOutcome::Output { data, .. } => std::io::stdout().write_all(&data)?,
+//!             SemihostingOutcome::Exit(code) => std::process::exit(code),
+//!             SemihostingOutcome::Resumed => (),
+//!         }
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{Core, Error, MemoryInterface};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io::Write,
+    time::Instant,
+};
+
+/// The reason code `ADP_Stopped_ApplicationExit`, used by the target to signal a normal exit.
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// Special file name, which refers to the console of the host.
+const CONSOLE_FILE_NAME: &[u8] = b":tt";
+
+/// Upper limit for strings read from the target, to avoid reading
+/// through all of the memory when a string is not terminated.
+const MAX_STRING_LENGTH: usize = 1024;
+
+/// Upper limits for the lengths in parameter blocks, to avoid allocating huge
+/// buffers on the host when a parameter block is corrupt.
+const MAX_PATH_LENGTH: u32 = 4096;
+const MAX_WRITE_LENGTH: u32 = 0x1_0000;
+
+/// Handles of the console streams, as returned when opening `:tt`.
+const STDIN_HANDLE: u32 = 1;
+const STDOUT_HANDLE: u32 = 2;
+const STDERR_HANDLE: u32 = 3;
+
+/// Value returned to the target when an operation failed.
+const FAILURE: u32 = -1i32 as u32;
+
+/// A console stream of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleStream {
+    /// Standard output
+    Stdout,
+    /// Standard error
+    Stderr,
+}
+
+/// A semihosting operation requested by the target, decoded from R0 and R1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SemihostingCommand {
+    /// `SYS_OPEN`: Open the file `path`, with the `fopen` mode given as
+    /// index into `r, rb, r+, r+b, w, wb, w+, w+b, a, ab, a+, a+b`.
+    Open { path: Vec<u8>, mode: u32 },
+    /// `SYS_CLOSE`: Close a handle returned by `SYS_OPEN`.
+    Close { handle: u32 },
+    /// `SYS_WRITEC`: Write a single character to the console.
+    WriteC(u8),
+    /// `SYS_WRITE0`: Write a null-terminated string to the console.
+    Write0(Vec<u8>),
+    /// `SYS_WRITE`: Write data to a handle returned by `SYS_OPEN`.
+    Write { handle: u32, data: Vec<u8> },
+    /// `SYS_CLOCK`: Get the number of centiseconds since the program started.
+    Clock,
+    /// `SYS_GET_CMDLINE`: Copy the command line into the buffer at `buffer`,
+    /// with a size of `length` bytes. The parameter block is at `block`.
+    GetCmdline {
+        block: u32,
+        buffer: u32,
+        length: u32,
+    },
+    /// `SYS_EXIT`: The application stopped, with the given reason code.
+    Exit { reason: u32 },
+    /// `SYS_EXIT_EXTENDED`: The application stopped, with the given
+    /// reason code and subcode.
+    ExitExtended { reason: u32, subcode: u32 },
+    /// An operation which is not supported.
+    Unknown { operation: u32, parameter: u32 },
+}
+
+impl SemihostingCommand {
+    const SYS_OPEN: u32 = 0x01;
+    const SYS_CLOSE: u32 = 0x02;
+    const SYS_WRITEC: u32 = 0x03;
+    const SYS_WRITE0: u32 = 0x04;
+    const SYS_WRITE: u32 = 0x05;
+    const SYS_CLOCK: u32 = 0x10;
+    const SYS_GET_CMDLINE: u32 = 0x15;
+    const SYS_EXIT: u32 = 0x18;
+    const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+    /// Decode the operation with number `operation` (R0) and the
+    /// `parameter` (R1), reading the parameter block from `memory` where required.
+    ///
+    /// Fails if a path or a write in the parameter block is unreasonably long,
+    /// which only happens when the parameter block is corrupt.
+    pub fn decode(
+        memory: &mut impl MemoryInterface,
+        operation: u32,
+        parameter: u32,
+    ) -> Result<Self, Error> {
+        let command = match operation {
+            Self::SYS_OPEN => {
+                let mut block = [0u32; 3];
+                memory.read_32(parameter, &mut block)?;
+
+                let mut path = vec![0u8; checked_length(block[2], MAX_PATH_LENGTH)?];
+                memory.read_8(block[0], &mut path)?;
+
+                SemihostingCommand::Open {
+                    path,
+                    mode: block[1],
+                }
+            }
+            Self::SYS_CLOSE => SemihostingCommand::Close {
+                handle: memory.read_word_32(parameter)?,
+            },
+            Self::SYS_WRITEC => SemihostingCommand::WriteC(memory.read_word_8(parameter)?),
+            Self::SYS_WRITE0 => SemihostingCommand::Write0(read_c_string(memory, parameter)?),
+            Self::SYS_WRITE => {
+                let mut block = [0u32; 3];
+                memory.read_32(parameter, &mut block)?;
+
+                let mut data = vec![0u8; checked_length(block[2], MAX_WRITE_LENGTH)?];
+                memory.read_8(block[1], &mut data)?;
+
+                SemihostingCommand::Write {
+                    handle: block[0],
+                    data,
+                }
+            }
+            Self::SYS_CLOCK => SemihostingCommand::Clock,
+            Self::SYS_GET_CMDLINE => {
+                let mut block = [0u32; 2];
+                memory.read_32(parameter, &mut block)?;
+
+                SemihostingCommand::GetCmdline {
+                    block: parameter,
+                    buffer: block[0],
+                    length: block[1],
+                }
+            }
+            // On 32-bit targets, the reason code is passed directly in R1.
+            Self::SYS_EXIT => SemihostingCommand::Exit { reason: parameter },
+            Self::SYS_EXIT_EXTENDED => {
+                let mut block = [0u32; 2];
+                memory.read_32(parameter, &mut block)?;
+
+                SemihostingCommand::ExitExtended {
+                    reason: block[0],
+                    subcode: block[1],
+                }
+            }
+            operation => SemihostingCommand::Unknown {
+                operation,
+                parameter,
+            },
+        };
+
+        Ok(command)
+    }
+}
+
+/// The result of servicing a semihosting request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SemihostingOutcome {
+    /// The request was serviced, and the core is running again.
+    Resumed,
+    /// The target wrote `data` to the console of the host. The core is running again.
+    ///
+    /// It's up to the caller to display the output.
+    Output {
+        stream: ConsoleStream,
+        data: Vec<u8>,
+    },
+    /// The application on the target exited with the given exit code.
+    /// The core stays halted.
+    Exit(i32),
+}
+
+/// Services semihosting requests of a target.
+///
+/// Files opened by the target stay open as long as this struct lives.
+#[derive(Debug)]
+pub struct Semihosting {
+    command_line: String,
+    start: Instant,
+    files: HashMap<u32, File>,
+    next_handle: u32,
+}
+
+impl Default for Semihosting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Semihosting {
+    /// Create a handler without open files, with an empty command line. The clock of
+    /// `SYS_CLOCK` starts when it is created.
+    pub fn new() -> Self {
+        Self {
+            command_line: String::new(),
+            start: Instant::now(),
+            files: HashMap::new(),
+            next_handle: STDERR_HANDLE + 1,
+        }
+    }
+
+    /// Set the command line returned to the target by `SYS_GET_CMDLINE`.
+    pub fn set_command_line(&mut self, command_line: impl Into<String>) {
+        self.command_line = command_line.into();
+    }
+
+    /// Service the semihosting request of a core, which is halted with
+    /// [`HaltReason::Semihosting`](crate::HaltReason::Semihosting).
+    ///
+    /// The result of the operation is written to R0, and the core is resumed
+    /// after the `BKPT` instruction. If the target requested to exit, the core
+    /// is left halted and [`SemihostingOutcome::Exit`] is returned.
+    pub fn service(&mut self, core: &mut Core) -> Result<SemihostingOutcome, Error> {
+        let registers = core.registers();
+
+        let operation = core.read_core_reg(registers.argument_register(0))?;
+        let parameter = core.read_core_reg(registers.argument_register(1))?;
+
+        let command = SemihostingCommand::decode(core, operation, parameter)?;
+        log::debug!("Semihosting request: {:x?}", command);
+
+        let (result, outcome) = self.execute(core, command)?;
+
+        if let SemihostingOutcome::Exit(_) = outcome {
+            return Ok(outcome);
+        }
+
+        core.write_core_reg(registers.result_register(0).into(), result)?;
+
+        // Continue after the BKPT instruction, which is always 2 bytes long.
+        let pc = core.read_core_reg(registers.program_counter())?;
+        core.write_core_reg(registers.program_counter().into(), pc + 2)?;
+
+        core.run()?;
+
+        Ok(outcome)
+    }
+
+    /// Execute a decoded command, returning the value for R0 and the outcome.
+    ///
+    /// This does not touch the core registers, so the core is not resumed.
+    pub fn execute(
+        &mut self,
+        memory: &mut impl MemoryInterface,
+        command: SemihostingCommand,
+    ) -> Result<(u32, SemihostingOutcome), Error> {
+        let mut outcome = SemihostingOutcome::Resumed;
+
+        let result = match command {
+            SemihostingCommand::Open { path, mode } => self.open(&path, mode),
+            SemihostingCommand::Close { handle } => match handle {
+                STDIN_HANDLE | STDOUT_HANDLE | STDERR_HANDLE => 0,
+                handle => match self.files.remove(&handle) {
+                    Some(_) => 0,
+                    None => FAILURE,
+                },
+            },
+            SemihostingCommand::WriteC(c) => {
+                outcome = console_output(ConsoleStream::Stdout, vec![c]);
+                0
+            }
+            SemihostingCommand::Write0(string) => {
+                outcome = console_output(ConsoleStream::Stdout, string);
+                0
+            }
+            SemihostingCommand::Write { handle, data } => match handle {
+                STDOUT_HANDLE => {
+                    outcome = console_output(ConsoleStream::Stdout, data);
+                    0
+                }
+                STDERR_HANDLE => {
+                    outcome = console_output(ConsoleStream::Stderr, data);
+                    0
+                }
+                handle => match self.files.get_mut(&handle) {
+                    // The number of bytes which were *not* written is returned.
+                    Some(file) => match file.write_all(&data) {
+                        Ok(()) => 0,
+                        Err(error) => {
+                            log::warn!("Semihosting: Failed to write to file: {}", error);
+                            data.len() as u32
+                        }
+                    },
+                    None => data.len() as u32,
+                },
+            },
+            SemihostingCommand::Clock => {
+                let centiseconds = self.start.elapsed().as_millis() / 10;
+                u32::try_from(centiseconds).unwrap_or(FAILURE)
+            }
+            SemihostingCommand::GetCmdline {
+                block,
+                buffer,
+                length,
+            } => {
+                let mut command_line = self.command_line.clone().into_bytes();
+
+                if command_line.len() + 1 > length as usize {
+                    FAILURE
+                } else {
+                    let command_line_length = command_line.len() as u32;
+                    command_line.push(0);
+
+                    memory.write_8(buffer, &command_line)?;
+                    memory.write_word_32(block + 4, command_line_length)?;
+                    0
+                }
+            }
+            SemihostingCommand::Exit { reason } => {
+                outcome = SemihostingOutcome::Exit(exit_code(reason, 0));
+                0
+            }
+            SemihostingCommand::ExitExtended { reason, subcode } => {
+                outcome = SemihostingOutcome::Exit(exit_code(reason, subcode));
+                0
+            }
+            SemihostingCommand::Unknown { operation, .. } => {
+                log::warn!(
+                    "Semihosting: Operation {:#04x} is not supported.",
+                    operation
+                );
+                FAILURE
+            }
+        };
+
+        Ok((result, outcome))
+    }
+
+    fn open(&mut self, path: &[u8], mode: u32) -> u32 {
+        if path == CONSOLE_FILE_NAME {
+            return match mode {
+                0..=3 => STDIN_HANDLE,
+                4..=7 => STDOUT_HANDLE,
+                8..=11 => STDERR_HANDLE,
+                _ => FAILURE,
+            };
+        }
+
+        let path = match std::str::from_utf8(path) {
+            Ok(path) => path,
+            Err(_) => return FAILURE,
+        };
+
+        let mut options = OpenOptions::new();
+        match mode {
+            // r, rb
+            0 | 1 => options.read(true),
+            // r+, r+b
+            2 | 3 => options.read(true).write(true),
+            // w, wb
+            4 | 5 => options.write(true).create(true).truncate(true),
+            // w+, w+b
+            6 | 7 => options.read(true).write(true).create(true).truncate(true),
+            // a, ab
+            8 | 9 => options.append(true).create(true),
+            // a+, a+b
+            10 | 11 => options.read(true).append(true).create(true),
+            _ => return FAILURE,
+        };
+
+        match options.open(path) {
+            Ok(file) => {
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.files.insert(handle, file);
+                handle
+            }
+            Err(error) => {
+                log::warn!("Semihosting: Failed to open '{}': {}", path, error);
+                FAILURE
+            }
+        }
+    }
+}
+
+fn console_output(stream: ConsoleStream, data: Vec<u8>) -> SemihostingOutcome {
+    SemihostingOutcome::Output { stream, data }
+}
+
+/// Map an exit reason to a process exit code. Only a normal application
+/// exit is treated as success.
+fn exit_code(reason: u32, subcode: u32) -> i32 {
+    if reason == ADP_STOPPED_APPLICATION_EXIT {
+        subcode as i32
+    } else {
+        1
+    }
+}
+
+/// Check a length from a parameter block against its upper limit.
+fn checked_length(length: u32, limit: u32) -> Result<usize, Error> {
+    if length > limit {
+        Err(Error::Other(anyhow::anyhow!(
+            "Semihosting: The length of {} bytes in the parameter block is larger than the limit of {} bytes.",
+            length,
+            limit
+        )))
+    } else {
+        Ok(length as usize)
+    }
+}
+
+/// Read a null-terminated string from the target, without the terminator.
+fn read_c_string(memory: &mut impl MemoryInterface, address: u32) -> Result<Vec<u8>, Error> {
+    let mut string = Vec::new();
+    let mut address = address;
+
+    while string.len() < MAX_STRING_LENGTH {
+        let byte = memory.read_word_8(address)?;
+
+        if byte == 0 {
+            break;
+        }
+
+        string.push(byte);
+        address += 1;
+    }
+
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConsoleStream, Semihosting, SemihostingCommand, SemihostingOutcome, FAILURE};
+    use crate::memory::mock::MockMemory;
+    use crate::MemoryInterface;
+
+    const RAM_START: u32 = 0x2000_0000;
+    const RAM_SIZE: u32 = 0x1000;
+
+    const BLOCK: u32 = RAM_START + 0x100;
+    const STRING: u32 = RAM_START + 0x200;
+
+    fn decode(mock: &mut MockMemory, operation: u32, parameter: u32) -> SemihostingCommand {
+        SemihostingCommand::decode(mock, operation, parameter).unwrap()
+    }
+
+    #[test]
+    fn decode_write0() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        mock.put_bytes(STRING, b"Hello\0World");
+
+        assert_eq!(
+            decode(&mut mock, 0x04, STRING),
+            SemihostingCommand::Write0(b"Hello".to_vec())
+        );
+    }
+
+    #[test]
+    fn decode_write() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        mock.put_bytes(STRING, b"Hello");
+        mock.put_u32(BLOCK, 2);
+        mock.put_u32(BLOCK + 4, STRING);
+        mock.put_u32(BLOCK + 8, 4);
+
+        assert_eq!(
+            decode(&mut mock, 0x05, BLOCK),
+            SemihostingCommand::Write {
+                handle: 2,
+                data: b"Hell".to_vec()
+            }
+        );
+    }
+
+    #[test]
+    fn decode_open() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        mock.put_bytes(STRING, b":tt\0");
+        mock.put_u32(BLOCK, STRING);
+        mock.put_u32(BLOCK + 4, 4);
+        mock.put_u32(BLOCK + 8, 3);
+
+        assert_eq!(
+            decode(&mut mock, 0x01, BLOCK),
+            SemihostingCommand::Open {
+                path: b":tt".to_vec(),
+                mode: 4
+            }
+        );
+    }
+
+    #[test]
+    fn decode_rejects_huge_lengths() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        mock.put_u32(BLOCK, 2);
+        mock.put_u32(BLOCK + 4, STRING);
+        mock.put_u32(BLOCK + 8, 0xffff_fff0);
+
+        assert!(SemihostingCommand::decode(&mut mock, 0x05, BLOCK).is_err());
+
+        mock.put_u32(BLOCK, STRING);
+        mock.put_u32(BLOCK + 4, 0);
+        mock.put_u32(BLOCK + 8, 0x10_0000);
+
+        assert!(SemihostingCommand::decode(&mut mock, 0x01, BLOCK).is_err());
+    }
+
+    #[test]
+    fn decode_unknown() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+
+        assert_eq!(
+            decode(&mut mock, 0x42, 7),
+            SemihostingCommand::Unknown {
+                operation: 0x42,
+                parameter: 7
+            }
+        );
+    }
+
+    #[test]
+    fn console_is_opened_by_mode() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        let mut semihosting = Semihosting::new();
+
+        for (mode, handle) in [(0, 1), (4, 2), (8, 3), (12, FAILURE)].iter() {
+            let command = SemihostingCommand::Open {
+                path: b":tt".to_vec(),
+                mode: *mode,
+            };
+
+            let (result, _) = semihosting.execute(&mut mock, command).unwrap();
+            assert_eq!(result, *handle);
+        }
+    }
+
+    #[test]
+    fn write_to_stderr_is_output() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        let mut semihosting = Semihosting::new();
+
+        let command = SemihostingCommand::Write {
+            handle: 3,
+            data: b"oops".to_vec(),
+        };
+
+        assert_eq!(
+            semihosting.execute(&mut mock, command).unwrap(),
+            (
+                0,
+                SemihostingOutcome::Output {
+                    stream: ConsoleStream::Stderr,
+                    data: b"oops".to_vec()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn write_to_unknown_handle_fails() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        let mut semihosting = Semihosting::new();
+
+        let command = SemihostingCommand::Write {
+            handle: 42,
+            data: b"data".to_vec(),
+        };
+
+        let (result, _) = semihosting.execute(&mut mock, command).unwrap();
+        assert_eq!(result, 4);
+    }
+
+    #[test]
+    fn get_cmdline() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        mock.put_u32(BLOCK, STRING);
+        mock.put_u32(BLOCK + 4, 64);
+
+        let mut semihosting = Semihosting::new();
+        semihosting.set_command_line("test --verbose");
+
+        let command = decode(&mut mock, 0x15, BLOCK);
+        let (result, _) = semihosting.execute(&mut mock, command).unwrap();
+        assert_eq!(result, 0);
+
+        let mut buffer = [0u8; 15];
+        mock.read_8(STRING, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"test --verbose\0");
+        assert_eq!(mock.read_word_32(BLOCK + 4).unwrap(), 14);
+    }
+
+    #[test]
+    fn get_cmdline_buffer_too_small() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        let mut semihosting = Semihosting::new();
+        semihosting.set_command_line("test");
+
+        let command = SemihostingCommand::GetCmdline {
+            block: BLOCK,
+            buffer: STRING,
+            length: 4,
+        };
+
+        let (result, _) = semihosting.execute(&mut mock, command).unwrap();
+        assert_eq!(result, FAILURE);
+    }
+
+    #[test]
+    fn exit_codes() {
+        let mut mock = MockMemory::new(RAM_START, RAM_SIZE);
+        let mut semihosting = Semihosting::new();
+
+        let cases = [
+            (SemihostingCommand::Exit { reason: 0x20026 }, 0),
+            (SemihostingCommand::Exit { reason: 0x20023 }, 1),
+            (
+                SemihostingCommand::ExitExtended {
+                    reason: 0x20026,
+                    subcode: 3,
+                },
+                3,
+            ),
+            (
+                SemihostingCommand::ExitExtended {
+                    reason: 0x20024,
+                    subcode: 0,
+                },
+                1,
+            ),
+        ];
+
+        for (command, code) in cases.iter() {
+            let (_, outcome) = semihosting.execute(&mut mock, command.clone()).unwrap();
+            assert_eq!(outcome, SemihostingOutcome::Exit(*code));
+        }
+    }
+}
@@ -249,6 +249,19 @@ impl Session {
         interface.read_swo()
     }
 
+    /// Change the protocol speed of the probe, in kHz.
+    ///
+    /// Returns the speed which was actually set, which can differ from the requested speed
+    /// if the probe doesn't support it exactly. Probes which can't change their speed while
+    /// attached, like the ST-Link, return
+    /// [`DebugProbeError::Attached`](crate::DebugProbeError::Attached).
+    pub fn set_speed(&mut self, speed_khz: u32) -> Result<u32, Error> {
+        match &mut self.interface {
+            ArchitectureInterface::Arm(interface) => interface.set_speed(speed_khz),
+            ArchitectureInterface::Riscv(interface) => interface.set_speed(speed_khz),
+        }
+    }
+
     fn get_arm_interface(&mut self) -> Result<&mut Box<dyn ArmProbeInterface>, Error> {
         let interface = match &mut self.interface {
             ArchitectureInterface::Arm(state) => state,
@@ -398,12 +411,17 @@ static_assertions::assert_impl_all!(Session: Send);
 impl Drop for Session {
     fn drop(&mut self) {
         let result = { 0..self.cores.len() }.try_for_each(|i| {
-            self.core(i)
-                .and_then(|mut core| core.clear_all_hw_breakpoints())
+            self.core(i).and_then(|mut core| {
+                core.clear_all_hw_breakpoints()?;
+                core.clear_all_hw_watchpoints()
+            })
         });
 
         if let Err(err) = result {
-            log::warn!("Could not clear all hardware breakpoints: {:?}", err);
+            log::warn!(
+                "Could not clear all hardware breakpoints and watchpoints: {:?}",
+                err
+            );
         }
     }
 }
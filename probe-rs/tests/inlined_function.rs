@@ -68,3 +68,28 @@ fn function_name_of_non_inlined_function() {
     let name = di.function_name(address, false).unwrap();
     assert_eq!(expected_name, name);
 }
+
+#[test]
+fn function_breakpoint_location() {
+    let di = DebugInfo::from_file("tests/inlined-function").unwrap();
+
+    // The function starts at 0x10c, the breakpoint is placed after the prologue.
+    let expected_location = Some(0x112);
+
+    assert_eq!(
+        di.get_function_breakpoint_location("__cortex_m_rt_main")
+            .unwrap(),
+        expected_location
+    );
+
+    assert_eq!(
+        di.get_function_breakpoint_location("inlined_function::__cortex_m_rt_main")
+            .unwrap(),
+        expected_location
+    );
+
+    assert_eq!(
+        di.get_function_breakpoint_location("rt_main").unwrap(),
+        None
+    );
+}
@@ -0,0 +1,16 @@
+use probe_rs::{DebugProbeError, Error, FakeProbe, Probe};
+
+#[test]
+fn set_speed_while_attached() {
+    let probe = Probe::from_specific_probe(Box::new(FakeProbe::new()));
+
+    let mut session = probe
+        .attach("stm32wb55ccux")
+        .expect("Failed to attach with 'fake' probe.");
+
+    // Like most probes, the fake probe can't change its speed while attached.
+    assert!(matches!(
+        session.set_speed(4000),
+        Err(Error::Probe(DebugProbeError::Attached))
+    ));
+}
@@ -0,0 +1,368 @@
+//! Generation of probe-rs target descriptions from the device tree of a CMSIS-Pack.
+
+use crate::pdsc::{Device, Family, MemoryKind};
+use anyhow::{anyhow, Context, Result};
+use probe_rs::flashing::parse_flm;
+use probe_rs_target::{
+    ArmCoreAccessOptions, Chip, ChipFamily, Core, CoreAccessOptions, CoreType, MemoryRegion,
+    NvmRegion, RamRegion, RawFlashAlgorithm, TargetDescriptionSource,
+};
+use std::ops::Range;
+use std::path::Path;
+
+/// Create the target description of a family. The FLM files of the flash algorithms are read
+/// with `read_file`, from their path in the pack.
+pub(crate) fn chip_family(
+    family: &Family,
+    mut read_file: impl FnMut(&str) -> Result<Vec<u8>>,
+) -> Result<ChipFamily> {
+    family_with_algorithms(family, |name, file_name| {
+        let data = read_file(file_name)?;
+        parse_flm(name, &data)
+            .with_context(|| format!("Could not read the flash algorithm {}", file_name))
+    })
+}
+
+/// Create the target description of a family, where `read_algorithm` reads the flash algorithm
+/// with the given name from the path of its FLM file.
+fn family_with_algorithms(
+    family: &Family,
+    mut read_algorithm: impl FnMut(&str, &str) -> Result<RawFlashAlgorithm>,
+) -> Result<ChipFamily> {
+    let mut variants = Vec::new();
+    let mut flash_algorithms: Vec<RawFlashAlgorithm> = Vec::new();
+    // The name of the FLM file and the flash range in the PDSC file of each flash algorithm.
+    let mut algorithm_sources: Vec<(String, Option<Range<u32>>)> = Vec::new();
+
+    for device in &family.devices {
+        let cores = match cores(device) {
+            Ok(cores) => cores,
+            Err(error) => {
+                log::warn!("Skipping {}: {}", device.name, error);
+                continue;
+            }
+        };
+
+        let mut algorithm_names = Vec::new();
+        for algorithm in &device.algorithms {
+            let name = algorithm_name(&algorithm.file_name);
+            let algorithm_cores = match &algorithm.processor {
+                Some(processor) => vec![core_name(processor, cores.len())],
+                None => cores.iter().map(|core| core.name.clone()).collect(),
+            };
+
+            let source = (name, algorithm.range.clone());
+            match algorithm_sources.iter().position(|other| *other == source) {
+                Some(index) => {
+                    let existing = &mut flash_algorithms[index];
+                    existing.default |= algorithm.default;
+                    for core in algorithm_cores {
+                        if !existing.cores.contains(&core) {
+                            existing.cores.push(core);
+                        }
+                    }
+                    algorithm_names.push(existing.name.clone());
+                }
+                None => {
+                    let (name, range) = &source;
+                    let mut flash_algorithm = read_algorithm(name, &algorithm.file_name)?;
+                    flash_algorithm.default = algorithm.default;
+                    flash_algorithm.cores = algorithm_cores;
+                    // The FLM file may describe a larger flash than the one of the chip.
+                    if let Some(range) = range {
+                        flash_algorithm.flash_properties.address_range = range.clone();
+                    }
+                    // Devices which use the FLM file for another flash get an algorithm of their
+                    // own, named after its range.
+                    if flash_algorithms
+                        .iter()
+                        .any(|other| other.name == flash_algorithm.name)
+                    {
+                        let range = &flash_algorithm.flash_properties.address_range;
+                        flash_algorithm.name =
+                            format!("{}_{:x}_{:x}", name, range.start, range.end);
+                    }
+                    algorithm_names.push(flash_algorithm.name.clone());
+                    flash_algorithms.push(flash_algorithm);
+                    algorithm_sources.push(source);
+                }
+            }
+        }
+
+        variants.push(Chip {
+            name: device.name.clone(),
+            part: None,
+            memory_map: memory_map(device, &cores),
+            cores,
+            flash_algorithms: algorithm_names,
+        });
+    }
+
+    Ok(ChipFamily {
+        name: family.name.clone(),
+        manufacturer: None,
+        variants,
+        flash_algorithms,
+        source: TargetDescriptionSource::External,
+    })
+}
+
+/// The cores of a chip. A single core is named `main`, as in the built-in targets, and the cores
+/// of chips with several processors are named after their processor.
+fn cores(device: &Device) -> Result<Vec<Core>> {
+    if device.processors.is_empty() {
+        return Err(anyhow!("The device has no processor"));
+    }
+
+    device
+        .processors
+        .iter()
+        .map(|processor| {
+            Ok(Core {
+                name: match &processor.name {
+                    Some(name) => core_name(name, device.processors.len()),
+                    None => "main".to_string(),
+                },
+                core_type: core_type(&processor.core)?,
+                core_access_options: CoreAccessOptions::Arm(ArmCoreAccessOptions {
+                    ap: processor.ap,
+                    psel: 0,
+                }),
+            })
+        })
+        .collect()
+}
+
+fn core_type(core: &str) -> Result<CoreType> {
+    match core {
+        "Cortex-M0" | "Cortex-M0+" | "Cortex-M1" | "SC000" => Ok(CoreType::M0),
+        "Cortex-M3" | "SC300" => Ok(CoreType::M3),
+        "Cortex-M4" => Ok(CoreType::M4),
+        "Cortex-M7" => Ok(CoreType::M7),
+        // The ARMv8-M cores share the debug architecture of the Cortex-M33.
+        "Cortex-M23" | "Cortex-M33" | "Cortex-M35P" => Ok(CoreType::M33),
+        core => Err(anyhow!("The core '{}' is not supported", core)),
+    }
+}
+
+/// The name of the core of a processor, on a chip with `core_count` cores.
+fn core_name(processor: &str, core_count: usize) -> String {
+    if core_count > 1 {
+        processor.to_lowercase()
+    } else {
+        "main".to_string()
+    }
+}
+
+fn memory_map(device: &Device, cores: &[Core]) -> Vec<MemoryRegion> {
+    device
+        .memories
+        .iter()
+        .filter_map(|memory| {
+            let end = match memory.start.checked_add(memory.size) {
+                Some(end) => end,
+                None => {
+                    log::warn!(
+                        "Skipping the memory {} of {}, which ends beyond the address space",
+                        memory.name,
+                        device.name
+                    );
+                    return None;
+                }
+            };
+            let range = memory.start..end;
+            let cores = match &memory.processor {
+                Some(processor) => vec![core_name(processor, cores.len())],
+                None => cores.iter().map(|core| core.name.clone()).collect(),
+            };
+            Some(match memory.kind {
+                MemoryKind::Ram => MemoryRegion::Ram(RamRegion {
+                    range,
+                    is_boot_memory: memory.startup,
+                    cores,
+                }),
+                MemoryKind::Nvm => MemoryRegion::Nvm(NvmRegion {
+                    range,
+                    is_boot_memory: memory.startup,
+                    cores,
+                }),
+            })
+        })
+        .collect()
+}
+
+/// Flash algorithms are named after their FLM file, e.g. `stm32f4xx_1024` for
+/// `CMSIS/Flash/STM32F4xx_1024.FLM`.
+fn algorithm_name(file_name: &str) -> String {
+    Path::new(file_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pdsc::{Algorithm, Memory, Processor};
+
+    #[test]
+    fn dual_core_chip() {
+        let processor = |name: &str, core: &str, ap| Processor {
+            name: Some(name.to_string()),
+            core: core.to_string(),
+            ap,
+        };
+        let family = Family {
+            name: "TEST Series".to_string(),
+            devices: vec![Device {
+                name: "TEST2".to_string(),
+                processors: vec![
+                    processor("CM7", "Cortex-M7", 0),
+                    processor("CM4", "Cortex-M4", 3),
+                ],
+                memories: vec![Memory {
+                    name: "IRAM1".to_string(),
+                    processor: Some("CM4".to_string()),
+                    kind: MemoryKind::Ram,
+                    start: 0x1000_0000,
+                    size: 0x4_8000,
+                    startup: false,
+                }],
+                algorithms: Vec::new(),
+            }],
+        };
+
+        let chip_family = chip_family(&family, |_| unreachable!()).unwrap();
+        let chip = &chip_family.variants[0];
+
+        let cores: Vec<_> = chip
+            .cores
+            .iter()
+            .map(|core| match &core.core_access_options {
+                CoreAccessOptions::Arm(options) => (core.name.as_str(), options.ap),
+                CoreAccessOptions::Riscv(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(cores, vec![("cm7", 0), ("cm4", 3)]);
+        assert_eq!(
+            chip.memory_map,
+            vec![MemoryRegion::Ram(RamRegion {
+                range: 0x1000_0000..0x1004_8000,
+                is_boot_memory: false,
+                cores: vec!["cm4".to_string()],
+            })]
+        );
+        assert_eq!(
+            algorithm_name("CMSIS/Flash/STM32H7x_2048.FLM"),
+            "stm32h7x_2048"
+        );
+    }
+
+    #[test]
+    fn skip_memory_beyond_address_space() {
+        let memory = |name: &str, start, size| Memory {
+            name: name.to_string(),
+            processor: None,
+            kind: MemoryKind::Nvm,
+            start,
+            size,
+            startup: false,
+        };
+        let device = Device {
+            name: "TEST3".to_string(),
+            processors: vec![Processor {
+                name: None,
+                core: "Cortex-M0".to_string(),
+                ap: 0,
+            }],
+            memories: vec![
+                memory("IROM1", 0x0, 0x1_0000),
+                memory("IROM2", 0xffff_0000, 0x2_0000),
+            ],
+            algorithms: Vec::new(),
+        };
+        let cores = cores(&device).unwrap();
+
+        assert_eq!(
+            memory_map(&device, &cores),
+            vec![MemoryRegion::Nvm(NvmRegion {
+                range: 0x0..0x1_0000,
+                is_boot_memory: false,
+                cores: vec!["main".to_string()],
+            })]
+        );
+    }
+
+    #[test]
+    fn flash_algorithm_for_several_ranges() {
+        let device = |name: &str, size: u32| Device {
+            name: name.to_string(),
+            processors: vec![Processor {
+                name: None,
+                core: "Cortex-M4".to_string(),
+                ap: 0,
+            }],
+            memories: Vec::new(),
+            algorithms: vec![Algorithm {
+                file_name: "CMSIS/Flash/TEST.FLM".to_string(),
+                processor: None,
+                default: true,
+                range: Some(0x0800_0000..0x0800_0000 + size),
+            }],
+        };
+        let family = Family {
+            name: "TEST Series".to_string(),
+            devices: vec![
+                device("TEST128", 0x2_0000),
+                device("TEST256", 0x4_0000),
+                device("TEST128B", 0x2_0000),
+            ],
+        };
+
+        let mut reads = 0;
+        let chip_family = family_with_algorithms(&family, |name, file_name| {
+            assert_eq!(file_name, "CMSIS/Flash/TEST.FLM");
+            reads += 1;
+            let mut algorithm = RawFlashAlgorithm {
+                name: name.to_string(),
+                ..Default::default()
+            };
+            algorithm.flash_properties.address_range = 0x0800_0000..0x0810_0000;
+            Ok(algorithm)
+        })
+        .unwrap();
+
+        let algorithms: Vec<_> = chip_family
+            .flash_algorithms
+            .iter()
+            .map(|algorithm| {
+                (
+                    algorithm.name.as_str(),
+                    algorithm.flash_properties.address_range.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            algorithms,
+            vec![
+                ("test", 0x0800_0000..0x0802_0000),
+                ("test_8000000_8040000", 0x0800_0000..0x0804_0000),
+            ]
+        );
+        assert_eq!(reads, 2);
+
+        let chip_algorithms: Vec<_> = chip_family
+            .variants
+            .iter()
+            .map(|chip| chip.flash_algorithms.clone())
+            .collect();
+        assert_eq!(
+            chip_algorithms,
+            vec![
+                vec!["test".to_string()],
+                vec!["test_8000000_8040000".to_string()],
+                vec!["test".to_string()],
+            ]
+        );
+    }
+}
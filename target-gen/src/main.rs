@@ -0,0 +1,101 @@
+//! Generates probe-rs target descriptions from CMSIS-Packs.
+//!
+//! The tool reads a `.pack` file from disk, and writes one target description for each device
+//! family of the pack, which can be added to `probe-rs/targets` or loaded at runtime.
+
+mod generate;
+mod pdsc;
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use zip::ZipArchive;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "target-gen",
+    about = "Generates probe-rs target descriptions from a CMSIS-Pack"
+)]
+struct Opt {
+    /// The CMSIS-Pack (.pack) file
+    #[structopt(parse(from_os_str))]
+    pack: PathBuf,
+    /// The directory for the target descriptions, one YAML file per device family
+    #[structopt(parse(from_os_str), default_value = ".")]
+    output_dir: PathBuf,
+}
+
+fn main() -> Result<()> {
+    pretty_env_logger::init();
+
+    let opt = Opt::from_args();
+
+    let file =
+        File::open(&opt.pack).with_context(|| format!("Could not open the pack {:?}", opt.pack))?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("The pack {:?} is not a zip file", opt.pack))?;
+
+    let pdsc_name = archive
+        .file_names()
+        .find(|name| !name.contains('/') && name.to_lowercase().ends_with(".pdsc"))
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("The pack {:?} has no PDSC file", opt.pack))?;
+    let pdsc = String::from_utf8(read_file(&mut archive, &pdsc_name)?)
+        .with_context(|| format!("The PDSC file {} is not valid UTF-8", pdsc_name))?;
+
+    let families = pdsc::parse_families(&pdsc)
+        .with_context(|| format!("Could not parse the PDSC file {}", pdsc_name))?;
+    if families.is_empty() {
+        return Err(anyhow!("The pack {:?} contains no devices", opt.pack));
+    }
+
+    fs::create_dir_all(&opt.output_dir)?;
+    for family in &families {
+        let chip_family =
+            generate::chip_family(family, |file_name| read_file(&mut archive, file_name))
+                .with_context(|| format!("Could not generate the family {}", family.name))?;
+        // The description is written anyway, as the problems are usually easy to fix by hand.
+        if let Err(error) = chip_family.validate() {
+            log::warn!("{}", error);
+        }
+
+        let path = output_path(&opt.output_dir, &family.name);
+        let output = File::create(&path)
+            .with_context(|| format!("Could not create the target description {:?}", path))?;
+        serde_yaml::to_writer(output, &chip_family)?;
+
+        println!(
+            "Generated {:?} with {} chips and {} flash algorithms.",
+            path,
+            chip_family.variants.len(),
+            chip_family.flash_algorithms.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Read a file of the pack. Paths in PDSC files are relative to the PDSC file, which is at the
+/// root of the pack, and are matched without regard to case, as packs are made on Windows.
+fn read_file(archive: &mut ZipArchive<File>, file_name: &str) -> Result<Vec<u8>> {
+    let name = archive
+        .file_names()
+        .find(|name| name.eq_ignore_ascii_case(file_name))
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("The pack has no file {}", file_name))?;
+
+    let mut file = archive.by_name(&name)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)
+        .with_context(|| format!("Could not read {} from the pack", name))?;
+
+    Ok(data)
+}
+
+/// The target descriptions are named after their family, like the built-in ones,
+/// e.g. `STM32F4_Series.yaml`.
+fn output_path(output_dir: &Path, family: &str) -> PathBuf {
+    output_dir.join(format!("{}.yaml", family.replace(' ', "_")))
+}
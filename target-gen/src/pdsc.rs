@@ -0,0 +1,374 @@
+//! Parsing of the device tree in the PDSC file of a CMSIS-Pack.
+//!
+//! The devices of a pack are described in a tree of `family`, `subFamily`, `device` and
+//! `variant` elements. The properties of an element, like its processors, memories and flash
+//! algorithms, are inherited by all the elements below it. Each `device` without variants, and
+//! each `variant`, is a chip of its family.
+
+use anyhow::{anyhow, Context, Result};
+use roxmltree::{Document, Node};
+use std::ops::Range;
+
+/// A `family` element with all its chips.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Family {
+    pub(crate) name: String,
+    pub(crate) devices: Vec<Device>,
+}
+
+/// A chip of a family, with the properties inherited from all the elements above it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Device {
+    pub(crate) name: String,
+    pub(crate) processors: Vec<Processor>,
+    pub(crate) memories: Vec<Memory>,
+    pub(crate) algorithms: Vec<Algorithm>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Processor {
+    /// The name of the processor, which is only given for chips with several processors.
+    pub(crate) name: Option<String>,
+    /// The type of the core, e.g. `Cortex-M4`.
+    pub(crate) core: String,
+    /// The access port of the core, from the `__ap` attribute of the `debug` element.
+    pub(crate) ap: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum MemoryKind {
+    Ram,
+    Nvm,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Memory {
+    /// The `id` or `name` of the memory, which identifies it when it is redefined by a child.
+    pub(crate) name: String,
+    /// The processor which can access the memory, or `None` if all can.
+    pub(crate) processor: Option<String>,
+    pub(crate) kind: MemoryKind,
+    pub(crate) start: u32,
+    pub(crate) size: u32,
+    pub(crate) startup: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Algorithm {
+    /// The path of the FLM file in the pack.
+    pub(crate) file_name: String,
+    /// The processor which can use the algorithm, or `None` if all can.
+    pub(crate) processor: Option<String>,
+    pub(crate) default: bool,
+    /// The flash programmed by the algorithm, from the `start` and `size` attributes.
+    /// If it is `None`, the address range of the FLM file is used.
+    pub(crate) range: Option<Range<u32>>,
+}
+
+/// The properties which are inherited along the device tree.
+#[derive(Debug, Default, Clone)]
+struct Properties {
+    processors: Vec<Processor>,
+    memories: Vec<Memory>,
+    algorithms: Vec<Algorithm>,
+}
+
+/// Parse the families of the devices in a PDSC file.
+pub(crate) fn parse_families(pdsc: &str) -> Result<Vec<Family>> {
+    let document = Document::parse(pdsc).context("The PDSC file is not valid XML")?;
+
+    let devices = match children(document.root_element(), "devices").next() {
+        Some(devices) => devices,
+        None => return Ok(Vec::new()),
+    };
+
+    children(devices, "family")
+        .map(|family| {
+            let name = required_attribute(family, "Dfamily")?.to_string();
+            let mut properties = Properties::default();
+            properties.merge(family)?;
+
+            let mut devices = Vec::new();
+            for sub_family in children(family, "subFamily") {
+                let mut properties = properties.clone();
+                properties.merge(sub_family)?;
+                for device in children(sub_family, "device") {
+                    add_devices(&mut devices, device, &properties)?;
+                }
+            }
+            for device in children(family, "device") {
+                add_devices(&mut devices, device, &properties)?;
+            }
+
+            Ok(Family { name, devices })
+        })
+        .collect()
+}
+
+/// Add a `device` element, or each of its variants, to the list of chips.
+fn add_devices(devices: &mut Vec<Device>, device: Node, properties: &Properties) -> Result<()> {
+    let mut properties = properties.clone();
+    properties.merge(device)?;
+
+    let mut variants = children(device, "variant").peekable();
+    if variants.peek().is_none() {
+        devices.push(properties.into_device(required_attribute(device, "Dname")?));
+        return Ok(());
+    }
+    for variant in variants {
+        let mut properties = properties.clone();
+        properties.merge(variant)?;
+        devices.push(properties.into_device(required_attribute(variant, "Dvariant")?));
+    }
+
+    Ok(())
+}
+
+impl Properties {
+    /// Add the properties of an element, which replace the inherited ones with the same name.
+    fn merge(&mut self, node: Node) -> Result<()> {
+        for processor in children(node, "processor") {
+            let name = processor.attribute("Pname").map(str::to_string);
+            // Some packs only give the name of the processor on a child element.
+            let core = match processor.attribute("Dcore") {
+                Some(core) => core.to_string(),
+                None => match self.processors.iter().find(|other| other.name == name) {
+                    Some(other) => other.core.clone(),
+                    None => continue,
+                },
+            };
+            self.processors.retain(|other| other.name != name);
+            self.processors.push(Processor { name, core, ap: 0 });
+        }
+
+        for debug in children(node, "debug") {
+            if let Some(ap) = debug.attribute("__ap") {
+                let ap =
+                    parse_number(ap).with_context(|| format!("Invalid access port '{}'", ap))?;
+                let name = debug.attribute("Pname");
+                for processor in self
+                    .processors
+                    .iter_mut()
+                    .filter(|processor| name.is_none() || processor.name.as_deref() == name)
+                {
+                    processor.ap = ap as u8;
+                }
+            }
+        }
+
+        for memory in children(node, "memory") {
+            let memory = parse_memory(memory)?;
+            self.memories.retain(|other| other.name != memory.name);
+            self.memories.push(memory);
+        }
+
+        for algorithm in children(node, "algorithm") {
+            let algorithm = parse_algorithm(algorithm)?;
+            self.algorithms
+                .retain(|other| other.file_name != algorithm.file_name);
+            self.algorithms.push(algorithm);
+        }
+
+        Ok(())
+    }
+
+    fn into_device(self, name: &str) -> Device {
+        Device {
+            name: name.to_string(),
+            processors: self.processors,
+            memories: self.memories,
+            algorithms: self.algorithms,
+        }
+    }
+}
+
+/// Parse a `memory` element. Memories are either named after their use, with an `id` of `IROM1`,
+/// `IRAM1` etc., or have a `name` and an `access` attribute, where writable memory is RAM.
+fn parse_memory(memory: Node) -> Result<Memory> {
+    let (name, kind) = match (memory.attribute("id"), memory.attribute("name")) {
+        (Some(id), _) if id.starts_with("IROM") => (id, MemoryKind::Nvm),
+        (Some(id), _) if id.starts_with("IRAM") => (id, MemoryKind::Ram),
+        (Some(id), _) => return Err(anyhow!("Unknown memory id '{}'", id)),
+        (None, Some(name)) => {
+            let access = memory.attribute("access").unwrap_or("r");
+            if access.contains('w') {
+                (name, MemoryKind::Ram)
+            } else {
+                (name, MemoryKind::Nvm)
+            }
+        }
+        (None, None) => return Err(anyhow!("A memory has neither an id nor a name")),
+    };
+
+    let start = required_attribute(memory, "start")?;
+    let size = required_attribute(memory, "size")?;
+
+    Ok(Memory {
+        name: name.to_string(),
+        processor: memory.attribute("Pname").map(str::to_string),
+        kind,
+        start: parse_number(start).with_context(|| format!("Invalid start '{}'", start))?,
+        size: parse_number(size).with_context(|| format!("Invalid size '{}'", size))?,
+        startup: memory.attribute("startup").map(parse_bool) == Some(true),
+    })
+}
+
+/// Parse an `algorithm` element. The `start` and `size` attributes are optional in older packs.
+fn parse_algorithm(algorithm: Node) -> Result<Algorithm> {
+    let file_name = required_attribute(algorithm, "name")?.replace('\\', "/");
+
+    let range = match (algorithm.attribute("start"), algorithm.attribute("size")) {
+        (Some(start), Some(size)) => {
+            let start =
+                parse_number(start).with_context(|| format!("Invalid start '{}'", start))?;
+            let size = parse_number(size).with_context(|| format!("Invalid size '{}'", size))?;
+            let end = start.checked_add(size).ok_or_else(|| {
+                anyhow!(
+                    "The flash of algorithm '{}' ends beyond the address space",
+                    file_name
+                )
+            })?;
+            Some(start..end)
+        }
+        _ => None,
+    };
+
+    Ok(Algorithm {
+        file_name,
+        processor: algorithm.attribute("Pname").map(str::to_string),
+        default: algorithm.attribute("default").map(parse_bool) == Some(true),
+        range,
+    })
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn required_attribute<'a>(node: Node<'a, '_>, name: &str) -> Result<&'a str> {
+    node.attribute(name).ok_or_else(|| {
+        anyhow!(
+            "The '{}' element has no '{}' attribute",
+            node.tag_name().name(),
+            name
+        )
+    })
+}
+
+/// Parse a decimal or hexadecimal number, as used for addresses and sizes in PDSC files.
+fn parse_number(number: &str) -> Result<u32> {
+    let number = number.trim();
+    let value = match number
+        .strip_prefix("0x")
+        .or_else(|| number.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16)?,
+        None => number.parse()?,
+    };
+    Ok(value)
+}
+
+fn parse_bool(value: &str) -> bool {
+    value == "1" || value == "true"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PDSC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package schemaVersion="1.4">
+  <vendor>Test</vendor>
+  <name>TEST_DFP</name>
+  <devices>
+    <family Dfamily="TEST Series" Dvendor="Test:0">
+      <processor Dcore="Cortex-M4" Dfpu="SP_FPU" Dendian="Little-endian"/>
+      <algorithm name="Flash\TEST_1M.FLM" start="0x08000000" size="0x100000" default="1"/>
+      <subFamily DsubFamily="TEST1">
+        <memory id="IRAM1" start="0x20000000" size="0x10000"/>
+        <device Dname="TEST1A">
+          <memory id="IROM1" start="0x08000000" size="0x80000" startup="1" default="1"/>
+        </device>
+        <device Dname="TEST1B">
+          <memory id="IROM1" start="0x08000000" size="0x100000" startup="1" default="1"/>
+          <variant Dvariant="TEST1B-Q"/>
+          <variant Dvariant="TEST1B-R">
+            <memory name="SRAM2" access="rwx" start="0x10000000" size="0x8000"/>
+            <algorithm name="Flash\TEST_OTP.FLM" start="0x1FFF7000" size="0x400"/>
+          </variant>
+        </device>
+      </subFamily>
+    </family>
+  </devices>
+</package>"#;
+
+    #[test]
+    fn inherit_properties() {
+        let families = parse_families(PDSC).unwrap();
+        assert_eq!(families.len(), 1);
+
+        let family = &families[0];
+        assert_eq!(family.name, "TEST Series");
+
+        let names: Vec<_> = family
+            .devices
+            .iter()
+            .map(|device| device.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["TEST1A", "TEST1B-Q", "TEST1B-R"]);
+
+        let device = &family.devices[2];
+        assert_eq!(
+            device.processors,
+            vec![Processor {
+                name: None,
+                core: "Cortex-M4".to_string(),
+                ap: 0
+            }]
+        );
+        assert_eq!(
+            device.algorithms,
+            vec![
+                Algorithm {
+                    file_name: "Flash/TEST_1M.FLM".to_string(),
+                    processor: None,
+                    default: true,
+                    range: Some(0x0800_0000..0x0810_0000),
+                },
+                Algorithm {
+                    file_name: "Flash/TEST_OTP.FLM".to_string(),
+                    processor: None,
+                    default: false,
+                    range: Some(0x1fff_7000..0x1fff_7400),
+                }
+            ]
+        );
+        assert_eq!(family.devices[1].algorithms.len(), 1);
+
+        let memories: Vec<_> = device
+            .memories
+            .iter()
+            .map(|memory| {
+                (
+                    memory.name.as_str(),
+                    memory.kind,
+                    memory.size,
+                    memory.startup,
+                )
+            })
+            .collect();
+        assert_eq!(
+            memories,
+            vec![
+                ("IRAM1", MemoryKind::Ram, 0x1_0000, false),
+                ("IROM1", MemoryKind::Nvm, 0x10_0000, true),
+                ("SRAM2", MemoryKind::Ram, 0x8000, false),
+            ]
+        );
+        assert_eq!(family.devices[0].memories[1].size, 0x8_0000);
+    }
+}